@@ -0,0 +1,111 @@
+; "Tifflin" Kernel
+; - By John Hodge (thePowersGang)
+;
+; arch/amd64/ap_trampoline.inc.asm
+; - Application processor startup
+;
+; `ap_trampoline` is copied to AP_TRAMPOLINE_BASE (below 1MiB) by smp.rs, and the AP
+; is started there (in real mode) by a startup IPI.
+
+%define AP_TRAMPOLINE_BASE	0x8000
+; Address of a trampoline symbol once the trampoline has been copied
+%define AP_REL(sym)	(AP_TRAMPOLINE_BASE + (sym) - ap_trampoline)
+
+[section .text]
+[BITS 16]
+EXPORT ap_trampoline
+	cli
+	cld
+	xor ax, ax
+	mov ds, ax
+	; 1. Enter protected mode (using the trampoline's GDT)
+	lgdt [AP_REL(.gdtptr)]
+	mov eax, cr0
+	or al, 1
+	mov cr0, eax
+	jmp dword 0x08:AP_REL(.pmode)
+[BITS 32]
+.pmode:
+	mov ax, 0x10
+	mov ds, ax
+	mov es, ax
+	mov ss, ax
+	; 2. Switch into IA-32e mode (same features as the BSP, see start.asm)
+	mov eax, cr4
+	or eax, 0x80|0x20|0x10|(1 << 9)|(1 << 10)
+	mov cr4, eax
+	; - Temporary PML4: kernel mappings, plus an identity mapping of this code
+	mov eax, [AP_REL(ap_trampoline_pml4)]
+	mov cr3, eax
+	mov ecx, 0xC0000080
+	rdmsr
+	or eax, (1 << 11)|(1 << 8)|(1 << 0)	; NXE, LME, SCE
+	wrmsr
+	; 3. Enable paging (PG, WP, TS, MP) and enter long mode
+	mov eax, cr0
+	or eax, 0x80010000|(1 << 3)|(1 << 1)
+	and ax, ~(1 << 2)
+	mov cr0, eax
+	jmp 0x18:AP_REL(.lmode)
+[BITS 64]
+.lmode:
+	mov rax, ap_start64
+	jmp rax
+
+ALIGN 8
+.gdt:
+	dd 0, 0
+	dd 0x0000FFFF, 0x00CF9A00	; 0x08: 32-bit Code
+	dd 0x0000FFFF, 0x00CF9200	; 0x10: 32-bit Data
+	dd 0x00000000, 0x00209A00	; 0x18: 64-bit Code
+.gdtptr:
+	dw	.gdtptr - .gdt - 1
+	dd	AP_REL(.gdt)
+; Physical address of the temporary PML4 (written by smp.rs)
+EXPORT ap_trampoline_pml4
+	dd	0
+EXPORT ap_trampoline_end
+
+[extern ap_entry]
+; Higher-half entry, using the parameters in `s_ap_boot_info`
+ap_start64:
+	; 4. Load the kernel's GDT and address space
+	lgdt [rel GDTPtr2]
+	mov ax, 0x10
+	mov ds, ax
+	mov ss, ax
+	mov es, ax
+	mov fs, ax
+	mov gs, ax
+	mov rax, [rel s_ap_boot_info+0*8]
+	mov cr3, rax
+
+	; 5. Initialise TLS on the boot stack (like TID0)
+	; - Use the bottom of the stack while preparing TLS at the top
+	mov rdi, [rel s_ap_boot_info+1*8]
+	mov rsi, [rel s_ap_boot_info+2*8]
+	lea rsp, [rsi+0x1000]
+	mov rdx, 0
+	call prep_tls
+	mov rsp, rax
+	; - FS/GS base
+	mov rdx, rax
+	shr rdx, 32
+	mov ecx, 0xC0000100	; FS Base
+	wrmsr
+	mov ecx, 0xC0000101	; GS Base
+	wrmsr
+
+	; 6. Shared IDT, and per-CPU SYSCALL MSRs
+	lidt [rel IDTPtr]
+	call syscall_msr_init
+
+	; 7. Call rust (never returns, switches to this CPU's idle thread)
+	mov rdi, [rel s_ap_boot_info+3*8]
+	call ap_entry
+.dead_loop:
+	cli
+	hlt
+	jmp .dead_loop
+
+; vim: ft=nasm
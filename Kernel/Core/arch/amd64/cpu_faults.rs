@@ -6,6 +6,7 @@
 //use prelude::*;
 use super::{puts,puth};
 use super::log::puth64;
+use threads::exceptions::{ExceptionFrame,ExceptionKind};
 
 #[repr(C)]
 //#[derive(Copy,Clone)]
@@ -25,6 +26,113 @@ pub struct InterruptRegs
 	rflags: u64, rsp: u64, ss: u64,
 }
 
+impl InterruptRegs
+{
+	/// Copy the (user) register state into an exception frame
+	fn save_to(&self, frame: &mut ExceptionFrame)
+	{
+		frame.ip = self.rip as usize;
+		frame.sp = self.rsp as usize;
+		frame.fp = self.rbp as usize;
+		let vals = [
+			self.rax, self.rcx, self.rdx, self.rbx, self.rsp, self.rbp, self.rsi, self.rdi,
+			self.r8 , self.r9 , self.r10, self.r11, self.r12, self.r13, self.r14, self.r15,
+			self.rflags,
+			];
+		for (d, s) in Iterator::zip( frame.regs.iter_mut(), vals.iter() ) {
+			*d = *s as usize;
+		}
+	}
+	/// Redirect the return to user-mode into an exception handler (frame already written by `exceptions::deliver`)
+	fn enter_handler(&mut self, entry: usize, frame_addr: usize)
+	{
+		self.rip = entry as u64;
+		self.rdi = frame_addr as u64;
+		// The handler never returns, so there's no return address (just the slot, to keep the ABI's alignment)
+		self.rsp = frame_addr as u64 - 8;
+		// Clear DF (as the ABI requires) and TF
+		self.rflags &= !(0x400 | 0x100);
+	}
+}
+
+/// Map a CPU exception vector to the kind reported to user exception handlers
+fn user_exception_kind(intnum: u64) -> Option<ExceptionKind>
+{
+	match intnum
+	{
+	0 => Some(ExceptionKind::DivideError),
+	3 => Some(ExceptionKind::Breakpoint),
+	6 => Some(ExceptionKind::InvalidInstruction),
+	12 | 13 | 17 => Some(ExceptionKind::ProtectionFault),
+	14 => Some(ExceptionKind::PageFault),
+	_ => None,
+	}
+}
+
+/// Deliver a fault to the current thread's exception handler, returns false if it can't be handled
+fn deliver_user_fault(regs: &mut InterruptRegs) -> bool
+{
+	let kind = match user_exception_kind(regs.intnum)
+		{
+		Some(k) => k,
+		None => return false,
+		};
+	let address = if kind == ExceptionKind::PageFault { get_cr2() as usize } else { 0 };
+	let mut frame = ExceptionFrame::new(kind, regs.errorcode as u32, address);
+	regs.save_to(&mut frame);
+	match ::threads::exceptions::deliver(&frame)
+	{
+	Some( (entry, frame_addr) ) => {
+		regs.enter_handler(entry, frame_addr);
+		true
+		},
+	None => false,
+	}
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Notification delivery, called by assembly when returning to user-mode with the pending flag set
+pub extern "C" fn user_event_deliver(regs: &mut InterruptRegs)
+{
+	if let Some(value) = ::threads::exceptions::take_notification()
+	{
+		let mut frame = ExceptionFrame::new(ExceptionKind::Notification, value, 0);
+		regs.save_to(&mut frame);
+		match ::threads::exceptions::deliver(&frame)
+		{
+		Some( (entry, frame_addr) ) => regs.enter_handler(entry, frame_addr),
+		None => log_notice!("Notification {:#x} dropped, exception stack not writable", value),
+		}
+	}
+}
+
+/// Leave a user exception handler, resuming with the state in `frame`
+pub fn exception_return(frame: &ExceptionFrame) -> !
+{
+	extern "C" {
+		fn exception_return_user(regs: *const InterruptRegs) -> !;
+	}
+	// Addresses outside user space (including non-canonical ones, which would fault in IRETQ) are replaced with zero
+	let user_addr = |v: usize| if v < ::arch::memory::addresses::USER_END { v as u64 } else { 0 };
+	let r = &frame.regs;
+	let regs = InterruptRegs {
+		rax: r[0] as u64, rcx: r[1] as u64, rdx: r[2] as u64, rbx: r[3] as u64,
+		rbp: r[5] as u64, rsi: r[6] as u64, rdi: r[7] as u64,
+		r8 : r[ 8] as u64, r9 : r[ 9] as u64, r10: r[10] as u64, r11: r[11] as u64,
+		r12: r[12] as u64, r13: r[13] as u64, r14: r[14] as u64, r15: r[15] as u64,
+		intnum: 0, errorcode: 0,
+		rip: user_addr(frame.ip), cs: 0x2B,
+		// Only the status flags and DF can be set, IF (and the reserved bit 1) always are
+		rflags: (r[16] as u64 & 0xCD5) | 0x202,
+		rsp: user_addr(frame.sp), ss: 0x23,
+		};
+	// SAFE: Segments are user-mode, addresses are in user space, and interrupts will be enabled
+	unsafe {
+		exception_return_user(&regs)
+	}
+}
+
 #[no_mangle]
 #[doc(hidden)]
 /// Error handler called by assembly
@@ -36,12 +144,16 @@ pub extern "C" fn spurrious_handler(regs: &InterruptRegs)
 #[no_mangle]
 #[doc(hidden)]
 /// Error handler called by assembly
-pub extern "C" fn error_handler(regs: &InterruptRegs)
+pub extern "C" fn error_handler(regs: &mut InterruptRegs)
 {
 	// If the fault originated in kernel mode, emit a mode reset
 	//if regs.cs == 0x8 {
 	//	puts("\x1b[m");
 	//}
+	// User faults are handed to the thread's exception handler (#NM and page faults are first checked below)
+	if regs.cs != 0x08 && regs.intnum != 7 && regs.intnum != 14 && deliver_user_fault(regs) {
+		return ;
+	}
 	match regs.intnum
 	{
 	7 => {
@@ -63,10 +175,14 @@ pub extern "C" fn error_handler(regs: &InterruptRegs)
 	13 => { puts("GPF ("); puth(regs.errorcode); puts(")\n"); },
 	14 => {
 		let cr2 = get_cr2();
-		puts("PF ("); puth(regs.errorcode); puts(") at "); puth(cr2 as u64); puts(" by "); puth(regs.rip); puts(" SP="); puth(regs.rsp); puts("\n");
+		// NOTE: Handled faults (e.g. COW/demand-zero) are common, so only print if the fault wasn't handled
 		if ::arch::imp::memory::virt::handle_page_fault(cr2 as usize, regs.errorcode as u32) {
 			return ;
 		}
+		if regs.cs != 0x08 && deliver_user_fault(regs) {
+			return ;
+		}
+		puts("PF ("); puth(regs.errorcode); puts(") at "); puth(cr2 as u64); puts(" by "); puth(regs.rip); puts(" SP="); puth(regs.rsp); puts("\n");
 		},
 	_ => { puts("ERROR "); puth(regs.intnum); puts(" (code "); puth(regs.errorcode); puts(")\n"); },
 	}
@@ -83,7 +199,7 @@ pub extern "C" fn error_handler(regs: &InterruptRegs)
 	puts("R14 "); puth64(regs.r14); puts("  R15 "); puth64(regs.r15); puts("\n");
 
 	if regs.cs != 0x08 {
-		// It's a user fault, terminate that thread
+		// It's a user fault with no handler, terminate the process
 		puts("Stack :");
 		for i in 0 .. 4 {
 			puts(" ");
@@ -93,7 +209,10 @@ pub extern "C" fn error_handler(regs: &InterruptRegs)
 			Err(_) => puts("INVAL"),
 			}
 		}
-		todo!("User fault");
+		puts("\n");
+		// SAFE: The fault is from user-mode, so no kernel state is inconsistent
+		unsafe { ::arch::sync::start_interrupts(); }
+		::threads::exit_process(::threads::exceptions::EXIT_STATUS_FAULT);
 	}
 	else
 	{
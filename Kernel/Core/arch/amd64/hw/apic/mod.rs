@@ -86,12 +86,103 @@ fn init()
 	s_lapic.init();
 	
 	// Enable interupts
-	// TODO: Does S_IRQS_ENABLED ever get read?
-	::arch::imp::threads::S_IRQS_ENABLED.store(true, ::core::sync::atomic::Ordering::Relaxed);
+	::arch::imp::threads::set_irqs_enabled();
 	// SAFE: Just STI, nothing to worry about
 	unsafe { asm!("sti"); }
 }
 
+/// APIC IDs of all enabled processors (including the current one)
+pub fn get_cpu_apic_ids() -> Vec<u32>
+{
+	match ::arch::acpi::find::<init::ACPI_MADT>("APIC", 0)
+	{
+	None => Vec::new(),
+	Some(madt) => madt.data().records(madt.data_len()).filter_map(
+			|r| match r {
+				init::MADTDevRecord::DevLAPIC(a) if a.flags & 1 != 0 => Some(a.apic_id as u32),
+				_ => None
+				}
+			).collect(),
+	}
+}
+/// APIC ID of the current processor
+pub fn local_apic_id() -> u32
+{
+	get_lapic().id()
+}
+/// Initialise the local APIC for an application processor (called on that processor)
+pub fn init_ap()
+{
+	// NOTE: `LAPIC::init` enables interrupts
+	get_lapic().init();
+	::arch::imp::threads::set_irqs_enabled();
+}
+/// Measure the local APIC timer's rate against the clocksource, then switch this CPU's timer to one-shot mode
+///
+/// Called once on the BSP (application processors are started afterwards, and use one-shot mode from the start).
+pub fn calibrate_timer(ms: u64)
+{
+	let hz = {
+		let _irq = ::sync::hold_interrupts();
+		let lapic = get_lapic();
+		let end = super::clocksource::now_ns() + ms * 1_000_000;
+		lapic.timer_start_count();
+		while super::clocksource::now_ns() < end {
+			::core::sync::atomic::spin_loop_hint();
+		}
+		lapic.timer_elapsed() as u64 * 1000 / ms
+		};
+	log_notice!("LAPIC timer: {} Hz", hz);
+	raw::S_TIMER_HZ.store(hz, ::core::sync::atomic::Ordering::Relaxed);
+
+	// Stop the periodic timer, and request an early interrupt to pick up the first real deadline
+	get_lapic().timer_oneshot(1);
+}
+/// Program the local APIC timer to fire at the given clocksource timestamp (`!0` stops the timer)
+///
+/// Ignored until the timer has been calibrated (it's periodic until then)
+pub fn set_timer_deadline(ns: u64)
+{
+	let hz = raw::S_TIMER_HZ.load(::core::sync::atomic::Ordering::Relaxed);
+	if hz == 0 || !s_lapic.ls_is_valid() {
+		return ;
+	}
+	let now = super::clocksource::now_ns();
+	// Keep the timer armed often enough for the clocksource to notice counter wraps
+	let ns = ::core::cmp::min(ns, now.saturating_add(super::clocksource::max_idle_ns()));
+	let count = if ns == !0 {
+			0
+		}
+		else {
+			let delta = ns.saturating_sub(now);
+			// Round up so the interrupt isn't early, and clamp (an early interrupt just re-arms)
+			let count = (delta / 1_000_000_000).saturating_mul(hz) + ((delta % 1_000_000_000) * hz + 999_999_999) / 1_000_000_000;
+			::core::cmp::max(1, ::core::cmp::min(count, 0xFFFF_FFFF) as u32)
+		};
+	get_lapic().timer_oneshot(count);
+}
+/// Acknowledge an interrupt delivered by the local APIC (e.g. an IPI)
+pub fn local_eoi(isr: usize)
+{
+	get_lapic().eoi(isr);
+}
+
+/// Send a fixed inter-processor interrupt to another CPU
+pub fn send_ipi(apic_id: u32, vector: u8)
+{
+	get_lapic().send_ipi(apic_id, vector, raw::IpiMode::Fixed);
+}
+/// Send an INIT IPI (resets the target CPU, ready for a startup IPI)
+pub fn send_init_ipi(apic_id: u32)
+{
+	get_lapic().send_ipi(apic_id, 0, raw::IpiMode::Init);
+}
+/// Send a startup IPI, starting the target CPU in real mode at `page * 0x1000`
+pub fn send_startup_ipi(apic_id: u32, page: u8)
+{
+	get_lapic().send_ipi(apic_id, page, raw::IpiMode::Startup);
+}
+
 fn get_ioapic(interrupt: usize) -> Option<(&'static raw::IOAPIC, usize)>
 {
 	match s_ioapics.iter().find( |a| a.contains(interrupt) )
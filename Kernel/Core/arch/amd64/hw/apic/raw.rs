@@ -6,6 +6,12 @@
 use prelude::*;
 
 static TIMER_VEC: u8 = 0x7E;
+/// Local APIC timer rate (Hz, after the divider), zero until calibrated
+pub static S_TIMER_HZ: ::sync::atomic::AtomicValue<u64> = ::sync::atomic::AtomicValue::new(0);
+/// Timer LVT: Periodic mode (one-shot when clear)
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// LVT: Masked
+const LVT_MASKED: u32 = 1 << 16;
 
 pub struct LAPIC
 {
@@ -59,6 +65,7 @@ enum ApicReg
 	ErrStatus = 0x28,	// Error Status
 	LVTCMCI   = 0x2F,	// LVT CMCI Registers (?)
 	ICR       = 0x30,	// Interrupt Command Register (1/2)
+	ICRHigh   = 0x31,	// Interrupt Command Register (2/2)
 	LVTTimer  = 0x32,
 	LVTThermalSensor = 0x33,
 	LVTPermCounters  = 0x34,
@@ -70,6 +77,15 @@ enum ApicReg
 	TmrDivide = 0x3E,
 }
 
+/// Delivery mode for an inter-processor interrupt
+#[derive(Copy,Clone)]
+pub enum IpiMode
+{
+	Fixed = 0,
+	Init = 5,
+	Startup = 6,
+}
+
 #[repr(C,packed)]
 struct APICReg
 {
@@ -130,9 +146,16 @@ impl LAPIC
 		
 		//self.write_reg(ApicReg::SIR as usize, self.read_reg(ApicReg_SIR as usize) | (1 << 8));
 		self.write_reg(ApicReg::SIR, 0x7F | (1 << 8));	// Enable LAPIC (and set Spurious to 127)
-		self.write_reg(ApicReg::InitCount, 0x100000);
 		self.write_reg(ApicReg::TmrDivide, 3);	// Timer Divide = 16
-		self.write_reg(ApicReg::LVTTimer, TIMER_VEC as u32);	// Enable Timer
+		if S_TIMER_HZ.load(::core::sync::atomic::Ordering::Relaxed) == 0 {
+			// Not yet calibrated (BSP during boot), periodic to provide a scheduler tick until then
+			self.write_reg(ApicReg::InitCount, 0x100000);
+			self.write_reg(ApicReg::LVTTimer, TIMER_VEC as u32 | LVT_TIMER_PERIODIC);
+		}
+		else {
+			// One-shot, stopped until the first deadline is set
+			self.timer_oneshot(0);
+		}
 		self.write_reg(ApicReg::LVTThermalSensor, 0);	// "Disable" Thermal Sensor
 		self.write_reg(ApicReg::LVTPermCounters, 0);	// "Disable" ? Counters
 		self.write_reg(ApicReg::LVT_LINT0, 0);	// "Disable" LINT0
@@ -158,6 +181,44 @@ impl LAPIC
 	{
 		self.write_reg(ApicReg::EOI, num as u32);
 	}
+	/// APIC ID of the current CPU
+	pub fn id(&self) -> u32
+	{
+		self.read_reg(ApicReg::LAPIC_ID) >> 24
+	}
+	/// Send an inter-processor interrupt to the specified APIC (waiting until it has been delivered)
+	//#[is_safe(irq)]	// Holds interrupts, so the two ICR writes aren't interleaved
+	pub fn send_ipi(&self, apic_id: u32, vector: u8, mode: IpiMode)
+	{
+		let _irql = ::sync::hold_interrupts();
+		self.write_reg(ApicReg::ICRHigh, apic_id << 24);
+		// Level = Assert, Physical destination
+		self.write_reg(ApicReg::ICR, (1 << 14) | (mode as u32) << 8 | vector as u32);
+		// Wait for the "Delivery Status" bit to clear
+		while self.read_reg(ApicReg::ICR) & (1 << 12) != 0 {
+			::core::sync::atomic::spin_loop_hint();
+		}
+	}
+
+	/// Start the timer counting down from its maximum, masked (for calibration)
+	pub fn timer_start_count(&self)
+	{
+		self.write_reg(ApicReg::LVTTimer, TIMER_VEC as u32 | LVT_MASKED);
+		self.write_reg(ApicReg::InitCount, 0xFFFF_FFFF);
+	}
+	/// Number of timer counts since `timer_start_count`
+	pub fn timer_elapsed(&self) -> u32
+	{
+		0xFFFF_FFFF - self.read_reg(ApicReg::CurCount)
+	}
+	/// Fire the timer interrupt once after `count` timer counts (zero stops the timer)
+	//#[is_safe(irq)]
+	pub fn timer_oneshot(&self, count: u32)
+	{
+		let _irql = ::sync::hold_interrupts();
+		self.write_reg(ApicReg::LVTTimer, TIMER_VEC as u32);
+		self.write_reg(ApicReg::InitCount, count);
+	}
 	
 	fn read_reg(&self, reg: ApicReg) -> u32
 	{
@@ -197,6 +258,12 @@ impl LAPIC
 		// SAFE: 'sp' is the bound pointer, and should be valid
 		let s: &LAPIC = unsafe { &*(sp as *const LAPIC) };
 		log_trace!("LAPIC Timer");
+		// NOTE: Only fires when a deadline is due (or periodically before calibration). While the profiler is enabled,
+		// `time::rearm` keeps a periodic deadline armed so busy and idle CPUs are both sampled.
+		let (ip, is_user) = ::arch::imp::interrupts::interrupted_context();
+		::profiler::sample(ip, is_user);
+		// Handle expired timers, and program the next deadline
+		::time::tick();
 		s.eoi(isr);
 	}
 }
@@ -0,0 +1,187 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// arch/amd64/hw/clocksource.rs
+//! Monotonic clock source selection and calibration
+//!
+//! The invariant TSC is preferred (fast to read, constant rate), calibrated against the HPET if present and the PIT
+//! otherwise. Without an invariant TSC the HPET counter is used directly. Once a source is selected, the LAPIC timer is
+//! calibrated against it (so it can be programmed with one-shot deadlines).
+#[allow(unused_imports)]
+use prelude::*;
+
+module_define!{Clocksource, [APIC, HPET], init}
+
+/// A free-running hardware counter
+pub trait ClockSource: Sync
+{
+	fn name(&self) -> &'static str;
+	/// Read the current counter value
+	fn read(&self) -> u64;
+}
+
+struct Tsc;
+impl ClockSource for Tsc
+{
+	fn name(&self) -> &'static str { "TSC" }
+	fn read(&self) -> u64 {
+		read_tsc()
+	}
+}
+
+struct Hpet;
+impl ClockSource for Hpet
+{
+	fn name(&self) -> &'static str { "HPET" }
+	fn read(&self) -> u64 {
+		super::hpet::read_counter().unwrap_or(0)
+	}
+}
+
+/// Selected source, with the conversion to nanoseconds
+struct Clock
+{
+	source: &'static dyn ClockSource,
+	/// Counter value at timestamp zero
+	base: u64,
+	/// Nanoseconds per count, as 32.32 fixed point
+	mult: u64,
+	/// Maximum time between reads (ns) for the source to track counter wraps
+	max_idle: u64,
+}
+
+static S_TSC: Tsc = Tsc;
+static S_HPET: Hpet = Hpet;
+static S_CLOCK: ::lib::LazyStatic<Clock> = lazystatic_init!();
+
+/// Duration used for calibration (ms)
+const CALIBRATE_MS: u64 = 10;
+
+/// Current timestamp in nanoseconds (zero before the clocksource is initialised)
+pub fn now_ns() -> u64
+{
+	if S_CLOCK.ls_is_valid() {
+		let c = &*S_CLOCK;
+		let count = c.source.read().wrapping_sub(c.base);
+		((count as u128 * c.mult as u128) >> 32) as u64
+	}
+	else {
+		0
+	}
+}
+/// Longest time (ns) the clocksource can go unread, used to cap timer deadlines
+pub fn max_idle_ns() -> u64
+{
+	if S_CLOCK.ls_is_valid() {
+		S_CLOCK.max_idle
+	}
+	else {
+		!0
+	}
+}
+
+fn init()
+{
+	let hpet_freq = super::hpet::frequency();
+	let (source, freq, max_idle): (&'static dyn ClockSource, u64, u64) = if tsc_is_invariant()
+		{
+			let freq = if hpet_freq != 0 {
+					calibrate_tsc_hpet(hpet_freq)
+				}
+				else {
+					calibrate_tsc_pit()
+				};
+			(&S_TSC, freq, !0)
+		}
+		else if hpet_freq != 0
+		{
+			// A 32-bit HPET is extended in software, which needs to see every wrap
+			(&S_HPET, hpet_freq, super::hpet::max_read_interval_ns().unwrap_or(!0))
+		}
+		else
+		{
+			log_warning!("No invariant TSC or HPET, using the TSC (timing may drift with CPU frequency changes)");
+			(&S_TSC, calibrate_tsc_pit(), !0)
+		};
+	log_notice!("Clocksource: {} at {}.{:03} MHz", source.name(), freq / 1_000_000, freq / 1_000 % 1_000);
+
+	// SAFE: 'init' is called in a single-threaded context
+	unsafe {
+		S_CLOCK.prep(|| Clock {
+			source: source,
+			base: source.read(),
+			mult: (1_000_000_000 << 32) / freq,
+			max_idle: max_idle,
+			});
+	}
+
+	super::apic::calibrate_timer(CALIBRATE_MS);
+}
+
+fn read_tsc() -> u64
+{
+	let lo: u32;
+	let hi: u32;
+	// SAFE: Reads the TSC, no side-effects
+	unsafe { asm!("rdtsc" : "={eax}" (lo), "={edx}" (hi) : : : "volatile"); }
+	(hi as u64) << 32 | lo as u64
+}
+
+/// Check CPUID for an invariant TSC (constant rate in all P/C-states)
+fn tsc_is_invariant() -> bool
+{
+	let max_ext: u32;
+	let edx: u32;
+	// SAFE: CPUID has no side-effects
+	unsafe {
+		asm!("cpuid" : "={eax}" (max_ext) : "{eax}" (0x8000_0000u32) : "rbx", "rcx", "rdx" : "volatile");
+		if max_ext < 0x8000_0007 {
+			return false;
+		}
+		asm!("cpuid" : "={edx}" (edx) : "{eax}" (0x8000_0007u32) : "rax", "rbx", "rcx" : "volatile");
+	}
+	edx & (1 << 8) != 0
+}
+
+/// Measure the TSC frequency (Hz) against the HPET
+fn calibrate_tsc_hpet(hpet_freq: u64) -> u64
+{
+	let _irq = ::sync::hold_interrupts();
+	let hpet_ticks = hpet_freq * CALIBRATE_MS / 1000;
+	let h0 = S_HPET.read();
+	let t0 = read_tsc();
+	while S_HPET.read() - h0 < hpet_ticks {
+		::core::sync::atomic::spin_loop_hint();
+	}
+	let t1 = read_tsc();
+	let h1 = S_HPET.read();
+	// Scale by the actual elapsed HPET time
+	(t1 - t0) * hpet_freq / (h1 - h0)
+}
+
+/// Measure the TSC frequency (Hz) against PIT channel 2 (the speaker channel, which can be polled)
+fn calibrate_tsc_pit() -> u64
+{
+	const PIT_FREQ: u64 = 1_193_182;
+	let latch = (PIT_FREQ * CALIBRATE_MS / 1000) as u16;
+	let _irq = ::sync::hold_interrupts();
+	// SAFE: Only the PIT and the speaker gate are touched, nothing else uses them
+	unsafe {
+		use arch::x86_io::{inb,outb};
+		// Gate channel 2 on, speaker output off
+		outb(0x61, (inb(0x61) & !0x02) | 0x01);
+		// Channel 2, lo/hi byte, mode 0 (interrupt on terminal count)
+		outb(0x43, 0xB0);
+		outb(0x42, (latch & 0xFF) as u8);
+		outb(0x42, (latch >> 8) as u8);
+		let t0 = read_tsc();
+		// Output goes high on terminal count
+		while inb(0x61) & 0x20 == 0 {
+			::core::sync::atomic::spin_loop_hint();
+		}
+		let t1 = read_tsc();
+		(t1 - t0) * 1000 / CALIBRATE_MS
+	}
+}
+
+// vim: ft=rust
@@ -6,14 +6,18 @@
 #[allow(unused_imports)]
 use prelude::*;
 use arch::imp::acpi::AddressSpaceID;
+use core::sync::atomic::{AtomicU64,Ordering};
 
-module_define!{HPET, [APIC, ACPI], init}
+module_define!{HPET, [ACPI], init}
 
 struct HPET
 {
 	mapping_handle: ::memory::virt::AllocHandle,
-	irq_handle: ::arch::imp::hw::apic::IRQHandle,
 	period: u64,
+	/// Main counter is 64 bits wide (`COUNT_SIZE_CAP`)
+	is_64bit: bool,
+	/// Last value returned by `current` (32-bit counters only), used to extend the counter across wraps
+	last_count: AtomicU64,
 }
 
 #[repr(C,packed)]
@@ -32,18 +36,45 @@ enum HPETReg
 {
 	CapsID  = 0x0,
 	Config  = 0x1,
-	ISR     = 0x2,
 	MainCtr = 0xF,
-	Timer0  = 0x10,
 }
 
+/// CapsID: Main counter is 64 bits
+const CAPS_COUNT_SIZE_CAP: u64 = 1 << 13;
+
 static S_INSTANCE: ::lib::LazyStatic<HPET> = lazystatic_init!();
 
-/// Reutrns the current system timestamp, in miliseconds since an arbitary point (usually power-on)
-pub fn get_timestamp() -> u64
+/// Returns the main counter value, or None if there is no HPET
+///
+/// Used as a clocksource (see `hw::clocksource`), no interrupts are generated.
+pub fn read_counter() -> Option<u64>
+{
+	if S_INSTANCE.ls_is_valid() {
+		Some( S_INSTANCE.current() )
+	}
+	else {
+		None
+	}
+}
+/// Longest time (ns) that can pass between counter reads without losing track of wraps, None if unlimited
+///
+/// Only limited for 32-bit counters (2^31 ticks, ~150s at 14.3MHz), halved to leave some margin.
+pub fn max_read_interval_ns() -> Option<u64>
+{
+	if S_INSTANCE.ls_is_valid() && !S_INSTANCE.is_64bit {
+		// period = femtoseconds per tick
+		Some( (1 << 30) * S_INSTANCE.period / (1000*1000) )
+	}
+	else {
+		None
+	}
+}
+/// Main counter frequency (Hz), zero if there is no HPET
+pub fn frequency() -> u64
 {
 	if S_INSTANCE.ls_is_valid() {
-		S_INSTANCE.current() / S_INSTANCE.ticks_per_ms()
+		// period = femtoseconds (10^-15 s) per tick
+		1000*1000*1000*1000*1000 / S_INSTANCE.period
 	}
 	else {
 		0
@@ -56,7 +87,7 @@ fn init()
 	let hpet = match ::arch::imp::acpi::find::<ACPI_HPET>("HPET", 0)
 		{
 		None => {
-			log_error!("No HPET, in ACPI, no timing avaliable");
+			log_notice!("No HPET in ACPI");
 			return ;
 			},
 		Some(v) => v,
@@ -68,24 +99,11 @@ fn init()
 	// Assume SAFE: Shouldn't be sharing paddrs
 	let mapping = unsafe { ::memory::virt::map_hw_rw(info.addr.address, 1, "HPET").unwrap() };
 
-	// HACK! Disable the PIT
-	// - This should really be done by the ACPI code (after it determines the PIT exists)
-	// SAFE: Nothing else attacks the PIT
-	unsafe {
-		::arch::x86_io::outb(0x43, 0<<7|3<<4|0);
-		::arch::x86_io::outb(0x43, 1<<7|3<<4|0);
-		::arch::x86_io::outb(0x43, 2<<7|3<<4|0);
-		::arch::x86_io::outb(0x43, 3<<7|3<<4|0);
-	}
-
 	// SAFE: 'init' is called in a single-threaded context
-	let inst = unsafe {
+	unsafe {
 		S_INSTANCE.prep(|| HPET::new(mapping));
-		S_INSTANCE.ls_unsafe_mut().bind_irq();
-		&*S_INSTANCE
-		};
-	
-	inst.oneshot(0, inst.current() + 100*1000 );
+	}
+	log_debug!("HPET: {} Hz, {}-bit", frequency(), if S_INSTANCE.is_64bit { 64 } else { 32 });
 }
 
 impl HPET
@@ -94,34 +112,19 @@ impl HPET
 	{
 		let mut rv = HPET {
 			mapping_handle: mapping,
-			irq_handle: Default::default(),
 			period: 1,
+			is_64bit: false,
+			last_count: AtomicU64::new(0),
 			};
 		// Enable
 		rv.write_reg(HPETReg::Config as usize, rv.read_reg(HPETReg::Config as usize) | (1 << 0));
-		rv.period = rv.read_reg(HPETReg::CapsID as usize) >> 32;
+		let caps = rv.read_reg(HPETReg::CapsID as usize);
+		rv.period = caps >> 32;
+		rv.is_64bit = caps & CAPS_COUNT_SIZE_CAP != 0;
+		// Seed the wrap tracking with the current count (a 32-bit counter may start anywhere)
+		*rv.last_count.get_mut() = rv.read_reg(HPETReg::MainCtr as usize) & 0xFFFF_FFFF;
 		rv
 	}
-	pub fn bind_irq(&mut self)
-	{
-		self.irq_handle = ::arch::imp::hw::apic::register_irq(2, HPET::irq, self as *mut _ as *const _).unwrap();
-	}
-	pub fn ticks_per_ms(&self) -> u64
-	{
-		// period = fempto (15) seconds per tick
-		// Want ticks per ms
-		// 
-		1000*1000*1000*1000 / self.period
-	}
-	
-	fn irq(sp: *const ())
-	{
-		// SAFE: Pointer associated should be an instance of HPET
-		let s = unsafe{ &*(sp as *const HPET) };
-		s.write_reg(HPETReg::ISR as usize, s.read_reg(HPETReg::ISR as usize));
-		
-		s.oneshot(0, s.current() + 100*1000 );
-	}
 	
 	fn read_reg(&self, reg: usize) -> u64 {
 		// SAFE: Hardware access, implicitly atomic on x86
@@ -139,21 +142,31 @@ impl HPET
 		// SAFE: Coerces to raw pointer instantly
 		unsafe { self.mapping_handle.as_int_mut(0) }
 	}
-	fn num_comparitors(&self) -> usize {
-		((self.read_reg(HPETReg::CapsID as usize) >> 8) & 0x1F) as usize
-	}
 	
 	fn current(&self) -> u64 {
-		self.read_reg(HPETReg::MainCtr as usize)
-	}
-	fn oneshot(&self, comparitor: usize, value: u64) {
-		assert!(comparitor < self.num_comparitors());
-		let comp_reg = HPETReg::Timer0 as usize + comparitor*2;
-		// Set comparitor value
-		self.write_reg(comp_reg + 1, value);
-		// HACK: Wire to APIC interrupt 2
-		// IRQ2, Interrups Enabled, Level Triggered
-		self.write_reg(comp_reg + 0, (2 << 9)|(1<<2)|(1<<1));
+		let raw = self.read_reg(HPETReg::MainCtr as usize);
+		if self.is_64bit {
+			return raw;
+		}
+
+		// 32-bit counter: extend using the last returned value. The distance from the last value is taken modulo
+		// 2^32 (handling wraps), and a negative distance is a read that raced with a newer one on another CPU
+		// (the newer value is returned, keeping the result monotonic).
+		// NOTE: Requires a read at least every 2^31 ticks, see `max_read_interval_ns`
+		let low = raw as u32;
+		let mut rv = 0;
+		let _ = self.last_count.fetch_update(|last| {
+				let delta = low.wrapping_sub(last as u32);
+				if (delta as i32) < 0 {
+					rv = last;
+					None
+				}
+				else {
+					rv = last + delta as u64;
+					Some(rv)
+				}
+			}, Ordering::Relaxed, Ordering::Relaxed);
+		rv
 	}
 }
 
@@ -167,12 +167,63 @@ BLANKINT i
 %assign i i+1
 %endrep
 [extern irq_handler]
+[extern irq_return_user]
 IRQCommon:
 	API_SAVE
+	; Load the kernel's GS base if the IRQ came from user-mode
+	test qword [rsp+API_SAVE_SIZE+2*8], 3
+	jz .inkernel
+	swapgs
+.inkernel:
 	mov rdi, rbx
+	mov rsi, [rsp+API_SAVE_SIZE+1*8]	; Interrupted RIP
+	mov rdx, [rsp+API_SAVE_SIZE+2*8]	; Interrupted CS (non-zero RPL = user)
+	and rdx, 3
 	call irq_handler
+	test qword [rsp+API_SAVE_SIZE+2*8], 3
+	jz .inkernel2
+	; Returning to user-mode, give the scheduler a chance to preempt
+	call irq_return_user
+	; Deliver a pending notification (needs the full register state)
+	cmp qword [gs:0x18], 0
+	jnz .user_event
+	swapgs
+.inkernel2:
 	API_RESTORE
 	pop rbx
 	iretq
+.user_event:
+	API_RESTORE
+	pop rbx
+	jmp UserEventDeliver
+
+; Deliver a notification to the current thread's exception handler
+; - Entered with the user's GPRs loaded, an IRETQ frame on the stack, the kernel's GS, and interrupts disabled
+; - The format of 'gs' is specified in arch/amd64/threads.rs (TLSData)
+[extern user_event_deliver]
+UserEventDeliver:
+	mov qword [gs:0x18], 0	; Clear the pending flag
+	push qword 0	; Error code
+	push qword 0	; Interrupt number
+	PUSH_GPR
+	mov rdi, rsp
+	call user_event_deliver
+	POP_GPR
+	add rsp, 2*8
+	swapgs
+	iretq
+
+; Resume user-mode with the provided register state (laid out as ErrorRegs + IRETQ frame), never returns
+; RDI = Register state
+EXPORT exception_return_user
+	cli
+	mov rsp, rdi
+	POP_GPR
+	add rsp, 2*8
+	; Another notification may be pending (now that the handler has finished)
+	cmp qword [gs:0x18], 0
+	jnz UserEventDeliver
+	swapgs
+	iretq
 
 ; vim: ft=nasm
@@ -31,20 +31,46 @@ static S_IRQ_HANDLERS_LOCK: ::sync::Spinlock<[IRQHandlersEnt; 256]> = ::sync::Sp
 	info: 0 as *const _,
 	idx: 0
 	}; 256] );
+/// Interrupted instruction pointer and mode of the IRQ being handled on each CPU (see `interrupted_context`)
+// NOTE: Only accessed by the owning CPU, with interrupts disabled
+static mut S_IRQ_CONTEXT: [(usize, bool); super::smp::MAX_CPUS] = [(0, false); super::smp::MAX_CPUS];
 
 #[no_mangle]
 #[doc(hidden)]
 //#[req_safe(irq)]
 /// ISR handler called by assembly
-pub extern "C" fn irq_handler(index: usize)
+pub extern "C" fn irq_handler(index: usize, ip: usize, from_user: usize)
 {
-	let lh = S_IRQ_HANDLERS_LOCK.lock_irqsafe();
-	let ent = (*lh)[index];
+	// SAFE: Per-CPU, and interrupts are disabled
+	unsafe {
+		S_IRQ_CONTEXT[super::threads::cpu_num()] = (ip, from_user != 0);
+	}
+	// Copy the entry and release the lock before calling, so other CPUs can handle interrupts concurrently
+	// (and a handler waiting on another CPU - e.g. for a TLB shootdown - can't deadlock)
+	let ent = S_IRQ_HANDLERS_LOCK.lock_irqsafe()[index];
 	if let Some(h) = ent.handler {
 		(h)(index, ent.info, ent.idx);
 	}
 }
 
+/// Instruction pointer interrupted by the IRQ currently being handled, and if it was in user-mode
+///
+/// Only valid within an IRQ handler
+pub fn interrupted_context() -> (usize, bool)
+{
+	// SAFE: Per-CPU, and interrupts are disabled within a handler
+	unsafe { S_IRQ_CONTEXT[super::threads::cpu_num()] }
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Called by assembly before returning from an IRQ to user-mode (with interrupts disabled)
+pub extern "C" fn irq_return_user()
+{
+	::threads::preempt_user();
+	::threads::exceptions::check_user_events();
+}
+
 #[derive(Debug,Copy,Clone)]
 /// Error code for bind_isr
 pub enum BindISRError
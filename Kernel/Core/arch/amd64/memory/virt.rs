@@ -15,8 +15,10 @@ const MASK_VBITS : usize = 0x0000FFFF_FFFFFFFF;
 const FLAG_P:   u64 = 1;
 const FLAG_W:   u64 = 2;
 const FLAG_U:   u64 = 4;
+const FLAG_A:   u64 = 0x20;
 const FLAG_G:   u64 = 0x100;
 const FLAG_COW: u64 = 0x200;	// free bit, overloaded as COW
+const FLAG_SWAP: u64 = 0x400;	// free bit, marks a non-present entry as swapped out (slot number in the address bits)
 const FLAG_NX:  u64 = (1<<63);
 
 const FAULT_LOCKED: u32 = 1;
@@ -25,6 +27,9 @@ const FAULT_USER:   u32 = 4;
 const FAULT_RESVD:  u32 = 8;
 const FAULT_FETCH:  u32 = 16;
 
+/// Anonymous user pages can be swapped out
+pub const SWAP_SUPPORTED: bool = true;
+
 extern "C" {
 	static InitialPML4: [u64; 512];
 }
@@ -250,7 +255,13 @@ pub fn get_info<T>(addr: *const T) -> Option<(PAddr,ProtectionMode)>
 	}
 }
 
+/// Invalidate a page on this CPU, and any others that could have it cached
 fn invlpg(addr: *mut ()) {
+	invlpg_local(addr);
+	let space = if (addr as usize) < addresses::USER_END { Some(current_address_space()) } else { None };
+	::arch::imp::smp::tlb_shootdown(space, addr as usize);
+}
+fn invlpg_local(addr: *mut ()) {
 	// SAFE: Cannot cause memory unsafety
 	unsafe {
 		asm!("invlpg ($0)" : : "r" (addr) : "memory" : "volatile");
@@ -270,20 +281,25 @@ pub unsafe fn map(addr: *mut (), phys: PAddr, prot: ::memory::virt::ProtectionMo
 	if pte.set_if_unset( phys, prot ).is_err() {
 		panic!("Attempting to map over existing allocation addr={:p}", addr);
 	}
-	invlpg(addr);
+	// Previously unmapped, so no other CPU can have it cached
+	invlpg_local(addr);
 }
 /// Removes a mapping
 pub unsafe fn unmap(addr: *mut ()) -> Option<PAddr>
 {
-	let mut pte = get_page_ent(addr as usize, false, LargeOk::No);
+	let pte = get_page_ent(addr as usize, false, LargeOk::No);
 	assert!( !pte.is_null(), "Failed to obtain ent for {:p}", addr );
-	let rv = if pte.is_present() {
-			Some(pte.addr())
+	// Exchanged atomically, so a racing swap-in either completes first or sees the entry cleared
+	let old = ::core::intrinsics::atomic_xchg(pte.data, 0);
+	let rv = if old & FLAG_P != 0 {
+			Some(old & 0x7FFFFFFF_FFFFF000)
 		}
 		else {
+			if old & FLAG_SWAP != 0 {
+				::memory::swap::release_slot( (old >> 12) as usize );
+			}
 			None
 		};
-	pte.set( 0, ::memory::virt::ProtectionMode::Unmapped );
 	
 	invlpg(addr);
 	
@@ -301,6 +317,109 @@ pub unsafe fn reprotect(addr: *mut (), prot: ::memory::virt::ProtectionMode)
 	invlpg(addr);
 }
 
+/// Physical address of the current address space's PML4
+pub fn current_address_space() -> PAddr {
+	let rv: u64;
+	// SAFE: Reads a register
+	unsafe { asm!("movq %cr3, $0" : "=r" (rv)); }
+	rv & 0x7FFFFFFF_FFFFF000
+}
+
+/// Run a closure on the 4KB page entry for `addr` in the address space rooted at `root`
+///
+/// Returns None if the page isn't covered by a page table.
+fn with_foreign_pte<F, R>(root: PAddr, addr: usize, f: F) -> Option<R>
+where
+	F: FnOnce(&mut PTE) -> R
+{
+	if root == current_address_space() {
+		let mut pte = get_page_ent(addr, false, LargeOk::No);
+		return if pte.is_null() { None } else { Some( f(&mut pte) ) };
+	}
+
+	let pagenum = (addr & MASK_VBITS) / PAGE_SIZE;
+	let mut table = root;
+	for &level in &[3usize, 2, 1]
+	{
+		let idx = (pagenum >> (9*level)) & 511;
+		// SAFE: Read-only access to a paging structure
+		let ent = unsafe {
+			::memory::virt::with_temp(table, |pg| {
+				let tab: &[u64; 512] = ::core::mem::transmute(pg);
+				::core::ptr::read_volatile(&tab[idx])
+				})
+			};
+		if ent & PF_PRESENT == 0 || ent & PF_LARGE != 0 {
+			return None;
+		}
+		table = ent & 0x7FFFFFFF_FFFFF000;
+	}
+	// SAFE: Entry is only modified atomically by callers
+	unsafe {
+		::memory::virt::with_temp(table, |pg| {
+			let tab: &mut [u64; 512] = ::core::mem::transmute(pg);
+			let mut pte = PTE::new(PTEPos::Page4K, &mut tab[pagenum & 511]);
+			Some( f(&mut pte) )
+			})
+	}
+}
+
+const SWAPPABLE_FLAGS: u64 = FLAG_P|FLAG_U|FLAG_W|FLAG_NX;
+
+/// Check and clear the accessed flag on a private (UserRW) page
+pub fn age_user_page(root: PAddr, addr: usize) -> ::arch::memory::virt::PageAge
+{
+	use arch::memory::virt::PageAge;
+	let rv = with_foreign_pte(root, addr, |pte| {
+		// SAFE: Valid pointer, atomic update
+		unsafe {
+			let v = ::core::ptr::read_volatile(pte.data);
+			if v & (SWAPPABLE_FLAGS|FLAG_COW) != SWAPPABLE_FLAGS {
+				PageAge::Absent
+			}
+			else if v & FLAG_A != 0 {
+				::core::intrinsics::atomic_and(pte.data, !FLAG_A);
+				PageAge::Accessed
+			}
+			else {
+				PageAge::Idle(v & 0x7FFFFFFF_FFFFF000)
+			}
+		}
+		});
+	if root == current_address_space() {
+		// Ensure that the next access re-sets the accessed flag
+		invlpg(addr as *mut ());
+	}
+	rv.unwrap_or(PageAge::Absent)
+}
+/// Replace an unaccessed private page with a swap entry, returning the frame that was mapped
+pub unsafe fn swap_out_page(root: PAddr, addr: usize, slot: usize) -> Option<PAddr>
+{
+	let rv = with_foreign_pte(root, addr, |pte| {
+		let v = ::core::ptr::read_volatile(pte.data);
+		if v & (SWAPPABLE_FLAGS|FLAG_COW|FLAG_A) != SWAPPABLE_FLAGS {
+			return None;
+		}
+		let new = ((slot as u64) << 12) | FLAG_SWAP;
+		// If the page was accessed since the check above, the exchange fails
+		if ::core::intrinsics::atomic_cxchg(pte.data, v, new).0 == v {
+			Some(v & 0x7FFFFFFF_FFFFF000)
+		}
+		else {
+			None
+		}
+		}).and_then(|v| v);
+	if rv.is_some() {
+		// The caller is about to write out and free the frame, so no CPU running this address space can be left
+		// with a cached (writable) mapping of it
+		if root == current_address_space() {
+			invlpg_local(addr as *mut ());
+		}
+		::arch::imp::smp::tlb_shootdown(Some(root), addr);
+	}
+	rv
+}
+
 static PF_PRESENT : u64 = 0x001;
 static PF_LARGE   : u64 = 0x080;
 
@@ -335,6 +454,19 @@ impl PTE
 			self.is_present() && *self.data & (PF_PRESENT | PF_LARGE) == PF_LARGE|PF_PRESENT
 		}
 	}
+	pub fn is_swapped(&self) -> bool {
+		// SAFE: Construction should ensure this pointer is valid
+		unsafe {
+			!self.is_null() && *self.data & (FLAG_P|FLAG_SWAP) == FLAG_SWAP
+		}
+	}
+	pub fn swap_slot(&self) -> usize {
+		assert!(self.is_swapped());
+		// SAFE: Construction should ensure this pointer is valid
+		unsafe {
+			(*self.data >> 12) as usize
+		}
+	}
 	pub fn is_cow(&self) -> bool {
 		// SAFE: Construction should ensure this pointer is valid
 		unsafe {
@@ -375,6 +507,15 @@ impl PTE
 		*self.data = (paddr & 0x7FFFFFFF_FFFFF000) | flags;
 	}
 	
+	/// Atomically replace the swap entry for `slot` with a mapping, returns false if the entry has changed
+	// UNSAFE: As for `set`
+	pub unsafe fn replace_swapped(&mut self, slot: usize, paddr: PAddr, prot: ::memory::virt::ProtectionMode) -> bool {
+		assert!(!self.is_null());
+		let old = ((slot as u64) << 12) | FLAG_SWAP;
+		let new = (paddr & 0x7FFFFFFF_FFFFF000) | Self::mode_to_flags(prot);
+		::core::intrinsics::atomic_cxchg(self.data, old, new).0 == old
+	}
+	
 	pub fn set_if_unset(&mut self, paddr: PAddr, prot: ::memory::virt::ProtectionMode) -> Result<(),()> {
 		assert!(!self.is_null());
 		let flags: u64 = Self::mode_to_flags(prot);
@@ -453,19 +594,61 @@ pub fn handle_page_fault(accessed_address: usize, error_code: u32) -> bool
 
 		// 1. Lock (relevant) address space
 		// SAFE: Changes to address space are transparent
-		::memory::virt::with_lock(accessed_address, || unsafe {
+		return ::memory::virt::with_lock(accessed_address, || unsafe {
 			let frame = pte.addr();
 			let pgaddr = (accessed_address as usize) & !PAGE_MASK;
 			// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
-			let newframe = ::memory::phys::make_unique( frame, &*(pgaddr as *const [u8; 4096]) );
-			// 3. Remap to this page as UserRW (because COW is user-only atm)
-			pte.set(newframe, ProtectionMode::UserRW);
-			invlpg( (accessed_address & !0xFFF) as *mut () );
+			match ::memory::phys::make_unique( frame, &*(pgaddr as *const [u8; 4096]) )
+			{
+			Ok(newframe) => {
+				// 3. Remap to this page as UserRW (because COW is user-only atm)
+				pte.set(newframe, ProtectionMode::UserRW);
+				invlpg( pgaddr as *mut () );
+				// 4. The page is now private, so can be swapped out
+				::memory::swap::track_page(pgaddr);
+				true
+				},
+			Err(_) => {
+				log_error!("Out of memory handling copy-on-write of {:#x}", accessed_address);
+				false
+				},
+			}
 			});
-		return true;
 	}
 	//  > Paged-out pages
-	if error_code & FAULT_LOCKED == 0 && pte.is_reserved() {
+	if error_code & FAULT_LOCKED == 0 && pte.is_swapped() {
+		let pgaddr = accessed_address & !PAGE_MASK;
+		let slot = pte.swap_slot();
+		match ::memory::swap::swap_in(slot)
+		{
+		Ok(Some(frame)) => {
+			// Only install the frame if the entry still refers to this slot (the page could have been unmapped)
+			// SAFE: Replaces a non-present entry
+			let installed = unsafe { pte.replace_swapped(slot, frame, ProtectionMode::UserRW) };
+			::memory::swap::swap_in_complete(slot, frame, installed);
+			if installed {
+				// Previously non-present, so no other CPU can have it cached
+				invlpg_local( pgaddr as *mut () );
+				::memory::swap::track_page(pgaddr);
+			}
+			// Retry the access (faulting again if the entry changed)
+			return true;
+			},
+		Ok(None) => {
+			// Another thread is reading this page in, retry the access once it has had a chance to finish
+			::threads::yield_time();
+			return true;
+			},
+		Err(_) => {
+			// If another thread raced us to swap the page back in, retry the access
+			if !pte.is_swapped() {
+				return true;
+			}
+			log_error!("Unable to swap in {:#x} from slot {}", accessed_address, slot);
+			},
+		}
+	}
+	else if error_code & FAULT_LOCKED == 0 && pte.is_reserved() {
 		todo!("Paged - {:#x} pte = {:?}", accessed_address, pte);
 	}
 	
@@ -502,7 +685,8 @@ pub unsafe fn temp_map<T>(phys: ::arch::memory::PAddr) -> *mut T {
 		let addr = (addresses::TEMP_BASE + i * ::PAGE_SIZE) as *mut ();
 
 		if get_page_ent(addr as usize, true, LargeOk::No).set_if_unset(phys, ProtectionMode::KernelRW).is_ok() {
-			invlpg(addr);
+			// Temporary mappings are short-lived and CPU-local, no need for a shootdown
+			invlpg_local(addr);
 			return addr as *mut T;
 		}
 	}
@@ -512,7 +696,7 @@ pub unsafe fn temp_unmap<T>(addr: *mut T) {
 	// SAFE: Owned allocation
 	/*unsafe*/ {
 		get_page_ent(addr as usize, false, LargeOk::No).set(0, ProtectionMode::Unmapped);
-		invlpg(addr as *mut ());
+		invlpg_local(addr as *mut ());
 	}
 	S_TEMP_FREE.release();
 }
@@ -573,22 +757,41 @@ impl AddressSpace
 			//log_trace!("opt_clone_page(idx={:#x})", idx);
 			
 			// SAFE: Only called when parent table is present
-			let ent = unsafe { get_entry(0, idx, false) };
+			let mut ent = unsafe { get_entry(0, idx, false) };
+			if ent.is_swapped() {
+				// Touch the page to bring it back in from swap before copying
+				// SAFE: Read-only access to a user page
+				unsafe { ::core::ptr::read_volatile((idx << 12) as *const u8); }
+			}
 			if ! ent.is_reserved()
 			{
 				Ok(0)
 			}
 			else
 			{
-				let p = ent.get_perms();
+				let mut p = ent.get_perms();
 				let frame = match p
 					{
-					ProtectionMode::UserRX | ProtectionMode::UserCOW => {
+					ProtectionMode::UserRO | ProtectionMode::UserRX | ProtectionMode::UserCOW => {
+						let addr = ent.addr();
+						::memory::phys::ref_frame( addr );
+						addr
+						},
+					ProtectionMode::UserRW => {
+						// Share the frame, with both the parent and the child copying it on their next write
 						let addr = ent.addr();
 						::memory::phys::ref_frame( addr );
+						p = ProtectionMode::UserCOW;
+						// SAFE: Only makes the page read-only, the next write will fault and resolve the COW
+						unsafe {
+							ent.set(addr, ProtectionMode::UserCOW);
+						}
+						// NOTE: Other CPUs are flushed once the whole clone is done
+						invlpg_local( (idx << 12) as *mut () );
 						addr
 						},
-					ProtectionMode::UserRWX | ProtectionMode::UserRW => {
+					// NOTE: COW resolution maps pages as UserRW, so executable+writable pages are copied eagerly
+					ProtectionMode::UserRWX => {
 						// SAFE: We've just determined that this page is mapped in, so we won't crash. Any race is the user's fault (and shouldn't impact the kernel)
 						let src = unsafe { ::core::slice::from_raw_parts((idx << 12) as *const u8, ::PAGE_SIZE) };
 						let mut newpg = try!(::memory::virt::alloc_free());
@@ -670,6 +873,8 @@ impl AddressSpace
 			}
 		}
 		log_debug!("ents[..256] = {:#x}", ::logging::print_iter(ents[..256].iter()));
+		// - Writable pages were made copy-on-write in this address space, so flush any other CPUs running it
+		::arch::imp::smp::ShootdownBatch::new( Some(current_address_space()) ).invalidate_all();
 		Ok( AddressSpace( ents.into_frame() ) )
 	}
 	pub fn pid0() -> AddressSpace {
@@ -681,38 +886,21 @@ impl AddressSpace
 		self.0
 	}
 }
-impl ::core::ops::Drop for AddressSpace {
-	fn drop(&mut self) {
-		
-		fn drop_table_ent(table_ent: &mut u64, level: u8) {
-			assert!(1 <= level && level <= 4, "AddressSpace::drop::drop_table_ent - level invalid, {}", level);
-			// SAFE: We have &mut
-			let pte = unsafe { PTE::new(PTEPos::from_level(level), table_ent) };
-			if ! pte.is_reserved() {
-				assert!( *table_ent == 0, "TODO: Handle non-zero non-present table entry" );
-			}
-			else {
-				let addr = pte.addr();
-				if level == 1 {
-					// Level 1, i.e. page table. Just dereference the page
-				}
-				else {
-					// Level 2-4 (PD, PDP, PML4). Recurse
-					// SAFE: All paging tables should be uniquely owned, transmute is valid
-					unsafe {
-						::memory::virt::with_temp(addr, |tab_pg| {
-							let tab: &mut [u64; 512] = ::core::mem::transmute(tab_pg);
-							for e in tab.iter_mut() {
-								drop_table_ent(e, level-1);
-							}
-							});
-					}
-				}
-				::memory::phys::deref_frame( addr );
-			}
-			*table_ent = 0;
-		}
+impl AddressSpace
+{
+	/// Returns true if any CPU has this address space loaded (or is still switching away from it)
+	pub fn is_loaded(&self) -> bool
+	{
+		::arch::imp::smp::is_space_loaded(self.0)
+	}
 
+	/// Release all user mappings (and the memory/page tables behind them)
+	///
+	/// The address space must not be active on any CPU
+	pub fn release_user(&self)
+	{
+		// Stop the swap reclaim thread walking the tables before any are freed
+		::memory::swap::forget_address_space(self.0);
 		// SAFE: All paging tables should be uniquely owned, transmute is valid
 		unsafe {
 			::memory::virt::with_temp(self.0, |pml4_pg| {
@@ -721,10 +909,86 @@ impl ::core::ops::Drop for AddressSpace {
 					drop_table_ent(e, 4);
 				}
 				});
+		}
+	}
+
+	/// Count the resident (present) user pages
+	///
+	/// NOTE: The address space may be modified by its threads while this runs, so the result is approximate
+	pub fn resident_user_pages(&self) -> usize
+	{
+		// SAFE: Only reads the tables, which are only freed by `release_user`/drop
+		unsafe {
+			::memory::virt::with_temp(self.0, |pml4_pg| {
+				let pml4: &[u64; 512] = ::core::mem::transmute(pml4_pg);
+				pml4[..256].iter().map(|&e| count_table_ent(e, 4)).sum()
+				})
+		}
+	}
+}
+impl ::core::ops::Drop for AddressSpace {
+	fn drop(&mut self) {
+		self.release_user();
+		// SAFE: Address space is no longer in use
+		unsafe {
 			::memory::phys::deref_frame( self.0 );
 		}
 	}
 }
 
+fn count_table_ent(table_ent: u64, level: u8) -> usize {
+	if table_ent & PF_PRESENT == 0 {
+		// Not present (or swapped out)
+		0
+	}
+	else if level == 1 {
+		1
+	}
+	else if table_ent & PF_LARGE != 0 {
+		// Large page (2MiB or 1GiB)
+		1 << (9 * (level - 1))
+	}
+	else {
+		// SAFE: Read-only access to a paging table
+		unsafe {
+			::memory::virt::with_temp(table_ent & 0x7FFFFFFF_FFFFF000, |tab_pg| {
+				let tab: &[u64; 512] = ::core::mem::transmute(tab_pg);
+				tab.iter().map(|&e| count_table_ent(e, level-1)).sum()
+				})
+		}
+	}
+}
+fn drop_table_ent(table_ent: &mut u64, level: u8) {
+	assert!(1 <= level && level <= 4, "AddressSpace::drop::drop_table_ent - level invalid, {}", level);
+	// SAFE: We have &mut
+	let pte = unsafe { PTE::new(PTEPos::from_level(level), table_ent) };
+	if level == 1 && pte.is_swapped() {
+		::memory::swap::release_slot(pte.swap_slot());
+	}
+	else if ! pte.is_reserved() {
+		assert!( *table_ent == 0, "TODO: Handle non-zero non-present table entry" );
+	}
+	else {
+		let addr = pte.addr();
+		if level == 1 {
+			// Level 1, i.e. page table. Just dereference the page
+		}
+		else {
+			// Level 2-4 (PD, PDP, PML4). Recurse
+			// SAFE: All paging tables should be uniquely owned, transmute is valid
+			unsafe {
+				::memory::virt::with_temp(addr, |tab_pg| {
+					let tab: &mut [u64; 512] = ::core::mem::transmute(tab_pg);
+					for e in tab.iter_mut() {
+						drop_table_ent(e, level-1);
+					}
+					});
+			}
+		}
+		::memory::phys::deref_frame( addr );
+	}
+	*table_ent = 0;
+}
+
 // vim: ft=rust
 
@@ -7,7 +7,7 @@ use core::option::Option;
 
 pub use self::log::{puts, puth};
 
-module_define!{arch, [APIC, HPET], init}
+module_define!{arch, [APIC, HPET, Clocksource, SMP], init}
 
 pub mod interrupts;
 #[doc(hidden)]
@@ -18,6 +18,7 @@ pub mod boot;
 pub mod sync;
 
 mod tss;
+pub mod smp;
 
 mod log;
 pub mod x86_io;
@@ -54,7 +55,27 @@ extern "C" {
 /// Return the system timestamp (miliseconds since an arbitary point)
 pub fn cur_timestamp() -> u64
 {
-	hw::hpet::get_timestamp()
+	hw::clocksource::now_ns() / 1_000_000
+}
+/// Return the system timestamp (nanoseconds since an arbitary point)
+pub fn cur_timestamp_ns() -> u64
+{
+	hw::clocksource::now_ns()
+}
+/// Program this CPU's one-shot timer to fire at the given timestamp (`!0` to stop it)
+pub fn set_timer_deadline(ns: u64)
+{
+	hw::apic::set_timer_deadline(ns)
+}
+
+/// Read the timestamp counter (used as an entropy sample)
+pub fn entropy_sample() -> u64
+{
+	let lo: u32;
+	let hi: u32;
+	// SAFE: Reads the TSC, no side-effects
+	unsafe { asm!("rdtsc" : "={eax}" (lo), "={edx}" (hi) : : : "volatile"); }
+	(hi as u64) << 32 | lo as u64
 }
 
 /// Print a backtrace, starting at the current location.
@@ -75,6 +96,28 @@ pub fn print_backtrace()
 		}
 	}
 }
+/// Obtain return addresses from the current call stack
+pub fn get_return_addresses(addrs: &mut [usize]) -> usize
+{
+	let cur_bp: u64;
+	// SAFE: Reads from bp
+	unsafe{ asm!("mov %rbp, $0" : "=r" (cur_bp)); }
+	let mut bp = cur_bp;
+	let mut n = 0;
+	while n < addrs.len()
+	{
+		match cpu_faults::backtrace(bp)
+		{
+		Option::Some((newbp, ip)) => {
+			addrs[n] = ip as usize;
+			n += 1;
+			bp = newbp;
+			},
+		Option::None => break,
+		}
+	}
+	n
+}
 // TODO: Put this somewhere common (in `symbols` maybe?)
 struct SymPrint(usize);
 impl ::core::fmt::Display for SymPrint
@@ -0,0 +1,392 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/arch/amd64/smp.rs
+//! Symmetric multi-processing (application processor startup, and inter-processor interrupts)
+use prelude::*;
+use core::sync::atomic::{AtomicUsize,AtomicBool,Ordering};
+use super::hw::apic;
+
+module_define!(SMP, [APIC, Clocksource, TSS], init);
+
+/// Maximum number of CPUs supported
+// NOTE: MUST match the value in common.inc.asm
+pub const MAX_CPUS: usize = 8;
+
+/// Physical page the startup trampoline is copied to (MUST match ap_trampoline.inc.asm)
+const AP_TRAMPOLINE_PAGE: usize = 0x8;
+/// Physical page used for the trampoline's temporary PML4
+const AP_PML4_PAGE: usize = 0x9;
+
+/// Vector for the "reschedule" IPI (wakes an idle CPU)
+const IPI_RESCHED_VEC: u8 = 0x7D;
+/// Vector for the TLB shootdown IPI
+const IPI_TLB_VEC: u8 = 0x7C;
+
+extern "C" {
+	static ap_trampoline: [u8; 0];
+	static ap_trampoline_pml4: [u8; 0];
+	static ap_trampoline_end: [u8; 0];
+	static mut s_ap_boot_info: [u64; 4];
+	static InitialPML4: [u64; 512];
+	static InitialPDP: [u64; 512];
+}
+
+/// Number of CPUs started (the BSP is always CPU 0)
+static S_NUM_CPUS: AtomicUsize = AtomicUsize::new(1);
+/// Bitmask of CPUs currently waiting in `idle`
+static S_IDLE_CPUS: AtomicUsize = AtomicUsize::new(0);
+/// Set by an AP once it has finished using the boot parameters
+static S_AP_STARTED: AtomicBool = AtomicBool::new(false);
+/// APIC IDs of each CPU (only written before the CPU is started)
+static mut S_APIC_IDS: [u32; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Maximum number of pages invalidated individually by one shootdown
+const SHOOTDOWN_MAX_PAGES: usize = 16;
+/// Value of `S_SHOOTDOWN_COUNT` requesting a flush of all non-global pages
+const SHOOTDOWN_FLUSH_ALL: usize = !0;
+
+/// Serialises TLB shootdowns (only one batch can be in flight)
+static S_SHOOTDOWN_LOCK: ::sync::Spinlock<()> = ::sync::Spinlock::new( () );
+/// Pages to invalidate (only written with `S_SHOOTDOWN_LOCK` held, before `S_SHOOTDOWN_PENDING` is set)
+static mut S_SHOOTDOWN_PAGES: [usize; SHOOTDOWN_MAX_PAGES] = [0; SHOOTDOWN_MAX_PAGES];
+/// Number of valid entries in `S_SHOOTDOWN_PAGES` (or `SHOOTDOWN_FLUSH_ALL`)
+static S_SHOOTDOWN_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// CPUs that have yet to handle the current shootdown
+static S_SHOOTDOWN_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Address space (CR3) loaded by each CPU
+static S_ACTIVE_SPACE: [AtomicUsize; MAX_CPUS] = [
+	AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+	AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+	];
+/// Address space each CPU is switching away from (cleared once the switch has completed)
+static S_OUTGOING_SPACE: [AtomicUsize; MAX_CPUS] = [
+	AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+	AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+	];
+
+fn init()
+{
+	let bsp_id = apic::local_apic_id();
+	// SAFE: Single-threaded (other CPUs not yet started)
+	unsafe { S_APIC_IDS[0] = bsp_id; }
+
+	if let Err(e) = ::arch::imp::interrupts::bind_isr(IPI_RESCHED_VEC, ipi_resched, 0 as *const _, 0) {
+		log_error!("Unable to bind reschedule IPI: {:?}", e);
+		return ;
+	}
+	if let Err(e) = ::arch::imp::interrupts::bind_isr(IPI_TLB_VEC, ipi_tlb_shootdown, 0 as *const _, 0) {
+		log_error!("Unable to bind TLB shootdown IPI: {:?}", e);
+		return ;
+	}
+	// NOTE: The ISR handles are leaked, the IPIs are needed forever
+
+	let ap_ids: Vec<u32> = apic::get_cpu_apic_ids().into_iter().filter(|&id| id != bsp_id).collect();
+	if ap_ids.len() == 0 {
+		log_notice!("Uniprocessor system");
+		return ;
+	}
+
+	prepare_trampoline();
+	for id in ap_ids
+	{
+		let cpu = S_NUM_CPUS.load(Ordering::Relaxed);
+		if cpu == MAX_CPUS {
+			log_warning!("Too many CPUs, ignoring APIC ID {} (maximum {})", id, MAX_CPUS);
+			continue ;
+		}
+		if start_ap(cpu, id) {
+			S_NUM_CPUS.store(cpu + 1, Ordering::SeqCst);
+		}
+		else {
+			log_error!("CPU{} (APIC ID {}) failed to start", cpu, id);
+		}
+	}
+	log_notice!("{} CPUs online", S_NUM_CPUS.load(Ordering::Relaxed));
+}
+
+/// Copy the trampoline code into low memory, and prepare its page tables
+fn prepare_trampoline()
+{
+	use super::memory::addresses::IDENT_START;
+	// SAFE: Low memory is not used by the physical allocator, and is identity mapped
+	unsafe {
+		let start = &ap_trampoline as *const _ as usize;
+		let len = &ap_trampoline_end as *const _ as usize - start;
+		assert!(len <= ::PAGE_SIZE);
+		let dst = (IDENT_START + AP_TRAMPOLINE_PAGE * ::PAGE_SIZE) as *mut u8;
+		::core::ptr::copy_nonoverlapping(start as *const u8, dst, len);
+
+		// - The PML4 is a copy of the kernel's, with the low identity mapping re-added
+		let pml4 = &mut *((IDENT_START + AP_PML4_PAGE * ::PAGE_SIZE) as *mut [u64; 512]);
+		*pml4 = InitialPML4;
+		pml4[0] = (&InitialPDP as *const _ as usize - IDENT_START) as u64 | 3;
+		let pml4_ofs = &ap_trampoline_pml4 as *const _ as usize - start;
+		*(dst.offset(pml4_ofs as isize) as *mut u32) = (AP_PML4_PAGE * ::PAGE_SIZE) as u32;
+	}
+}
+
+/// Start a single AP (using the INIT-SIPI-SIPI sequence), returns once it's running or timed out
+fn start_ap(cpu: usize, apic_id: u32) -> bool
+{
+	log_log!("Starting CPU{} (APIC ID {})", cpu, apic_id);
+	super::threads::init_ap_idle(cpu);
+
+	// Boot stack, only used until the AP switches to its idle thread
+	// - Leaked, as the AP may still be using it
+	let stack = ::memory::virt::alloc_stack().into_array::<u8>();
+	// SAFE: Single-threaded (the previous AP has finished with the parameters)
+	unsafe {
+		S_APIC_IDS[cpu] = apic_id;
+		s_ap_boot_info[0] = (&InitialPML4 as *const _ as usize - super::memory::addresses::IDENT_START) as u64;
+		s_ap_boot_info[1] = (&stack[stack.len()-1] as *const _ as usize + 1) as u64;
+		s_ap_boot_info[2] = &stack[0] as *const _ as usize as u64;
+		s_ap_boot_info[3] = cpu as u64;
+	}
+	::core::mem::forget(stack);
+	S_AP_STARTED.store(false, Ordering::SeqCst);
+
+	apic::send_init_ipi(apic_id);
+	spin_delay(10);
+	for _ in 0 .. 2
+	{
+		apic::send_startup_ipi(apic_id, AP_TRAMPOLINE_PAGE as u8);
+		let timeout = ::time::ticks() + 100;
+		while ::time::ticks() < timeout
+		{
+			if S_AP_STARTED.load(Ordering::SeqCst) {
+				return true;
+			}
+			::core::sync::atomic::spin_loop_hint();
+		}
+	}
+	false
+}
+
+/// Busy-wait for a number of milliseconds (used before the AP is running)
+fn spin_delay(ms: u64)
+{
+	let end = ::time::ticks() + ms;
+	while ::time::ticks() < end {
+		::core::sync::atomic::spin_loop_hint();
+	}
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Rust entrypoint for application processors (called by `ap_start64` in ap_trampoline.inc.asm)
+pub extern "C" fn ap_entry(cpu: usize) -> !
+{
+	super::threads::set_cpu_num(cpu);
+	super::tss::init_ap(cpu);
+	apic::init_ap();
+	log_notice!("CPU{} online (APIC ID {})", cpu, apic::local_apic_id());
+	S_AP_STARTED.store(true, Ordering::SeqCst);
+	super::threads::switch_to_idle(cpu);
+}
+
+/// Number of CPUs running
+pub fn num_cpus() -> usize
+{
+	S_NUM_CPUS.load(Ordering::Relaxed)
+}
+
+/// Mark the current CPU as idle (or not), so it can be woken by `kick_idle_cpu`
+pub fn set_idle(cpu: usize, is_idle: bool)
+{
+	if is_idle {
+		S_IDLE_CPUS.fetch_or(1 << cpu, Ordering::SeqCst);
+	}
+	else {
+		S_IDLE_CPUS.fetch_and(!(1 << cpu), Ordering::SeqCst);
+	}
+}
+/// Wake an idle CPU (if there is one) to check the run queue
+//#[is_safe(irq)]
+pub fn kick_idle_cpu()
+{
+	let idle = S_IDLE_CPUS.load(Ordering::SeqCst) & !(1 << super::threads::cpu_num());
+	if idle != 0
+	{
+		let cpu = idle.trailing_zeros() as usize;
+		// SAFE: Only written before the CPU is started
+		apic::send_ipi(unsafe { S_APIC_IDS[cpu] }, IPI_RESCHED_VEC);
+	}
+}
+
+/// Record that a CPU is about to load a new address space (called before CR3 is changed)
+///
+/// The previous address space is still counted as loaded until `end_space_switch` is called.
+pub fn begin_space_switch(cpu: usize, new_space: u64)
+{
+	let old = S_ACTIVE_SPACE[cpu].load(Ordering::SeqCst);
+	S_OUTGOING_SPACE[cpu].store(old, Ordering::SeqCst);
+	S_ACTIVE_SPACE[cpu].store(new_space as usize, Ordering::SeqCst);
+}
+/// Record that the current CPU has finished switching away from its previous address space
+pub fn end_space_switch(cpu: usize)
+{
+	S_OUTGOING_SPACE[cpu].store(0, Ordering::SeqCst);
+}
+/// Returns true if any CPU has the address space rooted at `space` loaded
+pub fn is_space_loaded(space: u64) -> bool
+{
+	space_cpus(space) != 0
+}
+/// Bitmask of CPUs that have (or are switching away from) the address space rooted at `space`
+fn space_cpus(space: u64) -> usize
+{
+	let mut rv = 0;
+	for cpu in 0 .. num_cpus()
+	{
+		if S_ACTIVE_SPACE[cpu].load(Ordering::SeqCst) == space as usize || S_OUTGOING_SPACE[cpu].load(Ordering::SeqCst) == space as usize {
+			rv |= 1 << cpu;
+		}
+	}
+	rv
+}
+
+/// A batch of page invalidations, sent to the other CPUs that could have the pages cached when flushed (or dropped)
+///
+/// The caller must have already invalidated the pages on the current CPU.
+pub struct ShootdownBatch
+{
+	/// Root of the address space containing the pages (`None` for kernel pages, which all CPUs can cache)
+	space: Option<u64>,
+	count: usize,
+	pages: [usize; SHOOTDOWN_MAX_PAGES],
+}
+impl ShootdownBatch
+{
+	pub fn new(space: Option<u64>) -> ShootdownBatch {
+		ShootdownBatch {
+			space: space,
+			count: 0,
+			pages: [0; SHOOTDOWN_MAX_PAGES],
+			}
+	}
+	/// Add a page to the batch
+	pub fn push(&mut self, addr: usize) {
+		if self.count == SHOOTDOWN_FLUSH_ALL {
+			return ;
+		}
+		if self.count == SHOOTDOWN_MAX_PAGES {
+			if self.space.is_some() {
+				// User pages aren't global, so a full flush is cheaper than several rounds of IPIs
+				self.count = SHOOTDOWN_FLUSH_ALL;
+				return ;
+			}
+			self.flush();
+		}
+		self.pages[self.count] = addr;
+		self.count += 1;
+	}
+	/// Flush the entire address space instead of individual pages
+	pub fn invalidate_all(&mut self) {
+		assert!(self.space.is_some(), "Full flush of kernel pages");
+		self.count = SHOOTDOWN_FLUSH_ALL;
+	}
+	/// Send the batch to the other CPUs, returning once they have all handled it
+	pub fn flush(&mut self) {
+		if self.count != 0 {
+			send_shootdown(self.space, self.count, &self.pages);
+			self.count = 0;
+		}
+	}
+}
+impl ::core::ops::Drop for ShootdownBatch
+{
+	fn drop(&mut self) {
+		self.flush();
+	}
+}
+
+/// Invalidate a single page on all other CPUs that could have it cached (see `ShootdownBatch`)
+pub fn tlb_shootdown(space: Option<u64>, addr: usize)
+{
+	ShootdownBatch::new(space).push(addr);
+}
+
+/// Send a shootdown to the other CPUs that could have the pages cached, and wait for them to handle it
+///
+/// NOTE: Waits for the other CPUs to handle the IPI, so must not be called while another CPU could be spinning with
+/// interrupts disabled on a lock held by the caller.
+fn send_shootdown(space: Option<u64>, count: usize, pages: &[usize; SHOOTDOWN_MAX_PAGES])
+{
+	let num_cpus = num_cpus();
+	if num_cpus <= 1 {
+		return ;
+	}
+	let cpu = super::threads::cpu_num();
+	// NOTE: Computed after the caller's page table changes, so a CPU that loads the address space later sees them
+	let cpus = match space
+		{
+		Some(s) => space_cpus(s),
+		None => (1 << num_cpus) - 1,
+		};
+	let others = cpus & !(1 << cpu);
+	if others == 0 {
+		return ;
+	}
+
+	let _lh = loop
+		{
+			if let Some(lh) = S_SHOOTDOWN_LOCK.try_lock_cpu() {
+				break lh;
+			}
+			// Another CPU is mid-shootdown (and possibly waiting on us), handle its request while waiting
+			handle_tlb_shootdown(cpu);
+			::core::sync::atomic::spin_loop_hint();
+		};
+	if count != SHOOTDOWN_FLUSH_ALL {
+		// SAFE: Lock held, and no other CPU reads the list until PENDING is set
+		unsafe {
+			S_SHOOTDOWN_PAGES[..count].copy_from_slice(&pages[..count]);
+		}
+	}
+	S_SHOOTDOWN_COUNT.store(count, Ordering::SeqCst);
+	S_SHOOTDOWN_PENDING.store(others, Ordering::SeqCst);
+	for i in 0 .. num_cpus
+	{
+		if others & (1 << i) != 0 {
+			// SAFE: Only written before the CPU is started
+			apic::send_ipi(unsafe { S_APIC_IDS[i] }, IPI_TLB_VEC);
+		}
+	}
+	while S_SHOOTDOWN_PENDING.load(Ordering::SeqCst) != 0 {
+		::core::sync::atomic::spin_loop_hint();
+	}
+}
+fn handle_tlb_shootdown(cpu: usize)
+{
+	if S_SHOOTDOWN_PENDING.load(Ordering::SeqCst) & (1 << cpu) != 0
+	{
+		let count = S_SHOOTDOWN_COUNT.load(Ordering::SeqCst);
+		// SAFE: Cannot cause memory unsafety, and the page list isn't modified until all CPUs have handled it
+		unsafe {
+			if count == SHOOTDOWN_FLUSH_ALL {
+				asm!("mov %cr3, %rax; mov %rax, %cr3" : : : "rax", "memory" : "volatile");
+			}
+			else {
+				for &addr in &S_SHOOTDOWN_PAGES[..count] {
+					asm!("invlpg ($0)" : : "r" (addr) : "memory" : "volatile");
+				}
+			}
+		}
+		S_SHOOTDOWN_PENDING.fetch_and(!(1 << cpu), Ordering::SeqCst);
+	}
+}
+
+extern "C" fn ipi_resched(isr: usize, _info: *const (), _idx: usize)
+{
+	// Nothing to do, returning from the interrupt will reschedule (or the idle thread will check the run queue)
+	apic::local_eoi(isr);
+}
+extern "C" fn ipi_tlb_shootdown(isr: usize, _info: *const (), _idx: usize)
+{
+	handle_tlb_shootdown(super::threads::cpu_num());
+	apic::local_eoi(isr);
+}
+
+// vim: ft=rust
@@ -240,7 +240,20 @@ start64_higher:
 	mov al, 10
 	out dx, al
 	
-	; Bind the 'SYSCALL' handler (and set flags for it)
+	call syscall_msr_init
+	
+	mov rax, InitialPML4
+	mov QWORD [rax], 0
+	; 7. Call rust kmain
+	call kmain
+.dead_loop:
+	cli
+	hlt
+	jmp .dead_loop
+
+; Bind the 'SYSCALL' handler (and set flags for it)
+; - Called on every CPU
+syscall_msr_init:
 	; LSTAR = 0xC000_0082
 	mov rax, syscall_handler
 	mov rdx, rax
@@ -257,22 +270,16 @@ start64_higher:
 	mov edx, 0
 	mov ecx, 0xC0000084
 	wrmsr
-	
-	mov rax, InitialPML4
-	mov QWORD [rax], 0
-	; 7. Call rust kmain
-	call kmain
-.dead_loop:
-	cli
-	hlt
-	jmp .dead_loop
+	ret
 
 %include "Core/arch/amd64/interrupts.inc.asm"
+%include "Core/arch/amd64/ap_trampoline.inc.asm"
 
 ; RDI: Save location for RSP
 ; RSI: New RSP (pointer)
 ; RDX: New FSBASE
 ; RCX: New CR3
+; R8: Old thread's "running" flag (cleared once its state is saved)
 [section .text.asm.task_switch]
 EXPORT task_switch
 	push rbp
@@ -282,13 +289,13 @@ EXPORT task_switch
 	; Perfom context save/restore
 	mov [rdi], rsp	; Save RSP
 	mov rsp, [rsi]	; New RSP
+	; Old thread's state is saved, it can now be run by another CPU
+	mov BYTE [r8], 0
 	mov cr3, rcx	; New CR3
 	invlpg [rsp]
 	
-	; Update stack top (RSP0) and TLS base (GS)
-	; TLS base and stack top are the same address.
-	; TODO: This assumes uniprocessor
-	mov [rel TSSes+tss.rsp0], rdx
+	; Update TLS base (GS)
+	; - The stack top (RSP0 in the TSS) is updated by the caller
 	mov rax, rdx
 	shr rdx, 32	; EDX = High
 	mov ecx, 0xC0000101	; GS Base
@@ -312,18 +319,27 @@ EXPORT thread_trampoline
 ; RDI: SP
 ; RDX: Arg
 EXPORT drop_to_user
-	mov rcx, rdi	; Set IP for SYSRET
+	mov r10, rdx	; Save argument (RDX is clobbered by RDMSR)
 	pushf
 	cli
 	pop r11	; Set RFLAGS for SYSRET
+	; Save FS base (the user TLS pointer), as loading FS clears it
+	mov ecx, 0xC0000100
+	rdmsr
+	mov r8d, eax
+	mov r9d, edx
 	swapgs
 	mov ax, 0x23
 	mov ds, ax
 	mov es, ax
 	mov fs, ax
 	mov gs, ax
+	mov eax, r8d
+	mov edx, r9d
+	wrmsr
+	mov rcx, rdi	; Set IP for SYSRET
 	mov rsp, rsi	; User's stack
-	mov rax, rdx	; Argument passed in RAX
+	mov rax, r10	; Argument passed in RAX
 	db 0x48
 	sysret
 
@@ -362,6 +378,9 @@ EXPORT syscall_handler
 	; All done
 	; >>> Restore RCX/R11 for sysret
 	RESTORE rcx, r11
+	; >>> Deliver a pending notification (needs the full register state, so returns via IRETQ)
+	cmp qword [gs:0x18], 0
+	jnz .user_event
 	; >>> Restore user's SP
 	mov rsp, [gs:0x10]
 	; >>> TODO: Restore user's FS
@@ -371,6 +390,15 @@ EXPORT syscall_handler
 	; - Returns to 64-bit mode, let's ignore compat mode
 	db 0x48
 	sysret
+.user_event:
+	cli
+	; Build an IRETQ frame for the return to user
+	push qword 0x23	; SS
+	push qword [gs:0x10]	; RSP
+	push r11	; RFLAGS
+	push qword 0x2B	; CS
+	push rcx	; RIP
+	jmp UserEventDeliver
 
 ; -------------------------------------------------
 ; Helpers
@@ -548,6 +576,12 @@ IDTPtr:
 	dq	IDT
 EXPORT s_tid0_tls_base
 	dq	0
+; Parameters for the AP currently being started (see smp.rs)
+EXPORT s_ap_boot_info
+	dq	0	; CR3
+	dq	0	; Boot stack top
+	dq	0	; Boot stack bottom
+	dq	0	; CPU index
 
 [section .bss]
 EXPORT TSSes
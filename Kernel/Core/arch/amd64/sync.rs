@@ -22,6 +22,8 @@ unsafe impl<T: Send> Sync for Spinlock<T> {}
 pub struct HeldSpinlock<'lock,T:'lock>
 {
 	lock: &'lock Spinlock<T>,
+	/// Interrupts held by `lock_irqsafe`, re-enabled after the lock is released
+	_irqs: Option<HeldInterrupts>,
 }
 
 /// A handle for frozen interrupts
@@ -53,23 +55,24 @@ impl<T> Spinlock<T>
 	pub fn lock(&self) -> HeldSpinlock<T>
 	{
 		self.inner_lock();
-		HeldSpinlock { lock: self }
+		HeldSpinlock { lock: self, _irqs: None }
 	}
 
-	/// Lock this spinning lock (accepting risk of panick/deadlock from IRQs)
+	/// Lock this spinning lock, holding interrupts until the lock is released
 	//#[is_safe(irq)]
 	pub fn lock_irqsafe(&self) -> HeldSpinlock<T> {
+		let irqs = hold_interrupts();
 		self.inner_lock();
-		HeldSpinlock { lock: self }
+		HeldSpinlock { lock: self, _irqs: Some(irqs) }
 	}
-	/// Attempt to acquire the lock, returning None if it is already held by this CPU
+	/// Attempt to acquire the lock, returning None if it is already held (by any CPU)
 	//#[is_safe(irq)]
 	pub fn try_lock_cpu(&self) -> Option<HeldSpinlock<T>>
 	{
 		//if self.lock.compare_and_swap(0, cpu_num()+1, Ordering::Acquire) == 0
 		if self.lock.compare_and_swap(false, true, Ordering::Acquire) == false
 		{
-			Some( HeldSpinlock { lock: self } )
+			Some( HeldSpinlock { lock: self, _irqs: None } )
 		}
 		else
 		{
@@ -81,6 +84,8 @@ impl<T> Spinlock<T>
 		//while self.lock.compare_and_swap(0, cpu_num()+1, Ordering::Acquire) != 0
 		while self.lock.compare_and_swap(false, true, Ordering::Acquire) == true
 		{
+			// Hint to the CPU that this is a spin loop (reduces contention with the holder)
+			::core::sync::atomic::spin_loop_hint();
 		}
 		::core::sync::atomic::fence(Ordering::Acquire);
 	}
@@ -111,6 +116,7 @@ impl<'lock,T> ::core::ops::Drop for HeldSpinlock<'lock, T>
 {
 	fn drop(&mut self)
 	{
+		// NOTE: `_irqs` is dropped after this, so interrupts stay disabled until the lock is released
 		self.lock.inner_release();
 	}
 }
@@ -4,6 +4,8 @@
 // Core/arch/amd64/threads.rs
 //! Architecture-level thread handling (helpers for ::threads).
 use prelude::*;
+use core::sync::atomic::{AtomicBool,Ordering};
+use super::smp::MAX_CPUS;
 
 #[derive(Default)]//,Copy,Clone)]
 /// Low-level thread state
@@ -12,10 +14,13 @@ pub struct State
 	cr3: u64,
 	rsp: u64,
 	tlsbase: u64,
+	/// Usermode TLS base (loaded into FS base)
+	user_tlsbase: u64,
+	/// Set while the thread is running on a CPU (cleared by `task_switch` once the state is saved)
+	running: AtomicBool,
 	// Not strictly part of the CPU state, but it prevents this thread's stack from disappearing
 	stack_handle: Option< ::memory::virt::ArrayHandle<u8> >,
 	// TODO: SSE state 
-	// TODO: Usermode TLS bsae
 }
 
 #[repr(align(16))]
@@ -27,11 +32,16 @@ impl Default for SSERegisters {
 extern "C" {
 	static InitialPML4: [u64; 512];
 	static s_tid0_tls_base: u64;
-	fn task_switch(oldrsp: &mut u64, newrsp: &u64, tlsbase: u64, cr3: u64);
+	fn task_switch(oldrsp: &mut u64, newrsp: &u64, tlsbase: u64, cr3: u64, old_running: &AtomicBool);
 }
 
-pub static S_IRQS_ENABLED: ::core::sync::atomic::AtomicBool = ::core::sync::atomic::AtomicBool::new(false);
-static mut S_IDLE_THREAD: *mut ::threads::Thread = 0 as *mut _;
+/// Set once each CPU has enabled interrupts (checked by `switch_to`)
+static S_IRQS_ENABLED: [AtomicBool; MAX_CPUS] = [
+	AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false),
+	AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false),
+	];
+/// Idle thread for each CPU
+static mut S_IDLE_THREADS: [*mut ::threads::Thread; MAX_CPUS] = [0 as *mut _; MAX_CPUS];
 
 #[repr(C)]
 /// Thread-local-storage block
@@ -44,10 +54,15 @@ struct TLSData {
 	stack_top: *const (),
 	// MUST be third (same as above)
 	user_stack: u64,
+	/// Non-zero if a notification should be delivered before returning to user-mode
+	// MUST be fourth (checked by the IRQ and SYSCALL return paths)
+	user_event_pending: u64,
 	
 	// Free to reorder these
 	thread_ptr: *mut ::threads::Thread,
 	thread_ptr_lent: bool,
+	/// Index of the CPU running this thread (updated when switched to)
+	cpu_num: usize,
 
 	futures_context: *mut (), //::core::task::Context<'static>,
 	
@@ -60,7 +75,7 @@ pub fn init_tid0_state() -> State
 {
 	// SAFE: Called in single-threaded context... hopefully (TODO)
 	unsafe {
-		S_IDLE_THREAD = ::core::mem::transmute( ::threads::new_idle_thread(0) );
+		S_IDLE_THREADS[0] = ::core::mem::transmute( ::threads::new_idle_thread(0) );
 	}
 	// SAFE: Just taking the address
 	let cr3 = unsafe { &InitialPML4 as *const _ as u64 - super::memory::addresses::IDENT_START as u64 };
@@ -70,6 +85,8 @@ pub fn init_tid0_state() -> State
 		rsp: 0,
 		// SAFE: Doesn't change outside rust control
 		tlsbase: unsafe { s_tid0_tls_base },
+		user_tlsbase: 0,
+		running: AtomicBool::new(true),
 		stack_handle: None,
 		}
 }
@@ -84,6 +101,68 @@ impl State
 		rv
 	}
 }
+impl ::core::ops::Drop for State
+{
+	fn drop(&mut self)
+	{
+		// The thread could still be in the process of switching out on another CPU (e.g. being reaped)
+		while self.running.load(Ordering::Acquire) {
+			::core::sync::atomic::spin_loop_hint();
+		}
+	}
+}
+
+/// Create the idle thread for an application processor (called on the BSP before starting it)
+pub fn init_ap_idle(cpu: usize)
+{
+	assert!(cpu > 0 && cpu < MAX_CPUS);
+	// SAFE: Called before the AP is started, so the entry isn't in use
+	unsafe {
+		assert!(S_IDLE_THREADS[cpu].is_null());
+		S_IDLE_THREADS[cpu] = ::core::mem::transmute( ::threads::new_idle_thread(cpu) );
+	}
+}
+/// Switch from an AP's boot context to its idle thread (never returns)
+pub fn switch_to_idle(cpu: usize) -> !
+{
+	// SAFE: Idle thread is only ever run on this CPU, and the boot context is never returned to
+	unsafe {
+		let state = &(*S_IDLE_THREADS[cpu]).cpu_state;
+		assert!( !state.running.swap(true, Ordering::Acquire) );
+		(*(state.tlsbase as *mut TLSData)).cpu_num = cpu;
+		super::tss::set_kernel_stack(cpu, state.tlsbase);
+		super::smp::begin_space_switch(cpu, state.cr3);
+		let mut boot_rsp = 0;
+		let boot_running = AtomicBool::new(true);
+		task_switch(&mut boot_rsp, &state.rsp, state.tlsbase, state.cr3, &boot_running);
+	}
+	unreachable!();
+}
+
+/// Index of the current CPU
+pub fn cpu_num() -> usize
+{
+	// SAFE: TLS pointer is valid
+	unsafe { (*get_tls_ptr()).cpu_num }
+}
+/// Set the current CPU index (called when an AP starts, before any threads run)
+pub fn set_cpu_num(cpu: usize)
+{
+	// SAFE: TLS pointer is valid
+	unsafe { (*get_tls_ptr()).cpu_num = cpu; }
+}
+
+/// Record that the current CPU has enabled interrupts
+pub fn set_irqs_enabled()
+{
+	S_IRQS_ENABLED[cpu_num()].store(true, Ordering::Relaxed);
+}
+
+/// Wake an idle CPU to pick up a newly runnable thread
+pub fn kick_idle_cpu()
+{
+	super::smp::kick_idle_cpu();
+}
 
 /// Idle for a short period, called when the CPU has nothing else to do
 pub fn idle()
@@ -93,8 +172,11 @@ pub fn idle()
 	//	let flags = unsafe { let v: u64; asm!("pushf; pop $0" : "=r" (v)); v };
 	//	assert!(flags & 0x200 != 0, "idle() with IF clear, RFLAGS = {:#x}", flags);
 	//}
+	let cpu = cpu_num();
+	super::smp::set_idle(cpu, true);
 	// SAFE: Safe assembly, just halts
 	unsafe { asm!("sti;hlt" : : : : "volatile"); }
+	super::smp::set_idle(cpu, false);
 }
 
 /// Prepares the TLS block at the stop of a kernel stack
@@ -114,9 +196,11 @@ pub unsafe extern "C" fn prep_tls(top: usize, _bottom: usize, thread_ptr: *mut :
 		self_ptr: data_ptr,
 		stack_top: tlsblock as *const (),
 		user_stack: 0,
+		user_event_pending: 0,
 		
 		thread_ptr: thread_ptr,
 		thread_ptr_lent: false,
+		cpu_num: 0,
 		futures_context: ::core::ptr::null_mut(),
 		sse_registers: None,
 		});
@@ -178,6 +262,8 @@ pub fn start_thread<F: FnOnce()+Send>(thread: &mut ::threads::Thread, code: F)
 		fn thread_trampoline();
 	}
 	fn thread_root<F: FnOnce()+Send>(code_ptr: *const F) -> ! {
+		// First run of this thread, complete the switch from the previous one (see `switch_to`)
+		super::smp::end_space_switch(cpu_num());
 		// Copy the closure locally
 		// - TODO: Find a way that avoids needing to make this unnessesary copy. By-value FnOnce is kinda undefined, sadly
 		// SAFE: Functionally owns that pointer
@@ -194,8 +280,9 @@ pub fn get_idle_thread() -> ::threads::ThreadPtr
 	// TODO: Shared mutability shouldn't be an issue (this thread pointer should not be created twice)
 	// SAFE: Passes a static pointer. `static mut` should be initialised
 	unsafe {
-		assert!(S_IDLE_THREAD != 0 as *mut _);
-		::threads::ThreadPtr::new_static( &mut *S_IDLE_THREAD )
+		let p = S_IDLE_THREADS[cpu_num()];
+		assert!(p != 0 as *mut _);
+		::threads::ThreadPtr::new_static( &mut *p )
 	}
 }
 
@@ -210,7 +297,7 @@ pub fn switch_to(newthread: ::threads::ThreadPtr)
 	}
 	else
 	{
-		if true && S_IRQS_ENABLED.load(::core::sync::atomic::Ordering::Relaxed) {
+		if true && S_IRQS_ENABLED[cpu_num()].load(Ordering::Relaxed) {
 			// SAFE: Just pulls rflags
 			let flags = unsafe { let v: u64; asm!("pushf; pop $0" : "=r" (v)); v };
 			assert!(flags & 0x200 != 0, "switch_to() with IF clear, RFLAGS = {:#x}", flags);
@@ -235,17 +322,32 @@ pub fn switch_to(newthread: ::threads::ThreadPtr)
 		// SAFE: Valid pointer accesses, task_switch trusted
 		unsafe
 		{
-			let outstate = &mut (*(*get_tls_ptr()).thread_ptr).cpu_state;
-			let state = &newthread.cpu_state;
-			// Don't assert RSP, could be switching to self
-			// - Wait, wouldn't that break the aliasing rules?
-			assert!(state.cr3 != 0);
-			assert!(state.tlsbase != 0);
-			//log_trace!("Switching to RSP={:#x},CR3={:#x},TLS={:#x}", state.rsp, state.cr3, state.tlsbase);
-			
-			assert!( *(outstate.tlsbase as *const usize) != 0, "outstate TLS Base clobbered before switch" );
-			assert!( *(state.tlsbase as *const usize) != 0, "TLS Base clobbered before switch" );
-			task_switch(&mut outstate.rsp, &state.rsp, state.tlsbase, state.cr3);
+			let cur_ptr = (*get_tls_ptr()).thread_ptr;
+			// Switching to self (nothing else to run), no state to save/restore
+			if &*newthread as *const ::threads::Thread != cur_ptr as *const _
+			{
+				let outstate = &mut (*cur_ptr).cpu_state;
+				let state = &newthread.cpu_state;
+				assert!(state.cr3 != 0);
+				assert!(state.tlsbase != 0);
+				//log_trace!("Switching to RSP={:#x},CR3={:#x},TLS={:#x}", state.rsp, state.cr3, state.tlsbase);
+				
+				assert!( *(outstate.tlsbase as *const usize) != 0, "outstate TLS Base clobbered before switch" );
+				assert!( *(state.tlsbase as *const usize) != 0, "TLS Base clobbered before switch" );
+
+				// Wait for the thread to finish switching out on another CPU, then claim it
+				while state.running.compare_and_swap(false, true, Ordering::Acquire) {
+					::core::sync::atomic::spin_loop_hint();
+				}
+				let cpu = (*get_tls_ptr()).cpu_num;
+				(*(state.tlsbase as *mut TLSData)).cpu_num = cpu;
+				super::tss::set_kernel_stack(cpu, state.tlsbase);
+				set_fs_base(state.user_tlsbase);
+				super::smp::begin_space_switch(cpu, state.cr3);
+				task_switch(&mut outstate.rsp, &state.rsp, state.tlsbase, state.cr3, &outstate.running);
+				// Resumed (possibly on another CPU), the thread that switched to this one has finished with its address space
+				super::smp::end_space_switch( (*get_tls_ptr()).cpu_num );
+			}
 		}
 		
 		if EAGER_SSE_ENABLE {
@@ -271,6 +373,37 @@ pub fn switch_to(newthread: ::threads::ThreadPtr)
 	}
 }
 
+/// Set the usermode TLS base of a thread that isn't yet running
+pub fn set_user_tls_base(thread: &mut ::threads::Thread, base: usize)
+{
+	thread.cpu_state.user_tlsbase = base as u64;
+}
+/// Set the usermode TLS base of the current thread
+pub fn set_cur_user_tls_base(base: usize)
+{
+	// SAFE: The current thread's state is only modified by itself, FS isn't used by the kernel
+	unsafe {
+		(*(*get_tls_ptr()).thread_ptr).cpu_state.user_tlsbase = base as u64;
+		set_fs_base(base as u64);
+	}
+}
+
+/// Request notification delivery on the current thread's next return to user-mode (see `user_event_deliver`)
+pub fn set_user_event_pending()
+{
+	// SAFE: Only accessed by the current thread (interrupts are disabled while the return path clears it)
+	unsafe {
+		(*get_tls_ptr()).user_event_pending = 1;
+	}
+}
+pub use super::cpu_faults::exception_return;
+
+/// Load the FS base MSR (used as the usermode TLS pointer)
+unsafe fn set_fs_base(v: u64)
+{
+	asm!("wrmsr" : : "{ecx}" (0xC0000100u32), "{eax}" (v as u32), "{edx}" ((v >> 32) as u32) : : "volatile");
+}
+
 fn get_tls_ptr() -> *mut TLSData {
 	let ret;
 	// SAFE: Just obtains the pointer from %gs
@@ -7,8 +7,7 @@
 // Just a run-of-the-mill module, as it's not needed until the switch to usermode
 module_define!(TSS, [], init);
 
-// NOTE: MUST match the value in common.inc.asm
-const MAX_CPUS: usize = 1;
+use super::smp::MAX_CPUS;
 
 #[repr(C,packed)]
 struct TSS
@@ -28,7 +27,7 @@ struct GDTEnt(u32,u32);
 
 extern "C" {
 	static mut GDT: [GDTEnt; 7+MAX_CPUS*2];
-	static mut TSSes: [TSS; 4];
+	static mut TSSes: [TSS; MAX_CPUS];
 	
 	static s_tid0_tls_base: u64;
 }
@@ -45,9 +44,31 @@ fn init()
 		TSSes[0].rsp0 = s_tid0_tls_base as u64;
 	}
 	
-	// SAFE: Just setting the task register
+	load_task_register(0);
+}
+
+/// Load the task register for an application processor (called on that processor)
+pub fn init_ap(cpu: usize)
+{
+	assert!(cpu < MAX_CPUS);
+	load_task_register(cpu);
+}
+
+/// Set the stack used when entering the kernel from user-mode on the specified CPU
+pub fn set_kernel_stack(cpu: usize, rsp0: u64)
+{
+	assert!(cpu < MAX_CPUS);
+	// SAFE: Each CPU only updates its own TSS
+	unsafe {
+		TSSes[cpu].rsp0 = rsp0;
+	}
+}
+
+fn load_task_register(cpu: usize)
+{
+	// SAFE: Just setting the task register, each CPU has its own descriptor
 	unsafe {
-		asm!("ltr %cx" : : "{ecx}" (7*8) );
+		asm!("ltr %cx" : : "{ecx}" ((7+cpu*2)*8) );
 	}
 }
 
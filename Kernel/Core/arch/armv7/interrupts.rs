@@ -53,6 +53,16 @@ pub extern "C" fn interrupt_handler()
 			},
 		}
 	}
+	// NOTE: Preemption is checked by `irq_return_user` (called by the assembly stub when returning to user-mode)
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Called by assembly before returning from an IRQ to user-mode (with interrupts disabled)
+pub extern "C" fn irq_return_user()
+{
+	::threads::preempt_user();
+	::threads::exceptions::check_user_events();
 }
 
 fn get_active_interrupt() -> u32
@@ -26,6 +26,9 @@ const PAGE_MASK_U32: u32 = PAGE_MASK as u32;
 // TODO: Why is this -1 here?
 static S_TEMP_MAP_SEMAPHORE: ::sync::Semaphore = ::sync::Semaphore::new(KERNEL_TEMP_COUNT as isize - 1, KERNEL_TEMP_COUNT as isize);
 
+/// Swapping isn't implemented for ARM page tables yet
+pub const SWAP_SUPPORTED: bool = false;
+
 pub fn post_init() {
 	// SAFE: Atomic
 	unsafe { kernel_table0[0].store(0, Ordering::SeqCst) };
@@ -658,8 +661,35 @@ impl AddressSpace
 			match flags_to_prot_mode(mode_flags)
 			{
 			ProtectionMode::Unmapped => {},
-			ProtectionMode::UserCOW => todo!("Clone when COW"),
-			ProtectionMode::UserRW | ProtectionMode::UserRO | ProtectionMode::UserRX => {
+			ProtectionMode::UserCOW => {
+				// Shared between both address spaces until written
+				let paddr = src_slot_0_val & !PAGE_MASK_U32;
+				::memory::phys::ref_frame(paddr as PAddr);
+				dst_slots[0] = src_slot_0_val;
+				dst_slots[1] = user_last_table()[ofs+1].load(Ordering::Relaxed);
+				},
+			ProtectionMode::UserRO | ProtectionMode::UserRX => {
+				// Read-only, can be shared directly
+				let paddr = src_slot_0_val & !PAGE_MASK_U32;
+				::memory::phys::ref_frame(paddr as PAddr);
+				dst_slots[0] = src_slot_0_val;
+				dst_slots[1] = user_last_table()[ofs+1].load(Ordering::Relaxed);
+				},
+			ProtectionMode::UserRW => {
+				// Share the frame, with both the parent and the child copying it on their next write
+				let paddr = src_slot_0_val & !PAGE_MASK_U32;
+				::memory::phys::ref_frame(paddr as PAddr);
+				let cow_flags = prot_mode_to_flags(ProtectionMode::UserCOW);
+				for i in 0 .. 2 {
+					// NOTE: Hardware entries are 4KB, so only the low 12 bits are flags
+					let v = (user_last_table()[ofs+i].load(Ordering::Relaxed) & !0xFFF) | cow_flags;
+					user_last_table()[ofs+i].store(v, Ordering::Relaxed);
+					dst_slots[i] = v;
+				}
+				tlbimva( (page * ::PAGE_SIZE) as *mut () );
+				tlbimva( (page * ::PAGE_SIZE + 0x1000) as *mut () );
+				},
+			ProtectionMode::UserRWX => {
 				let src_ptr = (page * ::PAGE_SIZE) as *const u8;
 				// SAFE: Memory is valid (TODO: What if this changes? Shouldn't cause errors, just inconsistent user data)
 				let src = unsafe { ::core::slice::from_raw_parts(src_ptr, ::PAGE_SIZE) };
@@ -679,6 +709,62 @@ impl AddressSpace
 	}
 
 	pub fn get_ttbr0(&self) -> u32 { self.0 }
+
+	/// Returns true if this address space is loaded (uniprocessor, so only the current CPU is checked)
+	pub fn is_loaded(&self) -> bool
+	{
+		current_address_space() == (self.0 & !0x3FFF) as PAddr
+	}
+
+	/// Release all user mappings (and the memory/page tables behind them)
+	///
+	/// The address space must not be active. The final table (holding the fractal and temporary mappings) is kept.
+	pub fn release_user(&self)
+	{
+		const ENTS_PER_ALLOC: usize = PAGE_SIZE / 0x400;
+		const LAST_GROUP: usize = 2048 / ENTS_PER_ALLOC - 1;
+		// Hardware (4KB) slot index of the first temporary mapping within the final table
+		const LAST_TAB_USER_SLOTS: usize = (USER_TEMP_BASE - (LAST_GROUP << 23)) >> 12;
+
+		// SAFE: The address space isn't in use, so its tables are uniquely owned
+		let mut root: TempHandle<u32> = unsafe { TempHandle::new(self.0 & !PAGE_MASK_U32) };
+		for group in 0 .. LAST_GROUP+1
+		{
+			let ent_v = root[group * ENTS_PER_ALLOC];
+			if ent_v & 3 != 1 {
+				// No table (sections aren't used for user memory)
+				continue ;
+			}
+			let tab_paddr = ent_v & !PAGE_MASK_U32;
+			let nslots = if group == LAST_GROUP { LAST_TAB_USER_SLOTS } else { 2048 };
+			{
+				// SAFE: Table is owned by this address space
+				let mut tab: TempHandle<u32> = unsafe { TempHandle::new(tab_paddr) };
+				// Each (8KB) page is two consecutive hardware entries
+				for slots in tab[.. nslots].chunks_mut(2)
+				{
+					if slots[0] & 3 != 0 {
+						::memory::phys::deref_frame( (slots[0] & !PAGE_MASK_U32) as PAddr );
+					}
+					slots[0] = 0;
+					slots[1] = 0;
+				}
+			}
+			if group != LAST_GROUP {
+				for v in root[group * ENTS_PER_ALLOC ..][.. ENTS_PER_ALLOC].iter_mut() {
+					*v = 0;
+				}
+				::memory::phys::deref_frame(tab_paddr as PAddr);
+			}
+		}
+	}
+
+	/// Count the resident user pages
+	pub fn resident_user_pages(&self) -> usize
+	{
+		// TODO: Walk the user tables (see `release_user`)
+		0
+	}
 }
 
 
@@ -695,30 +781,61 @@ pub struct AbortRegs
 	ret_pc: u32,	// SRSFD/RFEFD state
 	spsr: u32,
 }
+/// Physical address of the current address space's root table (TTBR0)
+pub fn current_address_space() -> PAddr {
+	let rv: u32;
+	// SAFE: Reads a register
+	unsafe { asm!("mrc p15,0, $0, c2,c0,0" : "=r" (rv)); }
+	(rv & !0x3FFF) as PAddr
+}
+pub fn age_user_page(_root: PAddr, _addr: usize) -> ::arch::memory::virt::PageAge {
+	::arch::memory::virt::PageAge::Absent
+}
+pub unsafe fn swap_out_page(_root: PAddr, _addr: usize, _slot: usize) -> Option<PAddr> {
+	None
+}
+
 #[no_mangle]
 pub fn data_abort_handler(pc: u32, reg_state: &AbortRegs, dfar: u32, dfsr: u32) {
+	// DFSR.WnR - Abort was caused by a write
+	const DFSR_WNR: u32 = 1 << 11;
+
+	// Copy-on-write (including demand-zero) pages
+	if dfsr & DFSR_WNR != 0 && dfsr & 0x40F == 0x00F {
+		let pgaddr = (dfar as usize) & !PAGE_MASK;
+		let mut ent = PageEntry::get(pgaddr as *const ());
+		if ent.mode() == ProtectionMode::UserCOW {
+			// 1. Lock (relevant) address space
+			// SAFE: Changes to address space are transparent
+			let handled = ::memory::virt::with_lock(dfar as usize, || unsafe {
+				let frame = ent.phys_addr();
+				// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
+				match ::memory::phys::make_unique( frame, &*(pgaddr as *const [u8; PAGE_SIZE]) )
+				{
+				Ok(newframe) => {
+					// 3. Remap to this page as UserRW (because COW is user-only atm)
+					// - Both 4KB halves of the 8KB page
+					ent.set(newframe, ProtectionMode::UserRW);
+					PageEntry::get((pgaddr + 0x1000) as *const ()).set(newframe + 0x1000, ProtectionMode::UserRW);
+					tlbimva( pgaddr as *mut () );
+					tlbimva( (pgaddr + 0x1000) as *mut () );
+					true
+					},
+				Err(_) => false,
+				}
+				});
+			if handled {
+				return ;
+			}
+			log_error!("Out of memory handling copy-on-write of {:#x}", dfar);
+		}
+	}
 
 	log_warning!("Data abort by {:#x} address {:#x} status {:#x} ({})", pc, dfar, dfsr, fsr_name(dfsr));
 	dump_tables();
 	//log_debug!("Registers:");
 	//log_debug!("R 0 {:08x}  R 1 {:08x}  R 2 {:08x}  R 3 {:08x}  R 4 {:08x}  R 5 {:08x}}  R 6 {:08x}", reg_state.gprs[0]);
 	
-	let mut ent = PageEntry::get(dfar as usize as *const ());
-	if ent.mode() == ProtectionMode::UserCOW {
-		// 1. Lock (relevant) address space
-		// SAFE: Changes to address space are transparent
-		::memory::virt::with_lock(dfar as usize, || unsafe {
-			let frame = ent.phys_addr();
-			// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
-			let newframe = ::memory::phys::make_unique( frame, &*(((dfar as usize) & !PAGE_MASK) as *const [u8; PAGE_SIZE]) );
-			// 3. Remap to this page as UserRW (because COW is user-only atm)
-			ent.set(newframe, ProtectionMode::UserRW);
-			log_debug!("- COW frame copied");
-			});
-
-		return ;
-	}
-	
 	if pc < 0x8000_0000 {
 		loop {}
 	}
@@ -109,7 +109,22 @@ pub fn puth(v: u64) {
 pub fn cur_timestamp() -> u64 {
 	0
 }
+pub fn cur_timestamp_ns() -> u64 {
+	0
+}
+pub fn set_timer_deadline(_ns: u64) {
+	// TODO: No timer driver, so no timer interrupts
+}
+pub fn entropy_sample() -> u64 {
+	// TODO: No cycle counter is enabled, so the entropy pool relies on IRQ timing
+	0
+}
 
+/// Obtain return addresses from the current call stack
+pub fn get_return_addresses(_addrs: &mut [usize]) -> usize {
+	// TODO: Use the EHABI unwinder (it's currently too slow to use on every allocation)
+	0
+}
 pub fn print_backtrace() {
 	let rs = aeabi_unwind::UnwindState::new_cur();
 	let addr = rs.get_lr() as usize;
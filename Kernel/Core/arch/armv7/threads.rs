@@ -4,6 +4,8 @@
 pub struct State {
 	sp: usize,
 	ttbr0: u32,
+	/// Usermode TLS base (loaded into TPIDRURO)
+	user_tls_base: usize,
 	stack_handle: Option< ::memory::virt::ArrayHandle<u8> >,
 }
 
@@ -13,6 +15,7 @@ impl State
 		State {
 			sp: 0,
 			ttbr0: address_space.get_ttbr0(),
+			user_tls_base: 0,
 			stack_handle: None,
 		}
 	}
@@ -31,6 +34,9 @@ pub fn init_tid0_state() -> State {
 pub fn get_idle_thread() -> ::threads::ThreadPtr {
 	todo!("get_idle_thread");
 }
+pub fn kick_idle_cpu() {
+	// Uniprocessor only
+}
 
 pub fn set_thread_ptr(thread: ::threads::ThreadPtr) {
 	let real = borrow_thread_mut();
@@ -82,9 +88,33 @@ pub fn switch_to(thread: ::threads::ThreadPtr) {
 		let new_sp = thread.cpu_state.sp;
 		let new_ttbr0 = thread.cpu_state.ttbr0;
 		log_trace!("Switching to SP={:#x},TTBR0={:#x}", new_sp, new_ttbr0);
+		asm!("mcr p15,0, $0, c13,c0,3" : : "r" (thread.cpu_state.user_tls_base) : : "volatile");	// TPIDRURO
 		task_switch(&mut outstate.sp, new_sp, new_ttbr0, thread.into_usize());
 	}
 }
+/// Set the usermode TLS base of a thread that isn't yet running
+pub fn set_user_tls_base(thread: &mut ::threads::Thread, base: usize) {
+	thread.cpu_state.user_tls_base = base;
+}
+/// Set the usermode TLS base of the current thread
+pub fn set_cur_user_tls_base(base: usize) {
+	// SAFE: The current thread's state is only modified by itself, TPIDRURO is only read by user code
+	unsafe {
+		(*borrow_thread_mut()).cpu_state.user_tls_base = base;
+		asm!("mcr p15,0, $0, c13,c0,3" : : "r" (base) : : "volatile");	// TPIDRURO
+	}
+}
+pub fn set_user_event_pending() {
+	// TODO: Check for pending notifications on the return-to-user path
+}
+/// Resume user code after an exception handler
+///
+/// Faults and notifications aren't delivered to user handlers on this architecture yet, so there's no valid
+/// handler state to resume: terminate the process instead of trusting the frame.
+pub fn exception_return(frame: &::threads::exceptions::ExceptionFrame) -> ! {
+	log_warning!("exception_return - Not supported on this architecture (ip={:#x}), terminating process", frame.ip);
+	::threads::exit_process(::threads::exceptions::EXIT_STATUS_FAULT);
+}
 pub fn idle() {
 	log_trace!("idle");
 	// SAFE: Calls 'wait for interrupt'
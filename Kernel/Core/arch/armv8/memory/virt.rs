@@ -12,10 +12,27 @@ const LEVEL1_FRACTAL_OFS: usize = (2048-2)*2048*2048 + (2048-2)*2048;	// Offset
 
 pub struct AddressSpace(u64);
 
+/// Swapping isn't implemented for ARMv8 page tables yet
+pub const SWAP_SUPPORTED: bool = false;
+
 pub fn post_init()
 {
 }
 
+/// Physical address of the current address space's root table (TTBR0)
+pub fn current_address_space() -> u64 {
+	let rv: u64;
+	// SAFE: Reads a register
+	unsafe { asm!("mrs $0, TTBR0_EL1" : "=r" (rv)); }
+	rv & 0x0000_FFFF_FFFF_F000
+}
+pub fn age_user_page(_root: u64, _addr: usize) -> ::arch::memory::virt::PageAge {
+	::arch::memory::virt::PageAge::Absent
+}
+pub unsafe fn swap_out_page(_root: u64, _addr: usize, _slot: usize) -> Option<u64> {
+	None
+}
+
 fn prot_mode_to_attrs(prot: ProtectionMode) -> u64
 {
 	match prot
@@ -226,5 +243,25 @@ impl AddressSpace
 	pub fn as_phys(&self) -> u64 {
 		self.0
 	}
+
+	/// Returns true if this address space is loaded on a CPU
+	pub fn is_loaded(&self) -> bool
+	{
+		// TODO: User address spaces aren't implemented yet, so none are ever switched to
+		false
+	}
+
+	/// Release all user mappings
+	pub fn release_user(&self)
+	{
+		// TODO: User address spaces aren't implemented yet, so there's nothing to release
+	}
+
+	/// Count the resident user pages
+	pub fn resident_user_pages(&self) -> usize
+	{
+		// TODO: User address spaces aren't implemented yet
+		0
+	}
 }
 
@@ -45,9 +45,44 @@ pub fn print_backtrace() {
 	puts("\n");
 }
 
+/// Obtain return addresses from the current call stack
+pub fn get_return_addresses(addrs: &mut [usize]) -> usize {
+	#[repr(C)]
+	struct FrameEntry {
+		next: *const FrameEntry,
+		ret_addr: usize,
+	}
+	let mut fp: *const FrameEntry;
+	// SAFE: Just loads the frame pointer
+	unsafe { asm!("mov $0, fp" : "=r"(fp)); }
+	let mut n = 0;
+	while n < addrs.len() && ! fp.is_null() && ::memory::buf_valid(fp as *const (), ::core::mem::size_of::<FrameEntry>())
+	{
+		// SAFE: Checked by above
+		let data = unsafe { &*fp };
+		addrs[n] = data.ret_addr;
+		n += 1;
+		fp = data.next;
+	}
+	n
+}
+
 pub fn cur_timestamp() -> u64 {
 	0
 }
+pub fn cur_timestamp_ns() -> u64 {
+	0
+}
+pub fn set_timer_deadline(_ns: u64) {
+	// TODO: No timer driver, so no timer interrupts
+}
+/// Read the generic timer's virtual count (used as an entropy sample)
+pub fn entropy_sample() -> u64 {
+	let rv: u64;
+	// SAFE: Reads a register
+	unsafe { asm!("mrs $0, CNTVCT_EL0" : "=r" (rv) : : : "volatile"); }
+	rv
+}
 
 extern "C" {
 	pub fn drop_to_user(entry: usize, stack: usize, args_len: usize) -> !;
@@ -8,6 +8,8 @@ pub struct State
 {
 	sp: usize,
 	ttbr0: u64,
+	/// Usermode TLS base (loaded into TPIDRRO_EL0)
+	user_tls_base: usize,
 	stack_handle: Option< ::memory::virt::ArrayHandle<u8> >,
 }
 
@@ -16,6 +18,7 @@ pub fn init_tid0_state() -> State
 	State {
 		sp: 0,
 		ttbr0: super::memory::virt::AddressSpace::pid0().as_phys(),
+		user_tls_base: 0,
 		stack_handle: None,
 		}
 }
@@ -26,6 +29,7 @@ impl State
 		State {
 			sp: 0,
 			ttbr0: addr_space.as_phys(),
+			user_tls_base: 0,
 			stack_handle: None,
 			}
 	}
@@ -34,6 +38,9 @@ impl State
 pub fn get_idle_thread() -> ::threads::ThreadPtr {
 	todo!("get_idle_thread");
 }
+pub fn kick_idle_cpu() {
+	// Uniprocessor only
+}
 
 pub fn set_thread_ptr(thread: ::threads::ThreadPtr) {
 	// SAFE: Write to per-CPU register
@@ -80,9 +87,33 @@ pub fn switch_to(thread: ::threads::ThreadPtr) {
 		let new_sp = thread.cpu_state.sp;
 		let new_ttbr0 = thread.cpu_state.ttbr0;
 		log_trace!("Switching to SP={:#x},TTBR0={:#x}", new_sp, new_ttbr0);
+		asm!("msr TPIDRRO_EL0, $0" : : "r" (thread.cpu_state.user_tls_base) : : "volatile");
 		task_switch(&mut outstate.sp, new_sp, new_ttbr0, thread.into_usize());
 	}
 }
+/// Set the usermode TLS base of a thread that isn't yet running
+pub fn set_user_tls_base(thread: &mut ::threads::Thread, base: usize) {
+	thread.cpu_state.user_tls_base = base;
+}
+/// Set the usermode TLS base of the current thread
+pub fn set_cur_user_tls_base(base: usize) {
+	// SAFE: The current thread's state is only modified by itself, TPIDRRO_EL0 is only read by user code
+	unsafe {
+		(*borrow_thread_mut()).cpu_state.user_tls_base = base;
+		asm!("msr TPIDRRO_EL0, $0" : : "r" (base) : : "volatile");
+	}
+}
+pub fn set_user_event_pending() {
+	// TODO: Check for pending notifications on the return-to-user path
+}
+/// Resume user code after an exception handler
+///
+/// Faults and notifications aren't delivered to user handlers on this architecture yet, so there's no valid
+/// handler state to resume: terminate the process instead of trusting the frame.
+pub fn exception_return(frame: &::threads::exceptions::ExceptionFrame) -> ! {
+	log_warning!("exception_return - Not supported on this architecture (ip={:#x}), terminating process", frame.ip);
+	::threads::exit_process(::threads::exceptions::EXIT_STATUS_FAULT);
+}
 pub fn idle() {
 	log_trace!("idle");
 	// SAFE: Calls 'wait for interrupt'
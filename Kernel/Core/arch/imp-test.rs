@@ -35,6 +35,14 @@ pub mod memory {
 			pub fn new(_cstart: usize, _cend: usize) -> Result<AddressSpace,()> {
 				todo!("AddressSpace::new");
 			}
+			pub fn is_loaded(&self) -> bool {
+				false
+			}
+			pub fn release_user(&self) {
+			}
+			pub fn resident_user_pages(&self) -> usize {
+				0
+			}
 		}
 
 		pub fn post_init() {
@@ -74,6 +82,17 @@ pub mod memory {
 		pub unsafe fn unmap(_a: *mut ()) -> Option<::memory::PAddr> {
 			None
 		}
+
+		pub const SWAP_SUPPORTED: bool = false;
+		pub fn current_address_space() -> ::memory::PAddr {
+			0
+		}
+		pub fn age_user_page(_root: ::memory::PAddr, _addr: usize) -> ::arch::memory::virt::PageAge {
+			::arch::memory::virt::PageAge::Absent
+		}
+		pub unsafe fn swap_out_page(_root: ::memory::PAddr, _addr: usize, _slot: usize) -> Option<::memory::PAddr> {
+			None
+		}
 	}
 	pub mod phys {
 		pub fn ref_frame(_frame_idx: u64) {
@@ -323,6 +342,8 @@ pub mod threads {
 		// SAFE: Same as `get_thread_ptr`, doesn't actually own the result
 		unsafe { std::mem::transmute(*TS_ZERO) }
 	}
+	pub fn kick_idle_cpu() {
+	}
 	pub fn switch_to(t: ::threads::ThreadPtr) {
 		THIS_THREAD_STATE.with(|v| {
 			let h = v.borrow();
@@ -344,6 +365,17 @@ pub mod threads {
 		});
 	}
 
+	pub fn set_user_tls_base(_thread: &mut ::threads::Thread, _base: usize) {
+		// No usermode in tests
+	}
+	pub fn set_cur_user_tls_base(_base: usize) {
+	}
+	pub fn set_user_event_pending() {
+	}
+	pub fn exception_return(_frame: &::threads::exceptions::ExceptionFrame) -> ! {
+		unimplemented!("exception_return");
+	}
+
 	/// Test hack: Releases the current thread from scheduling
 	pub fn test_unlock_thread() {
 		// - Hold switching lock until function returns
@@ -415,13 +447,25 @@ pub fn puts(s: &str) {
 pub fn puth(v: u64) {
 	print!("{:08x}", v);
 }
+pub fn entropy_sample() -> u64 {
+	std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+lazy_static::lazy_static! {
+	static ref TS_ZERO: std::time::Instant = std::time::Instant::now();
+}
 pub fn cur_timestamp() -> u64 {
-	lazy_static::lazy_static! {
-		static ref TS_ZERO: std::time::Instant = std::time::Instant::now();
-	}
+	cur_timestamp_ns() / 1_000_000
+}
+pub fn cur_timestamp_ns() -> u64 {
 	let ts0 = *TS_ZERO;
-	(std::time::Instant::now() - ts0).as_millis() as u64
+	(std::time::Instant::now() - ts0).as_nanos() as u64
+}
+pub fn set_timer_deadline(_ns: u64) {
+	// No timer interrupts in the test environment
 }
 pub fn print_backtrace() {
 }
+pub fn get_return_addresses(_addrs: &mut [usize]) -> usize {
+	0
+}
 
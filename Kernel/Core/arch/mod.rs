@@ -164,6 +164,38 @@ pub mod memory {
 		pub unsafe fn unmap(a: *mut ()) -> Option<::memory::PAddr> {
 			imp::unmap(a)
 		}
+
+		/// Result of checking (and clearing) a user page's accessed flag
+		#[derive(Debug)]
+		pub enum PageAge
+		{
+			/// Page is no longer mapped as private read-write
+			Absent,
+			/// Page has been accessed since the last check
+			Accessed,
+			/// Page hasn't been accessed since the last check, and is backed by this frame
+			Idle(::memory::PAddr),
+		}
+
+		/// The architecture supports swapping user pages out (see `age_user_page`/`swap_out_page`)
+		pub const SWAP_SUPPORTED: bool = imp::SWAP_SUPPORTED;
+		#[inline]
+		/// Physical address of the current address space's root
+		pub fn current_address_space() -> ::memory::PAddr {
+			imp::current_address_space()
+		}
+		#[inline]
+		/// Check and clear the accessed flag on a private user page in any address space
+		pub fn age_user_page(root: ::memory::PAddr, addr: usize) -> PageAge {
+			imp::age_user_page(root, addr)
+		}
+		#[inline]
+		/// Replace an unaccessed private user page with a swap entry, returning the frame it used
+		///
+		/// UNSAFE: Caller must ensure that the frame's contents are saved in the slot before it is released
+		pub unsafe fn swap_out_page(root: ::memory::PAddr, addr: usize, slot: usize) -> Option<::memory::PAddr> {
+			imp::swap_out_page(root, addr, slot)
+		}
 	}
 	/// Physical memory state tracking
 	pub mod phys {
@@ -297,6 +329,11 @@ pub mod threads {
 		imp::get_idle_thread()
 	}
 	#[inline]
+	/// Notify idle CPUs that a thread has become runnable
+	pub fn kick_idle_cpu() {
+		imp::kick_idle_cpu()
+	}
+	#[inline]
 	pub fn switch_to(t: ::threads::ThreadPtr) {
 		imp::switch_to(t)
 	}
@@ -305,6 +342,28 @@ pub mod threads {
 	pub fn start_thread<F: FnOnce()+Send+'static>(thread: &mut ::threads::Thread, code: F) {
 		imp::start_thread(thread, code)
 	}
+
+	#[inline]
+	/// Set the usermode TLS base of a thread that hasn't been started yet
+	pub fn set_user_tls_base(thread: &mut ::threads::Thread, base: usize) {
+		imp::set_user_tls_base(thread, base)
+	}
+	#[inline]
+	/// Set the usermode TLS base of the current thread (takes effect immediately)
+	pub fn set_cur_user_tls_base(base: usize) {
+		imp::set_cur_user_tls_base(base)
+	}
+
+	#[inline]
+	/// Flag the current thread's return-to-user path to call `::threads::exceptions::take_notification`
+	pub fn set_user_event_pending() {
+		imp::set_user_event_pending()
+	}
+	#[inline]
+	/// Resume user code with the register state in `frame` (sanitised by the architecture)
+	pub fn exception_return(frame: &::threads::exceptions::ExceptionFrame) -> ! {
+		imp::exception_return(frame)
+	}
 }
 
 /// x86 IO bus accesses
@@ -336,13 +395,36 @@ pub fn puth(v: u64) {
 }
 
 #[inline]
+/// Monotonic timestamp in milliseconds (since an arbitary point, usually boot)
 pub fn cur_timestamp() -> u64 {
 	imp::cur_timestamp()
 }
 #[inline]
+/// Monotonic timestamp in nanoseconds (same timebase as `cur_timestamp`)
+pub fn cur_timestamp_ns() -> u64 {
+	imp::cur_timestamp_ns()
+}
+#[inline]
+/// Request a timer interrupt (calling `::time::tick`) on this CPU at the given `cur_timestamp_ns` value
+///
+/// Replaces any previously requested deadline, `!0` cancels the interrupt.
+pub fn set_timer_deadline(ns: u64) {
+	imp::set_timer_deadline(ns)
+}
+#[inline]
+/// Obtain a fast-changing hardware value (e.g. a cycle counter) for use as an entropy sample
+pub fn entropy_sample() -> u64 {
+	imp::entropy_sample()
+}
+#[inline]
 pub fn print_backtrace() {
 	imp::print_backtrace()
 }
+#[inline]
+/// Fill `addrs` with return addresses from the current call stack (innermost first), returning the number found
+pub fn get_return_addresses(addrs: &mut [usize]) -> usize {
+	imp::get_return_addresses(addrs)
+}
 
 #[inline]
 pub unsafe fn drop_to_user(entry: usize, stack: usize, args_len: usize) -> ! {
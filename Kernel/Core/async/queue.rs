@@ -63,6 +63,15 @@ impl Source
 			false
 		}
 	}
+	/// Wake all waiting threads
+	pub fn wake_all(&self)
+	{
+		let mut lh = self.waiters.lock();
+		while let Some(waiter) = lh.pop()
+		{
+			waiter.signal();
+		}
+	}
 }
 
 impl<'a> fmt::Debug for Waiter<'a>
@@ -88,6 +88,12 @@ def_config_set! {
 		Loader @ "LOADER" = "/sysroot/bin/loader",
 //		/// Startup - Init executable (first userland process)
 		Init @ "INIT" = "/sysroot/bin/init",
+//		/// Memory - Volume used for swap space (swap is disabled if empty)
+		Swap @ "SWAP" = "",
+//		/// Userland - Randomise the memory layout of processes (set to "0" for reproducible debugging)
+		Aslr @ "ASLR" = "1",
+//		/// Profiler - Sample periodically on every CPU (set to "1", disables tickless idle)
+		Profile @ "PROFILE" = "0",
 		TestFlags @ "TEST" = "",
 	}
 }
@@ -115,6 +115,7 @@ impl_from! {
 		{
 		::memory::virt::MapError::OutOfMemory => DriverBindError::OutOfMemory,
 		::memory::virt::MapError::RangeInUse => DriverBindError::Bug("Memory map range collision"),
+		::memory::virt::MapError::LimitReached => DriverBindError::OutOfMemory,
 		}
 	}
 }
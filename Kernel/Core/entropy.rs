@@ -0,0 +1,62 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/entropy.rs
+//! Entropy pool
+//!
+//! Collects unpredictable samples (interrupt timing and hardware counters) and hands out random values.
+//! NOTE: This is NOT cryptographically secure, it's intended for layout randomisation and hash seeds.
+use core::sync::atomic::{AtomicUsize,Ordering};
+
+const POOL_SIZE: usize = 8;
+
+static S_POOL: [AtomicUsize; POOL_SIZE] = [
+	AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+	AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+	];
+/// Next pool word to receive a sample
+static S_INDEX: AtomicUsize = AtomicUsize::new(0);
+/// Number of values handed out (ensures that consecutive calls differ, even without new samples)
+static S_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Add a sample to the pool (safe to call from IRQ context)
+pub fn add_sample(value: u64)
+{
+	let i = S_INDEX.fetch_add(1, Ordering::Relaxed) % POOL_SIZE;
+	let m = mix(value ^ i as u64);
+	S_POOL[i].fetch_xor( (m ^ (m >> 32)) as usize, Ordering::Relaxed );
+}
+
+/// Obtain a random 64-bit value
+pub fn get_u64() -> u64
+{
+	add_sample( ::arch::entropy_sample() ^ ::time::ticks().rotate_left(32) );
+
+	let mut h = S_COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+	for w in S_POOL.iter() {
+		h = mix(h ^ w.load(Ordering::Relaxed) as u64);
+	}
+	// Stir the result back in, so the pool state changes on every call
+	add_sample(h);
+	mix(h.rotate_left(17))
+}
+
+/// Fill a buffer with random bytes
+pub fn fill(buf: &mut [u8])
+{
+	for chunk in buf.chunks_mut(8)
+	{
+		let v = get_u64();
+		for (i,b) in chunk.iter_mut().enumerate() {
+			*b = (v >> (i * 8)) as u8;
+		}
+	}
+}
+
+/// 64-bit finaliser from SplitMix64
+fn mix(mut z: u64) -> u64
+{
+	z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+	z ^ (z >> 31)
+}
@@ -0,0 +1,406 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/hw/mapper_gpt.rs
+/// GUID Partition Table logical volume mapper
+use prelude::*;
+use lib::byteorder::{ByteOrder,LittleEndian};
+use lib::crc32::crc32;
+use metadevs::storage;
+
+module_define!{MapperGPT, [Storage], init}
+
+static S_MAPPER: Mapper = Mapper;
+
+/// Upper limit on the size of the partition entry array (the spec minimum is 16KiB)
+const MAX_ENTRY_ARRAY_SIZE: usize = 1024*1024;
+/// Partition attribute: Legacy BIOS bootable
+const ATTR_LEGACY_BIOS_BOOTABLE: u64 = 1 << 2;
+
+fn init()
+{
+	storage::register_mapper(&S_MAPPER);
+}
+
+struct Mapper;
+
+/// Validated GPT header
+#[derive(Debug)]
+struct Header
+{
+	my_lba: u64,
+	alternate_lba: u64,
+	first_usable: u64,
+	last_usable: u64,
+	disk_guid: Guid,
+	entries_lba: u64,
+	num_entries: u32,
+	entry_size: u32,
+	entries_crc: u32,
+}
+
+#[derive(Debug)]
+struct Entry
+{
+	type_guid: Guid,
+	unique_guid: Guid,
+	first_lba: u64,
+	last_lba: u64,
+	attributes: u64,
+	label: String,
+}
+
+/// GUID, stored in the on-disk (mixed-endian) format
+#[derive(PartialEq,Copy,Clone)]
+struct Guid([u8; 16]);
+
+impl storage::Mapper for Mapper
+{
+	fn name(&self) -> &str { "gpt" }
+
+	fn handles_pv(&self, pv: &dyn storage::PhysicalVolume) -> Result<usize,storage::IoError> {
+		match try!(find_table(pv))
+		{
+		Some(_) => Ok(2),
+		None => Ok(0),
+		}
+	}
+
+	fn enum_volumes(&self, pv: &dyn (::metadevs::storage::PhysicalVolume), new_volume_cb: &mut dyn FnMut(String, u64, u64, storage::PartitionInfo)) -> Result<(),storage::IoError> {
+		let (hdr, entries) = match try!(find_table(pv))
+			{
+			Some(v) => v,
+			None => return Err( storage::IoError::InvalidParameter ),
+			};
+		log_debug!("PV '{}' GPT disk {:?}, {} entries", pv.name(), hdr.disk_guid, hdr.num_entries);
+
+		let entries: Vec<Entry> = entries.chunks(hdr.entry_size as usize)
+			.filter_map(Entry::read)
+			.collect();
+		for (i, ent) in entries.iter().enumerate()
+		{
+			log_debug!("{:?}", ent);
+			if ent.first_lba < hdr.first_usable || ent.last_lba > hdr.last_usable || ent.first_lba > ent.last_lba {
+				log_warning!("GPT entry {} on {} is outside the usable area ({:#x}--{:#x} not within {:#x}--{:#x})",
+					i, pv.name(), ent.first_lba, ent.last_lba, hdr.first_usable, hdr.last_usable);
+				continue ;
+			}
+			// Use the label if it's unique on this disk, otherwise fall back to the GUID
+			let label = storage::sanitise_name(&ent.label);
+			let name = if label.len() > 0 && entries.iter().filter(|e| storage::sanitise_name(&e.label) == label).count() == 1 {
+					format!("{}-{}", pv.name(), label)
+				}
+				else {
+					format!("{}-{:?}", pv.name(), ent.unique_guid)
+				};
+			let info = storage::PartitionInfo {
+				bootable: ent.attributes & ATTR_LEGACY_BIOS_BOOTABLE != 0,
+				type_guid: Some(ent.type_guid.0),
+				..Default::default()
+				};
+			new_volume_cb( name, ent.first_lba, ent.last_lba - ent.first_lba + 1, info );
+		}
+
+		Ok( () )
+	}
+}
+
+/// Locate a valid GPT header and entry array, trying the backup if the primary is corrupted
+fn find_table(pv: &dyn storage::PhysicalVolume) -> Result<Option<(Header, Vec<u8>)>, storage::IoError>
+{
+	let capacity = match pv.capacity()
+		{
+		Some(v) => v,
+		None => return Ok(None),
+		};
+	if capacity < 3 {
+		return Ok(None);
+	}
+
+	// 1. Primary header (always at LBA 1, following the protective MBR)
+	let backup_lba = match try!(read_header(pv, 1))
+		{
+		Some(hdr) => {
+			match try!(read_entries(pv, &hdr))
+			{
+			Some(ents) => return Ok(Some( (hdr, ents) )),
+			None => {
+				log_warning!("GPT entry array CRC mismatch on {}, trying backup", pv.name());
+				hdr.alternate_lba
+				},
+			}
+			},
+		None => capacity - 1,
+		};
+
+	// 2. Backup header (pointed to by the primary if that was valid, otherwise the last LBA)
+	if backup_lba <= 1 || backup_lba >= capacity {
+		return Ok(None);
+	}
+	match try!(read_header(pv, backup_lba))
+	{
+	Some(hdr) => {
+		match try!(read_entries(pv, &hdr))
+		{
+		Some(ents) => {
+			log_notice!("Using backup GPT header on {} (LBA {:#x})", pv.name(), backup_lba);
+			Ok(Some( (hdr, ents) ))
+			},
+		None => {
+			log_warning!("Backup GPT entry array CRC mismatch on {}", pv.name());
+			Ok(None)
+			},
+		}
+		},
+	None => Ok(None),
+	}
+}
+
+/// Read (and fully read) a sequence of blocks from the physical volume
+fn read_blocks(pv: &dyn storage::PhysicalVolume, mut lba: u64, mut dst: &mut [u8]) -> Result<(), storage::IoError>
+{
+	let bs = pv.blocksize();
+	assert!(dst.len() % bs == 0);
+	while dst.len() > 0
+	{
+		let count = dst.len() / bs;
+		let real_count = try!(pv.read(0, lba, count, dst).wait());
+		if real_count == 0 {
+			return Err( storage::IoError::Unknown("Zero-length read") );
+		}
+		lba += real_count as u64;
+		dst = &mut {dst}[real_count * bs..];
+	}
+	Ok( () )
+}
+
+fn read_header(pv: &dyn storage::PhysicalVolume, lba: u64) -> Result<Option<Header>, storage::IoError>
+{
+	let mut block = vec![0u8; pv.blocksize()];
+	try!(read_blocks(pv, lba, &mut block));
+	Ok( Header::parse(&mut block, lba, pv.name()) )
+}
+
+impl Header
+{
+	/// Parse and validate a header read from `lba` (the block is modified when checking the CRC)
+	fn parse(block: &mut [u8], lba: u64, pv_name: &str) -> Option<Header>
+	{
+		if &block[0..8] != b"EFI PART" {
+			return None;
+		}
+		let revision = LittleEndian::read_u32(&block[8..]);
+		let header_size = LittleEndian::read_u32(&block[12..]) as usize;
+		if header_size < 92 || header_size > block.len() {
+			log_warning!("GPT header on {} LBA {} has bad size {}", pv_name, lba, header_size);
+			return None;
+		}
+
+		// Header CRC is calculated with the CRC field zeroed
+		let header_crc = LittleEndian::read_u32(&block[16..]);
+		LittleEndian::write_u32(&mut block[16..], 0);
+		let calc_crc = crc32(&block[..header_size]);
+		if calc_crc != header_crc {
+			log_warning!("GPT header on {} LBA {} CRC mismatch ({:#x} != {:#x})", pv_name, lba, calc_crc, header_crc);
+			return None;
+		}
+
+		let hdr = Header {
+			my_lba: LittleEndian::read_u64(&block[24..]),
+			alternate_lba: LittleEndian::read_u64(&block[32..]),
+			first_usable: LittleEndian::read_u64(&block[40..]),
+			last_usable: LittleEndian::read_u64(&block[48..]),
+			disk_guid: Guid::from_slice(&block[56..72]),
+			entries_lba: LittleEndian::read_u64(&block[72..]),
+			num_entries: LittleEndian::read_u32(&block[80..]),
+			entry_size: LittleEndian::read_u32(&block[84..]),
+			entries_crc: LittleEndian::read_u32(&block[88..]),
+			};
+		log_debug!("PV '{}' GPT rev {:#x} @{}: {:?}", pv_name, revision, lba, hdr);
+
+		if hdr.my_lba != lba {
+			log_warning!("GPT header on {} at LBA {} claims to be at LBA {}", pv_name, lba, hdr.my_lba);
+			return None;
+		}
+		if hdr.entry_size < 128 || hdr.entry_size % 8 != 0 {
+			log_warning!("GPT header on {} has bad entry size {}", pv_name, hdr.entry_size);
+			return None;
+		}
+		if hdr.num_entries as usize * hdr.entry_size as usize > MAX_ENTRY_ARRAY_SIZE {
+			log_warning!("GPT header on {} has an oversized entry array ({} * {})", pv_name, hdr.num_entries, hdr.entry_size);
+			return None;
+		}
+		Some(hdr)
+	}
+}
+
+/// Read the partition entry array, returning `None` if the CRC doesn't match
+fn read_entries(pv: &dyn storage::PhysicalVolume, hdr: &Header) -> Result<Option<Vec<u8>>, storage::IoError>
+{
+	let bs = pv.blocksize();
+	let len = hdr.num_entries as usize * hdr.entry_size as usize;
+	let mut data = vec![0u8; ::lib::num::round_up(len, bs)];
+	try!(read_blocks(pv, hdr.entries_lba, &mut data));
+	data.truncate(len);
+
+	if crc32(&data) != hdr.entries_crc {
+		Ok(None)
+	}
+	else {
+		Ok(Some(data))
+	}
+}
+
+impl Entry
+{
+	fn read(data: &[u8]) -> Option<Entry>
+	{
+		assert!(data.len() >= 128);
+		let type_guid = Guid::from_slice(&data[0..16]);
+		if type_guid == Guid([0; 16]) {
+			// Unused entry
+			return None;
+		}
+
+		let label_units = (0 .. 36)
+			.map(|i| LittleEndian::read_u16(&data[56 + i*2..]))
+			.take_while(|&c| c != 0);
+		let label = ::core::char::decode_utf16(label_units)
+			.map(|r| r.unwrap_or(::core::char::REPLACEMENT_CHARACTER))
+			.collect();
+
+		Some(Entry {
+			type_guid: type_guid,
+			unique_guid: Guid::from_slice(&data[16..32]),
+			first_lba: LittleEndian::read_u64(&data[32..]),
+			last_lba: LittleEndian::read_u64(&data[40..]),
+			attributes: LittleEndian::read_u64(&data[48..]),
+			label: label,
+			})
+	}
+}
+
+impl Guid
+{
+	fn from_slice(data: &[u8]) -> Guid {
+		let mut rv = [0; 16];
+		rv.copy_from_slice(&data[..16]);
+		Guid(rv)
+	}
+}
+impl ::core::fmt::Debug for Guid
+{
+	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result
+	{
+		let d = &self.0;
+		// First three fields are little-endian, the remainder is a byte sequence
+		write!(f, "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+			LittleEndian::read_u32(&d[0..]), LittleEndian::read_u16(&d[4..]), LittleEndian::read_u16(&d[6..]),
+			d[8], d[9],
+			d[10], d[11], d[12], d[13], d[14], d[15]
+			)
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use prelude::*;
+	use lib::byteorder::{ByteOrder,LittleEndian};
+	use lib::crc32::crc32;
+	use super::{Entry,Guid,Header};
+
+	/// EFI System Partition type GUID (C12A7328-F81F-11D2-BA4B-00A0C93EC93B), in on-disk order
+	const ESP_GUID: [u8; 16] = [0x28,0x73,0x2A,0xC1, 0x1F,0xF8, 0xD2,0x11, 0xBA,0x4B, 0x00,0xA0,0xC9,0x3E,0xC9,0x3B];
+
+	/// Build a primary header block (LBA 1) for a 1MiB disk
+	fn make_header() -> Vec<u8>
+	{
+		let mut b = vec![0u8; 512];
+		b[0..8].copy_from_slice(b"EFI PART");
+		LittleEndian::write_u32(&mut b[8..], 0x0001_0000);
+		LittleEndian::write_u32(&mut b[12..], 92);
+		LittleEndian::write_u64(&mut b[24..], 1);
+		LittleEndian::write_u64(&mut b[32..], 2047);
+		LittleEndian::write_u64(&mut b[40..], 34);
+		LittleEndian::write_u64(&mut b[48..], 2014);
+		b[56..72].copy_from_slice(&ESP_GUID);
+		LittleEndian::write_u64(&mut b[72..], 2);
+		LittleEndian::write_u32(&mut b[80..], 128);
+		LittleEndian::write_u32(&mut b[84..], 128);
+		LittleEndian::write_u32(&mut b[88..], 0x1234_5678);
+		set_crc(&mut b);
+		b
+	}
+	fn set_crc(b: &mut [u8])
+	{
+		LittleEndian::write_u32(&mut b[16..], 0);
+		let crc = crc32(&b[..92]);
+		LittleEndian::write_u32(&mut b[16..], crc);
+	}
+
+	#[test]
+	fn header_valid()
+	{
+		let hdr = Header::parse(&mut make_header(), 1, "test").expect("parse");
+		assert_eq!(hdr.my_lba, 1);
+		assert_eq!(hdr.alternate_lba, 2047);
+		assert_eq!(hdr.first_usable, 34);
+		assert_eq!(hdr.last_usable, 2014);
+		assert!(hdr.disk_guid == Guid(ESP_GUID));
+		assert_eq!(hdr.entries_lba, 2);
+		assert_eq!(hdr.num_entries, 128);
+		assert_eq!(hdr.entry_size, 128);
+		assert_eq!(hdr.entries_crc, 0x1234_5678);
+	}
+
+	#[test]
+	fn header_invalid()
+	{
+		// Bad signature
+		let mut b = make_header();
+		b[0] = b'X';
+		assert!(Header::parse(&mut b, 1, "test").is_none());
+		// Corrupted (CRC mismatch)
+		let mut b = make_header();
+		b[40] ^= 1;
+		assert!(Header::parse(&mut b, 1, "test").is_none());
+		// Read from a different LBA than it claims
+		assert!(Header::parse(&mut make_header(), 2047, "test").is_none());
+		// Entry size too small
+		let mut b = make_header();
+		LittleEndian::write_u32(&mut b[84..], 64);
+		set_crc(&mut b);
+		assert!(Header::parse(&mut b, 1, "test").is_none());
+		// Header larger than the block
+		let mut b = make_header();
+		LittleEndian::write_u32(&mut b[12..], 1024);
+		assert!(Header::parse(&mut b, 1, "test").is_none());
+	}
+
+	#[test]
+	fn entry_parse()
+	{
+		let mut e = vec![0u8; 128];
+		assert!(Entry::read(&e).is_none(), "Zero type GUID is an unused entry");
+
+		e[0..16].copy_from_slice(&ESP_GUID);
+		LittleEndian::write_u64(&mut e[32..], 2048);
+		LittleEndian::write_u64(&mut e[40..], 4095);
+		LittleEndian::write_u64(&mut e[48..], 1 << 2);
+		for (i, c) in "EFI system".encode_utf16().enumerate() {
+			LittleEndian::write_u16(&mut e[56 + i*2..], c);
+		}
+		let ent = Entry::read(&e).expect("read");
+		assert_eq!(ent.first_lba, 2048);
+		assert_eq!(ent.last_lba, 4095);
+		assert_eq!(ent.attributes, 1 << 2);
+		assert_eq!(ent.label, "EFI system");
+	}
+
+	#[test]
+	fn guid_format()
+	{
+		assert_eq!(format!("{:?}", Guid(ESP_GUID)), "c12a7328-f81f-11d2-ba4b-00a0c93ec93b");
+	}
+}
@@ -18,6 +18,9 @@ fn init()
 
 struct Mapper;
 
+/// Maximum number of logical partitions followed in an extended partition chain
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
 #[derive(Debug)]
 struct Entry
 {
@@ -32,14 +35,12 @@ impl storage::Mapper for Mapper
 	fn name(&self) -> &str { "mbr" }
 
 	fn handles_pv(&self, pv: &dyn storage::PhysicalVolume) -> Result<usize,storage::IoError> {
-		if pv.blocksize() != 512 {
-			log_log!("Support non 512 byte sectors in MBR mapper (got {} for {})", pv.blocksize(), pv.name());
+		if pv.blocksize() < 512 {
+			log_log!("MBR mapper requires at least 512 byte sectors (got {} for {})", pv.blocksize(), pv.name());
 			return Ok(0);
 		}
 		
-		// SAFE: Plain old data
-		let mut block: [u8; 512] = unsafe { ::core::mem::zeroed() };
-		try!(pv.read(0, 0, 1, &mut block).wait());
+		let block = try!(read_sector(pv, 0));
 		
 		log_debug!("PV '{}' boot sig {:02x} {:02x}", pv.name(), block[0x1FE], block[0x1FF]);
 		if block[0x1FE] == 0x55 && block[0x1FE+1] == 0xAA {
@@ -50,14 +51,12 @@ impl storage::Mapper for Mapper
 		}
 	}
 	
-	fn enum_volumes(&self, pv: &dyn (::metadevs::storage::PhysicalVolume), new_volume_cb: &mut dyn FnMut(String, u64, u64)) -> Result<(),storage::IoError> {
-		if !(pv.blocksize() == 512) {
+	fn enum_volumes(&self, pv: &dyn (::metadevs::storage::PhysicalVolume), new_volume_cb: &mut dyn FnMut(String, u64, u64, storage::PartitionInfo)) -> Result<(),storage::IoError> {
+		if pv.blocksize() < 512 {
 			return Err( storage::IoError::InvalidParameter );
 		}
 		
-		// SAFE: Plain old data
-		let mut block: [u8; 512] = unsafe { ::core::mem::zeroed() };
-		try!( pv.read(0, 0, 1, &mut block).wait() );
+		let block = try!(read_sector(pv, 0));
 		if !(block[510] == 0x55 && block[511] == 0xAA) {
 			return Err( storage::IoError::InvalidParameter );
 		}
@@ -65,17 +64,22 @@ impl storage::Mapper for Mapper
 		// the "unique ID" (according to the osdev.org wiki) might just be the tail of the MBR code
 		//let uid = &block[0x1b4 .. 0x1be];
 		
+		// Logical partitions are numbered after the four primary entries
+		let mut next_logical = 4;
 		for i in 0 .. 4 {
 			let ofs = 0x1BE + i*16;
 			
 			if let Some(info) = Entry::read( &block[ofs .. ofs + 16] )
 			{
 				log_debug!("{:?}", info);
-				if info.system_id == 0x5 || info.system_id == 0xF {
-					todo!("Extended partition");
+				if info.is_extended() {
+					try!(enum_logical(pv, info.lba_start, &mut next_logical, new_volume_cb));
+				}
+				else if info.system_id == 0xEE {
+					// GPT protective partition, the GPT mapper handles the real layout
 				}
 				else {
-					new_volume_cb( format!("{}p{}", pv.name(), i), info.lba_start, info.lba_count );
+					new_volume_cb( format!("{}p{}", pv.name(), i), info.lba_start, info.lba_count, info.partition_info() );
 				}
 			}
 		}
@@ -84,6 +88,55 @@ impl storage::Mapper for Mapper
 	}
 }
 
+/// Read a single sector (only the first 512 bytes are returned, that's all the MBR/EBR uses)
+fn read_sector(pv: &dyn storage::PhysicalVolume, lba: u64) -> Result<[u8; 512], storage::IoError>
+{
+	let mut block = vec![0u8; pv.blocksize()];
+	try!(pv.read(0, lba, 1, &mut block).wait());
+	
+	let mut rv = [0u8; 512];
+	rv.copy_from_slice(&block[..512]);
+	Ok(rv)
+}
+
+/// Walk the chain of extended boot records (EBRs) starting at `ext_base`
+fn enum_logical(pv: &dyn storage::PhysicalVolume, ext_base: u64, next_idx: &mut usize, new_volume_cb: &mut dyn FnMut(String, u64, u64, storage::PartitionInfo)) -> Result<(),storage::IoError>
+{
+	let mut ebr_lba = ext_base;
+	for _ in 0 .. MAX_LOGICAL_PARTITIONS
+	{
+		let block = try!(read_sector(pv, ebr_lba));
+		if !(block[510] == 0x55 && block[511] == 0xAA) {
+			log_warning!("EBR at {:#x} on {} has a bad signature", ebr_lba, pv.name());
+			return Ok( () );
+		}
+		
+		// 1. The logical partition itself (relative to this EBR)
+		if let Some(info) = Entry::read( &block[0x1BE .. 0x1BE + 16] )
+		{
+			log_debug!("EBR@{:#x} {:?}", ebr_lba, info);
+			new_volume_cb( format!("{}p{}", pv.name(), *next_idx), ebr_lba + info.lba_start, info.lba_count, info.partition_info() );
+			*next_idx += 1;
+		}
+		
+		// 2. Link to the next EBR (relative to the start of the extended partition)
+		match Entry::read( &block[0x1CE .. 0x1CE + 16] )
+		{
+		Some(ref next) if next.is_extended() => {
+			let next_lba = ext_base + next.lba_start;
+			if next_lba <= ebr_lba {
+				log_warning!("EBR chain on {} goes backwards ({:#x} -> {:#x}), stopping", pv.name(), ebr_lba, next_lba);
+				return Ok( () );
+			}
+			ebr_lba = next_lba;
+			},
+		_ => return Ok( () ),
+		}
+	}
+	log_warning!("Too many logical partitions on {}, ignoring the remainder", pv.name());
+	Ok( () )
+}
+
 impl Entry
 {
 	fn read(data: &[u8]) -> Option<Entry>
@@ -114,5 +167,21 @@ impl Entry
 			lba_count: len,
 			})
 	}
+	
+	fn is_extended(&self) -> bool {
+		match self.system_id
+		{
+		0x05 | 0x0F | 0x85 => true,
+		_ => false,
+		}
+	}
+	
+	fn partition_info(&self) -> storage::PartitionInfo {
+		storage::PartitionInfo {
+			bootable: self.bootable,
+			system_id: Some(self.system_id),
+			..Default::default()
+			}
+	}
 }
 
@@ -0,0 +1,289 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/hw/mapper_md.rs
+/// Software RAID mapper (Linux MD version 1.x superblocks)
+///
+/// Recognises whole-disk members of linear, RAID0, and RAID1 arrays. The array is assembled by the
+/// storage subsystem once all members have been seen (or, for RAID1, after a delay with the members present).
+///
+/// When a RAID1 member fails, the other members' superblocks are updated (marking it faulty and bumping the event
+/// count), so the failed member isn't assembled again until it has been resynced.
+use prelude::*;
+use lib::byteorder::{ByteOrder,LittleEndian};
+use metadevs::storage;
+
+module_define!{MapperMD, [Storage], init}
+
+static S_MAPPER: Mapper = Mapper;
+
+const MD_SB_MAGIC: u32 = 0xa92b4efc;
+/// Size of the region read when looking for a superblock (covers `max_dev` up to 1920)
+const SB_READ_SIZE: usize = 4096;
+
+/// `dev_roles` value for a spare device
+const ROLE_SPARE: u16 = 0xFFFF;
+/// `dev_roles` value for a faulty device
+const ROLE_FAULTY: u16 = 0xFFFE;
+
+fn init()
+{
+	storage::register_mapper(&S_MAPPER);
+}
+
+struct Mapper;
+
+/// Parsed fields from a version 1 superblock
+#[derive(Debug)]
+struct Superblock
+{
+	set_uuid: [u8; 16],
+	set_name: String,
+	level: i32,
+	/// Used size of each member (512 byte sectors)
+	size: u64,
+	/// Chunk size (512 byte sectors)
+	chunk_size: u32,
+	raid_disks: u32,
+	/// Start of data on this device (512 byte sectors)
+	data_offset: u64,
+	/// Sectors available for data on this device
+	data_size: u64,
+	/// This device's position in the array (or a ROLE_ value)
+	role: u16,
+	/// Number of superblock updates
+	events: u64,
+	/// Location of the superblock (512 byte sectors)
+	sector: u64,
+}
+
+impl storage::Mapper for Mapper
+{
+	fn name(&self) -> &str { "md" }
+
+	fn handles_pv(&self, pv: &dyn storage::PhysicalVolume) -> Result<usize,storage::IoError> {
+		match try!(find_superblock(pv))
+		{
+		Some(ref sb) if sb.role < ROLE_FAULTY => Ok(3),
+		Some(sb) => {
+			log_notice!("PV '{}' is an inactive member (role {:#x}) of MD array '{}'", pv.name(), sb.role, sb.set_name);
+			Ok(0)
+			},
+		None => Ok(0),
+		}
+	}
+
+	fn enum_volumes(&self, _pv: &dyn storage::PhysicalVolume, _new_volume_cb: &mut dyn FnMut(String, u64, u64, storage::PartitionInfo)) -> Result<(),storage::IoError> {
+		// Volumes are only created once the array is assembled
+		Ok( () )
+	}
+
+	fn enum_array_members(&self, pv: &dyn storage::PhysicalVolume, new_member_cb: &mut dyn FnMut(storage::ArrayMember)) -> Result<(),storage::IoError> {
+		let sb = match try!(find_superblock(pv))
+			{
+			Some(v) => v,
+			None => return Err( storage::IoError::InvalidParameter ),
+			};
+		let bs = pv.blocksize() as u64;
+		let to_blocks = |sectors: u64| -> Option<u64> {
+			if (sectors * 512) % bs != 0 { None } else { Some(sectors * 512 / bs) }
+			};
+
+		let layout = match sb.level
+			{
+			-1 => storage::ArrayLayout::Linear,
+			0 => match to_blocks(sb.chunk_size as u64)
+				{
+				Some(v) if v > 0 => storage::ArrayLayout::Striped(v as usize),
+				_ => {
+					log_warning!("MD array '{}' chunk size {} isn't a multiple of the block size ({})", sb.set_name, sb.chunk_size, bs);
+					return Ok( () );
+					},
+				},
+			1 => storage::ArrayLayout::Mirrored,
+			_ => {
+				log_warning!("MD array '{}' uses unsupported RAID level {}", sb.set_name, sb.level);
+				return Ok( () );
+				},
+			};
+		let data_sectors = if sb.level == 1 && sb.size != 0 { sb.size } else { sb.data_size };
+		let (first_block, block_count) = match (to_blocks(sb.data_offset), to_blocks(data_sectors))
+			{
+			(Some(a), Some(b)) => (a, b),
+			_ => {
+				log_warning!("MD array '{}' data area isn't aligned to the block size ({})", sb.set_name, bs);
+				return Ok( () );
+				},
+			};
+
+		new_member_cb(storage::ArrayMember {
+			array_id: sb.set_uuid,
+			name: format!("md-{}", storage::sanitise_name(&sb.set_name)),
+			layout: layout,
+			member_count: sb.raid_disks as usize,
+			member_index: sb.role as usize,
+			first_block: first_block,
+			block_count: block_count,
+			event_count: sb.events,
+			});
+		Ok( () )
+	}
+
+	fn record_member_failed(&self, pv: &dyn storage::PhysicalVolume, member_index: usize) -> Result<(),storage::IoError> {
+		let sb = match try!(find_superblock(pv))
+			{
+			Some(v) => v,
+			None => return Err( storage::IoError::InvalidParameter ),
+			};
+		let lba = sb.sector * 512 / pv.blocksize() as u64;
+		let mut buf = vec![0u8; SB_READ_SIZE];
+		try!(read_region(pv, lba, &mut buf));
+
+		// Mark the failed member's device(s) as faulty, and bump the event count so it's seen as out of date
+		let max_dev = LittleEndian::read_u32(&buf[220..]) as usize;
+		for i in 0 .. max_dev
+		{
+			if LittleEndian::read_u16(&buf[256 + i*2..]) as usize == member_index {
+				LittleEndian::write_u16(&mut buf[256 + i*2..], ROLE_FAULTY);
+			}
+		}
+		LittleEndian::write_u64(&mut buf[200..], sb.events + 1);
+		let csum = checksum(&buf[..256 + max_dev * 2]);
+		LittleEndian::write_u32(&mut buf[216..], csum);
+
+		log_notice!("PV '{}': Recording failure of member {} of MD array '{}'", pv.name(), member_index, sb.set_name);
+		write_region(pv, lba, &buf)
+	}
+}
+
+/// Check the three possible superblock locations (v1.0 at the end, v1.1 at the start, v1.2 at 4KiB)
+fn find_superblock(pv: &dyn storage::PhysicalVolume) -> Result<Option<Superblock>, storage::IoError>
+{
+	let bs = pv.blocksize();
+	if bs > SB_READ_SIZE || SB_READ_SIZE % bs != 0 {
+		return Ok(None);
+	}
+	let capacity_sectors = match pv.capacity()
+		{
+		Some(v) => v * (bs / 512) as u64,
+		None => return Ok(None),
+		};
+	if capacity_sectors < 16 + 8 {
+		return Ok(None);
+	}
+
+	let mut buf = vec![0u8; SB_READ_SIZE];
+	for &sector in &[8, 0, (capacity_sectors - 16) & !7]
+	{
+		try!(read_region(pv, sector * 512 / bs as u64, &mut buf));
+		if let Some(sb) = Superblock::parse(&buf, sector) {
+			log_debug!("PV '{}' MD superblock at sector {}: {:?}", pv.name(), sector, sb);
+			return Ok(Some(sb));
+		}
+	}
+	Ok(None)
+}
+
+/// Read `buf.len()` bytes starting at block `lba`
+fn read_region(pv: &dyn storage::PhysicalVolume, lba: u64, buf: &mut [u8]) -> Result<(), storage::IoError>
+{
+	let bs = pv.blocksize();
+	let mut ofs = 0;
+	while ofs < buf.len()
+	{
+		let count = (buf.len() - ofs) / bs;
+		let n = try!(pv.read(0, lba + (ofs / bs) as u64, count, &mut buf[ofs..]).wait());
+		if n == 0 {
+			return Err( storage::IoError::Unknown("Zero-length read") );
+		}
+		ofs += n * bs;
+	}
+	Ok( () )
+}
+/// Write `buf` starting at block `lba`
+fn write_region(pv: &dyn storage::PhysicalVolume, lba: u64, buf: &[u8]) -> Result<(), storage::IoError>
+{
+	let bs = pv.blocksize();
+	let mut ofs = 0;
+	while ofs < buf.len()
+	{
+		let count = (buf.len() - ofs) / bs;
+		let n = try!(pv.write(0, lba + (ofs / bs) as u64, count, &buf[ofs..]).wait());
+		if n == 0 {
+			return Err( storage::IoError::Unknown("Zero-length write") );
+		}
+		ofs += n * bs;
+	}
+	Ok( () )
+}
+
+impl Superblock
+{
+	fn parse(data: &[u8], sector: u64) -> Option<Superblock>
+	{
+		if LittleEndian::read_u32(&data[0..]) != MD_SB_MAGIC {
+			return None;
+		}
+		if LittleEndian::read_u32(&data[4..]) != 1 {
+			log_notice!("MD superblock version {} not supported", LittleEndian::read_u32(&data[4..]));
+			return None;
+		}
+		if LittleEndian::read_u64(&data[144..]) != sector {
+			// Superblock not at its recorded location (e.g. a stale copy)
+			return None;
+		}
+		let max_dev = LittleEndian::read_u32(&data[220..]) as usize;
+		let sb_len = 256 + max_dev * 2;
+		if sb_len > data.len() {
+			log_warning!("MD superblock max_dev {} too large", max_dev);
+			return None;
+		}
+		let csum = checksum(&data[..sb_len]);
+		if csum != LittleEndian::read_u32(&data[216..]) {
+			log_warning!("MD superblock checksum mismatch ({:#x} != {:#x})", csum, LittleEndian::read_u32(&data[216..]));
+			return None;
+		}
+		let feature_map = LittleEndian::read_u32(&data[8..]);
+		if feature_map & !1 != 0 {
+			// Only the write-intent bitmap (which can be ignored when reading) is supported
+			log_notice!("MD superblock has unsupported features {:#x}", feature_map);
+			return None;
+		}
+
+		let dev_number = LittleEndian::read_u32(&data[160..]) as usize;
+		let role = if dev_number < max_dev { LittleEndian::read_u16(&data[256 + dev_number*2..]) } else { ROLE_SPARE };
+
+		let mut set_uuid = [0; 16];
+		set_uuid.copy_from_slice(&data[16..32]);
+		let name_bytes = &data[32..64];
+		let name_len = name_bytes.iter().position(|&b| b == 0).unwrap_or(name_bytes.len());
+		Some(Superblock {
+			set_uuid: set_uuid,
+			set_name: name_bytes[..name_len].iter().map(|&b| b as char).collect(),
+			level: LittleEndian::read_i32(&data[72..]),
+			size: LittleEndian::read_u64(&data[80..]),
+			chunk_size: LittleEndian::read_u32(&data[88..]),
+			raid_disks: LittleEndian::read_u32(&data[92..]),
+			data_offset: LittleEndian::read_u64(&data[128..]),
+			data_size: LittleEndian::read_u64(&data[136..]),
+			role: role,
+			events: LittleEndian::read_u64(&data[200..]),
+			sector: sector,
+			})
+	}
+}
+
+/// Superblock checksum: 32-bit words summed into a 64-bit value (with the checksum field zeroed), then folded
+fn checksum(data: &[u8]) -> u32
+{
+	let mut sum: u64 = 0;
+	for (i, w) in data.chunks(4).enumerate()
+	{
+		// Skip the checksum field
+		if i == 216 / 4 {
+			continue ;
+		}
+		sum += if w.len() == 4 { LittleEndian::read_u32(w) as u64 } else { LittleEndian::read_u16(w) as u64 };
+	}
+	((sum & 0xFFFF_FFFF) + (sum >> 32)) as u32
+}
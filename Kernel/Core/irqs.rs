@@ -50,7 +50,7 @@ pub fn init() {
 	unsafe {
 		// SAFE: The SleepObject here is static, so is never invalidated
 		S_IRQ_WORKER_SIGNAL.prep(|| /*unsafe*/ { ::threads::SleepObject::new("IRQ Worker") });
-		S_IRQ_WORKER.prep(|| ::threads::WorkerThread::new("IRQ Worker", irq_worker));
+		S_IRQ_WORKER.prep(|| ::threads::WorkerThread::new_with_priority("IRQ Worker", ::threads::PRIORITY_HIGH, irq_worker));
 	}
 }
 
@@ -147,6 +147,8 @@ impl IRQBinding
 		//log_trace!("handle() num={}", self.arch_handle.num());
 		// The CPU owns the lock, so we don't care about ordering
 		self.has_fired.store(true, ::core::sync::atomic::Ordering::Relaxed);
+		// Interrupt timing is unpredictable, so feed it into the entropy pool
+		::entropy::add_sample( ::arch::entropy_sample() ^ self as *const _ as usize as u64 );
 		
 		S_IRQ_WORKER_SIGNAL.signal();
 	}
@@ -74,6 +74,24 @@ impl<K: Ord, V> VecMap<K,V>
 			},
 		}
 	}
+	/// Number of items in the map
+	pub fn len(&self) -> usize {
+		self.ents.len()
+	}
+	/// Remove all items that don't match the predicate
+	pub fn retain<F: FnMut(&K, &mut V)->bool>(&mut self, mut f: F) {
+		let mut i = 0;
+		while i < self.ents.len()
+		{
+			let keep = { let e = &mut self.ents[i]; f(&e.0, &mut e.1) };
+			if keep {
+				i += 1;
+			}
+			else {
+				self.ents.remove(i);
+			}
+		}
+	}
 	/// Remove an item from the map
 	pub fn remove(&mut self, k: &K) -> Option<V> {
 		match self.ents.binary_search_by(|e| e.0.cmp(k))
@@ -48,6 +48,12 @@ impl String
 	{
 		self.0.extend_from_slice(s.as_bytes());
 	}
+	/// Append a single character to the string
+	pub fn push(&mut self, c: char)
+	{
+		let mut buf = [0; 4];
+		self.push_str(c.encode_utf8(&mut buf));
+	}
 	
 	/// Return the string as a &str
 	fn as_slice(&self) -> &str {
@@ -62,6 +68,17 @@ impl Default for String {
 	fn default() -> String { String::new() }
 }
 
+impl ::core::iter::FromIterator<char> for String
+{
+	fn from_iter<T: IntoIterator<Item=char>>(iterator: T) -> String {
+		let mut rv = String::new();
+		for c in iterator {
+			rv.push(c);
+		}
+		rv
+	}
+}
+
 impl fmt::Write for String
 {
 	fn write_str(&mut self, s: &str) -> ::core::fmt::Result
@@ -19,6 +19,7 @@
 //#![feature(integer_atomics)]	// AtomicU8
 #![feature(dropck_eyepatch)]
 #![feature(panic_info_message)]
+#![feature(no_more_cas)]	// AtomicUsize::fetch_update
 
 #![cfg_attr(not(feature="test"),no_std)]
 
@@ -80,6 +81,10 @@ pub mod memory;
 pub mod threads;
 /// Timekeeping (timers and wall time)
 pub mod time;
+/// Sampling profiler
+pub mod profiler;
+/// Entropy pool for non-cryptographic random numbers (e.g. ASLR)
+pub mod entropy;
 
 /// Module management (loading and initialisation of kernel modules)
 pub mod modules;
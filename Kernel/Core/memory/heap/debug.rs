@@ -0,0 +1,388 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/memory/heap/debug.rs
+//! Debug heap wrappers (enabled by the `heap_debug` feature)
+//!
+//! Each allocation is surrounded by redzones and poisoned on allocate and free. Freed blocks are held in a
+//! quarantine ring before being returned to the heap, so writes after free are detected when they leave it.
+//! Allocations are also tagged with the module that made them (found by walking the call stack), so usage can
+//! be reported per-module in `/proc/heap`.
+//!
+//! Layout: `[padding] [DebugHead] [data] [redzone]`, the padding keeps the data aligned when the requested
+//! alignment is larger than the header (the header is always immediately before the data).
+use core::fmt;
+use super::S_GLOBAL_HEAP;
+
+pub const ENABLED: bool = cfg!(feature="heap_debug");
+
+const MAGIC_USED: u32 = 0xA110CA7E;
+const MAGIC_FREED: u32 = 0xF4EED0FF;
+
+const REDZONE_SIZE: usize = 16;
+const REDZONE_BYTE: u8 = 0xFD;
+const POISON_ALLOC: u8 = 0xA5;
+const POISON_FREE: u8 = 0xDD;
+
+const QUARANTINE_SIZE: usize = 64;
+const MAX_TAGS: usize = 64;
+const SITE_CACHE_SIZE: usize = 256;
+/// Number of stack frames inspected when looking for the allocating module
+const MAX_FRAMES: usize = 8;
+
+#[repr(C)]
+struct DebugHead
+{
+	magic: u32,
+	tag: u16,
+	_pad: u16,
+	/// Offset from the start of the heap block to the data (header size plus alignment padding)
+	prefix: usize,
+	size: usize,
+	caller: usize,
+	redzone: [u8; REDZONE_SIZE],
+}
+const HEAD_SIZE: usize = ::core::mem::size_of::<DebugHead>();
+
+#[derive(Copy,Clone)]
+struct TagStats
+{
+	crate_name: &'static str,
+	module: &'static str,
+	in_use: usize,
+	peak: usize,
+	allocs: usize,
+	/// Bytes currently used by the debug headers and redzones
+	slack: usize,
+}
+impl TagStats
+{
+	const fn new(crate_name: &'static str, module: &'static str) -> TagStats {
+		TagStats { crate_name: crate_name, module: module, in_use: 0, peak: 0, allocs: 0, slack: 0 }
+	}
+}
+
+#[derive(Copy,Clone)]
+enum Site
+{
+	Empty,
+	/// Return address belongs to the allocator, keep walking
+	Skip(usize),
+	Tag(usize, u16),
+}
+
+struct State
+{
+	quarantine: [(usize, usize); QUARANTINE_SIZE],
+	quarantine_next: usize,
+	tags: [TagStats; MAX_TAGS],
+	n_tags: usize,
+	sites: [Site; SITE_CACHE_SIZE],
+}
+
+// NOTE: Lock ordering is this lock, then the heap lock. Nothing done with this held may allocate.
+static S_STATE: ::sync::Mutex<State> = ::sync::Mutex::new(State {
+	quarantine: [(0,0); QUARANTINE_SIZE],
+	quarantine_next: 0,
+	tags: [TagStats::new("?", ""); MAX_TAGS],
+	n_tags: 1,
+	sites: [Site::Empty; SITE_CACHE_SIZE],
+	});
+
+fn total_size(prefix: usize, size: usize) -> usize {
+	prefix + size + REDZONE_SIZE
+}
+/// Start of the heap block containing an allocation
+unsafe fn block_start(head: *const DebugHead) -> *mut () {
+	(head.offset(1) as *mut u8).offset(-((*head).prefix as isize)) as *mut ()
+}
+
+pub unsafe fn allocate(size: usize, align: usize) -> Option<*mut ()>
+{
+	let mut frames = [0; MAX_FRAMES];
+	let n_frames = ::arch::get_return_addresses(&mut frames);
+
+	// Round the header up so the data keeps the requested alignment
+	let prefix = ::lib::num::round_up(HEAD_SIZE, ::core::cmp::max(align, 1));
+
+	let mut lh = S_STATE.lock();
+	let rv = S_GLOBAL_HEAP.lock().allocate(total_size(prefix, size), align);
+	let head = match rv
+		{
+		Ok(v) => (v as *mut u8).offset((prefix - HEAD_SIZE) as isize) as *mut DebugHead,
+		Err(e) => {
+			// NOTE: Logging can allocate, so the state lock is released first
+			drop(lh);
+			log_error!("Unable to allocate: {:?}", e);
+			return None;
+			},
+		};
+	let (tag, caller) = lh.get_tag(&frames[..n_frames]);
+
+	::core::ptr::write(head, DebugHead {
+		magic: MAGIC_USED,
+		tag: tag,
+		_pad: 0,
+		prefix: prefix,
+		size: size,
+		caller: caller,
+		redzone: [REDZONE_BYTE; REDZONE_SIZE],
+		});
+	let data = head.offset(1) as *mut u8;
+	::core::ptr::write_bytes(data, POISON_ALLOC, size);
+	::core::ptr::write_bytes(data.offset(size as isize), REDZONE_BYTE, REDZONE_SIZE);
+
+	let t = &mut lh.tags[tag as usize];
+	t.in_use += size;
+	t.allocs += 1;
+	t.slack += prefix + REDZONE_SIZE;
+	if t.in_use > t.peak {
+		t.peak = t.in_use;
+	}
+
+	Some(data as *mut ())
+}
+
+pub unsafe fn expand(ptr: *mut (), newsize: usize) -> bool
+{
+	let mut lh = S_STATE.lock();
+	let head = get_head(ptr, "expand");
+	let oldsize = (*head).size;
+	if ! S_GLOBAL_HEAP.lock().expand_alloc(block_start(head), total_size((*head).prefix, newsize)) {
+		return false;
+	}
+	let data = ptr as *mut u8;
+	::core::ptr::write_bytes(data.offset(oldsize as isize), POISON_ALLOC, newsize - oldsize);
+	::core::ptr::write_bytes(data.offset(newsize as isize), REDZONE_BYTE, REDZONE_SIZE);
+	(*head).size = newsize;
+
+	let t = &mut lh.tags[(*head).tag as usize];
+	t.in_use += newsize - oldsize;
+	if t.in_use > t.peak {
+		t.peak = t.in_use;
+	}
+	true
+}
+
+pub unsafe fn shrink(ptr: *mut (), newsize: usize)
+{
+	let mut lh = S_STATE.lock();
+	let head = get_head(ptr, "shrink");
+	let oldsize = (*head).size;
+	assert!(newsize <= oldsize, "Calling shrink with a larger size ({} > {})", newsize, oldsize);
+	S_GLOBAL_HEAP.lock().shrink_alloc(block_start(head), total_size((*head).prefix, newsize));
+	::core::ptr::write_bytes((ptr as *mut u8).offset(newsize as isize), REDZONE_BYTE, REDZONE_SIZE);
+	(*head).size = newsize;
+
+	lh.tags[(*head).tag as usize].in_use -= oldsize - newsize;
+}
+
+/// Release an allocation (`size` is zero if unknown)
+pub unsafe fn deallocate(ptr: *mut (), size: usize)
+{
+	let mut lh = S_STATE.lock();
+	let head = get_head(ptr, "deallocate");
+	let h = &mut *head;
+	assert!(size == 0 || size == h.size, "Heap free of {:p} with size {} (allocated {}) - tag {}, caller {:#x}",
+		ptr, size, h.size, lh.tags[h.tag as usize], h.caller);
+	check_redzones(&lh, head);
+
+	{
+		let t = &mut lh.tags[h.tag as usize];
+		t.in_use -= h.size;
+		t.slack -= h.prefix + REDZONE_SIZE;
+	}
+	h.magic = MAGIC_FREED;
+	::core::ptr::write_bytes(ptr as *mut u8, POISON_FREE, h.size);
+
+	// Place the block in quarantine, releasing the block it displaces
+	let slot = lh.quarantine_next;
+	lh.quarantine_next = (slot + 1) % QUARANTINE_SIZE;
+	let (old_ptr, old_size) = ::core::mem::replace(&mut lh.quarantine[slot], (head as usize, h.size));
+	if old_ptr != 0
+	{
+		let old_head = old_ptr as *mut DebugHead;
+		let data = ::core::slice::from_raw_parts(old_head.offset(1) as *const u8, old_size);
+		if let Some(ofs) = data.iter().position(|&b| b != POISON_FREE) {
+			panic!("Heap use-after-free: {:p}+{:#x} written after free (size {}) - tag {}, caller {:#x}",
+				data.as_ptr(), ofs, old_size, lh.tags[(*old_head).tag as usize], (*old_head).caller);
+		}
+		check_redzones(&lh, old_head);
+		S_GLOBAL_HEAP.lock().deallocate(block_start(old_head), total_size((*old_head).prefix, old_size), 0);
+	}
+}
+
+/// Obtain and validate the header for an allocation
+unsafe fn get_head(ptr: *mut (), op: &str) -> *mut DebugHead
+{
+	let head = (ptr as *mut DebugHead).offset(-1);
+	match (*head).magic
+	{
+	MAGIC_USED => head,
+	MAGIC_FREED => panic!("Heap {} of freed pointer {:p} (double free?) - caller {:#x}", op, ptr, (*head).caller),
+	v => panic!("Heap {} of invalid pointer {:p} (magic {:#x})", op, ptr, v),
+	}
+}
+
+unsafe fn check_redzones(state: &State, head: *const DebugHead)
+{
+	let h = &*head;
+	let back = ::core::slice::from_raw_parts( (head.offset(1) as *const u8).offset(h.size as isize), REDZONE_SIZE );
+	if h.redzone.iter().any(|&b| b != REDZONE_BYTE) {
+		panic!("Heap underflow: Front redzone of {:p} corrupted ({:?}) - tag {}, caller {:#x}",
+			head.offset(1), h.redzone, state.tags[h.tag as usize], h.caller);
+	}
+	if back.iter().any(|&b| b != REDZONE_BYTE) {
+		panic!("Heap overflow: Back redzone of {:p} (size {}) corrupted ({:?}) - tag {}, caller {:#x}",
+			head.offset(1), h.size, back, state.tags[h.tag as usize], h.caller);
+	}
+}
+
+impl State
+{
+	/// Locate the tag for an allocation, returning the tag and the first non-allocator return address
+	fn get_tag(&mut self, frames: &[usize]) -> (u16, usize)
+	{
+		for &addr in frames
+		{
+			let idx = (addr >> 2) % SITE_CACHE_SIZE;
+			let tag = match self.sites[idx]
+				{
+				Site::Skip(a) if a == addr => continue,
+				Site::Tag(a, tag) if a == addr => tag,
+				_ => {
+					let site = match get_module(addr)
+						{
+						None => Site::Tag(addr, 0),
+						Some(("alloc", _)) | Some(("core", _)) => Site::Skip(addr),
+						Some(("kernel", m)) if m == "lib" || m == "memory::heap" => Site::Skip(addr),
+						Some((c, m)) => Site::Tag(addr, self.find_tag(c, m)),
+						};
+					self.sites[idx] = site;
+					match site
+					{
+					Site::Tag(_, tag) => tag,
+					_ => continue,
+					}
+					},
+				};
+			return (tag, addr);
+		}
+		(0, frames.last().cloned().unwrap_or(0))
+	}
+
+	fn find_tag(&mut self, crate_name: &'static str, module: &'static str) -> u16
+	{
+		if let Some(i) = self.tags[..self.n_tags].iter().position(|t| t.crate_name == crate_name && t.module == module) {
+			i as u16
+		}
+		else if self.n_tags < MAX_TAGS {
+			self.tags[self.n_tags] = TagStats::new(crate_name, module);
+			self.n_tags += 1;
+			(self.n_tags - 1) as u16
+		}
+		else {
+			0
+		}
+	}
+}
+
+/// Get the crate and module (submodules of the kernel crate only) containing an address
+fn get_module(addr: usize) -> Option<(&'static str, &'static str)>
+{
+	let (name, _ofs) = match ::symbols::get_symbol_for_addr(addr - 1)
+		{
+		Some(v) => v,
+		None => return None,
+		};
+	if ! name.starts_with("_ZN") {
+		return None;
+	}
+	let (first, rest) = match split_ident(&name[3..])
+		{
+		Some(v) => v,
+		None => return None,
+		};
+	// Collect up to two module names (excluding the final item name)
+	let mut names = [""; 3];
+	let crate_name;
+	let mut n = 0;
+	if first.starts_with("_$LT$") {
+		// Trait impls: `_$LT$kernel..vfs..Foo$u20$as$u20$...`
+		let mut it = first[5..].split("..");
+		crate_name = it.next().unwrap_or("?");
+		for c in it.take_while(|c| !c.contains('$')).take(3) {
+			names[n] = c;
+			n += 1;
+		}
+	}
+	else {
+		crate_name = first;
+		let mut r = rest;
+		while let Some((c, r2)) = split_ident(r) {
+			if n == 3 { break; }
+			names[n] = c;
+			n += 1;
+			r = r2;
+		}
+	}
+	// The last collected name may be the item itself
+	if n > 0 {
+		n -= 1;
+	}
+
+	if crate_name != "kernel" || n == 0 {
+		Some( (crate_name, "") )
+	}
+	else if n >= 2 && names[0] == "memory" && names[1] == "heap" {
+		Some( ("kernel", "memory::heap") )
+	}
+	else {
+		Some( ("kernel", names[0]) )
+	}
+}
+
+/// Split a length-prefixed identifier from the front of a mangled symbol
+fn split_ident(s: &str) -> Option<(&str, &str)>
+{
+	let digits = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+	if digits == 0 {
+		return None;
+	}
+	let len: usize = match s[..digits].parse() { Ok(v) => v, Err(_) => return None };
+	if s.len() < digits + len {
+		return None;
+	}
+	Some( (&s[digits .. digits + len], &s[digits + len ..]) )
+}
+
+/// Write per-module heap usage
+pub fn dump_modules(f: &mut dyn fmt::Write) -> fmt::Result
+{
+	if !ENABLED {
+		return writeln!(f, "(Per-module usage requires the `heap_debug` feature)");
+	}
+	// Copy the table out, as writing to `f` may allocate
+	let (tags, n_tags) = {
+		let lh = S_STATE.lock();
+		(lh.tags, lh.n_tags)
+		};
+	try!(writeln!(f, "{:>10} {:>10} {:>8} {:>8} Module", "In Use", "Peak", "Allocs", "Slack"));
+	for t in &tags[..n_tags]
+	{
+		try!(writeln!(f, "{:>10} {:>10} {:>8} {:>8} {}", t.in_use, t.peak, t.allocs, t.slack, t));
+	}
+	Ok( () )
+}
+
+impl fmt::Display for TagStats
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.module == "" {
+			f.write_str(self.crate_name)
+		}
+		else {
+			write!(f, "{}::{}", self.crate_name, self.module)
+		}
+	}
+}
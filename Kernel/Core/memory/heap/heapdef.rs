@@ -15,9 +15,36 @@ pub struct HeapDef
 	start: *mut HeapHead,
 	last_foot: *mut HeapFoot,
 	first_free: *mut HeapHead,
+	stats: HeapStats,
 }
 unsafe impl ::core::marker::Send for HeapDef {}
 
+/// Usage counters for a heap
+#[derive(Copy,Clone,Default)]
+pub struct HeapStats
+{
+	/// Bytes requested by live allocations
+	pub in_use: usize,
+	/// Highest value of `in_use`
+	pub peak: usize,
+	/// Bytes of heap blocks (including headers and rounding) used by live allocations
+	pub block_bytes: usize,
+	/// Number of live allocations
+	pub allocations: usize,
+}
+
+/// Result of walking the heap's blocks
+#[derive(Copy,Clone,Default)]
+pub struct HeapSummary
+{
+	/// Total size of the heap
+	pub total: usize,
+	pub used_blocks: usize,
+	pub free_blocks: usize,
+	pub free_bytes: usize,
+	pub largest_free: usize,
+}
+
 #[derive(Debug,PartialEq)]	// RawPtr Debug is the address
 enum HeapState
 {
@@ -51,9 +78,23 @@ impl HeapDef
 			start: 0 as *mut _,
 			last_foot: 0 as *mut _,
 			first_free: 0 as *mut _,
+			stats: HeapStats { in_use: 0, peak: 0, block_bytes: 0, allocations: 0 },
 			}
 	}
 
+	/// Current usage counters
+	pub fn stats(&self) -> HeapStats {
+		self.stats
+	}
+	fn stats_add(&mut self, size: usize, block_size: usize) {
+		self.stats.in_use += size;
+		self.stats.block_bytes += block_size;
+		self.stats.allocations += 1;
+		if self.stats.in_use > self.stats.peak {
+			self.stats.peak = self.stats.in_use;
+		}
+	}
+
 	/// Allocate arbitary bytes from the heap
 	/// 
 	// TODO: Is this actually unsafe?
@@ -135,6 +176,7 @@ impl HeapDef
 			}
 			// Return newly allocated block
 			fb.state = HeapState::Used(size);
+			self.stats_add(size, fb.size());
 			log_debug!("Returning block {:p} (Freelist)", fb);
 			return Ok( fb.data() );
 		}
@@ -158,6 +200,7 @@ impl HeapDef
 		}
 		
 		block.state = HeapState::Used(size);	
+		self.stats_add(size, block.size());
 	
 		log_trace!("Returning block {:p} (new)", block);
 		Ok( block.data() )
@@ -183,6 +226,12 @@ impl HeapDef
 		// If the new size fits within the old block, update the cached size and return true
 		if size + headers_size <= headptr.size()
 		{
+			if let HeapState::Used(old) = headptr.state {
+				self.stats.in_use = self.stats.in_use - old + size;
+				if self.stats.in_use > self.stats.peak {
+					self.stats.peak = self.stats.in_use;
+				}
+			}
 			headptr.state = HeapState::Used(size);
 			true
 		}
@@ -214,22 +263,25 @@ impl HeapDef
 		HeapState::Used(ref mut sz) => {
 			// TODO: Split block if possible
 			assert!(*sz >= new_size, "Calling shrink_alloc with a larger size");
+			self.stats.in_use -= *sz - new_size;
 			*sz = new_size;
 			},
 		HeapState::Free(..) => panic!("Calling shrink_alloc on a free block ({:p})", ptr),
 		}
 	}
 	
-	pub unsafe fn deallocate(&mut self, ptr: *mut (), size: usize, _align: usize)
+	/// Release an allocation, returning the size that was allocated (`size` is zero if unknown)
+	pub unsafe fn deallocate(&mut self, ptr: *mut (), size: usize, _align: usize) -> usize
 	{
 		log_debug!("deallocate(ptr={:p},size={:#x})", ptr, size);
 		if ptr == ZERO_ALLOC {
 			assert!(size == 0, "ZERO_ALLOC but size({}) != 0", size);
 			log_trace!("Free zero alloc");
-			return ;
+			return 0;
 		}
 
 		let mut no_add = false;
+		let rv;
 		let headptr = (ptr as *mut HeapHead).offset(-1);
 		assert!(headptr as usize >= addresses::HEAP_START);
 		
@@ -245,6 +297,11 @@ impl HeapDef
 				assert_eq!( headref.state, HeapState::Used(size), "Header {:p} state invalid {:?} not Used({})",
 					headref, headref.state, size );
 			}
+			let alloc_size = match headref.state { HeapState::Used(v) => v, _ => unreachable!() };
+			self.stats.in_use -= alloc_size;
+			self.stats.block_bytes -= headref.size();
+			self.stats.allocations -= 1;
+			rv = alloc_size;
 			
 			// Merge left and right
 			// 1. Left:
@@ -271,6 +328,39 @@ impl HeapDef
 			(*headptr).state = HeapState::Free(self.first_free);
 			self.first_free = headptr;
 		}
+		rv
+	}
+
+	/// Walk the heap and summarise block usage
+	pub fn summary(&self) -> HeapSummary
+	{
+		let mut rv = HeapSummary::default();
+		if self.start.is_null() {
+			return rv;
+		}
+		// SAFE: Does an immutable heap walk
+		unsafe {
+			let mut block_head = self.start;
+			loop
+			{
+				let head_ref = &*block_head;
+				rv.total += head_ref.size();
+				match head_ref.state
+				{
+				HeapState::Used(_) => rv.used_blocks += 1,
+				HeapState::Free(_) => {
+					rv.free_blocks += 1;
+					rv.free_bytes += head_ref.size();
+					rv.largest_free = ::core::cmp::max(rv.largest_free, head_ref.size());
+					},
+				}
+				if head_ref.foot_im() as *const HeapFoot == self.last_foot {
+					break;
+				}
+				block_head = head_ref.next();
+			}
+		}
+		rv
 	}
 	
 	/// Expand the heap to create a block at least `min_size` bytes long at the end
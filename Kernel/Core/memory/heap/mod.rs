@@ -3,13 +3,18 @@
 //
 // Core/memory/heap.rs
 //! Dynamic memory manager
+//!
+//! With the `heap_debug` feature, allocations are checked for overflows, double frees, and use-after-free writes
+//! (see the `debug` module), and are tagged with the module that made them.
 use core::ptr::NonNull;
+use core::fmt;
 
 // TODO: Rewrite this to correctly use the size information avaliable
 
 use self::heapdef::HeapDef;
 
 mod heapdef;
+mod debug;
 
 // --------------------------------------------------------
 // Types
@@ -79,7 +84,7 @@ unsafe fn box_free<T: ?Sized>(ptr: *mut T) {
 	let size = ::core::mem::size_of_val(&*ptr);
 	let align = ::core::mem::align_of_val(&*ptr);
 	if size != 0 {
-		S_GLOBAL_HEAP.lock().deallocate(ptr as *mut (), size, align);
+		deallocate(ptr as *mut (), size, align);
 	}
 }
 
@@ -251,12 +256,31 @@ impl<T> ::core::ops::Drop for ArrayAlloc<T>
 	}
 }
 
+/// Write heap usage statistics (the contents of `/proc/heap`)
+pub fn dump_stats(f: &mut dyn fmt::Write) -> fmt::Result
+{
+	// NOTE: Copy the state out before writing, as `f` may allocate
+	let (stats, summary) = {
+		let lh = S_GLOBAL_HEAP.lock();
+		(lh.stats(), lh.summary())
+		};
+	try!(writeln!(f, "In use: {} bytes in {} allocations (peak {} bytes)", stats.in_use, stats.allocations, stats.peak));
+	try!(writeln!(f, "Heap: {} bytes, {} used blocks ({} bytes), {} free blocks ({} bytes, largest {})",
+		summary.total, summary.used_blocks, stats.block_bytes, summary.free_blocks, summary.free_bytes, summary.largest_free));
+	// Internal: Block space not used by the allocation, External: Free space outside the largest free block
+	let internal = if stats.block_bytes > 0 { (stats.block_bytes - stats.in_use) * 100 / stats.block_bytes } else { 0 };
+	let external = if summary.free_bytes > 0 { (summary.free_bytes - summary.largest_free) * 100 / summary.free_bytes } else { 0 };
+	try!(writeln!(f, "Fragmentation: {}% internal, {}% external", internal, external));
+	debug::dump_modules(f)
+}
+
 // Main entrypoints
 /// Allocate memory from the specified heap
 unsafe fn allocate(heap: HeapId, size: usize, align: usize) -> Option<*mut ()>
 {
 	match heap
 	{
+	HeapId::Global if debug::ENABLED && size != 0 => debug::allocate(size, align),
 	HeapId::Global => match S_GLOBAL_HEAP.lock().allocate(size, align)
 		{
 		Ok(v) => Some(v),
@@ -272,16 +296,31 @@ unsafe fn allocate(heap: HeapId, size: usize, align: usize) -> Option<*mut ()>
 /// Attempt to expand in-place
 unsafe fn expand(pointer: *mut (), newsize: usize) -> bool
 {
-	S_GLOBAL_HEAP.lock().expand_alloc(pointer, newsize)
+	if debug::ENABLED && pointer != ZERO_ALLOC {
+		debug::expand(pointer, newsize)
+	}
+	else {
+		S_GLOBAL_HEAP.lock().expand_alloc(pointer, newsize)
+	}
 }
 unsafe fn shrink(pointer: *mut (), newsize: usize)
 {
-	S_GLOBAL_HEAP.lock().shrink_alloc(pointer, newsize)
+	if debug::ENABLED && pointer != ZERO_ALLOC {
+		debug::shrink(pointer, newsize)
+	}
+	else {
+		S_GLOBAL_HEAP.lock().shrink_alloc(pointer, newsize)
+	}
 }
 
 unsafe fn deallocate(pointer: *mut (), size: usize, align: usize)
 {
-	S_GLOBAL_HEAP.lock().deallocate(pointer as *mut (), size, align);
+	if debug::ENABLED && pointer != ZERO_ALLOC {
+		debug::deallocate(pointer, size);
+	}
+	else {
+		S_GLOBAL_HEAP.lock().deallocate(pointer as *mut (), size, align);
+	}
 }
 
 
@@ -23,6 +23,7 @@ pub mod memorymap;
 pub mod bump_region;
 pub mod page_cache;
 pub mod page_array;
+pub mod swap;
 
 pub use arch::memory::PAddr;
 /*
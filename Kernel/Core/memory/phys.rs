@@ -7,6 +7,7 @@
 use prelude::*;
 use arch::memory::PAddr;
 use arch::memory::virt::TempHandle;
+use core::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
 
 pub const NOPAGE : PAddr = 1;
 
@@ -17,12 +18,62 @@ impl_fmt! {
 	}
 }
 
+/// Largest buddy block (2^MAX_ORDER frames)
+///
+/// Zone boundaries are multiples of the largest block, so a block (and its buddy) never spans two zones.
+const MAX_ORDER: usize = 10;
+
+/// Physical memory zones, allocations use the highest zone that satisfies their address limit
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Zone
+{
+	/// Below 16MiB (ISA DMA)
+	Dma,
+	/// Below 4GiB (32-bit bus masters)
+	Dma32,
+	/// Everything else
+	Normal,
+}
+const ZONES: [Zone; 3] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+
 static S_MEM_MAP: ::lib::LazyStatic<&'static [::memory::MemoryMapEnt]> = lazystatic_init!();
-/// Tracks the allocation point in S_MEM_MAP : (Entry Index, Address)
+/// Bootstrap allocation point in S_MEM_MAP : (Entry Index, Address)
+///
+/// Only used until the buddy allocator is initialised (the buddy allocator's bitmaps are on the heap)
 static S_MAPALLOC : ::sync::Mutex<(usize,PAddr)> = mutex_init!( (0,0) );
-// TODO: Multiple stacks based on page colouring
-static S_FREE_STACK : ::sync::Mutex<PAddr> = mutex_init!( NOPAGE );
-// TODO: Reference counts (maybe require arch to expose that)
+/// Buddy allocator, set up by `init_buddy`
+static S_BUDDY: ::sync::mutex::LazyMutex<Buddy> = ::sync::mutex::LazyMutex::new();
+static S_BUDDY_READY: AtomicBool = AtomicBool::new(false);
+/// Number of free frames in the buddy allocator
+static S_FREE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Maximum number of registered reclaimers
+const MAX_RECLAIMERS: usize = 8;
+/// Registered sources of reclaimable memory
+///
+/// Append-only, entries below `S_RECLAIMER_COUNT` are never modified (so `reclaim` can use them without locking or
+/// allocating). Registration is serialised by `S_RECLAIMER_LOCK`.
+static mut S_RECLAIMERS: [Option<&'static dyn Reclaimer>; MAX_RECLAIMERS] = [None; MAX_RECLAIMERS];
+static S_RECLAIMER_COUNT: AtomicUsize = AtomicUsize::new(0);
+static S_RECLAIMER_LOCK: ::sync::Mutex<()> = mutex_init!( () );
+/// Prevents recursion if a reclaimer allocates memory
+static S_RECLAIM_ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Free frame count below which reclaimers are asked to release memory
+const LOW_WATERMARK_FRAMES: usize = 64;
+/// Number of frames requested from reclaimers in one pass
+const RECLAIM_BATCH_FRAMES: usize = 32;
+
+/// A source of memory that can be released when the system runs low on free frames (e.g. a disk cache)
+pub trait Reclaimer: Sync
+{
+	/// Name (for logging)
+	fn name(&self) -> &str;
+	/// Release up to `target` frames, returning the number actually released
+	///
+	/// NOTE: This is called from within the allocator, so must not block on locks that could be held by the
+	/// allocating thread (use `try_lock` and return zero if contended).
+	fn reclaim(&self, target: usize) -> usize;
+}
 
 /// A handle to a physical page (maintaining a reference to it, even when not mapped)
 pub struct FrameHandle(PAddr);
@@ -54,6 +105,47 @@ pub fn init()
 	*S_MAPALLOC.lock() = (i, map[i].start as PAddr);
 }
 
+/// Hand the remaining free memory over to the buddy allocator (called once the heap is available)
+pub fn init_buddy()
+{
+	use memory::memorymap::MemoryState;
+	let map = get_memory_map();
+	let ram_end = map.iter()
+		.filter(|e| e.state == MemoryState::Free || e.state == MemoryState::Used)
+		.map(|e| e.end())
+		.max().unwrap_or(0);
+	let n_frames = (ram_end / ::PAGE_SIZE as u64) as usize;
+
+	// 1. Allocate the free bitmaps (this can grow the heap, so the bootstrap allocator is still active)
+	let mut buddy = Buddy {
+		free_lists: [[NOPAGE; MAX_ORDER+1]; 3],
+		zone_free: [0; 3],
+		zone_total: [0; 3],
+		ram_end: ram_end,
+		free_bits: (0 .. MAX_ORDER+1).map(|o| vec![0u32; (n_frames >> o) / 32 + 1]).collect(),
+		};
+
+	// 2. Stop the bootstrap allocator and give the rest of the map to the buddy allocator
+	// - Nothing should allocate until the buddy allocator is marked as ready (the temporary mapping region's tables
+	//   are already populated, so `with_temp` doesn't allocate)
+	let (i, addr) = ::core::mem::replace(&mut *S_MAPALLOC.lock(), (map.len(), 0));
+	if i < map.len()
+	{
+		buddy.add_range(addr as u64, map[i].end());
+		for e in map[i+1 ..].iter().filter(|e| e.state == MemoryState::Free)
+		{
+			buddy.add_range(e.start, e.end());
+		}
+	}
+	for &z in ZONES.iter()
+	{
+		log_log!("Zone {:?}: {} of {} frames free", z, buddy.zone_free[z as usize], buddy.zone_total[z as usize]);
+	}
+	S_FREE_COUNT.store(buddy.zone_free.iter().sum(), Ordering::Relaxed);
+	S_BUDDY.init(|| buddy);
+	S_BUDDY_READY.store(true, Ordering::Release);
+}
+
 impl FrameHandle
 {
 	/// UNSAFE due to using a raw physical address
@@ -87,6 +179,48 @@ impl Drop for FrameHandle
 	}
 }
 
+/// Register a source of reclaimable memory
+pub fn register_reclaimer(r: &'static dyn Reclaimer)
+{
+	let _lh = S_RECLAIMER_LOCK.lock();
+	let idx = S_RECLAIMER_COUNT.load(Ordering::Relaxed);
+	assert!(idx < MAX_RECLAIMERS, "Too many reclaimers registered (registering '{}')", r.name());
+	// SAFE: Lock held, and the entry isn't visible to `reclaim` until the count is updated
+	unsafe {
+		S_RECLAIMERS[idx] = Some(r);
+	}
+	S_RECLAIMER_COUNT.store(idx + 1, Ordering::Release);
+}
+
+/// Returns `true` if the number of free frames is below the low watermark
+pub fn is_memory_low() -> bool
+{
+	S_BUDDY_READY.load(Ordering::Relaxed) && S_FREE_COUNT.load(Ordering::Relaxed) < LOW_WATERMARK_FRAMES
+}
+
+/// Ask registered reclaimers to release up to `target` frames, returning the number released
+pub fn reclaim(target: usize) -> usize
+{
+	if S_RECLAIM_ACTIVE.swap(true, Ordering::Acquire) {
+		// Already reclaiming (a reclaimer needed memory), don't recurse
+		return 0;
+	}
+	let n_reclaimers = S_RECLAIMER_COUNT.load(Ordering::Acquire);
+	let mut count = 0;
+	// SAFE: Entries below the count are never modified
+	for r in unsafe { S_RECLAIMERS[..n_reclaimers].iter() }.filter_map(|r| *r)
+	{
+		if count >= target {
+			break;
+		}
+		let n = r.reclaim(target - count);
+		log_debug!("reclaim: '{}' released {} frames", r.name(), n);
+		count += n;
+	}
+	S_RECLAIM_ACTIVE.store(false, Ordering::Release);
+	count
+}
+
 fn get_memory_map() -> &'static [::memory::MemoryMapEnt]
 {
 	&*S_MEM_MAP
@@ -109,74 +243,122 @@ fn is_ram(phys: PAddr) -> bool
 	false
 }
 
-pub fn make_unique(page: PAddr, virt_addr: &[u8; ::PAGE_SIZE]) -> PAddr
+/// Returns true if the frame is in a region of the memory map that the allocator manages
+fn is_managed(phys: PAddr) -> bool
+{
+	S_MEM_MAP.iter().any(|e| e.state == ::memory::memorymap::MemoryState::Free && e.start <= phys as u64 && (phys as u64) < e.end())
+}
+
+/// Obtain a frame that is only referenced by the caller's mapping (copying the frame if it's shared)
+///
+/// If memory is exhausted, this waits for the swap reclaim thread before failing.
+pub fn make_unique(page: PAddr, virt_addr: &[u8; ::PAGE_SIZE]) -> Result<PAddr, Error>
 {
 	if !is_ram(page) {
 		panic!("Calling 'make_unique' on non-RAM page");
 	}
 	else if ::arch::memory::phys::get_multiref_count(page as u64 / ::PAGE_SIZE as u64) == 0 {
-		page
+		Ok(page)
 	}
 	else {
 		// 1. Allocate a new frame in temp region
-		let mut new_frame = ::memory::virt::alloc_free().expect("TODO: handle OOM in make_unique");
+		let mut new_frame = loop
+			{
+				match ::memory::virt::alloc_free()
+				{
+				Ok(v) => break v,
+				Err(_) if ::memory::swap::wait_for_memory() => {},
+				Err(_) => {
+					log_error!("make_unique({:#x}) - Out of memory", page);
+					return Err(Error);
+					},
+				}
+			};
 		// 2. Copy in content of old frame
 		new_frame.clone_from_slice( virt_addr );
-		new_frame.into_frame().into_addr()
+		// 3. Release this mapping's reference to the old frame
+		deref_frame(page);
+		Ok( new_frame.into_frame().into_addr() )
+	}
+}
+
+/// Allocate `count` physically contiguous frames below `1 << bits`
+pub fn allocate_range_bits(bits: u8, count: usize) -> Result<PAddr, Error>
+{
+	assert!(count > 0);
+	if !S_BUDDY_READY.load(Ordering::Acquire) {
+		// Only single frames are available from the bootstrap allocator
+		if count != 1 {
+			return Err(Error);
+		}
+		return match bootstrap_allocate()
+			{
+			Some(p) if bits >= 64 || (p as u64) >> bits == 0 => Ok(p),
+			Some(p) => {
+				log_warning!("allocate_range_bits({}, 1) - Bootstrap frame {:#x} is above the limit (leaked)", bits, p);
+				Err(Error)
+				},
+			None => Err(Error),
+			};
+	}
+	let order = try!( (0 .. MAX_ORDER+1).find(|&o| 1 << o >= count).ok_or(Error) );
+	let rv = try!( allocate_block(bits, order) );
+	// Release the unused tail of the block (these frames merge back together)
+	if count < 1 << order
+	{
+		let mut lh = S_BUDDY.lock();
+		for i in count .. 1 << order {
+			lh.free_block(rv + (i * ::PAGE_SIZE) as PAddr, 0);
+		}
+		S_FREE_COUNT.fetch_add((1 << order) - count, Ordering::Relaxed);
 	}
+	log_trace!("allocate_range_bits(bits={}, count={}) = {:#x}", bits, count, rv);
+	Ok(rv)
 }
 
-pub fn allocate_range_bits(bits: u8, count: usize) -> PAddr
+/// Allocate a block of `2^order` frames from the highest zone below `1 << bits`
+fn allocate_block(bits: u8, order: usize) -> Result<PAddr, Error>
 {
-	// XXX: HACK! Falls back to the simple code if possible
-	if bits >= 64 || get_memory_map().last().unwrap().start >> bits == 0
+	let mut lh = S_BUDDY.lock();
+	let limit = if bits >= 64 { !0 } else { 1u64 << bits };
+	for &zone in ZONES.iter().rev()
 	{
-		return allocate_range(count);
+		if lh.zone_end(zone) > limit {
+			continue ;
+		}
+		if let Some(p) = lh.alloc_block(zone, order) {
+			S_FREE_COUNT.fetch_sub(1 << order, Ordering::Relaxed);
+			return Ok(p);
+		}
 	}
-	// 1. Locate the last block of a suitable bitness
-	// - Take care to correctly handle blocks that straddle bitness boundaries
-	// NOTE: Memory map constructor _can_ break blocks up at common bitness boundaries (16, 24, 32 bits) to make this more efficient
-	// 2. Obtain `count` pages from either the end (if possible) or the start of this block
-	// TODO: If the block is not large enough, return an error (NOPAGE)
-	panic!("TODO: allocate_range_bits(bits={}, count={})", bits, count);
+	Err(Error)
 }
 
-pub fn allocate_range(count: usize) -> PAddr
+/// Obtain a frame from the memory map (before the buddy allocator is ready)
+fn bootstrap_allocate() -> Option<PAddr>
 {
 	let mut h = S_MAPALLOC.lock();
-	log_trace!("allocate_range: *h = ({},{:#x}) (init)", h.0, h.1);
-	let (mut i,mut addr) = *h;
-	
+	let (mut i, mut addr) = *h;
 	let map = get_memory_map();
 	if i == map.len() {
-		log_error!("Out of physical memory");
-		return NOPAGE;
+		return None;
 	}
 	// If there's less than one page left in the map entry, go to the next one
-	if addr + (1 * ::PAGE_SIZE) as PAddr > map[i].end() as PAddr
+	if addr as u64 + ::PAGE_SIZE as u64 > map[i].end()
 	{
 		i += 1;
 		while i != map.len() && map[i].state != ::memory::memorymap::MemoryState::Free {
 			i += 1;
 		}
 		if i == map.len() {
-			log_error!("Out of physical memory");
+			log_warning!("Memory map exhausted before the buddy allocator was initialised");
 			*h = (i, 0);
-			return NOPAGE;
+			return None;
 		}
 		addr = map[i].start as PAddr;
 	}
-	let rv = addr;
-	let shift = (count * ::PAGE_SIZE) as PAddr;
-	if addr + shift > map[i].end() as PAddr {
-		todo!("Handle allocating from ahead in map ({:#x} + {:#x} > {:#x}, start={:#x})", addr, shift, map[i].end(), map[i].start);
-		// TODO: If the shift pushes this allocation over the edge of a map entry, stick the remaining entries onto the free stack and move to the next free block
-	}
-	addr += shift;
-	//log_trace!("allocate_range: rv={:#x}, i={}, addr={:#x}", rv, i, addr);
-	*h = (i, addr);
-	//log_trace!("allocate_range: *h = {:?}", *h);
-	return rv;
+	*h = (i, addr + ::PAGE_SIZE as PAddr);
+	Some(addr)
 }
 
 /// Allocate a page with no fixed alocation, returns a temporary handle to it
@@ -191,75 +373,53 @@ pub fn allocate(address: *mut ()) -> bool {
 
 /// Allocate a page at the given (optional) address
 /// 
-/// If no address is provided, a temporary handle is returned
+/// If no address is provided, a temporary handle is returned. Reclaimers are invoked if memory is low.
 fn allocate_int( address: Option<*mut ()> ) -> Result<Option<TempHandle<u8>>, Error>
 {
-	log_trace!("allocate(address={:?})", address);
-	// 1. Pop a page from the free stack
-	// SAFE: Frames on the free are not aliased, alloc is safe
-	unsafe
+	match allocate_int_inner(address)
 	{
-		let mut h = S_FREE_STACK.lock();
-		let paddr = *h;
-		if paddr != NOPAGE
-		{
-			match address
-			{
-			Some(address) => {
-				// Check that calling `virt::map` will not cause us to be called
-				if ::arch::memory::virt::can_map_without_alloc(address) {
-					// Map and obtain the next page
-					::memory::virt::map(address, paddr, super::virt::ProtectionMode::KernelRW);
-					*h = *(address as *const PAddr);
-				}
-				else {
-					// Otherwise, do a temp mapping, extract the next page, then drop the lock and map
-					// NOTE: A race here doesn't matter, as lower operations are atomic, and it'd just be slower
-					::memory::virt::with_temp(paddr, |page| *h = *(&page[0] as *const u8 as *const PAddr));
-					drop(h);
-					::memory::virt::map(address, paddr, super::virt::ProtectionMode::KernelRW);
-				}
-				// Zero page - Why? - Should fill it with dropped :)
-				*(address as *mut [u8; ::PAGE_SIZE]) = ::core::mem::zeroed();
-				log_trace!("- {:p} (stack) paddr = {:#x}", address, paddr);
-				mark_used(paddr);
-				return Ok(None);
-				},
-			None => {
-				let handle = ::arch::memory::virt::TempHandle::new(paddr);
-				*h = *(&handle[0] as *const u8 as *const PAddr);
-				log_trace!("- None (stack) paddr = {:#x}", paddr);
-				mark_used(paddr);
-				return Ok( Some(handle) );
-				},
-			}
+	Ok(rv) => {
+		if is_memory_low() {
+			reclaim(RECLAIM_BATCH_FRAMES);
 		}
+		Ok(rv)
+		},
+	Err(Error) => {
+		// Out of frames, try to free some up and retry
+		if reclaim(RECLAIM_BATCH_FRAMES) > 0 {
+			allocate_int_inner(address)
+		}
+		else {
+			log_warning!("Out of physical memory");
+			Err(Error)
+		}
+		},
 	}
-	// 2. If none, allocate from map
-	let paddr = allocate_range(1);
-	if paddr != NOPAGE
+}
+
+fn allocate_int_inner( address: Option<*mut ()> ) -> Result<Option<TempHandle<u8>>, Error>
+{
+	log_trace!("allocate(address={:?})", address);
+	let paddr = if S_BUDDY_READY.load(Ordering::Acquire) {
+			try!(allocate_block(64, 0))
+		}
+		else {
+			try!(bootstrap_allocate().ok_or(Error))
+		};
+	// SAFE: Physical address was just allocated, can't alias
+	unsafe
 	{
 		if let Some(address) = address {
-			// SAFE: Physical address just allocated
-			unsafe {
-				::memory::virt::map(address, paddr, super::virt::ProtectionMode::KernelRW);
-				*(address as *mut [u8; ::PAGE_SIZE]) = ::core::mem::zeroed();
-			}
-			log_trace!("- {:p} (range) paddr = {:#x}", address, paddr);
-			mark_used(paddr);
-			return Ok( None );
+			::memory::virt::map(address, paddr, super::virt::ProtectionMode::KernelRW);
+			*(address as *mut [u8; ::PAGE_SIZE]) = ::core::mem::zeroed();
+			log_trace!("- {:p} paddr = {:#x}", address, paddr);
+			Ok( None )
 		}
 		else {
-			log_trace!("- None (range) paddr = {:#x}", paddr);
-			mark_used(paddr);
-			// SAFE: Physical address was just allocated, can't alias
-			let handle = unsafe { ::arch::memory::virt::TempHandle::new(paddr) };
-			return Ok( Some(handle) );
+			log_trace!("- None paddr = {:#x}", paddr);
+			Ok( Some(::arch::memory::virt::TempHandle::new(paddr)) )
 		}
 	}
-	// 3. Fail
-	log_warning!("Out of physical memory");
-	Err( Error )
 }
 
 pub fn ref_frame(paddr: PAddr)
@@ -279,30 +439,196 @@ pub fn deref_frame(paddr: PAddr)
 	}
 	// Dereference page (returns prevous value, zero meaning page was not multi-referenced)
 	else if ::arch::memory::phys::deref_frame(paddr as u64 / ::PAGE_SIZE as u64) == 0 {
-		// - This page is the only reference.
-		if ::arch::memory::phys::mark_free(paddr as u64 / ::PAGE_SIZE as u64) == true {
-			// Release frame back into the pool
-			// SAFE: This frame is unaliased
-			unsafe {
-				let mut h = S_FREE_STACK.lock();
-				::memory::virt::with_temp(paddr, |page| *(&mut page[0] as *mut u8 as *mut PAddr) = *h);
-				*h = paddr;
-			}
+		// - This page is the only reference, release it back into the pool
+		free_frame(paddr);
+	}
+	else {
+		// References still exist
+	}
+}
+
+fn free_frame(paddr: PAddr)
+{
+	if ! is_managed(paddr) {
+		// Not from the free part of the map (e.g. the kernel image), ignore
+		return ;
+	}
+	if ! S_BUDDY_READY.load(Ordering::Acquire) {
+		// Very early frees are leaked (the bootstrap allocator can't reuse frames)
+		log_trace!("deref_frame({:#x}) - Freed before buddy allocator is ready", paddr);
+		return ;
+	}
+	let mut lh = S_BUDDY.lock();
+	if lh.is_free( frame_idx(paddr) ) {
+		log_error!("deref_frame({:#x}) - Frame is already free (double free)", paddr);
+		return ;
+	}
+	lh.free_block(paddr, 0);
+	S_FREE_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+fn frame_idx(paddr: PAddr) -> usize {
+	(paddr / ::PAGE_SIZE as PAddr) as usize
+}
+fn frame_addr(idx: usize) -> PAddr {
+	(idx * ::PAGE_SIZE) as PAddr
+}
+
+impl Zone
+{
+	fn of(paddr: PAddr) -> Zone {
+		if (paddr as u64) < Zone::Dma.limit() {
+			Zone::Dma
+		}
+		else if (paddr as u64) < Zone::Dma32.limit() {
+			Zone::Dma32
 		}
 		else {
-			// Page was either not allocated (oops) or is not managed
-			// - Either way, ignore
+			Zone::Normal
 		}
 	}
-	else {
-		// References still exist
+	/// Upper address limit of this zone (exclusive)
+	fn limit(&self) -> u64 {
+		match *self
+		{
+		Zone::Dma => 1 << 24,
+		Zone::Dma32 => 1 << 32,
+		Zone::Normal => !0,
+		}
 	}
 }
 
-fn mark_used(_paddr: PAddr)
+/// Free list links, stored at the start of each free block
+#[repr(C)]
+struct FreeLinks
+{
+	next: PAddr,
+	prev: PAddr,
+}
+/// UNSAFE: The frame must be the head of a free block
+unsafe fn with_links<R, F: FnOnce(&mut FreeLinks)->R>(frame: PAddr, f: F) -> R
+{
+	::memory::virt::with_temp(frame, |page| f( &mut *(page.as_mut_ptr() as *mut FreeLinks) ))
+}
+
+/// Zone-aware binary buddy allocator
+struct Buddy
 {
-	// TODO: This causes a double-lock in the PMM
-	//::arch::memory::phys::mark_used(paddr / ::PAGE_SIZE as PAddr)
+	/// Free list heads for each zone, indexed by order
+	free_lists: [[PAddr; MAX_ORDER+1]; 3],
+	zone_free: [usize; 3],
+	zone_total: [usize; 3],
+	/// End of RAM (the upper limit of the highest zone)
+	ram_end: u64,
+	/// Per-order bitmaps, bit `N` of order `K` is set if frames `N<<K .. (N+1)<<K` are a free block
+	free_bits: Vec<Vec<u32>>,
+}
+impl Buddy
+{
+	fn zone_end(&self, zone: Zone) -> u64 {
+		::core::cmp::min(zone.limit(), self.ram_end)
+	}
+
+	fn bit_test(&self, order: usize, idx: usize) -> bool {
+		let b = idx >> order;
+		self.free_bits[order].get(b / 32).map(|w| w & (1 << (b % 32)) != 0).unwrap_or(false)
+	}
+	fn bit_set(&mut self, order: usize, idx: usize, set: bool) {
+		let b = idx >> order;
+		let w = &mut self.free_bits[order][b / 32];
+		if set {
+			*w |= 1 << (b % 32);
+		}
+		else {
+			*w &= !(1 << (b % 32));
+		}
+	}
+	/// Returns true if the frame is within a free block
+	fn is_free(&self, idx: usize) -> bool {
+		(0 .. MAX_ORDER+1).any(|o| self.bit_test(o, idx))
+	}
+
+	fn push(&mut self, zone: Zone, order: usize, block: PAddr) {
+		let head = self.free_lists[zone as usize][order];
+		// SAFE: Block is free (owned by the allocator), and so is the list head
+		unsafe {
+			with_links(block, |l| { l.next = head; l.prev = NOPAGE; });
+			if head != NOPAGE {
+				with_links(head, |l| l.prev = block);
+			}
+		}
+		self.free_lists[zone as usize][order] = block;
+		self.bit_set(order, frame_idx(block), true);
+	}
+	fn unlink(&mut self, zone: Zone, order: usize, block: PAddr) {
+		// SAFE: Block and its neighbours are on the free list
+		unsafe {
+			let (next, prev) = with_links(block, |l| (l.next, l.prev));
+			if prev == NOPAGE {
+				self.free_lists[zone as usize][order] = next;
+			}
+			else {
+				with_links(prev, |l| l.next = next);
+			}
+			if next != NOPAGE {
+				with_links(next, |l| l.prev = prev);
+			}
+		}
+		self.bit_set(order, frame_idx(block), false);
+	}
+
+	/// Add a range of free memory to the allocator
+	fn add_range(&mut self, start: u64, end: u64) {
+		let page_size = ::PAGE_SIZE as u64;
+		let mut idx = ((start + page_size - 1) / page_size) as usize;
+		let end_idx = (end / page_size) as usize;
+		while idx < end_idx
+		{
+			// Largest aligned block that fits in the remaining space
+			let mut order = 0;
+			while order < MAX_ORDER && idx % (2 << order) == 0 && idx + (2 << order) <= end_idx {
+				order += 1;
+			}
+			self.zone_total[Zone::of(frame_addr(idx)) as usize] += 1 << order;
+			self.free_block(frame_addr(idx), order);
+			idx += 1 << order;
+		}
+	}
+
+	/// Release a block, merging it with its buddy while the buddy is also free
+	fn free_block(&mut self, block: PAddr, mut order: usize) {
+		let zone = Zone::of(block);
+		self.zone_free[zone as usize] += 1 << order;
+		let mut idx = frame_idx(block);
+		while order < MAX_ORDER
+		{
+			let buddy_idx = idx ^ (1 << order);
+			if !self.bit_test(order, buddy_idx) {
+				break;
+			}
+			self.unlink(zone, order, frame_addr(buddy_idx));
+			idx &= !(1 << order);
+			order += 1;
+		}
+		self.push(zone, order, frame_addr(idx));
+	}
+
+	/// Allocate a block of `2^order` frames from the specified zone
+	fn alloc_block(&mut self, zone: Zone, order: usize) -> Option<PAddr> {
+		let found_order = match (order .. MAX_ORDER+1).find(|&o| self.free_lists[zone as usize][o] != NOPAGE)
+			{
+			Some(o) => o,
+			None => return None,
+			};
+		let block = self.free_lists[zone as usize][found_order];
+		self.unlink(zone, found_order, block);
+		// Split the block, returning the upper halves to the free lists
+		for o in (order .. found_order).rev() {
+			self.push(zone, o, block + ((1 << o) * ::PAGE_SIZE) as PAddr);
+		}
+		self.zone_free[zone as usize] -= 1 << order;
+		Some(block)
+	}
 }
 
 // vim: ft=rust
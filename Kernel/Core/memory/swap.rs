@@ -0,0 +1,449 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/memory/swap.rs
+//! Swapping of anonymous user memory to a block device
+//!
+//! Private user pages (ones that have been written since allocation, see `virt::allocate_user`) are tracked along
+//! with an age. When physical memory is low, the reclaim thread writes the oldest pages to the swap volume and
+//! replaces their mappings with swap entries, which the page fault handler reads back in on access.
+use prelude::*;
+use core::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
+use metadevs::storage::VolumeHandle;
+use arch::memory::PAddr;
+use arch::memory::virt::PageAge;
+use lib::mem::Arc;
+use sync::mutex::LazyMutex;
+use sync::EventChannel;
+
+/// Number of aging passes a page must go unaccessed before it is swapped out
+const MIN_SWAP_AGE: u8 = 2;
+/// Maximum number of pages written out per reclaim pass
+const RECLAIM_BATCH_PAGES: usize = 32;
+/// Maximum number of tracked pages (pages faulted in once the table is full aren't considered for swapping)
+const MAX_TRACKED_PAGES: usize = 4096;
+
+struct SwapState
+{
+	volume: Arc<VolumeHandle>,
+	/// Number of volume blocks per swap slot
+	blocks_per_slot: u64,
+	/// Bitmap of allocated slots
+	used_slots: Vec<u32>,
+	/// Bitmap of slots being read back in by the fault handler (see `swap_in`)
+	reading_slots: Vec<u32>,
+	/// Bitmap of slots released while being read in (freed once the read completes)
+	released_slots: Vec<u32>,
+	/// Slots that are being written out, and the frame that still holds the data
+	///
+	/// If the page is accessed before the write completes, the fault handler takes the frame back.
+	in_flight: Vec<(usize, PAddr)>,
+}
+
+struct TrackedPage
+{
+	/// Address space root
+	aspace: PAddr,
+	addr: usize,
+	/// Number of aging passes since the page was last accessed
+	age: u8,
+}
+
+static S_SWAP_ENABLED: AtomicBool = AtomicBool::new(false);
+static S_SWAP: LazyMutex<SwapState> = LazyMutex::new();
+/// Held while the reclaim thread accesses another address space's page tables, and by `forget_address_space` (so
+/// the tables can't be freed during a walk)
+static S_WALK_LOCK: ::sync::Mutex<()> = ::sync::Mutex::new(());
+/// Tracked private pages
+///
+/// Updated from the page fault handler, so it's a spinlock and its capacity is reserved by `enable` (pushes never
+/// allocate).
+static S_TRACKED_PAGES: ::sync::Spinlock<Vec<TrackedPage>> = ::sync::Spinlock::new( Vec::new_const() );
+
+static S_RECLAIM_THREAD: LazyMutex<::threads::WorkerThread> = LazyMutex::new();
+/// Signalled when memory is low
+static S_RECLAIM_REQUEST: EventChannel = EventChannel::new();
+/// Woken after each reclaim pass
+static S_RECLAIM_DONE: ::async::queue::Source = ::async::queue::Source::new();
+/// Number of frames released by the last reclaim pass
+static S_LAST_RECLAIMED: AtomicUsize = AtomicUsize::new(0);
+static S_RECLAIMER: Reclaimer = Reclaimer;
+
+/// Enable swapping to the named logical volume
+pub fn enable(volume_name: &str) -> Result<(), &'static str>
+{
+	if !::arch::memory::virt::SWAP_SUPPORTED {
+		return Err("Swap isn't supported on this architecture");
+	}
+	if S_SWAP_ENABLED.load(Ordering::Relaxed) {
+		return Err("Swap is already enabled");
+	}
+	let vh = match VolumeHandle::open_named(volume_name)
+		{
+		Ok(v) => v,
+		Err(::metadevs::storage::VolOpenError::NotFound) => return Err("Swap volume not found"),
+		Err(::metadevs::storage::VolOpenError::Locked) => return Err("Swap volume is in use"),
+		};
+	let bs = vh.block_size();
+	if ::PAGE_SIZE % bs != 0 {
+		return Err("Swap volume block size is larger than a page");
+	}
+	let blocks_per_slot = (::PAGE_SIZE / bs) as u64;
+	let slot_count = (vh.block_count() / blocks_per_slot) as usize;
+	if slot_count == 0 {
+		return Err("Swap volume is too small");
+	}
+	log_notice!("Swap enabled on '{}', {} slots ({} KiB)", vh.name(), slot_count, slot_count * (::PAGE_SIZE / 1024));
+
+	let bitmap_len = (slot_count + 31) / 32;
+	let mut used_slots = vec![0u32; bitmap_len];
+	// Mark the slots past the end of the volume as used
+	for i in slot_count .. used_slots.len() * 32 {
+		used_slots[i / 32] |= 1 << (i % 32);
+	}
+	S_SWAP.init(|| SwapState {
+		volume: Arc::new(vh),
+		blocks_per_slot: blocks_per_slot,
+		used_slots: used_slots,
+		reading_slots: vec![0u32; bitmap_len],
+		released_slots: vec![0u32; bitmap_len],
+		in_flight: Vec::new(),
+		});
+	*S_TRACKED_PAGES.lock() = Vec::with_capacity(MAX_TRACKED_PAGES);
+	S_RECLAIM_THREAD.init(|| ::threads::WorkerThread::new("Swap Reclaim", reclaim_thread));
+	::memory::phys::register_reclaimer(&S_RECLAIMER);
+	S_SWAP_ENABLED.store(true, Ordering::Release);
+	Ok( () )
+}
+
+/// Returns true if a swap volume is active
+pub fn is_enabled() -> bool
+{
+	S_SWAP_ENABLED.load(Ordering::Acquire)
+}
+
+/// Register a user page (in the current address space) as a candidate for swapping
+///
+/// Called once a page has a private frame (e.g. after a copy-on-write fault)
+pub fn track_page(addr: usize)
+{
+	if !is_enabled() || ::arch::memory::addresses::is_global(addr) {
+		return ;
+	}
+	let aspace = ::arch::memory::virt::current_address_space();
+	let addr = addr & !(::PAGE_SIZE - 1);
+	let mut lh = S_TRACKED_PAGES.lock();
+	if let Some(e) = lh.iter_mut().find(|e| e.aspace == aspace && e.addr == addr) {
+		e.age = 0;
+	}
+	else if lh.len() < lh.capacity() {
+		lh.push(TrackedPage { aspace: aspace, addr: addr, age: 0 });
+	}
+}
+
+/// Stop tracking all pages in an address space
+///
+/// Must be called before any of the address space's page tables are freed. Waits for an in-progress walk of the
+/// tables to complete, and the reclaim thread re-checks that a page is tracked before walking to it.
+pub fn forget_address_space(root: PAddr)
+{
+	if !is_enabled() {
+		return ;
+	}
+	let _walk = S_WALK_LOCK.lock();
+	S_TRACKED_PAGES.lock().retain(|e| e.aspace != root);
+}
+
+fn is_tracked(aspace: PAddr, addr: usize) -> bool
+{
+	S_TRACKED_PAGES.lock().iter().any(|e| e.aspace == aspace && e.addr == addr)
+}
+
+/// Release a swap slot without reading it (the mapping referencing it was discarded)
+pub fn release_slot(slot: usize)
+{
+	let mut lh = S_SWAP.lock();
+	if let Some(pos) = lh.in_flight.iter().position(|e| e.0 == slot) {
+		// Write-out hasn't finished, release the frame too
+		let (_, frame) = lh.in_flight.swap_remove(pos);
+		::memory::phys::deref_frame(frame);
+	}
+	if bit_get(&lh.reading_slots, slot) {
+		// Being read in, freed by `swap_in_complete`
+		bit_set(&mut lh.released_slots, slot);
+	}
+	else {
+		lh.free_slot(slot);
+	}
+}
+
+/// Read a page back from swap, returning the frame now holding its data
+///
+/// Returns `Ok(None)` if another thread is already reading the slot in (the caller should retry the access). On
+/// success the slot stays allocated until the caller reports whether the frame was installed with
+/// `swap_in_complete`.
+pub fn swap_in(slot: usize) -> Result<Option<PAddr>, ()>
+{
+	let (vh, first_block) = {
+		let mut lh = S_SWAP.lock();
+		if !lh.is_slot_used(slot) {
+			log_error!("swap_in({}) - Slot not allocated", slot);
+			return Err( () );
+		}
+		if bit_get(&lh.reading_slots, slot) {
+			return Ok(None);
+		}
+		bit_set(&mut lh.reading_slots, slot);
+		// If the write-out is still in progress, just take the frame back
+		if let Some(pos) = lh.in_flight.iter().position(|e| e.0 == slot) {
+			let (_, frame) = lh.in_flight.swap_remove(pos);
+			return Ok(Some(frame));
+		}
+		(lh.volume.clone(), slot as u64 * lh.blocks_per_slot)
+		};
+
+	let mut page = loop
+		{
+			match ::memory::virt::alloc_free()
+			{
+			Ok(v) => break v,
+			Err(_) if wait_for_memory() => {},
+			Err(_) => {
+				S_SWAP.lock().end_read(slot, false);
+				return Err( () );
+				},
+			}
+		};
+	if let Err(e) = vh.read_blocks(first_block, &mut page[..]) {
+		log_error!("swap_in({}) - Read failed: {:?}", slot, e);
+		// Release the new frame (the slot is kept, in case the error is transient)
+		drop( page.into_frame() );
+		S_SWAP.lock().end_read(slot, false);
+		return Err( () );
+	}
+	Ok( Some(page.into_frame().into_addr()) )
+}
+
+/// Complete a successful `swap_in`, `installed` is true if the page's entry was changed from the swap entry to `frame`
+///
+/// If the entry had changed (e.g. the page was unmapped while being read), the frame is released.
+pub fn swap_in_complete(slot: usize, frame: PAddr, installed: bool)
+{
+	if !installed {
+		::memory::phys::deref_frame(frame);
+	}
+	S_SWAP.lock().end_read(slot, installed);
+}
+
+/// Wait for the reclaim thread to attempt to free memory, returns false if no memory could be freed
+pub fn wait_for_memory() -> bool
+{
+	if !is_enabled() {
+		return false;
+	}
+	::threads::SleepObject::with_new("wait_for_memory", |so| {
+		S_RECLAIM_DONE.wait_upon(so);
+		S_RECLAIM_REQUEST.post();
+		so.wait();
+		S_RECLAIM_DONE.clear_wait(so);
+		});
+	S_LAST_RECLAIMED.load(Ordering::Relaxed) > 0
+}
+
+impl SwapState
+{
+	fn is_slot_used(&self, slot: usize) -> bool {
+		slot / 32 < self.used_slots.len() && self.used_slots[slot / 32] & (1 << (slot % 32)) != 0
+	}
+	fn allocate_slot(&mut self) -> Option<usize> {
+		for (i, w) in self.used_slots.iter_mut().enumerate()
+		{
+			if *w != !0 {
+				let bit = (!*w).trailing_zeros() as usize;
+				*w |= 1 << bit;
+				return Some(i * 32 + bit);
+			}
+		}
+		None
+	}
+	fn free_slot(&mut self, slot: usize) {
+		assert!(self.is_slot_used(slot), "Freeing unallocated swap slot {}", slot);
+		self.used_slots[slot / 32] &= !(1 << (slot % 32));
+	}
+	/// Finish reading a slot in, freeing it if the reader now owns the data or the slot was released meanwhile
+	fn end_read(&mut self, slot: usize, owned: bool) {
+		bit_clear(&mut self.reading_slots, slot);
+		let released = bit_get(&self.released_slots, slot);
+		bit_clear(&mut self.released_slots, slot);
+		if owned || released {
+			self.free_slot(slot);
+		}
+	}
+}
+
+fn bit_get(map: &[u32], i: usize) -> bool {
+	map[i / 32] & (1 << (i % 32)) != 0
+}
+fn bit_set(map: &mut [u32], i: usize) {
+	map[i / 32] |= 1 << (i % 32);
+}
+fn bit_clear(map: &mut [u32], i: usize) {
+	map[i / 32] &= !(1 << (i % 32));
+}
+
+fn reclaim_thread()
+{
+	loop
+	{
+		S_RECLAIM_REQUEST.sleep();
+
+		let mut n_freed = 0;
+		// Age pages (repeating until enough pages are old enough, so a low-memory request always makes progress)
+		for _ in 0 ..= MIN_SWAP_AGE
+		{
+			let candidates = age_pages();
+			n_freed += swap_out(candidates);
+			if n_freed > 0 || ! ::memory::phys::is_memory_low() {
+				break ;
+			}
+		}
+		log_debug!("Swap reclaim pass freed {} frames", n_freed);
+		S_LAST_RECLAIMED.store(n_freed, Ordering::Relaxed);
+		S_RECLAIM_DONE.wake_all();
+	}
+}
+
+/// Update the age of all tracked pages, returning the oldest (up to the batch size)
+fn age_pages() -> Vec<(PAddr, usize)>
+{
+	let mut candidates = Vec::new();
+	let mut i = 0;
+	loop
+	{
+		let (aspace, addr) = match S_TRACKED_PAGES.lock().get(i)
+			{
+			Some(e) => (e.aspace, e.addr),
+			None => break,
+			};
+		// NOTE: The table isn't locked while checking the page, as that can sleep (mapping foreign page tables)
+		let state = {
+			let _walk = S_WALK_LOCK.lock();
+			// The address space could have been released since the entry was read
+			if !is_tracked(aspace, addr) {
+				continue ;
+			}
+			::arch::memory::virt::age_user_page(aspace, addr)
+			};
+		// Only private frames can be swapped (shared ones would need every mapping updated)
+		let is_shared = match state
+			{
+			PageAge::Idle(frame) => ::arch::memory::phys::get_multiref_count(frame as u64 / ::PAGE_SIZE as u64) != 0,
+			_ => false,
+			};
+
+		let mut lh = S_TRACKED_PAGES.lock();
+		// If the table changed while unlocked, check the entry now at this position instead
+		if lh.get(i).map(|e| e.aspace == aspace && e.addr == addr) != Some(true) {
+			continue ;
+		}
+		match state
+		{
+		PageAge::Absent => {
+			lh.swap_remove(i);
+			continue ;
+			},
+		PageAge::Accessed => {
+			lh[i].age = 0;
+			},
+		PageAge::Idle(_) => {
+			let e = &mut lh[i];
+			e.age = e.age.saturating_add(1);
+			if e.age >= MIN_SWAP_AGE && !is_shared {
+				candidates.push( (e.age, aspace, addr) );
+			}
+			},
+		}
+		i += 1;
+	}
+	candidates.sort_by(|a,b| b.0.cmp(&a.0));
+	candidates.into_iter().take(RECLAIM_BATCH_PAGES).map(|(_,s,a)| (s,a)).collect()
+}
+
+/// Write the provided pages out to swap, returning the number of frames released
+fn swap_out(pages: Vec<(PAddr, usize)>) -> usize
+{
+	let mut buf = vec![0u8; ::PAGE_SIZE];
+	let mut n_freed = 0;
+	for (aspace, addr) in pages
+	{
+		let (slot, vh, first_block) = {
+			let _walk = S_WALK_LOCK.lock();
+			// Candidates are collected without the walk lock, so the address space could have been released since
+			if !is_tracked(aspace, addr) {
+				continue ;
+			}
+			let mut lh = S_SWAP.lock();
+			let slot = match lh.allocate_slot()
+				{
+				Some(v) => v,
+				None => {
+					log_warning!("Swap volume is full");
+					break;
+					},
+				};
+			// SAFE: Page is a private user page, and is only replaced if unchanged since it was last aged
+			let frame = match unsafe { ::arch::memory::virt::swap_out_page(aspace, addr, slot) }
+				{
+				Some(f) => f,
+				None => {
+					// Page was accessed or changed, try again later
+					lh.free_slot(slot);
+					continue ;
+					},
+				};
+			lh.in_flight.push( (slot, frame) );
+			// SAFE: The frame is owned by the swap code until the write completes
+			unsafe {
+				::memory::virt::with_temp(frame, |page| buf.copy_from_slice(&page[..]));
+			}
+			(slot, lh.volume.clone(), slot as u64 * lh.blocks_per_slot)
+			};
+		{
+			let mut lh = S_TRACKED_PAGES.lock();
+			if let Some(pos) = lh.iter().position(|e| e.aspace == aspace && e.addr == addr) {
+				lh.swap_remove(pos);
+			}
+		}
+
+		match vh.write_blocks(first_block, &buf)
+		{
+		Ok(_) => {
+			let mut lh = S_SWAP.lock();
+			// If the fault handler took the frame back in the meantime, it now owns it (and has freed the slot)
+			if let Some(pos) = lh.in_flight.iter().position(|e| e.0 == slot) {
+				let (_, frame) = lh.in_flight.swap_remove(pos);
+				::memory::phys::deref_frame(frame);
+				n_freed += 1;
+			}
+			},
+		Err(e) => {
+			// Leave the frame in-flight, the next access will recover it
+			log_error!("Swap write of {:#x} to slot {} failed: {:?}", addr, slot, e);
+			},
+		}
+	}
+	n_freed
+}
+
+/// Memory pressure callback (kicks the reclaim thread, IO can't be done from allocation context)
+struct Reclaimer;
+impl ::memory::phys::Reclaimer for Reclaimer
+{
+	fn name(&self) -> &str {
+		"swap"
+	}
+	fn reclaim(&self, _target: usize) -> usize {
+		S_RECLAIM_REQUEST.post();
+		0
+	}
+}
@@ -18,3 +18,33 @@ pub fn read<T: ::lib::POD>(addr: usize) -> Result<T, ()> {
 		}
 	}
 }
+
+pub fn write<T: ::lib::POD>(addr: usize, val: T) -> Result<(), ()> {
+	assert!( ::core::mem::size_of::<T>() < ::PAGE_SIZE );
+	let size = ::core::mem::size_of::<T>();
+	if addr % ::core::mem::align_of::<T>() != 0 {
+		return Err( () );
+	}
+	if addr.checked_add(size).map(|e| e > ::arch::memory::addresses::USER_END).unwrap_or(true) {
+		return Err( () );
+	}
+	// Check that every page covered is user-writable (COW pages are duplicated by the fault handler)
+	let mut page = addr & !(::PAGE_SIZE - 1);
+	while page < addr + size
+	{
+		match ::memory::virt::get_info(page as *const ())
+		{
+		Some( (_, ::memory::virt::ProtectionMode::UserRW) )
+		| Some( (_, ::memory::virt::ProtectionMode::UserCOW) )
+		| Some( (_, ::memory::virt::ProtectionMode::UserRWX) ) => {},
+		_ => return Err( () ),
+		}
+		page += ::PAGE_SIZE;
+	}
+	// TODO: XXX Handle potential for user to alter the AS during this
+	// SAFE: (Assuming single-thread) Alignment and writability checked
+	unsafe {
+		::core::ptr::write(addr as *mut T, val);
+	}
+	Ok( () )
+}
@@ -32,6 +32,8 @@ pub enum MapError
 {
 	OutOfMemory,
 	RangeInUse,
+	/// The process's `MemoryPages` limit would be exceeded
+	LimitReached,
 }
 impl_from! {
 	From<::memory::phys::Error>(_v) for MapError {
@@ -41,6 +43,7 @@ impl_from! {
 		match v {
 		MapError::OutOfMemory => "VMM: Out of memory",
 		MapError::RangeInUse => "VMM: Range in use",
+		MapError::LimitReached => "VMM: Memory limit reached",
 		}
 	}
 }
@@ -78,12 +81,25 @@ static s_userspace_lock : ::sync::Mutex<()> = mutex_init!( () );
 #[allow(non_upper_case_globals)]
 static s_kernelspace_lock : ::sync::Mutex<()> = mutex_init!( () );
 
+/// Shared all-zero frame, mapped copy-on-write to back demand-zero user memory
+static S_ZERO_FRAME: ::lib::LazyStatic<PAddr> = lazystatic_init!();
+
 #[doc(hidden)]
 pub fn init()
 {
 	// 1. Tell the architecture-specific VMM that it can clean up init state
 	::arch::memory::virt::post_init();
-	// 2. ???
+	// 2. Allocate the zero frame (this reference is never released, so the frame is never made unique)
+	// SAFE: Called once during init, before anything can use the zero frame
+	unsafe {
+		S_ZERO_FRAME.prep(|| {
+			let mut h = ::memory::phys::allocate_bare().expect("Unable to allocate the zero frame");
+			for b in h.iter_mut() {
+				*b = 0;
+			}
+			h.phys_addr()
+			});
+	}
 }
 
 struct Pages(*mut (), usize);
@@ -119,25 +135,31 @@ where
 }
 
 /// Run the provided closure with no changes possible to the address space
-pub fn with_lock<F>(addr: usize, fcn: F)
+pub fn with_lock<F, R>(addr: usize, fcn: F) -> R
 where
-	F: FnOnce()
+	F: FnOnce() -> R
 {
 	// TODO: Lock
-	log_notice!("TODO: with_lock(addr={:#x})", addr);
-	fcn();
+	log_trace!("TODO: with_lock(addr={:#x})", addr);
+	fcn()
 }
 
-/// Ensure that the provded pages are valid (i.e. backed by memory)
-pub fn allocate(addr: *mut (), page_count: usize) -> Result<(), MapError> {
-	allocate_int(addr, page_count, false)
+/// Returns true if the passed frame is the shared zero frame
+pub fn is_zero_frame(paddr: PAddr) -> bool {
+	S_ZERO_FRAME.ls_is_valid() && paddr == *S_ZERO_FRAME
 }
-/// Allocate memory for user access
-pub fn allocate_user(addr: *mut (), page_count: usize) -> Result<(), MapError> {
-	allocate_int(addr, page_count, true)
+
+/// Map the zero frame (copy-on-write) at the given address
+/// UNSAFE: Caller must ensure that the address is a free user page, and that the address space is locked
+unsafe fn map_zero(addr: *mut ())
+{
+	let frame = *S_ZERO_FRAME;
+	::memory::phys::ref_frame(frame);
+	::arch::memory::virt::map(addr, frame, ProtectionMode::UserCOW);
 }
 
-fn allocate_int(addr: *mut (), page_count: usize, is_user: bool) -> Result<(), MapError>
+/// Ensure that the provded pages are valid (i.e. backed by memory)
+pub fn allocate(addr: *mut (), page_count: usize) -> Result<(), MapError>
 {
 	use arch::memory::addresses::is_global;
 
@@ -169,12 +191,43 @@ fn allocate_int(addr: *mut (), page_count: usize, is_user: bool) -> Result<(), M
 			return Err( MapError::OutOfMemory );
 		}
 	}
-	if is_user {
-		for pgptr in Pages(addr, page_count) {
-			// SAFE: This region has just been allocated, and is KernelRW, upgrading to allow user access
-			unsafe {
-				::arch::memory::virt::reprotect(pgptr, ProtectionMode::UserRW);
-			}
+
+	Ok( () )
+}
+
+/// Allocate memory for user access
+///
+/// The pages are demand-zero: they all share the zero frame (copy-on-write) until first written, at which
+/// point the fault handler gives the page its own frame.
+pub fn allocate_user(addr: *mut (), page_count: usize) -> Result<(), MapError>
+{
+	use arch::memory::addresses::is_global;
+
+	if is_global(addr as usize) || is_global(addr as usize + page_count * ::PAGE_SIZE - 1) {
+		log_warning!("allocate_user({:p},{}) - Range is not user memory", addr, page_count);
+		return Err(MapError::RangeInUse);
+	}
+
+	// 1. Lock
+	let _lh = s_userspace_lock.lock();
+	// 2. Ensure range is free
+	for pgptr in Pages(addr, page_count)
+	{
+		if ::arch::memory::virt::is_reserved( pgptr ) {
+			log_warning!("Allocated memory ({:p}) in allocate_user({:p},{})", pgptr, addr, page_count);
+			return Err(MapError::RangeInUse);
+		}
+	}
+	// 3. Reserve against the process's memory limit
+	if ! ::threads::reserve_user_pages(page_count) {
+		return Err(MapError::LimitReached);
+	}
+	// 4. Point every page at the zero frame
+	for pgptr in Pages(addr, page_count)
+	{
+		// SAFE: Range is unused user memory, and the lock is held
+		unsafe {
+			map_zero(pgptr);
 		}
 	}
 
@@ -202,11 +255,19 @@ pub fn reserve(addr: *mut (), page_count: usize) -> Result<Reservation, ()>
 			return Err( () );
 		}
 	}
-	// 3. do `page_count` single arbitary allocations
+	// 3. Back the region
 	for pgptr in Pages(addr, page_count)
 	{
-		// TODO: Instead map in COW zero pages
-		::memory::phys::allocate( pgptr );
+		if is_global(addr as usize) {
+			::memory::phys::allocate( pgptr );
+		}
+		else {
+			// User reservations start as demand-zero, and gain real frames as they're populated
+			// SAFE: Range is unused user memory, and the lock is held
+			unsafe {
+				map_zero(pgptr);
+			}
+		}
 	}
 	
 	Ok( Reservation(addr, page_count) )
@@ -230,9 +291,27 @@ impl Reservation
 	pub fn finalise(self, final_mode: ProtectionMode) -> Result<(),()> {
 		log_trace!("Reservation::finalise(final_mode={:?})", final_mode);
 		for addr in Pages(self.0, self.1) {
+			// Pages that were never written still reference the zero frame, which must never become writable
+			let mode = match final_mode
+				{
+				ProtectionMode::UserRW | ProtectionMode::UserRWX if is_zero_frame(get_phys(addr)) => {
+					if final_mode == ProtectionMode::UserRWX {
+						// COW mappings are non-executable, so give this page its own frame now
+						// SAFE: 'self' owns this region, and the page is mapped
+						unsafe {
+							let frame = try!( ::memory::phys::make_unique( get_phys(addr), &*(addr as *const Page) ).map_err(|_| ()) );
+							::arch::memory::virt::unmap(addr);
+							::arch::memory::virt::map(addr, frame, final_mode);
+						}
+						continue ;
+					}
+					ProtectionMode::UserCOW
+					},
+				m @ _ => m,
+				};
 			// SAFE: Just changing flags, and 'self' owns this region of memory.
 			unsafe {
-				::arch::memory::virt::reprotect(addr, final_mode);
+				::arch::memory::virt::reprotect(addr, mode);
 			}
 		}
 		Ok( () )
@@ -278,6 +357,7 @@ pub unsafe fn reprotect_user(addr: *mut (), prot: ProtectionMode) -> Result<(),(
 		if prot == ProtectionMode::Unmapped {
 			if let Some(paddr) = ::arch::memory::virt::unmap(addr) {
 				::memory::phys::deref_frame(paddr);
+				::threads::release_user_pages(1);
 			}
 		}
 		else {
@@ -544,10 +624,7 @@ impl ops::DerefMut for FreePage {
 pub fn alloc_dma(bits: u8, count: usize, module: &'static str) -> Result<AllocHandle,MapError>
 {
 	// 1. Allocate enough pages within the specified range
-	let phys = ::memory::phys::allocate_range_bits(bits, count);
-	if phys == ::memory::phys::NOPAGE {
-		return Err( MapError::OutOfMemory );
-	}
+	let phys = try!( ::memory::phys::allocate_range_bits(bits, count) );
 	// 2. Map that
 	// SAFE: Physical address has just been allocated
 	unsafe {
@@ -598,6 +675,7 @@ impl fmt::Display for MapError
 		{
 		MapError::RangeInUse => write!(f, "Requested range is in use"),
 		MapError::OutOfMemory => write!(f, "Out of memory"),
+		MapError::LimitReached => write!(f, "Memory limit reached"),
 		}
 	}
 }
@@ -4,7 +4,7 @@
 // Core/metadevs/storage.rs
 // - Storage (block device) subsystem
 use prelude::*;
-use core::sync::atomic::{AtomicUsize};
+use core::sync::atomic::{AtomicUsize,AtomicBool,Ordering};
 use sync::mutex::LazyMutex;
 use lib::{VecMap};
 use lib::mem::Arc;
@@ -22,13 +22,85 @@ pub struct VolumeHandle
 
 /// Physical volume registration (PV will be deregistered when this handle is dropped)
 /// 
-// TODO: What is the behavior when this PV still has LVs (open LVs too?). Just waiting will not
-// be the correct behavior.
+/// When dropped, all logical volumes using this PV are removed (except RAID1 arrays with other working
+/// members, which continue degraded). Open handles to removed volumes return `IoError::NoMedium`.
 pub struct PhysicalVolumeReg
 {
 	idx: usize,
 }
 
+/// Mapper-provided information about a logical volume (e.g. for automount logic)
+#[derive(Debug,Default,Clone)]
+pub struct PartitionInfo
+{
+	/// Partition is flagged as bootable/active
+	pub bootable: bool,
+	/// MBR system ID (partition type)
+	pub system_id: Option<u8>,
+	/// GPT partition type GUID (on-disk byte order)
+	pub type_guid: Option<[u8; 16]>,
+}
+
+/// Layout of a logical volume spanning multiple physical regions
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum ArrayLayout
+{
+	/// Regions are concatenated (JBOD)
+	Linear,
+	/// RAID0 - Data is striped across the regions in chunks of the specified number of blocks
+	Striped(usize),
+	/// RAID1 - Each region holds a complete copy of the data
+	Mirrored,
+}
+impl Default for ArrayLayout {
+	fn default() -> ArrayLayout { ArrayLayout::Linear }
+}
+
+/// Membership of a physical volume in a multi-volume array (reported by a mapper)
+#[derive(Debug)]
+pub struct ArrayMember
+{
+	/// Identifier shared by all members of the array
+	pub array_id: [u8; 16],
+	/// Name of the assembled logical volume
+	pub name: String,
+	pub layout: ArrayLayout,
+	/// Total number of members in the array
+	pub member_count: usize,
+	/// Position of this member within the array
+	pub member_index: usize,
+	/// First data block on this physical volume
+	pub first_block: u64,
+	/// Number of data blocks on this physical volume
+	pub block_count: u64,
+	/// Count of metadata updates (members with a lower count than the others missed updates, and are out of date)
+	pub event_count: u64,
+}
+
+/// Storage subsystem event (logical volume arrival/removal)
+#[derive(Debug,Clone)]
+pub enum VolumeEvent
+{
+	/// A new logical volume is avaliable (LV index, name)
+	Added(usize, String),
+	/// A logical volume has been removed, any open handles now return `IoError::NoMedium` (LV index, name)
+	Removed(usize, String),
+}
+
+/// Kernel-internal handler for volume events
+///
+/// NOTE: Handlers can be called with storage locks held, so must not call back into this module
+pub type EventHandler = fn(&VolumeEvent);
+
+/// Subscription to the volume event log (used to expose events to userland)
+///
+/// Only events that occur after the subscription is created are seen. If the subscriber falls too far
+/// behind, the oldest events are lost.
+pub struct EventSubscription
+{
+	next_seq: AtomicUsize,
+}
+
 /// Helper to print out the size of a volume/size as a pretty SI base 2 number
 pub struct SizePrinter(pub u64);
 
@@ -122,7 +194,24 @@ pub trait Mapper: Send + Sync
 	fn handles_pv(&self, pv: &dyn PhysicalVolume) -> Result<usize,IoError>;
 	
 	/// Enumerate volumes
-	fn enum_volumes(&self, pv: &dyn PhysicalVolume, f: &mut dyn FnMut(String, u64, u64)) -> Result<(),IoError>;
+	///
+	/// Calls `f` with the name, first block, block count, and partition information of each volume
+	fn enum_volumes(&self, pv: &dyn PhysicalVolume, f: &mut dyn FnMut(String, u64, u64, PartitionInfo)) -> Result<(),IoError>;
+
+	/// Enumerate multi-volume arrays that this PV is a member of
+	///
+	/// Arrays are assembled into a logical volume once all members have been reported.
+	fn enum_array_members(&self, _pv: &dyn PhysicalVolume, _f: &mut dyn FnMut(ArrayMember)) -> Result<(),IoError> {
+		Ok( () )
+	}
+
+	/// Record in this PV's metadata that another member (at position `member_index`) of its array has failed
+	///
+	/// Called on the working members of a degraded RAID1 array, so the failed member is seen as out of date if
+	/// it's reported again.
+	fn record_member_failed(&self, _pv: &dyn PhysicalVolume, _member_index: usize) -> Result<(),IoError> {
+		Ok( () )
+	}
 }
 
 
@@ -144,10 +233,18 @@ struct LogicalVolume
 	is_opened: bool,
 	/// Logical block size (max physical block size)
 	block_size: usize,
-	/// Stripe size (number of blocks), None = JBOD
-	chunk_size: Option<usize>,
+	/// How the regions are combined (JBOD, striped, or mirrored)
+	layout: ArrayLayout,
 	/// Physical regions that compose this logical volume
 	regions: Vec<PhysicalRegion>,
+	/// Next mirror to read from (for balancing reads across RAID1 members)
+	next_mirror: AtomicUsize,
+	/// Information provided by the mapper
+	info: PartitionInfo,
+	/// Set when the underlying storage has been removed, all further IO fails
+	is_removed: AtomicBool,
+	/// Identifier of the array this volume was assembled from (if any)
+	array_id: Option<[u8; 16]>,
 }
 /// Physical region used by a logical volume
 struct PhysicalRegion
@@ -155,21 +252,75 @@ struct PhysicalRegion
 	volume: usize,
 	block_count: usize,	// usize to save space in average case
 	first_block: u64,
+	/// Position within the array (for arrays)
+	member_index: usize,
+	/// Set when an IO error is seen on a redundant member, the region is then no longer used
+	is_failed: AtomicBool,
+}
+impl PhysicalRegion
+{
+	fn new(volume: usize, first_block: u64, block_count: usize) -> PhysicalRegion {
+		PhysicalRegion {
+			volume: volume,
+			block_count: block_count,
+			first_block: first_block,
+			member_index: 0,
+			is_failed: AtomicBool::new(false),
+			}
+	}
+}
+
+/// An array that has not yet seen all of its members
+struct PendingArray
+{
+	name: String,
+	layout: ArrayLayout,
+	block_size: usize,
+	/// Members seen so far, with their event counts
+	members: Vec<Option<(PhysicalRegion, u64)>>,
+	/// Time the first member was seen (mirrored arrays are assembled degraded after `DEGRADED_ASSEMBLY_DELAY`)
+	first_seen: ::time::TickCount,
+}
+/// Time to wait for all members of a RAID1 array before assembling it with the members present (ms)
+const DEGRADED_ASSEMBLY_DELAY: ::time::TickCount = 5000;
+/// Interval between attempts to lock the PV list when removing a PV (ms)
+const PV_REMOVE_POLL_INTERVAL: ::time::TickCount = 50;
+/// Interval between warnings while a PV's removal is blocked by in-flight IO (ms)
+const PV_REMOVE_WARN_INTERVAL: ::time::TickCount = 5000;
+
+/// Recent volume events (for `EventSubscription`)
+struct EventLog
+{
+	/// Sequence number of the first entry in `events`
+	first_seq: usize,
+	events: Vec<VolumeEvent>,
 }
+/// Maximum number of events retained for subscribers
+const MAX_LOGGED_EVENTS: usize = 32;
 
 static S_NEXT_PV_IDX: AtomicUsize = AtomicUsize::new(0);
 static S_PHYSICAL_VOLUMES: LazyMutex<VecMap<usize,PhysicalVolumeInfo>> = lazymutex_init!();
 static S_NEXT_LV_IDX: AtomicUsize = AtomicUsize::new(0);
 static S_LOGICAL_VOLUMES: LazyMutex<VecMap<usize,Arc<LogicalVolume>>> = lazymutex_init!();
 static S_MAPPERS: LazyMutex<Vec<&'static dyn Mapper>> = lazymutex_init!();
-
-// NOTE: Should unbinding of LVs be allowed? (Yes, for volume removal)
+static S_PENDING_ARRAYS: LazyMutex<VecMap<[u8; 16],PendingArray>> = lazymutex_init!();
+/// Assembles incomplete mirrored arrays once they've waited long enough
+static S_ASSEMBLY_THREAD: LazyMutex<::threads::WorkerThread> = lazymutex_init!();
+/// Signalled when an incomplete mirrored array is seen
+static S_ASSEMBLY_REQUEST: ::sync::EventChannel = ::sync::EventChannel::new();
+static S_EVENT_HANDLERS: LazyMutex<Vec<EventHandler>> = lazymutex_init!();
+static S_EVENT_LOG: LazyMutex<EventLog> = lazymutex_init!();
+static S_EVENT_WAITERS: ::async::queue::Source = ::async::queue::Source::new();
 
 fn init()
 {
 	S_PHYSICAL_VOLUMES.init( || VecMap::new() );
 	S_LOGICAL_VOLUMES.init( || VecMap::new() );
 	S_MAPPERS.init( || Vec::new() );
+	S_PENDING_ARRAYS.init( || VecMap::new() );
+	S_EVENT_HANDLERS.init( || Vec::new() );
+	S_EVENT_LOG.init( || EventLog { first_seq: 0, events: Vec::new() } );
+	S_ASSEMBLY_THREAD.init( || ::threads::WorkerThread::new("Array Assembly", assembly_thread) );
 	
 	// Default mapper just exposes the PV as a single LV
 	//S_MAPPERS.lock().push_back(&default_mapper::Mapper);
@@ -220,12 +371,16 @@ pub fn register_pv(dev: Box<dyn PhysicalVolume>) -> PhysicalVolumeReg
 		mapper: None,
 		});
 	
-	if let Some(mapper) = best_mapper {
-		apply_mapper_to_pv(mapper, best_mapper_level, pv_id, S_PHYSICAL_VOLUMES.lock().get_mut(&pv_id).unwrap())
-	}
-	else {
-		// Apply the fallback (full volume) mapper
-		apply_mapper_to_pv(&default_mapper::S_MAPPER, 0, pv_id, S_PHYSICAL_VOLUMES.lock().get_mut(&pv_id).unwrap())
+	let events = if let Some(mapper) = best_mapper {
+			apply_mapper_to_pv(mapper, best_mapper_level, pv_id, S_PHYSICAL_VOLUMES.lock().get_mut(&pv_id).unwrap())
+		}
+		else {
+			// Apply the fallback (full volume) mapper
+			apply_mapper_to_pv(&default_mapper::S_MAPPER, 0, pv_id, S_PHYSICAL_VOLUMES.lock().get_mut(&pv_id).unwrap())
+		};
+	// Emitted with the PV list unlocked, as handlers can perform IO
+	for ev in events {
+		emit_event(ev);
 	}
 	
 	PhysicalVolumeReg { idx: pv_id }
@@ -241,6 +396,7 @@ pub fn register_mapper(mapper: &'static dyn Mapper)
 	S_MAPPERS.lock().push(mapper);
 	
 	// Check unbound PVs
+	let mut events = Vec::new();
 	for (&id,pv) in S_PHYSICAL_VOLUMES.lock().iter_mut()
 	{
 		if pv.dev.capacity().is_none() {
@@ -262,24 +418,83 @@ pub fn register_mapper(mapper: &'static dyn Mapper)
 				}
 				else {
 					// Replace
-					apply_mapper_to_pv(mapper, level, id, pv);
+					events.extend( apply_mapper_to_pv(mapper, level, id, pv) );
 				}
 			}
 			else
 			{
-				apply_mapper_to_pv(mapper, level, id, pv);
+				events.extend( apply_mapper_to_pv(mapper, level, id, pv) );
 			},
 		}
 	}
+	// Emitted with the PV list unlocked, as handlers can perform IO
+	for ev in events {
+		emit_event(ev);
+	}
+}
+
+/// Convert a label (e.g. a partition label or array name) into something usable as a volume name
+///
+/// Characters other than ASCII alphanumerics, `-`, `_`, and `.` are replaced with `_`.
+pub fn sanitise_name(label: &str) -> String
+{
+	label.chars()
+		.map(|c| match c
+			{
+			'a' ..= 'z' | 'A' ..= 'Z' | '0' ..= '9' | '-' | '_' | '.' => c,
+			_ => '_',
+			})
+		.collect()
+}
+
+/// Register a handler to be called when logical volumes are added or removed
+pub fn register_event_handler(handler: EventHandler)
+{
+	S_EVENT_HANDLERS.lock().push(handler);
+}
+
+/// Inform handlers and subscribers of a volume event
+fn emit_event(ev: VolumeEvent)
+{
+	log_debug!("emit_event({:?})", ev);
+	let handlers = S_EVENT_HANDLERS.lock().clone();
+	for h in handlers {
+		h(&ev);
+	}
+	{
+		let mut lh = S_EVENT_LOG.lock();
+		if lh.events.len() == MAX_LOGGED_EVENTS {
+			lh.events.remove(0);
+			lh.first_seq += 1;
+		}
+		lh.events.push(ev);
+	}
+	S_EVENT_WAITERS.wake_all();
+}
+
+/// Remove all partial array memberships for a PV
+fn forget_pending_members(pv_id: usize)
+{
+	for (_,a) in S_PENDING_ARRAYS.lock().iter_mut() {
+		for m in a.members.iter_mut() {
+			if m.as_ref().map(|&(ref r, _)| r.volume == pv_id).unwrap_or(false) {
+				*m = None;
+			}
+		}
+	}
 }
 
 /// Apply the passed mapper to the provided physical volume
-fn apply_mapper_to_pv(mapper: &'static dyn Mapper, level: usize, pv_id: usize, pvi: &mut PhysicalVolumeInfo)
+///
+/// Returns the removal events for any LVs from the previous mapper, and the arrival events for the new LVs, for the
+/// caller to emit once the PV list is unlocked
+fn apply_mapper_to_pv(mapper: &'static dyn Mapper, level: usize, pv_id: usize, pvi: &mut PhysicalVolumeInfo) -> Vec<VolumeEvent>
 {
 	// - Can't compare fat raw pointers (ICE, #23888)
 	//assert!(level > 0 || mapper as *const _ == &default_mapper::S_MAPPER as *const _);
 	
 	// TODO: LOCK THE PVI
+	let mut events = Vec::new();
 	// 1. Determine if a previous mapper was controlling this volume
 	if let Some(..) = pvi.mapper
 	{
@@ -295,7 +510,7 @@ fn apply_mapper_to_pv(mapper: &'static dyn Mapper, level: usize, pv_id: usize, p
 				.count();
 			if num_mounted > 0 {
 				log_notice!("{}LVs using PV #{} {} are mounted, not updating mapping", num_mounted, pv_id, pvi.dev.name() );
-				return ;
+				return Vec::new();
 			}
 			// > If none are mounted, then remove the mappings
 			lh.iter()
@@ -305,8 +520,14 @@ fn apply_mapper_to_pv(mapper: &'static dyn Mapper, level: usize, pv_id: usize, p
 			};
 		log_debug!("Removing {} LVs", keys.len());
 		for k in keys {
-			lh.remove(&k);
+			if let Some(lv) = lh.remove(&k) {
+				lv.is_removed.store(true, Ordering::Relaxed);
+				events.push( VolumeEvent::Removed(lv.index, lv.name.clone()) );
+			}
 		}
+		drop(lh);
+		// > Also forget any partial array membership
+		forget_pending_members(pv_id);
 		pvi.mapper = None;
 	}
 	// 2. Bind this new mapper to the volume
@@ -314,15 +535,26 @@ fn apply_mapper_to_pv(mapper: &'static dyn Mapper, level: usize, pv_id: usize, p
 	pvi.mapper = Some( (level, mapper) );
 	// - Enumerate volumes
 	//  TODO: Support more complex volume types
-	match mapper.enum_volumes(&*pvi.dev, &mut |name, base, len| {
-		new_simple_lv(name, pv_id, pvi.dev.blocksize(), base, len);
+	let block_size = pvi.dev.blocksize();
+	match mapper.enum_volumes(&*pvi.dev, &mut |name, base, len, info| {
+		events.push( new_simple_lv(name, pv_id, block_size, base, len, info) );
 		})
 	{
 	Err(e) => log_error!("IO Error while enumerating {}: {:?}", pvi.dev.name(), e),
 	Ok(_) => {},
 	}
+	// - Enumerate array memberships (assembling any arrays that are now complete)
+	match mapper.enum_array_members(&*pvi.dev, &mut |member| {
+		events.extend( add_array_member(pv_id, block_size, member) );
+		})
+	{
+	Err(e) => log_error!("IO Error while enumerating arrays on {}: {:?}", pvi.dev.name(), e),
+	Ok(_) => {},
+	}
+	events
 }
-fn new_simple_lv(name: String, pv_id: usize, block_size: usize, base: u64, size: u64)
+/// Create a single-region logical volume, returning the arrival event (emitted by the caller once unlocked)
+fn new_simple_lv(name: String, pv_id: usize, block_size: usize, base: u64, size: u64, info: PartitionInfo) -> VolumeEvent
 {
 	let lvidx = S_NEXT_LV_IDX.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
 	
@@ -332,18 +564,193 @@ fn new_simple_lv(name: String, pv_id: usize, block_size: usize, base: u64, size:
 		name: name,
 		is_opened: false,
 		block_size: block_size,
-		chunk_size: None,
-		regions: vec![ PhysicalRegion{ volume: pv_id, block_count: size as usize, first_block: base } ],
+		layout: ArrayLayout::Linear,
+		regions: vec![ PhysicalRegion::new(pv_id, base, size as usize) ],
+		next_mirror: AtomicUsize::new(0),
+		info: info,
+		is_removed: AtomicBool::new(false),
+		array_id: None,
 		} );
 	
 	log_log!("Logical Volume: {} {}", lv.name, SizePrinter(size*block_size as u64));
+	let ev = VolumeEvent::Added(lvidx, lv.name.clone());
 	
 	// Add to global list
 	{
 		let mut lh = S_LOGICAL_VOLUMES.lock();
 		lh.insert(lvidx, lv);
 	}
-	// TODO: Inform something of the new LV
+	ev
+}
+
+/// Record a PV as a member of an array, and assemble the array if all members are present
+///
+/// Returns the arrival event if the array was assembled.
+fn add_array_member(pv_id: usize, block_size: usize, member: ArrayMember) -> Option<VolumeEvent>
+{
+	use lib::vec_map::Entry;
+	log_debug!("PV #{} is member {}/{} of array '{}' ({:?})", pv_id, member.member_index, member.member_count, member.name, member.layout);
+	if member.member_index >= member.member_count || member.block_count > !0usize as u64 || member.layout == ArrayLayout::Striped(0) {
+		log_warning!("Invalid array membership for PV #{}: {:?}", pv_id, member);
+		return None;
+	}
+
+	if S_LOGICAL_VOLUMES.lock().iter().any(|(_,lv)| lv.array_id == Some(member.array_id)) {
+		// NOTE: Adding a member back into a running (degraded) array isn't supported
+		log_notice!("PV #{} is member {} of already assembled array '{}', ignoring", pv_id, member.member_index, member.name);
+		return None;
+	}
+
+	let mut lh = S_PENDING_ARRAYS.lock();
+	let is_complete = {
+		let ent = match lh.entry(member.array_id)
+			{
+			Entry::Occupied(e) => e.into_mut(),
+			Entry::Vacant(e) => e.insert(PendingArray {
+				name: member.name.clone(),
+				layout: member.layout,
+				block_size: block_size,
+				members: (0 .. member.member_count).map(|_| None).collect(),
+				first_seen: ::time::ticks(),
+				}),
+			};
+		if ent.layout != member.layout || ent.members.len() != member.member_count || ent.block_size != block_size {
+			log_warning!("PV #{} disagrees with other members of array '{}' ({:?} {} {}B vs {:?} {} {}B)",
+				pv_id, ent.name,
+				member.layout, member.member_count, block_size,
+				ent.layout, ent.members.len(), ent.block_size
+				);
+			return None;
+		}
+		let slot = &mut ent.members[member.member_index];
+		if slot.is_some() {
+			log_warning!("Duplicate member {} of array '{}' (PV #{})", member.member_index, ent.name, pv_id);
+			return None;
+		}
+		let region = PhysicalRegion {
+			member_index: member.member_index,
+			..PhysicalRegion::new(pv_id, member.first_block, member.block_count as usize)
+			};
+		*slot = Some( (region, member.event_count) );
+		ent.members.iter().all(|m| m.is_some())
+		};
+
+	if is_complete
+	{
+		let a = lh.remove(&member.array_id).unwrap();
+		drop(lh);
+		Some( assemble_array(member.array_id, a) )
+	}
+	else
+	{
+		if member.layout == ArrayLayout::Mirrored {
+			drop(lh);
+			S_ASSEMBLY_REQUEST.post();
+		}
+		None
+	}
+}
+/// Create the logical volume for a pending array, using the members present
+///
+/// Mirrored array members with an out-of-date event count (e.g. a member that failed and has come back) are left
+/// out, as they've missed writes. They aren't used until resynced.
+fn assemble_array(array_id: [u8; 16], a: PendingArray) -> VolumeEvent
+{
+	let count = a.members.len();
+	let mut members: Vec<(PhysicalRegion, u64)> = a.members.into_iter().filter_map(|m| m).collect();
+	if a.layout == ArrayLayout::Mirrored
+	{
+		let latest = members.iter().map(|m| m.1).max().unwrap_or(0);
+		for &(ref r, ev) in members.iter().filter(|m| m.1 < latest) {
+			log_warning!("Array '{}' member {} (PV #{}) is out of date ({} < {} events), not using until resynced",
+				a.name, r.member_index, r.volume, ev, latest);
+		}
+		members.retain(|m| m.1 == latest);
+	}
+	if members.len() < count {
+		log_warning!("Array '{}' is missing {} of {} members, assembling degraded", a.name, count - members.len(), count);
+	}
+	let regions = members.into_iter().map(|m| m.0).collect();
+	new_array_lv(array_id, a.name, a.layout, a.block_size, regions)
+}
+/// Assemble mirrored arrays that are still missing members after `DEGRADED_ASSEMBLY_DELAY`
+fn assembly_thread()
+{
+	loop
+	{
+		let any_pending = S_PENDING_ARRAYS.lock().iter().any(|(_,a)| a.layout == ArrayLayout::Mirrored);
+		if !any_pending {
+			S_ASSEMBLY_REQUEST.sleep();
+			continue ;
+		}
+
+		::threads::SleepObject::with_new("assembly_delay", |obj| {
+			let _timer = ::time::Timer::new(::time::ticks() + DEGRADED_ASSEMBLY_DELAY / 2, obj.get_ref());
+			obj.wait();
+			});
+
+		let now = ::time::ticks();
+		let mut degraded = Vec::new();
+		{
+			let mut lh = S_PENDING_ARRAYS.lock();
+			let ids: Vec<[u8; 16]> = lh.iter()
+				.filter(|&(_,a)| a.layout == ArrayLayout::Mirrored && now >= a.first_seen + DEGRADED_ASSEMBLY_DELAY)
+				.filter(|&(_,a)| a.members.iter().any(|m| m.is_some()))
+				.map(|(&id,_)| id)
+				.collect();
+			for id in ids {
+				degraded.push( (id, lh.remove(&id).unwrap()) );
+			}
+		}
+		for (id, a) in degraded
+		{
+			let ev = assemble_array(id, a);
+			emit_event(ev);
+		}
+	}
+}
+/// Create a logical volume composed of several physical regions, returning the arrival event
+fn new_array_lv(array_id: [u8; 16], name: String, layout: ArrayLayout, block_size: usize, mut regions: Vec<PhysicalRegion>) -> VolumeEvent
+{
+	let lvidx = S_NEXT_LV_IDX.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
+
+	// Striped and mirrored volumes use the same amount of each member
+	let min_count = regions.iter().map(|r| r.block_count).min().unwrap_or(0);
+	let size = match layout
+		{
+		ArrayLayout::Linear => regions.iter().map(|r| r.block_count as u64).sum(),
+		ArrayLayout::Striped(chunk_size) => {
+			let per_member = min_count - min_count % chunk_size;
+			for r in regions.iter_mut() {
+				r.block_count = per_member;
+			}
+			per_member as u64 * regions.len() as u64
+			},
+		ArrayLayout::Mirrored => {
+			for r in regions.iter_mut() {
+				r.block_count = min_count;
+			}
+			min_count as u64
+			},
+		};
+
+	let lv = Arc::new( LogicalVolume {
+		index: lvidx,
+		name: name,
+		is_opened: false,
+		block_size: block_size,
+		layout: layout,
+		regions: regions,
+		next_mirror: AtomicUsize::new(0),
+		info: Default::default(),
+		is_removed: AtomicBool::new(false),
+		array_id: Some(array_id),
+		} );
+	log_log!("Logical Volume: {} {} ({:?} over {} PVs)", lv.name, SizePrinter(size*block_size as u64), layout, lv.regions.len());
+	let ev = VolumeEvent::Added(lvidx, lv.name.clone());
+
+	S_LOGICAL_VOLUMES.lock().insert(lvidx, lv);
+	ev
 }
 
 /// Enumerate present physical volumes (returning both the identifier and name)
@@ -418,16 +825,31 @@ impl VolumeHandle
 	pub fn name(&self) -> &str {
 		&self.handle.name
 	}
+	/// Number of blocks in the volume
+	pub fn block_count(&self) -> u64 {
+		let regions = &self.handle.regions;
+		match self.handle.layout
+		{
+		// NOTE: Striped members are all trimmed to the same size on creation
+		ArrayLayout::Linear | ArrayLayout::Striped(_) => regions.iter().map(|r| r.block_count as u64).sum(),
+		ArrayLayout::Mirrored => regions.first().map(|r| r.block_count as u64).unwrap_or(0),
+		}
+	}
+	/// Partition information provided by the mapper
+	pub fn partition_info(&self) -> &PartitionInfo {
+		&self.handle.info
+	}
+	/// Returns `true` if the underlying storage has been removed (all IO will fail)
+	pub fn is_removed(&self) -> bool {
+		self.handle.is_removed.load(Ordering::Relaxed)
+	}
 	
 	// TODO: Return a more complex type that can be incremented
 	// Returns: VolIdx, Block, Count
 	fn get_phys_block(&self, idx: u64, count: usize) -> Option<(usize,u64,usize)> {
-		if let Some(size) = self.handle.chunk_size
-		{
-			todo!("Non JBOD logocal volumes ({} block stripe)", size);
-		}
-		else
+		match self.handle.layout
 		{
+		ArrayLayout::Linear => {
 			let mut idx_rem = idx;
 			for v in self.handle.regions.iter()
 			{
@@ -442,8 +864,98 @@ impl VolumeHandle
 					idx_rem -= v.block_count as u64;
 				}
 			}
+			None
+			},
+		ArrayLayout::Striped(chunk_size) => {
+			let n_members = self.handle.regions.len() as u64;
+			let chunk_size = chunk_size as u64;
+			let (stripe, ofs) = ::lib::num::div_rem(idx, chunk_size);
+			let v = &self.handle.regions[(stripe % n_members) as usize];
+			let blk = (stripe / n_members) * chunk_size + ofs;
+			if blk >= v.block_count as u64 {
+				return None;
+			}
+			let ret_count = ::core::cmp::min(chunk_size - ofs, count as u64) as usize;
+			Some( (v.volume, v.first_block + blk, ret_count) )
+			},
+		// Mirrored volumes are handled by `read_mirrored`/`write_mirrored`
+		ArrayLayout::Mirrored => None,
+		}
+	}
+
+	/// Read from a RAID1 volume, balancing between members and skipping failed ones
+	fn read_mirrored(&self, idx: u64, dst: &mut [u8]) -> Result<(),IoError> {
+		let regions = &self.handle.regions;
+		let count = (dst.len() / self.block_size()) as u64;
+		if regions.len() == 0 || idx + count > regions[0].block_count as u64 {
+			return Err( IoError::BadAddr );
+		}
+		let start = self.handle.next_mirror.fetch_add(1, Ordering::Relaxed);
+		let mut last_err = IoError::NoMedium;
+		for i in 0 .. regions.len()
+		{
+			let r = &regions[(start + i) % regions.len()];
+			if r.is_failed.load(Ordering::Relaxed) {
+				continue ;
+			}
+			let res = match S_PHYSICAL_VOLUMES.lock().get(&r.volume)
+				{
+				Some(pv) => pv.read(r.first_block + idx, dst),
+				None => Err(IoError::NoMedium),
+				};
+			match res
+			{
+			Ok(_) => return Ok( () ),
+			Err(e) => {
+				log_error!("{}: Mirror member PV #{} failed ({:?}), continuing degraded", self.name(), r.volume, e);
+				if !r.is_failed.swap(true, Ordering::Relaxed) {
+					record_mirror_failure(&self.handle, r.member_index);
+				}
+				last_err = e;
+				},
+			}
+		}
+		log_error!("{}: All mirror members have failed", self.name());
+		Err(last_err)
+	}
+	/// Write to all working members of a RAID1 volume
+	fn write_mirrored(&self, idx: u64, src: &[u8]) -> Result<(),IoError> {
+		let regions = &self.handle.regions;
+		let count = (src.len() / self.block_size()) as u64;
+		if regions.len() == 0 || idx + count > regions[0].block_count as u64 {
+			return Err( IoError::BadAddr );
+		}
+		let mut n_written = 0;
+		let mut last_err = IoError::NoMedium;
+		for r in regions.iter()
+		{
+			if r.is_failed.load(Ordering::Relaxed) {
+				continue ;
+			}
+			let res = match S_PHYSICAL_VOLUMES.lock().get(&r.volume)
+				{
+				Some(pv) => pv.write(r.first_block + idx, src),
+				None => Err(IoError::NoMedium),
+				};
+			match res
+			{
+			Ok(_) => n_written += 1,
+			Err(e) => {
+				log_error!("{}: Mirror member PV #{} failed ({:?}), continuing degraded", self.name(), r.volume, e);
+				if !r.is_failed.swap(true, Ordering::Relaxed) {
+					record_mirror_failure(&self.handle, r.member_index);
+				}
+				last_err = e;
+				},
+			}
+		}
+		if n_written == 0 {
+			log_error!("{}: All mirror members have failed", self.name());
+			Err(last_err)
+		}
+		else {
+			Ok( () )
 		}
-		None
 	}
 	
 	/// Read a series of blocks from the volume into the provided buffer.
@@ -451,10 +963,16 @@ impl VolumeHandle
 	/// The buffer must be a multiple of the logical block size
 	pub fn read_blocks(&self, idx: u64, dst: &mut [u8]) -> Result<(),IoError> {
 		log_trace!("VolumeHandle::read_blocks(idx={}, dst={{len={}}})", idx, dst.len());
+		if self.is_removed() {
+			return Err( IoError::NoMedium );
+		}
 		if dst.len() % self.block_size() != 0 {
 			log_warning!("Read size {} not a multiple of {} bytes", dst.len(), self.block_size());
 			return Err( IoError::InvalidParameter );
 		}
+		if self.handle.layout == ArrayLayout::Mirrored {
+			return self.read_mirrored(idx, dst);
+		}
 		
 		let mut rem = dst.len() / self.block_size();
 		let mut blk = 0;
@@ -471,7 +989,7 @@ impl VolumeHandle
 			assert!(count <= rem);
 			let bofs = blk as usize * self.block_size();
 			let dst = &mut dst[bofs .. bofs + count * self.block_size()];
-			try!( S_PHYSICAL_VOLUMES.lock().get(&pv).expect("Volume missing").read(ofs, dst) );
+			try!( S_PHYSICAL_VOLUMES.lock().get(&pv).ok_or(IoError::NoMedium).and_then(|pv| pv.read(ofs, dst)) );
 			blk += count;
 			rem -= count;
 		}
@@ -480,10 +998,16 @@ impl VolumeHandle
 
 	pub fn write_blocks(&self, idx: u64, dst: &[u8]) -> Result<(),IoError> {
 		log_trace!("VolumeHandle::write_blocks(idx={}, dst={{len={}}})", idx, dst.len());
+		if self.is_removed() {
+			return Err( IoError::NoMedium );
+		}
 		if dst.len() % self.block_size() != 0 {
 			log_warning!("Write size {} not a multiple of {} bytes", dst.len(), self.block_size());
 			return Err( IoError::InvalidParameter );
 		}
+		if self.handle.layout == ArrayLayout::Mirrored {
+			return self.write_mirrored(idx, dst);
+		}
 		
 		let mut rem = dst.len() / self.block_size();
 		let mut blk = 0;
@@ -500,7 +1024,7 @@ impl VolumeHandle
 			assert!(count <= rem);
 			let bofs = blk as usize * self.block_size();
 			let dst = &dst[bofs .. bofs + count * self.block_size()];
-			try!( S_PHYSICAL_VOLUMES.lock().get(&pv).unwrap().write(ofs, dst) );
+			try!( S_PHYSICAL_VOLUMES.lock().get(&pv).ok_or(IoError::NoMedium).and_then(|pv| pv.write(ofs, dst)) );
 			blk += count;
 			rem -= count;
 		}
@@ -537,7 +1061,10 @@ impl PhysicalVolumeInfo
 				let real_count = match self.dev.read(prio, blk_id, blocks, buf).wait()
 					{
 					Ok(v) => v,
-					Err(e) => todo!("Error when PV fails to read: {:?}", e),
+					Err(e) => {
+						log_error!("PV {} read error at block {}: {:?}", self.dev.name(), blk_id, e);
+						return Err(e);
+						},
 					};
 				assert!(real_count <= blocks);
 				blk_id += real_count as u64;
@@ -570,7 +1097,10 @@ impl PhysicalVolumeInfo
 				match self.dev.write(prio, blk_id, blocks, buf).wait()
 				{
 				Ok(real_count) => { assert!(real_count == blocks, "TODO: Handle incomplete writes"); },
-				Err(e) => todo!("Error when PV fails to write: {:?}", e),
+				Err(e) => {
+					log_error!("PV {} write error at block {}: {:?}", self.dev.name(), blk_id, e);
+					return Err(e);
+					},
 				}
 			}
 		}
@@ -582,7 +1112,144 @@ impl ::core::ops::Drop for PhysicalVolumeReg
 {
 	fn drop(&mut self)
 	{
-		todo!("PhysicalVolumeReg::drop idx={}", self.idx);
+		// NOTE: IO is performed with the PV list locked, so this waits for any in-flight requests to complete
+		// (the driver is expected to fail them once the device is gone). A driver that never does would block here
+		// forever, so the wait is logged.
+		let start = ::time::ticks();
+		let mut next_warn = start + PV_REMOVE_WARN_INTERVAL;
+		let mut lh = loop
+			{
+				if let Some(lh) = S_PHYSICAL_VOLUMES.try_lock() {
+					break lh;
+				}
+				let now = ::time::ticks();
+				if now >= next_warn {
+					log_warning!("PV #{} removal waiting on in-flight IO ({}ms)", self.idx, now - start);
+					next_warn = now + PV_REMOVE_WARN_INTERVAL;
+				}
+				::threads::SleepObject::with_new("pv_remove", |obj| {
+					let _timer = ::time::Timer::new(::time::ticks() + PV_REMOVE_POLL_INTERVAL, obj.get_ref());
+					obj.wait();
+					});
+			};
+		let pvi = match lh.remove(&self.idx)
+			{
+			Some(v) => v,
+			None => return,
+			};
+		drop(lh);
+		log_notice!("PV #{} '{}' removed", self.idx, pvi.dev.name());
+		forget_pending_members(self.idx);
+
+		// Tear down all LVs that depend on this PV
+		let mut removed = Vec::new();
+		let mut degraded = Vec::new();
+		{
+			let mut lh = S_LOGICAL_VOLUMES.lock();
+			let keys: Vec<usize> = lh.iter()
+				.filter( |&(_,lv)| lv.regions.iter().any(|r| r.volume == self.idx) )
+				.map(|(&i,_)| i)
+				.collect();
+			for k in keys
+			{
+				if let Some(lv) = lh.get(&k)
+				{
+					if lv.layout == ArrayLayout::Mirrored
+					{
+						let newly_failed: Vec<usize> = lv.regions.iter()
+							.filter(|r| r.volume == self.idx)
+							.filter(|r| !r.is_failed.swap(true, Ordering::Relaxed))
+							.map(|r| r.member_index)
+							.collect();
+						if lv.regions.iter().any(|r| !r.is_failed.load(Ordering::Relaxed)) {
+							log_warning!("{}: Mirror member PV #{} removed, continuing degraded", lv.name, self.idx);
+							for i in newly_failed {
+								degraded.push( (lv.clone(), i) );
+							}
+							continue ;
+						}
+					}
+				}
+				if let Some(lv) = lh.remove(&k) {
+					log_log!("Logical Volume {} removed{}", lv.name, if Arc::strong_count(&lv) > 1 { " (while open)" } else { "" });
+					lv.is_removed.store(true, Ordering::Relaxed);
+					removed.push( VolumeEvent::Removed(lv.index, lv.name.clone()) );
+				}
+			}
+		}
+		drop(pvi);
+
+		for (lv, member_index) in degraded {
+			record_mirror_failure(&lv, member_index);
+		}
+		for ev in removed {
+			emit_event(ev);
+		}
+	}
+}
+
+/// Record the failure of a mirror member in the metadata of the array's working members
+///
+/// Called with no storage locks held.
+fn record_mirror_failure(lv: &LogicalVolume, member_index: usize)
+{
+	let pvs = S_PHYSICAL_VOLUMES.lock();
+	for r in lv.regions.iter().filter(|r| !r.is_failed.load(Ordering::Relaxed))
+	{
+		let pv = match pvs.get(&r.volume)
+			{
+			Some(v) => v,
+			None => continue,
+			};
+		if let Some((_, mapper)) = pv.mapper {
+			if let Err(e) = mapper.record_member_failed(&*pv.dev, member_index) {
+				log_error!("{}: Unable to record failure of member {} on PV #{} ({:?})", lv.name, member_index, r.volume, e);
+			}
+		}
+	}
+}
+
+impl EventSubscription
+{
+	/// Create a new subscription (seeing only future events)
+	pub fn new() -> EventSubscription {
+		let lh = S_EVENT_LOG.lock();
+		EventSubscription {
+			next_seq: AtomicUsize::new(lh.first_seq + lh.events.len()),
+		}
+	}
+
+	/// Returns `true` if there are events waiting to be read
+	pub fn has_event(&self) -> bool {
+		let lh = S_EVENT_LOG.lock();
+		self.next_seq.load(Ordering::Relaxed) < lh.first_seq + lh.events.len()
+	}
+	/// Read the next event (if avaliable)
+	pub fn pop(&self) -> Option<VolumeEvent> {
+		let lh = S_EVENT_LOG.lock();
+		let mut seq = self.next_seq.load(Ordering::Relaxed);
+		if seq < lh.first_seq {
+			log_notice!("EventSubscription: {} events lost", lh.first_seq - seq);
+			seq = lh.first_seq;
+		}
+		let rv = lh.events.get(seq - lh.first_seq).cloned();
+		if rv.is_some() {
+			seq += 1;
+		}
+		self.next_seq.store(seq, Ordering::Relaxed);
+		rv
+	}
+
+	/// Register a sleep object to be woken when a new event arrives
+	pub fn wait_upon(&self, waiter: &mut ::threads::SleepObject) {
+		S_EVENT_WAITERS.wait_upon(waiter);
+		if self.has_event() {
+			waiter.signal();
+		}
+	}
+	/// Remove a sleep object registered with `wait_upon`
+	pub fn clear_wait(&self, waiter: &mut ::threads::SleepObject) {
+		S_EVENT_WAITERS.clear_wait(waiter);
 	}
 }
 
@@ -629,9 +1296,9 @@ mod default_mapper
 			// The fallback mapper never explicitly handles
 			Ok(0)
 		}
-		fn enum_volumes(&self, pv: &dyn storage::PhysicalVolume, new_volume_cb: &mut dyn FnMut(String, u64, u64)) -> Result<(),super::IoError> {
+		fn enum_volumes(&self, pv: &dyn storage::PhysicalVolume, new_volume_cb: &mut dyn FnMut(String, u64, u64, storage::PartitionInfo)) -> Result<(),super::IoError> {
 			if let Some(cap) = pv.capacity() {
-				new_volume_cb(format!("{}w", pv.name()), 0, cap );
+				new_volume_cb(format!("{}w", pv.name()), 0, cap, Default::default() );
 			}
 			Ok( () )
 		}
@@ -0,0 +1,187 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/profiler.rs
+//! Sampling profiler
+//!
+//! The architecture's timer interrupt records the interrupted instruction pointer into a ring buffer, so the buffer
+//! always holds the most recent samples. `/proc/profile` summarises the buffer, with kernel addresses grouped by
+//! function (using the kernel's symbol table) and user addresses grouped by thread.
+//!
+//! Timer interrupts only fire when a deadline is due, so the profiler must be enabled at boot (`PROFILE=1`) to keep
+//! a periodic deadline armed on every CPU (see `time::rearm`).
+use prelude::*;
+use core::fmt;
+use core::sync::atomic::{AtomicBool,Ordering};
+
+/// Number of samples retained
+const NUM_SAMPLES: usize = 4096;
+/// Number of entries listed in each section of the summary
+const MAX_LISTED: usize = 40;
+/// Maximum interval between samples on each CPU (ms), while enabled
+pub const SAMPLE_PERIOD: ::time::TickCount = 10;
+
+/// Set if periodic sampling is enabled
+static S_ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Copy,Clone)]
+struct Sample
+{
+	ip: usize,
+	tid: ::threads::ThreadID,
+	is_user: bool,
+}
+const SAMPLE_INIT: Sample = Sample { ip: 0, tid: 0, is_user: false };
+
+struct SampleBuf
+{
+	samples: [Sample; NUM_SAMPLES],
+	/// Index of the next sample to write
+	pos: usize,
+	/// Number of valid samples
+	count: usize,
+}
+
+static S_SAMPLES: ::sync::Spinlock<SampleBuf> = ::sync::Spinlock::new(SampleBuf {
+	samples: [SAMPLE_INIT; NUM_SAMPLES],
+	pos: 0,
+	count: 0,
+	});
+
+/// Enable periodic sampling if requested on the command line
+pub fn init()
+{
+	if ::config::get_string(::config::Value::Profile) != "0" {
+		log_notice!("Profiling enabled, sampling every {}ms", SAMPLE_PERIOD);
+		S_ENABLED.store(true, Ordering::Relaxed);
+	}
+}
+
+/// Returns true if the timer interrupt should fire periodically to take samples
+//#[is_safe(irq)]
+pub fn is_enabled() -> bool
+{
+	S_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Record a sample (called from the timer interrupt with the interrupted instruction pointer)
+//#[is_safe(irq)]
+pub fn sample(ip: usize, is_user: bool)
+{
+	let tid = ::threads::get_thread_id();
+	let mut lh = S_SAMPLES.lock();
+	let pos = lh.pos;
+	lh.samples[pos] = Sample { ip: ip, tid: tid, is_user: is_user };
+	lh.pos = (pos + 1) % NUM_SAMPLES;
+	if lh.count < NUM_SAMPLES {
+		lh.count += 1;
+	}
+}
+
+/// Copy the current samples out of the ring buffer
+fn get_samples() -> Vec<Sample>
+{
+	let mut rv = Vec::with_capacity(NUM_SAMPLES);
+	// NOTE: Interrupts held, as `sample` is called from an interrupt
+	let _irq = ::arch::sync::hold_interrupts();
+	let lh = S_SAMPLES.lock();
+	rv.push_all(&lh.samples[..lh.count]);
+	rv
+}
+
+/// Name of the kernel function containing `addr`
+#[cfg(not(feature="test"))]
+fn symbol_for(addr: usize) -> Option<&'static str> {
+	::symbols::get_symbol_for_addr(addr).map(|(name, _ofs)| name)
+}
+#[cfg(not(feature="test"))]
+fn demangle(name: &str) -> ::symbols::Demangle {
+	::symbols::Demangle(name)
+}
+// No symbol table when running as a test
+#[cfg(feature="test")]
+fn symbol_for(_addr: usize) -> Option<&'static str> {
+	None
+}
+#[cfg(feature="test")]
+fn demangle(name: &str) -> &str {
+	name
+}
+
+/// Sort `(key, count)` pairs with the most frequent first
+fn sort_by_count<T>(ents: &mut [(T, usize)])
+{
+	ents.sort_by(|a, b| b.1.cmp(&a.1));
+}
+
+/// Write a summary of the recorded samples (`/proc/profile`)
+pub fn dump(f: &mut dyn fmt::Write) -> fmt::Result
+{
+	let samples = get_samples();
+	if !is_enabled() {
+		try!(writeln!(f, "Periodic sampling disabled (boot with PROFILE=1), samples are only taken at deadlines"));
+	}
+	let n_user = samples.iter().filter(|s| s.is_user).count();
+	let n_kernel = samples.len() - n_user;
+	try!(writeln!(f, "{} samples ({} kernel, {} user)", samples.len(), n_kernel, n_user));
+	if samples.len() == 0 {
+		return Ok( () );
+	}
+
+	// Kernel: Group by address, then by the function containing it
+	let mut addrs: Vec<usize> = samples.iter().filter(|s| !s.is_user).map(|s| s.ip).collect();
+	addrs.sort();
+	let mut funcs: Vec<(Option<&'static str>, usize)> = Vec::new();
+	let mut i = 0;
+	while i < addrs.len()
+	{
+		let addr = addrs[i];
+		let n = addrs[i..].iter().take_while(|&&a| a == addr).count();
+		i += n;
+		let name = symbol_for(addr);
+		match funcs.iter().position(|e| e.0 == name)
+		{
+		Some(j) => funcs[j].1 += n,
+		None => funcs.push( (name, n) ),
+		}
+	}
+	sort_by_count(&mut funcs);
+	try!(writeln!(f, ""));
+	try!(writeln!(f, "Kernel:"));
+	for &(name, n) in funcs.iter().take(MAX_LISTED)
+	{
+		match name
+		{
+		Some(name) => try!(writeln!(f, "{:6} {:3}% {}", n, n * 100 / samples.len(), demangle(name))),
+		None => try!(writeln!(f, "{:6} {:3}% (unknown)", n, n * 100 / samples.len())),
+		}
+	}
+
+	// User: Group by thread (user symbols aren't available to the kernel)
+	let mut threads: Vec<(::threads::ThreadID, usize)> = Vec::new();
+	for s in samples.iter().filter(|s| s.is_user)
+	{
+		match threads.iter().position(|e| e.0 == s.tid)
+		{
+		Some(j) => threads[j].1 += 1,
+		None => threads.push( (s.tid, 1) ),
+		}
+	}
+	sort_by_count(&mut threads);
+	try!(writeln!(f, ""));
+	try!(writeln!(f, "User:"));
+	for &(tid, n) in threads.iter().take(MAX_LISTED)
+	{
+		let mut name = None;
+		::threads::enumerate_threads(tid, |t| {
+			if t.tid == tid {
+				name = Some(String::from(t.name));
+			}
+			false
+			});
+		try!(writeln!(f, "{:6} {:3}% {} {}", n, n * 100 / samples.len(), tid, name.as_ref().map(|s| &s[..]).unwrap_or("(exited)")));
+	}
+	Ok( () )
+}
+
+// vim: ft=rust
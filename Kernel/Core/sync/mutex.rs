@@ -77,6 +77,19 @@ impl<T: Send> Mutex<T>
 		::core::sync::atomic::fence(::core::sync::atomic::Ordering::Acquire);
 		return HeldMutex { lock: self };
 	}
+	/// Attempt to lock the mutex, returning `None` if it is already held (by any thread)
+	pub fn try_lock(&self) -> Option<HeldMutex<T>> {
+		{
+			let mut lh = self.inner.lock();
+			if lh.held {
+				return None;
+			}
+			lh.held = true;
+			lh.holder = ::threads::get_thread_id();
+		}
+		::core::sync::atomic::fence(::core::sync::atomic::Ordering::Acquire);
+		Some( HeldMutex { lock: self } )
+	}
 	/// Release the mutex
 	fn unlock(&self) {
 		::core::sync::atomic::fence(::core::sync::atomic::Ordering::Release);
@@ -133,6 +146,15 @@ impl<T: Send> LazyMutex<T>
 		assert!(lh.is_some(), "Locking an uninitialised LazyMutex<{}>", type_name!(T));
 		HeldLazyMutex( lh )
 	}
+	/// Attempt to lock the lazy mutex (returning `None` if held, or not initialised)
+	pub fn try_lock(&self) -> Option<HeldLazyMutex<T>>
+	{
+		match self.0.try_lock()
+		{
+		Some(lh) if lh.is_some() => Some( HeldLazyMutex( lh ) ),
+		_ => None,
+		}
+	}
 }
 
 impl<'lock,T:Send> ops::Drop for HeldMutex<'lock,T>
@@ -0,0 +1,175 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/exceptions.rs
+//! User exception delivery
+//!
+//! A user thread can register a handler that is invoked (on an optional alternate stack) when the thread faults, or
+//! when another thread sends it a notification. The handler receives the interrupted register state, and resumes
+//! by passing a (possibly modified) copy back to `exception_return`.
+//!
+//! Faults are delivered immediately by the architecture's fault handler. Notifications are queued, and delivered
+//! when the thread next returns to user-mode (`check_user_events` flags the return path, which then calls
+//! `take_notification`).
+use prelude::*;
+
+/// Exit status of a process killed by an unhandled exception (matches `EXITSTATUS_FAULT` in the syscall ABI)
+pub const EXIT_STATUS_FAULT: u32 = 0x8000_0002;
+
+/// Exception type (values match `ExceptionKind` in the syscall ABI)
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum ExceptionKind
+{
+	PageFault = 0,
+	InvalidInstruction = 1,
+	DivideError = 2,
+	ProtectionFault = 3,
+	Breakpoint = 4,
+	Notification = 5,
+}
+
+/// Saved state passed to the handler (layout matches `ExceptionFrame` in the syscall ABI)
+#[derive(Copy,Clone)]
+#[repr(C)]
+pub struct ExceptionFrame
+{
+	pub kind: u32,
+	pub code: u32,
+	pub address: usize,
+	pub ip: usize,
+	pub sp: usize,
+	pub fp: usize,
+	pub regs: [usize; 32],
+}
+unsafe impl ::lib::POD for ExceptionFrame {}
+
+impl ExceptionFrame
+{
+	pub fn new(kind: ExceptionKind, code: u32, address: usize) -> ExceptionFrame {
+		ExceptionFrame {
+			kind: kind as u32,
+			code: code,
+			address: address,
+			ip: 0,
+			sp: 0,
+			fp: 0,
+			regs: [0; 32],
+		}
+	}
+}
+
+/// Per-thread handler registration and pending notifications
+pub struct ThreadExceptions
+{
+	inner: ::sync::Spinlock<Inner>,
+}
+#[derive(Default)]
+struct Inner
+{
+	/// Handler entrypoint (zero if none)
+	handler: usize,
+	/// Top of the handler's stack (zero to use the interrupted stack)
+	stack_top: usize,
+	/// Set while the handler is running (a fault in the handler isn't delivered, and notifications wait)
+	in_handler: bool,
+	notifications: Vec<u32>,
+}
+
+impl ThreadExceptions
+{
+	pub fn new() -> ThreadExceptions {
+		ThreadExceptions {
+			inner: ::sync::Spinlock::new(Default::default()),
+		}
+	}
+
+	fn with<R, F: FnOnce(&mut Inner)->R>(&self, f: F) -> R {
+		// NOTE: Used from fault/IRQ handlers, so interrupts must be held
+		let _irq = ::arch::sync::hold_interrupts();
+		f(&mut self.inner.lock())
+	}
+
+	/// Queue a notification (delivered when the thread next returns to user-mode, if it has a handler)
+	pub fn notify(&self, value: u32) {
+		self.with(|st| st.notifications.push(value));
+	}
+}
+
+/// Set the current thread's handler (zero `handler` to remove), returning the previous handler
+pub fn set_handler(handler: usize, stack_top: usize) -> usize
+{
+	super::with_cur_thread(|cur| cur.exceptions().with(|st| {
+		st.stack_top = stack_top;
+		::core::mem::replace(&mut st.handler, handler)
+		}))
+}
+
+/// Deliver an exception to the current thread's handler
+///
+/// Writes `frame` to the handler's stack, returning the handler's address and the frame's address (which the caller
+/// passes to the handler as the first argument). Returns None if there is no handler, the handler is already running,
+/// or the stack isn't writable; the caller should then terminate the process.
+pub fn deliver(frame: &ExceptionFrame) -> Option<(usize, usize)>
+{
+	// Claim the handler (and pick the frame location) under the lock, the frame is written once it's released
+	let claimed = super::with_cur_thread(|cur| cur.exceptions().with(|st| {
+		if st.handler == 0 || st.in_handler {
+			return None;
+		}
+		let stack_top = if st.stack_top != 0 {
+				st.stack_top
+			}
+			else {
+				// Skip the interrupted code's red zone
+				frame.sp.wrapping_sub(128)
+			};
+		st.in_handler = true;
+		Some( (st.handler, stack_top.wrapping_sub(::core::mem::size_of::<ExceptionFrame>()) & !0xF) )
+		}));
+	let (handler, frame_addr) = match claimed
+		{
+		Some(v) => v,
+		None => return None,
+		};
+	if let Err(_) = ::memory::user::write(frame_addr, *frame) {
+		log_notice!("deliver - Exception stack {:#x} not writable", frame_addr);
+		super::with_cur_thread(|cur| cur.exceptions().with(|st| st.in_handler = false));
+		return None;
+	}
+	Some( (handler, frame_addr) )
+}
+
+/// Take a pending notification for delivery (None if there are none, or they can't be delivered yet)
+pub fn take_notification() -> Option<u32>
+{
+	super::with_cur_thread(|cur| cur.exceptions().with(|st| {
+		if st.handler == 0 || st.in_handler || st.notifications.len() == 0 {
+			None
+		}
+		else {
+			Some( st.notifications.remove(0) )
+		}
+		}))
+}
+
+/// Called before returning to user-mode, flags the return path if a notification can be delivered
+pub fn check_user_events()
+{
+	let pending = super::with_cur_thread(|cur| cur.exceptions().with(|st| {
+		st.handler != 0 && !st.in_handler && st.notifications.len() > 0
+		}));
+	if pending {
+		::arch::threads::set_user_event_pending();
+	}
+}
+
+/// Leave the handler, obtaining the frame to resume with (read from user memory at `frame_addr`)
+pub fn exception_return(frame_addr: usize) -> Result<ExceptionFrame, ()>
+{
+	let frame: ExceptionFrame = try!(::memory::user::read(frame_addr));
+	super::with_cur_thread(|cur| cur.exceptions().with(|st| st.in_handler = false));
+	check_user_events();
+	Ok(frame)
+}
+
+// vim: ft=rust
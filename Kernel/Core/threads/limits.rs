@@ -0,0 +1,73 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/limits.rs
+//! Per-process resource limits
+//!
+//! A new process starts with a copy of its creator's limits, and limits can only be lowered (by the process itself,
+//! or by its creator before the process is started). Thread and memory limits are enforced here in the core, the
+//! handle and window limits are checked by the modules that allocate those resources.
+
+/// A limited resource
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Resource
+{
+	/// Mapped user memory pages
+	MemoryPages = 0,
+	/// Open object handles (including the "this process" handle)
+	Handles = 1,
+	/// Threads (including the root thread)
+	Threads = 2,
+	/// GUI windows
+	Windows = 3,
+}
+const NUM_RESOURCES: usize = 4;
+
+impl Resource
+{
+	pub fn from_u32(v: u32) -> Option<Resource> {
+		match v
+		{
+		0 => Some(Resource::MemoryPages),
+		1 => Some(Resource::Handles),
+		2 => Some(Resource::Threads),
+		3 => Some(Resource::Windows),
+		_ => None,
+		}
+	}
+}
+
+/// Set of limits for a process (`!0` means unlimited)
+#[derive(Copy,Clone,Debug)]
+pub struct Limits([usize; NUM_RESOURCES]);
+
+impl Limits
+{
+	/// Limits for the initial process (unlimited)
+	pub const fn unlimited() -> Limits {
+		Limits([!0; NUM_RESOURCES])
+	}
+
+	pub fn get(&self, r: Resource) -> usize {
+		self.0[r as usize]
+	}
+	/// Lower a limit (attempts to raise it are ignored), returning the resulting limit
+	pub fn lower(&mut self, r: Resource, value: usize) -> usize {
+		let l = &mut self.0[r as usize];
+		if value < *l {
+			*l = value;
+		}
+		*l
+	}
+
+	/// Check that `current + count` units of a resource are within the limit
+	pub fn allows(&self, r: Resource, current: usize, count: usize) -> bool {
+		match current.checked_add(count)
+		{
+		Some(v) => v <= self.get(r),
+		None => false,
+		}
+	}
+}
+
+// vim: ft=rust
@@ -12,15 +12,26 @@ mod worker_thread;
 
 mod sleep_object;
 
-pub use self::thread::{Thread,ThreadPtr,ThreadID};
-pub use self::thread::{ThreadHandle,ProcessHandle};
+mod sched;
+
+mod registry;
+
+mod limits;
+
+pub mod exceptions;
+
+pub use self::thread::{Thread,ThreadPtr,ThreadID,ProcessID};
+pub use self::thread::{ThreadInfo,ThreadState,ProcessInfo,ProcessState,CpuTime,enumerate_threads,enumerate_processes,dump_threads};
+pub use self::thread::{ThreadHandle,ProcessHandle,UserThreadHandle};
 pub use self::thread::new_idle_thread;
 
 pub use self::worker_thread::WorkerThread;
 
 pub use self::thread_list::{ThreadList,THREADLIST_INIT};
-pub use self::sleep_object::{SleepObject,SleepObjectRef};
+pub use self::sleep_object::{SleepObject,SleepObjectRef,Killed};
+pub use self::limits::Resource;
 pub use self::wait_queue::WaitQueue;
+pub use self::sched::{Priority,PRIORITY_IDLE,PRIORITY_DEFAULT,PRIORITY_USER_MAX,PRIORITY_HIGH,PRIORITY_MAX};
 
 use lib::mem::aref::{Aref,ArefBorrow};
 
@@ -29,9 +40,8 @@ pub type EventMask = u32;
 
 // ----------------------------------------------
 // Statics
-//static s_all_threads:	::sync::Mutex<Map<uint,*const Thread>> = mutex_init!(Map{});
 #[allow(non_upper_case_globals)]
-static s_runnable_threads: ::sync::Spinlock<ThreadList> = ::sync::Spinlock::new(THREADLIST_INIT);
+static s_runnable_threads: ::sync::Spinlock<sched::RunQueue> = ::sync::Spinlock::new(sched::RUNQUEUE_INIT);
 static S_PID0: ::lib::LazyStatic<::lib::mem::Arc<thread::Process>> = ::lib::LazyStatic::new();
 // Spinlocked due to low contention, and because the current thread is pushed to it
 static S_TO_REAP_THREADS: ::sync::Spinlock<ThreadList> = ::sync::Spinlock::new(THREADLIST_INIT);
@@ -63,6 +73,7 @@ fn reap_threads() -> bool
 		}
 		rv = true;
 	}
+	thread::release_deferred();
 	rv
 }
 
@@ -78,7 +89,7 @@ pub fn idle_thread()
 				// SAFE: We turned them off, we turn them back on
 				unsafe { ::arch::sync::start_interrupts(); }
 				log_debug!("Idle task switch to {:?}", thread);
-				::arch::threads::switch_to(thread);
+				switch_to(thread);
 			}
 			else {
 				// NOTE: Idle _must_ re-enable interrupts
@@ -107,7 +118,52 @@ pub fn yield_to(thread: ThreadPtr)
 {
 	log_debug!("Yielding CPU to {:?}", thread);
 	s_runnable_threads.lock().push( get_cur_thread() );
-	::arch::threads::switch_to( thread );
+	switch_to( thread );
+}
+
+/// Called by the architecture's interrupt handlers before returning to user-mode
+///
+/// Switches to another thread if the current thread's time slice has expired, or a higher priority thread is waiting.
+/// Interrupts must be disabled on entry (they're enabled while other threads run)
+pub fn preempt_user()
+{
+	let now = ::time::ticks();
+	let mut cur = get_cur_thread();
+	let mut rq = s_runnable_threads.lock();
+	if rq.should_preempt(&mut cur, now)
+	{
+		log_trace!("Preempting {:?}", cur);
+		rq.push(cur);
+		drop(rq);
+		// SAFE: Interrupts are disabled again before returning to the caller
+		unsafe { ::arch::sync::start_interrupts(); }
+		reschedule();
+		// SAFE: Re-disabled (was disabled on entry)
+		unsafe { ::arch::sync::stop_interrupts(); }
+	}
+	else
+	{
+		drop(rq);
+		// The slice may have been restarted
+		::time::rearm(cur.sched.slice_end());
+		rel_cur_thread(cur);
+	}
+}
+
+/// Get the base scheduling priority of the current thread
+pub fn get_cur_priority() -> Priority
+{
+	with_cur_thread(|cur| cur.sched.base())
+}
+/// Set the base scheduling priority of the current thread, returning the previous value
+pub fn set_cur_priority(priority: Priority) -> Priority
+{
+	let mut cur = get_cur_thread();
+	let rv = cur.sched.base();
+	cur.set_base_priority(priority);
+	log_debug!("{:?} priority {} -> {}", cur, rv, cur.sched.base());
+	rel_cur_thread(cur);
+	rv
 }
 
 pub fn terminate_thread() -> !
@@ -117,6 +173,9 @@ pub fn terminate_thread() -> !
 		panic!("TID 0 terminated");
 	}
 
+	// Remove from the process (releasing its resources if this is the last thread)
+	with_cur_thread(|cur| cur.on_terminate());
+
 	// NOTE: Can this just obtain a handle to the current thread then drop it?
 	// - No... kinda needs to be properly reaped. (so that no outstanding pointers exist)
 	//
@@ -130,22 +189,60 @@ pub fn terminate_thread() -> !
 	unreachable!();
 }
 
+/// Terminate the current thread, recording an exit status for `UserThreadHandle`
+pub fn exit_thread(status: u32) -> !
+{
+	with_cur_thread(|cur| cur.mark_exit(status));
+	terminate_thread();
+}
+
 pub fn exit_process(status: u32) -> ! {
-	// Requirements:
-	// - Save exit status somewhere
-	match with_cur_thread( |cur| cur.get_process_info().mark_exit(status) )
-	{
-	Ok(_) => {},
-	Err(_) => todo!("Two threads raced to exit"),
-	}
 	log_notice!("Terminating process with status={:#x}", status);
-
-	// - Request all other threads terminate
-	// TODO: How would this be done cleanly? Need to wake all and terminate on syscall boundary?
+	// - Save the exit status (if two threads race to exit, the first wins) and request all other threads terminate
+	//  > They're woken from sleep, and terminate when they reach the syscall boundary
+	with_cur_thread( |cur| cur.get_process_info().kill(status) );
 	
 	// - Terminate this thread
-	//  > Process reaping is handled by the PCB dropping when refcount reaches zero
-	terminate_thread();
+	//  > Objects are released when the last thread terminates, and memory when the last thread is reaped
+	exit_thread(status);
+}
+
+/// Returns true if the current process has been killed (the caller should call `exit_thread` as soon as possible)
+pub fn is_cur_process_killed() -> bool {
+	with_cur_thread(|cur| cur.get_process_info().is_killed())
+}
+
+/// Get a resource limit of the current process (`!0` if unlimited)
+pub fn get_limit(r: Resource) -> usize {
+	with_cur_thread(|cur| cur.get_process_info().get_limit(r))
+}
+/// Lower a resource limit of the current process (it can't be raised again), returning the resulting limit
+pub fn lower_limit(r: Resource, value: usize) -> usize {
+	with_cur_thread(|cur| cur.get_process_info().lower_limit(r, value))
+}
+/// Reserve `count` pages of user memory for the current process, returning `false` if its memory limit would be exceeded
+pub fn reserve_user_pages(count: usize) -> bool {
+	with_cur_thread(|cur| cur.get_process_info().reserve_user_pages(count))
+}
+/// Account for `count` user pages released by the current process
+pub fn release_user_pages(count: usize) {
+	with_cur_thread(|cur| cur.get_process_info().release_user_pages(count))
+}
+
+/// CPU time accounting: The current thread is about to return to user code
+pub fn account_user_entry() {
+	let now = ::time::ticks();
+	with_cur_thread(|cur| cur.account_mode(now, true));
+}
+/// CPU time accounting: The current thread has entered the kernel (via a system call)
+pub fn account_kernel_entry() {
+	let now = ::time::ticks();
+	with_cur_thread(|cur| cur.account_mode(now, false));
+}
+
+/// Record the sleep object the current thread is sleeping on (see `Thread::set_sleep_object`)
+fn set_cur_sleep_object(addr: usize, killable: bool) -> bool {
+	with_cur_thread(|cur| cur.set_sleep_object(addr, killable))
 }
 
 pub fn get_thread_id() -> thread::ThreadID
@@ -233,13 +330,13 @@ pub fn reschedule()
 			if &*thread as *const _ == ::arch::threads::borrow_thread() as *const _
 			{
 				log_debug!("Task switch to self, idle");
-				::arch::threads::switch_to(thread);
+				switch_to(thread);
 				::arch::threads::idle();
 			}
 			else
 			{
 				log_debug!("Task switch to {:?}", thread);
-				::arch::threads::switch_to(thread);
+				switch_to(thread);
 				//log_debug!("Awoke");
 			}
 			return ;
@@ -252,7 +349,7 @@ pub fn reschedule()
 				log_trace!("reschedule() - No active threads, idling");
 				
 				// Switch to the idle thread
-				::arch::threads::switch_to( thread );
+				switch_to( thread );
 			}
 			else {
 				::core::mem::forget(thread);
@@ -262,6 +359,29 @@ pub fn reschedule()
 	}
 }
 
+/// Switch to another thread (or the current thread), updating CPU time accounting
+fn switch_to(thread: ThreadPtr)
+{
+	let now = ::time::ticks();
+	with_cur_thread(|cur| cur.account_stop(now));
+	thread.account_start(now);
+	// Time the new thread's slice (idle threads never get one, so only timers wake an idle CPU)
+	::time::rearm(thread.sched.slice_end());
+	::arch::threads::switch_to(thread);
+}
+
+/// End of the current thread's time slice (`!0` if called before threading is running)
+//#[is_safe(irq)]
+pub fn cur_slice_end() -> u64
+{
+	if ::arch::threads::borrow_thread().is_null() {
+		!0
+	}
+	else {
+		with_cur_thread(|cur| cur.sched.slice_end())
+	}
+}
+
 fn get_cur_thread() -> ThreadPtr
 {
 	::arch::threads::get_thread_ptr().expect("Current thread is None")
@@ -286,8 +406,8 @@ fn get_thread_to_run() -> Option<ThreadPtr>
 	}
 	else
 	{
-		// 2. Pop off a new thread
-		handle.pop()
+		// 2. Pop off the highest priority thread
+		handle.pop( ::time::ticks() )
 	}
 }
 
@@ -0,0 +1,219 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/registry.rs
+//! Global registry of processes and threads (ID allocation and lookup)
+use prelude::*;
+
+/// Table of live objects, indexed by a recycled ID
+///
+/// Entries are raw pointers, added once the object is at a fixed address and removed by the object's destructor
+/// (so a pointer obtained with the lock held is valid until the lock is released).
+pub struct Registry<T>
+{
+	ids: IdAllocator,
+	/// Registered entries, sorted by ID
+	entries: Vec<(u32, *const T)>,
+}
+// SAFE: Only contains pointers to Sync objects
+unsafe impl<T: Sync> Send for Registry<T> {}
+
+impl<T> Registry<T>
+{
+	pub const fn new(max_id: u32) -> Registry<T> {
+		Registry {
+			ids: IdAllocator::new(max_id),
+			entries: Vec::new_const(),
+		}
+	}
+
+	/// Reserve a new ID (returns None if all IDs are in use)
+	pub fn allocate_id(&mut self) -> Option<u32> {
+		self.ids.allocate()
+	}
+	/// Register an object under an ID obtained from `allocate_id` (or zero)
+	pub fn insert(&mut self, id: u32, ptr: *const T) {
+		match self.entries.binary_search_by(|e| e.0.cmp(&id))
+		{
+		Ok(_) => panic!("Registry::insert - Duplicate ID {}", id),
+		Err(i) => self.entries.insert(i, (id, ptr)),
+		}
+	}
+	/// Remove an object (and release its ID for reuse)
+	pub fn remove(&mut self, id: u32) {
+		if let Ok(i) = self.entries.binary_search_by(|e| e.0.cmp(&id)) {
+			self.entries.remove(i);
+		}
+		if id != 0 {
+			self.ids.release(id);
+		}
+	}
+
+	/// Iterate over registered entries with an ID of at least `start`, in ID order
+	pub fn iter_from<'a>(&'a self, start: u32) -> impl Iterator<Item=(u32, &'a T)> + 'a {
+		let first = match self.entries.binary_search_by(|e| e.0.cmp(&start))
+			{
+			Ok(i) => i,
+			Err(i) => i,
+			};
+		// SAFE: Entries are removed (with the lock held) before the object is destroyed
+		self.entries[first..].iter().map(|&(id, p)| (id, unsafe { &*p }))
+	}
+}
+
+/// Bitmap of allocated IDs
+///
+/// IDs are handed out in rotating order (so a just-released ID isn't immediately reused), and the bitmap only grows
+/// once every ID it covers is in use. ID zero is reserved (TID0 and PID0 are allocated statically).
+struct IdAllocator
+{
+	bitmap: Vec<u32>,
+	/// Position to start the next search from
+	next: u32,
+	max: u32,
+}
+
+impl IdAllocator
+{
+	const fn new(max: u32) -> IdAllocator {
+		IdAllocator {
+			bitmap: Vec::new_const(),
+			next: 1,
+			max: max,
+		}
+	}
+
+	fn allocate(&mut self) -> Option<u32> {
+		let limit = self.bitmap.len() as u32 * 32;
+		// 1. Search from the cursor to the end of the bitmap, then from the start
+		let found = match self.find_free(self.next, limit)
+			{
+			Some(v) => Some(v),
+			None => self.find_free(1, self.next),
+			};
+		let id = match found
+			{
+			Some(v) => v,
+			// 2. Bitmap is full, extend it
+			None if limit < self.max => {
+				self.bitmap.push(0);
+				if limit == 0 {
+					// ID 0 is reserved
+					self.bitmap[0] = 1;
+					1
+				}
+				else {
+					limit
+				}
+				},
+			None => return None,
+			};
+		self.bitmap[id as usize / 32] |= 1 << (id % 32);
+		self.next = id + 1;
+		Some(id)
+	}
+	fn find_free(&self, start: u32, end: u32) -> Option<u32> {
+		let mut id = start;
+		while id < end && id < self.max
+		{
+			let word = self.bitmap[id as usize / 32];
+			if word == !0 {
+				// Skip entire word
+				id = (id | 31) + 1;
+			}
+			else if word & (1 << (id % 32)) == 0 {
+				return Some(id);
+			}
+			else {
+				id += 1;
+			}
+		}
+		None
+	}
+	fn release(&mut self, id: u32) {
+		let word = &mut self.bitmap[id as usize / 32];
+		assert!(*word & (1 << (id % 32)) != 0, "IdAllocator::release - ID {} not allocated", id);
+		*word &= !(1 << (id % 32));
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use prelude::*;
+	use super::{IdAllocator,Registry};
+
+	#[test]
+	fn ids_sequential()
+	{
+		let mut a = IdAllocator::new(100);
+		// ID 0 is reserved, and allocation continues past the first bitmap word
+		for i in 1 .. 40 {
+			assert_eq!(a.allocate(), Some(i));
+		}
+	}
+
+	#[test]
+	fn ids_exhaustion()
+	{
+		let mut a = IdAllocator::new(8);
+		for i in 1 .. 8 {
+			assert_eq!(a.allocate(), Some(i));
+		}
+		assert_eq!(a.allocate(), None);
+		a.release(3);
+		assert_eq!(a.allocate(), Some(3));
+		assert_eq!(a.allocate(), None);
+	}
+
+	#[test]
+	fn ids_wraparound()
+	{
+		let mut a = IdAllocator::new(8);
+		for i in 1 .. 5 {
+			assert_eq!(a.allocate(), Some(i));
+		}
+		// A released ID isn't reused until the cursor wraps
+		a.release(2);
+		assert_eq!(a.allocate(), Some(5));
+		assert_eq!(a.allocate(), Some(6));
+		assert_eq!(a.allocate(), Some(7));
+		assert_eq!(a.allocate(), Some(2));
+		assert_eq!(a.allocate(), None);
+	}
+
+	#[test]
+	#[should_panic]
+	fn ids_double_release()
+	{
+		let mut a = IdAllocator::new(8);
+		let id = a.allocate().unwrap();
+		a.release(id);
+		a.release(id);
+	}
+
+	#[test]
+	fn registry_reuse()
+	{
+		let values = [10u32, 20, 30];
+		let mut r = Registry::<u32>::new(4);
+		let ids: Vec<u32> = values.iter().map(|v| {
+			let id = r.allocate_id().unwrap();
+			r.insert(id, v);
+			id
+			}).collect();
+		assert_eq!(&ids[..], &[1, 2, 3]);
+		assert_eq!(r.allocate_id(), None);
+		assert_eq!(&r.iter_from(0).map(|(id, &v)| (id, v)).collect::<Vec<_>>()[..], &[(1, 10), (2, 20), (3, 30)]);
+
+		// Removing an entry releases its ID for reuse
+		r.remove(2);
+		assert_eq!(&r.iter_from(2).map(|(id, &v)| (id, v)).collect::<Vec<_>>()[..], &[(3, 30)]);
+		let id = r.allocate_id().unwrap();
+		assert_eq!(id, 2);
+		r.insert(id, &values[0]);
+		assert_eq!(&r.iter_from(2).map(|(id, &v)| (id, v)).collect::<Vec<_>>()[..], &[(2, 10), (3, 30)]);
+	}
+}
+
+// vim: ft=rust
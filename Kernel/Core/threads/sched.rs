@@ -0,0 +1,172 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/sched.rs
+//! Scheduler run queue (multi-level feedback queue)
+//!
+//! Each thread has a base priority, and is queued at that priority less a "demotion" that grows each time the thread
+//! uses its entire time slice. CPU-bound threads thus sink below interactive threads of the same base priority, while
+//! getting longer (but less frequent) slices. Demotion is cleared when the thread sleeps, and periodically for all
+//! queued threads (so demoted threads are not starved forever).
+use super::{ThreadPtr,ThreadList,THREADLIST_INIT};
+
+/// Thread scheduling priority (higher values run first)
+pub type Priority = u8;
+
+/// Number of distinct priority levels
+pub const NUM_PRIORITIES: usize = 8;
+/// Lowest priority, only runs when nothing else is runnable
+pub const PRIORITY_IDLE: Priority = 0;
+/// Default priority for user threads and kernel workers
+pub const PRIORITY_DEFAULT: Priority = 3;
+/// Highest priority that can be requested by user code
+pub const PRIORITY_USER_MAX: Priority = 5;
+/// Priority for latency-sensitive kernel workers (IRQ handling, network RX, GUI rendering)
+pub const PRIORITY_HIGH: Priority = 6;
+/// Highest possible priority
+pub const PRIORITY_MAX: Priority = (NUM_PRIORITIES - 1) as Priority;
+
+/// Maximum number of levels a thread can be demoted below its base priority
+const MAX_DEMOTION: u8 = 2;
+/// Length of an undemoted time slice (ms), doubled for every level of demotion
+const BASE_QUANTUM: u64 = 10;
+/// Interval between clearing the demotion of all queued threads (ms)
+const BOOST_INTERVAL: u64 = 1000;
+
+/// Per-thread scheduling state
+pub struct SchedState
+{
+	base: Priority,
+	demotion: u8,
+	/// Timestamp at which the current time slice ends
+	slice_end: u64,
+}
+
+impl SchedState
+{
+	pub fn new(base: Priority) -> SchedState {
+		SchedState {
+			base: ::core::cmp::min(base, PRIORITY_MAX),
+			demotion: 0,
+			slice_end: 0,
+		}
+	}
+
+	/// Base (requested) priority
+	pub fn base(&self) -> Priority {
+		self.base
+	}
+	/// Change the base priority (takes effect next time the thread is queued)
+	pub fn set_base(&mut self, priority: Priority) {
+		self.base = ::core::cmp::min(priority, PRIORITY_MAX);
+	}
+	/// Current effective priority (base less demotion)
+	pub fn priority(&self) -> Priority {
+		self.base.saturating_sub(self.demotion)
+	}
+
+	/// Clear demotion (the thread blocked before using its time slice)
+	pub fn reset(&mut self) {
+		self.demotion = 0;
+	}
+	/// Start a new time slice (length depends on demotion)
+	pub fn start_slice(&mut self, now: u64) {
+		self.slice_end = now + (BASE_QUANTUM << self.demotion);
+	}
+	/// Timestamp at which the current time slice ends (zero if the thread has never been given a slice)
+	pub fn slice_end(&self) -> u64 {
+		self.slice_end
+	}
+	fn slice_expired(&self, now: u64) -> bool {
+		now >= self.slice_end
+	}
+	fn demote(&mut self) {
+		if self.demotion < MAX_DEMOTION && self.demotion < self.base {
+			self.demotion += 1;
+		}
+	}
+}
+
+/// Queue of runnable threads, one list per priority level
+pub struct RunQueue
+{
+	levels: [ThreadList; NUM_PRIORITIES],
+	next_boost: u64,
+}
+pub const RUNQUEUE_INIT: RunQueue = RunQueue {
+	levels: [
+		THREADLIST_INIT, THREADLIST_INIT, THREADLIST_INIT, THREADLIST_INIT,
+		THREADLIST_INIT, THREADLIST_INIT, THREADLIST_INIT, THREADLIST_INIT,
+		],
+	next_boost: 0,
+	};
+
+impl RunQueue
+{
+	/// Returns true if there are no runnable threads
+	pub fn empty(&self) -> bool {
+		self.levels.iter().all(|l| l.empty())
+	}
+	/// Priority of the highest queued thread
+	pub fn highest(&self) -> Option<Priority> {
+		self.levels.iter().rposition(|l| !l.empty()).map(|i| i as Priority)
+	}
+
+	/// Add a thread to the back of the list for its current priority
+	pub fn push(&mut self, t: ThreadPtr) {
+		self.enqueue(t);
+		// Let another CPU pick up the thread if it's idle
+		::arch::threads::kick_idle_cpu();
+	}
+	fn enqueue(&mut self, t: ThreadPtr) {
+		let level = t.sched.priority() as usize;
+		self.levels[level].push(t);
+	}
+	/// Remove the highest priority thread (starting its time slice)
+	pub fn pop(&mut self, now: u64) -> Option<ThreadPtr> {
+		if now >= self.next_boost {
+			self.boost();
+			self.next_boost = now + BOOST_INTERVAL;
+		}
+		for level in self.levels.iter_mut().rev()
+		{
+			if let Some(mut t) = level.pop() {
+				t.sched.start_slice(now);
+				return Some(t);
+			}
+		}
+		None
+	}
+
+	/// Clear the demotion of all queued threads, moving them back to their base priority
+	fn boost(&mut self) {
+		for i in 0 .. NUM_PRIORITIES
+		{
+			let mut list = ::core::mem::replace(&mut self.levels[i], THREADLIST_INIT);
+			while let Some(mut t) = list.pop() {
+				t.sched.reset();
+				self.enqueue(t);
+			}
+		}
+	}
+
+	/// Check if the running thread should yield to a queued thread
+	///
+	/// Demotes the thread if its time slice has expired, and starts a new slice if it will keep running.
+	pub fn should_preempt(&self, cur: &mut super::Thread, now: u64) -> bool {
+		let expired = cur.sched.slice_expired(now);
+		if expired {
+			cur.sched.demote();
+		}
+		let rv = match self.highest()
+			{
+			None => false,
+			// Expired threads yield to equal priority (round-robin), otherwise only to higher priority
+			Some(p) => if expired { p >= cur.sched.priority() } else { p > cur.sched.priority() },
+			};
+		if expired && !rv {
+			cur.sched.start_slice(now);
+		}
+		rv
+	}
+}
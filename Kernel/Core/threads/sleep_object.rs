@@ -32,6 +32,10 @@ struct SleepObjectInner
 	thread: Option<ThreadPtr>,
 }
 
+/// Returned by `SleepObject::wait_killable` when the current process has been killed
+#[derive(Debug)]
+pub struct Killed;
+
 /// Referece to an active sleep object
 pub struct SleepObjectRef
 {
@@ -68,7 +72,35 @@ impl<'a> SleepObject<'a>
 	}
 	
 	/// Wait the current thread on this object
+	///
+	/// If the thread's process is killed while it's sleeping, it's woken (callers see this as a spurious wakeup, and
+	/// can check `is_cur_process_killed`)
 	pub fn wait(&self)
+	{
+		// Registered with the thread (even if already killed), so a kill can wake the sleeper
+		super::set_cur_sleep_object(self as *const _ as *const () as usize, false);
+		self.wait_inner();
+	}
+
+	/// Wait the current thread on this object, unless its process has been killed
+	///
+	/// Used by waits at the syscall level: `Err(Killed)` is returned (without sleeping, or once woken by the kill) so
+	/// the caller can unwind to the syscall boundary, where the thread terminates.
+	pub fn wait_killable(&self) -> Result<(), Killed>
+	{
+		if ! super::set_cur_sleep_object(self as *const _ as *const () as usize, true) {
+			return Err(Killed);
+		}
+		self.wait_inner();
+		if super::is_cur_process_killed() {
+			Err(Killed)
+		}
+		else {
+			Ok( () )
+		}
+	}
+
+	fn wait_inner(&self)
 	{
 		//log_trace!("SleepObject::wait {:p} '{}'", self, self.name);
 		
@@ -79,7 +111,7 @@ impl<'a> SleepObject<'a>
 		if lh.flag == false
 		{
 			let mut cur = super::get_cur_thread();
-			cur.run_state = RunState::Sleep(self as *const _ as *const () as *const _);	// Go via () to erase the lifetime
+			cur.set_state( RunState::Sleep(self as *const _ as *const () as *const _) );	// Go via () to erase the lifetime
 			lh.thread = Some(cur);
 			
 			::core::mem::drop(lh);
@@ -95,7 +127,10 @@ impl<'a> SleepObject<'a>
 		else
 		{
 			lh.flag = false;
+			::core::mem::drop(lh);
+			::core::mem::drop(irql);
 		}
+		super::set_cur_sleep_object(0, false);
 	}
 	
 	/// Signal this sleep object (waking threads)
@@ -18,6 +18,82 @@ use lib::mem::Arc;
 pub type ThreadID = u32;
 pub type ProcessID = u32;
 
+/// Thread state, as reported by `enumerate_threads`
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum ThreadState
+{
+	/// Currently executing on a CPU
+	Running,
+	/// Waiting to be scheduled
+	Runnable,
+	/// Sleeping (on a WaitQueue or SleepObject)
+	Sleeping,
+	/// Terminated
+	Dead,
+}
+/// Process state, as reported by `enumerate_processes`
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum ProcessState
+{
+	Running,
+	/// An exit status has been set (e.g. killed), but some threads are still running
+	Exiting,
+	/// All threads have terminated
+	Exited,
+}
+
+/// CPU time used (milliseconds)
+///
+/// Time is charged to user-mode from when a thread drops to user until its next system call, so interrupts and
+/// faults taken from user-mode count as user time.
+#[derive(Copy,Clone,Default,Debug)]
+pub struct CpuTime
+{
+	pub user: u64,
+	pub kernel: u64,
+}
+impl CpuTime
+{
+	pub fn total(&self) -> u64 {
+		self.user + self.kernel
+	}
+}
+impl ::core::ops::Add for CpuTime
+{
+	type Output = CpuTime;
+	fn add(self, other: CpuTime) -> CpuTime {
+		CpuTime { user: self.user + other.user, kernel: self.kernel + other.kernel }
+	}
+}
+
+/// Information about a process (see `enumerate_processes`)
+pub struct ProcessInfo<'a>
+{
+	pub pid: ProcessID,
+	/// PID of the process that created this process
+	pub parent: ProcessID,
+	pub name: &'a str,
+	pub state: ProcessState,
+	pub exit_status: Option<u32>,
+	/// Number of threads that haven't yet terminated
+	pub thread_count: usize,
+	/// Total CPU time used by the process's threads
+	pub cpu_time: CpuTime,
+	/// Number of resident user pages
+	pub resident_pages: usize,
+}
+/// Information about a thread (see `enumerate_threads`)
+pub struct ThreadInfo<'a>
+{
+	pub tid: ThreadID,
+	pub pid: ProcessID,
+	pub name: &'a str,
+	pub state: ThreadState,
+	pub priority: super::Priority,
+	pub exit_status: Option<u32>,
+	pub cpu_time: CpuTime,
+}
+
 //#[deriving(PartialEq)]
 /// Thread run state
 pub enum RunState
@@ -39,13 +115,28 @@ pub struct Process
 {
 	name: String,
 	pid: ProcessID,
+	parent: ProcessID,
 	address_space: ::memory::virt::AddressSpace,
 	// TODO: use of a tuple here looks a little crufty
 	exit_status: ::sync::Mutex< (Option<u32>, Option<::threads::sleep_object::SleepObjectRef>) >,
+	/// Set once the process has been asked to terminate (threads exit when they next reach the syscall boundary)
+	kill_requested: ::core::sync::atomic::AtomicBool,
+	/// All threads that haven't yet terminated
+	threads: ::sync::Mutex<Vec<Arc<SharedBlock>>>,
+	/// Set once all threads have terminated
+	all_terminated: ::core::sync::atomic::AtomicBool,
+	/// CPU time used by threads that have terminated
+	exited_cpu_time: ::sync::Mutex<CpuTime>,
+	/// Resource limits (copied from the creating process)
+	limits: ::sync::Mutex<super::limits::Limits>,
+	/// User memory pages allocated by this process (see `memory::virt::allocate_user`), checked against the `MemoryPages` limit
+	user_pages: ::core::sync::atomic::AtomicUsize,
 	pub proc_local_data: ::sync::RwLock<Vec< ::lib::mem::aref::Aref<dyn core::any::Any+Sync+Send> >>,
 }
 /// Handle to a process, used for spawning and communicating
-pub struct ProcessHandle(Arc<Process>);
+///
+/// The root thread's TID is reserved when the process is created (and released if it's never started)
+pub struct ProcessHandle(Arc<Process>, Option<ThreadID>);
 impl_fmt! {
 	Debug(self, f) for ProcessHandle {
 		write!(f, "P({} {})", self.0.pid, self.0.name)
@@ -58,6 +149,30 @@ struct SharedBlock
 	tid: ThreadID,
 	process: Arc<Process>,
 	complete: crate::sync::EventChannel,
+	exit_state: ::sync::Mutex<ThreadExitState>,
+	/// Address of the `SleepObject` this thread is sleeping on (zero if not sleeping), used to wake it when killed
+	sleep_object: ::sync::Spinlock<usize>,
+	/// State and accounting information, updated by the thread and read by `enumerate_threads`
+	stats: ::sync::Spinlock<ThreadStats>,
+	/// User exception handler and pending notifications
+	exceptions: super::exceptions::ThreadExceptions,
+}
+struct ThreadStats
+{
+	state: ThreadState,
+	priority: super::Priority,
+	/// Accumulated CPU time
+	cpu_time: CpuTime,
+	/// Set while the thread is executing user code (between `account_user_entry` and the next system call)
+	in_user: bool,
+	/// Start of the current accounting period, i.e. when the thread was switched to or last changed mode (None if not running)
+	run_start: Option<u64>,
+}
+#[derive(Default)]
+struct ThreadExitState
+{
+	status: Option<u32>,
+	waiters: Vec<::threads::sleep_object::SleepObjectRef>,
 }
 
 /// An owning thread handle
@@ -68,6 +183,12 @@ pub struct ThreadHandle
 	// - Race problems
 }
 
+/// Handle to a user thread (unlike `ThreadHandle`, doesn't wait for the thread when dropped)
+pub struct UserThreadHandle
+{
+	block: Arc<SharedBlock>,
+}
+
 /// "Owned" pointer to a thread (panics if dropped)
 pub struct ThreadPtr(::lib::mem::Unique<Thread>);
 
@@ -77,6 +198,8 @@ pub struct Thread
 	block: Arc<SharedBlock>,
 	/// Execution state
 	pub run_state: RunState,
+	/// Scheduling priority and time slice
+	pub sched: super::sched::SchedState,
 	
 	/// CPU state
 	pub cpu_state: ::arch::threads::State,
@@ -85,62 +208,134 @@ pub struct Thread
 }
 assert_trait!{Thread : Send}
 
-/// Last allocated TID (because TID0 is allocated differently)
-static S_LAST_TID: ::core::sync::atomic::AtomicUsize = ::core::sync::atomic::AtomicUsize::new(0);
-const C_MAX_TID: usize = 0x7FFF_FFF0;	// Leave 16 TIDs spare at end of 31 bit number
-static S_LAST_PID: ::core::sync::atomic::AtomicUsize = ::core::sync::atomic::AtomicUsize::new(0);
-const C_MAX_PID: usize = 0x007F_FFF0;	// Leave 16 PIDs spare at end of 23 bit number
+const C_MAX_TID: u32 = 0x7FFF_FFF0;	// Leave 16 TIDs spare at end of 31 bit number
+const C_MAX_PID: u32 = 0x007F_FFF0;	// Leave 16 PIDs spare at end of 23 bit number
+/// Exit status of a thread destroyed without exiting (matches `EXITSTATUS_KILLED` in the syscall ABI)
+const EXIT_STATUS_KILLED: u32 = 0x8000_0001;
+/// All processes (by PID)
+static S_PROCESSES: ::sync::Mutex<super::registry::Registry<Process>> = ::sync::Mutex::new(super::registry::Registry::new(C_MAX_PID));
+/// All threads (by TID), references the shared block so threads with outstanding handles are still listed
+static S_THREADS: ::sync::Mutex<super::registry::Registry<SharedBlock>> = ::sync::Mutex::new(super::registry::Registry::new(C_MAX_TID));
+/// Terminated processes with memory still to be released (their address space was loaded on a CPU when reaped)
+static S_DEFERRED_RELEASE: ::sync::Mutex<Vec<Arc<Process>>> = ::sync::Mutex::new(Vec::new_const());
 
-fn allocate_tid() -> ThreadID
+/// Returns None if all TIDs are in use
+fn allocate_tid() -> Option<ThreadID>
 {
-	// Preemptively prevent rollover
-	if S_LAST_TID.load(::core::sync::atomic::Ordering::Relaxed) == C_MAX_TID - 1 {
-		panic!("TODO: Handle TID exhaustion by searching for free");
-	}
-	let rv = S_LAST_TID.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
-	// Handle rollover after (in case of heavy contention)
-	if rv >= C_MAX_TID {
-		panic!("TODO: Handle TID exhaustion by searching for free (raced)");
-	}
-	
-	(rv + 1) as ThreadID
+	S_THREADS.lock().allocate_id()
+}
+
+/// Returns None if all PIDs are in use
+fn allocate_pid() -> Option<ProcessID>
+{
+	S_PROCESSES.lock().allocate_id()
 }
 
-fn allocate_pid() -> u32
+/// Enumerate processes with a PID of at least `start` (in PID order), stopping when the callback returns false
+pub fn enumerate_processes<F: FnMut(&ProcessInfo)->bool>(start: ProcessID, mut cb: F)
 {
-	// Preemptively prevent rollover
-	if S_LAST_PID.load(::core::sync::atomic::Ordering::Relaxed) == C_MAX_PID - 1 {
-		panic!("TODO: Handle PID exhaustion by searching for free");
+	let lh = S_PROCESSES.lock();
+	for (_, p) in lh.iter_from(start)
+	{
+		if !cb(&p.get_info()) {
+			break;
+		}
 	}
-	let rv = S_LAST_PID.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
-	// Handle rollover after (in case of heavy contention)
-	if rv >= C_MAX_PID {
-		panic!("TODO: Handle PID exhaustion by searching for free (raced)");
+}
+/// Enumerate threads with a TID of at least `start` (in TID order), stopping when the callback returns false
+pub fn enumerate_threads<F: FnMut(&ThreadInfo)->bool>(start: ThreadID, mut cb: F)
+{
+	let lh = S_THREADS.lock();
+	let now = ::time::ticks();
+	for (_, b) in lh.iter_from(start)
+	{
+		if !cb(&b.get_info(now)) {
+			break;
+		}
 	}
-	
-	(rv + 1) as u32
+}
+
+/// Write a table of all processes and threads (`/proc/threads`)
+pub fn dump_threads(f: &mut dyn ::core::fmt::Write) -> ::core::fmt::Result
+{
+	let mut rv = Ok( () );
+	try!(writeln!(f, "  PID  PPID State   Threads  User(ms) Kernel(ms)  Pages Name"));
+	enumerate_processes(0, |p| {
+		let state = match p.state
+			{
+			ProcessState::Running => "Running",
+			ProcessState::Exiting => "Exiting",
+			ProcessState::Exited  => "Exited",
+			};
+		rv = writeln!(f, "{:5} {:5} {:7} {:7} {:9} {:10} {:6} {}",
+			p.pid, p.parent, state, p.thread_count, p.cpu_time.user, p.cpu_time.kernel, p.resident_pages, p.name);
+		rv.is_ok()
+		});
+	try!(rv);
+	try!(writeln!(f, ""));
+	try!(writeln!(f, "  TID   PID State    Pri  User(ms) Kernel(ms) Name"));
+	enumerate_threads(0, |t| {
+		let state = match t.state
+			{
+			ThreadState::Running  => "Running",
+			ThreadState::Runnable => "Runnable",
+			ThreadState::Sleeping => "Sleeping",
+			ThreadState::Dead     => "Dead",
+			};
+		rv = writeln!(f, "{:5} {:5} {:8} {:3} {:9} {:10} {}",
+			t.tid, t.pid, state, t.priority, t.cpu_time.user, t.cpu_time.kernel, t.name);
+		rv.is_ok()
+		});
+	rv
 }
 
 impl Process
 {
 	pub fn new_pid0() -> Arc<Process> {
-		Arc::new(Process {
+		let rv = Arc::new(Process {
 			name: String::from("PID0"),
 			pid: 0,
+			parent: 0,
 			exit_status: Default::default(),
+			kill_requested: Default::default(),
+			threads: Default::default(),
+			all_terminated: Default::default(),
+			exited_cpu_time: Default::default(),
 			address_space: ::memory::virt::AddressSpace::pid0(),
+			limits: ::sync::Mutex::new( super::limits::Limits::unlimited() ),
+			user_pages: Default::default(),
 			proc_local_data: ::sync::RwLock::new( Vec::new() ),
-		})
+		});
+		S_PROCESSES.lock().insert(0, &*rv);
+		rv
 	}
-	pub fn new<S: Into<String>+::core::fmt::Debug>(name: S, addr_space: ::memory::virt::AddressSpace) -> Arc<Process>
+	/// Create a new process, fails if all PIDs are in use
+	pub fn new<S: Into<String>+::core::fmt::Debug>(name: S, addr_space: ::memory::virt::AddressSpace) -> Result<Arc<Process>, ()>
 	{
-		Arc::new(Process {
-			pid: allocate_pid(),
+		let pid = match allocate_pid()
+			{
+			Some(v) => v,
+			None => {
+				log_notice!("Process::new({:?}) - All PIDs in use", name);
+				return Err( () );
+				},
+			};
+		let rv = Arc::new(Process {
+			pid: pid,
+			parent: super::get_process_id(),
 			name: name.into(),
 			exit_status: Default::default(),
+			kill_requested: Default::default(),
+			threads: Default::default(),
+			all_terminated: Default::default(),
+			exited_cpu_time: Default::default(),
 			address_space: addr_space,
+			limits: ::sync::Mutex::new( super::with_cur_thread(|cur| *cur.get_process_info().limits.lock()) ),
+			user_pages: Default::default(),
 			proc_local_data: ::sync::RwLock::new( Vec::new() ),
-		})
+		});
+		S_PROCESSES.lock().insert(rv.pid, &*rv);
+		Ok(rv)
 	}
 	
 	fn empty_cpu_state(&self) -> ::arch::threads::State {
@@ -164,23 +359,166 @@ impl Process
 			Ok( () )
 		}
 	}
+
+	/// Request that all threads in the process terminate, and set the exit status (if not already set)
+	///
+	/// Sleeping threads are woken, and threads exit when they next pass the syscall boundary.
+	/// NOTE: A thread that never makes a system call (e.g. a busy loop) won't be stopped
+	pub fn kill(&self, status: u32) {
+		log_notice!("Killing {} (status={:#x})", self, status);
+		let _ = self.mark_exit(status);
+		self.kill_requested.store(true, ::core::sync::atomic::Ordering::SeqCst);
+		for block in self.threads.lock().iter() {
+			block.wake_sleeper();
+		}
+	}
+	pub fn is_killed(&self) -> bool {
+		self.kill_requested.load(::core::sync::atomic::Ordering::SeqCst)
+	}
+
+	/// Get a resource limit (`!0` if unlimited)
+	pub fn get_limit(&self, r: super::limits::Resource) -> usize {
+		self.limits.lock().get(r)
+	}
+	/// Lower a resource limit, returning the resulting limit
+	pub fn lower_limit(&self, r: super::limits::Resource, value: usize) -> usize {
+		self.limits.lock().lower(r, value)
+	}
+	/// Reserve `count` pages of user memory, returning `false` if the `MemoryPages` limit would be exceeded
+	///
+	/// The check and the update are a single atomic operation, so concurrent allocations can't exceed the limit.
+	pub fn reserve_user_pages(&self, count: usize) -> bool {
+		use core::sync::atomic::Ordering;
+		let limits = *self.limits.lock();
+		self.user_pages.fetch_update(
+			|cur| if limits.allows(super::limits::Resource::MemoryPages, cur, count) { Some(cur + count) } else { None },
+			Ordering::Relaxed, Ordering::Relaxed
+			).is_ok()
+	}
+	/// Account for user pages released
+	pub fn release_user_pages(&self, count: usize) {
+		use core::sync::atomic::Ordering;
+		// NOTE: Saturating, as pages mapped by other means (e.g. a forked parent's memory) can also be released
+		let _ = self.user_pages.fetch_update(|cur| Some(cur.saturating_sub(count)), Ordering::Relaxed, Ordering::Relaxed);
+	}
+
+	/// Called by a thread as it terminates, releases the process's resources once all threads have terminated
+	fn thread_terminated(&self, block: &Arc<SharedBlock>) {
+		let remaining = {
+			let mut lh = self.threads.lock();
+			if let Some(i) = lh.iter().position(|b| &**b as *const _ == &**block as *const _) {
+				lh.remove(i);
+				let mut exited = self.exited_cpu_time.lock();
+				*exited = *exited + block.cpu_time(::time::ticks());
+			}
+			lh.len()
+		};
+		if remaining == 0 && self.pid != 0 {
+			self.all_terminated.store(true, ::core::sync::atomic::Ordering::SeqCst);
+			// Report an exit if the process wasn't explicitly exited (last thread terminated)
+			let _ = self.mark_exit(0);
+			log_notice!("{}: All threads terminated, releasing objects", self);
+			// Release syscall objects (and the VFS handles/memory they hold)
+			// - Moved out of the lock first, as their destructors may access process-local data
+			let objects = ::core::mem::replace(&mut *self.proc_local_data.write(), Vec::new());
+			drop(objects);
+		}
+	}
+	/// Release user memory once all threads have terminated (the process's handles may keep it alive for longer)
+	///
+	/// Called from the reaper. If a CPU still has the address space loaded (e.g. the final thread is still switching
+	/// out), the release is deferred until a later `release_deferred` call.
+	fn release_memory(this: &Arc<Process>) {
+		if !this.all_terminated.load(::core::sync::atomic::Ordering::SeqCst) {
+			return ;
+		}
+		if this.address_space.is_loaded() {
+			let mut lh = S_DEFERRED_RELEASE.lock();
+			if !lh.iter().any(|p| &**p as *const Process == &**this as *const Process) {
+				lh.push(this.clone());
+			}
+		}
+		else {
+			// NOTE: Locked to prevent a race with `get_info` walking the address space
+			let _lh = this.threads.lock();
+			this.address_space.release_user();
+		}
+	}
+
+	fn get_info(&self) -> ProcessInfo {
+		let threads = self.threads.lock();
+		let now = ::time::ticks();
+		let exit_status = self.exit_status.lock().0;
+		let all_terminated = self.all_terminated.load(::core::sync::atomic::Ordering::SeqCst);
+		ProcessInfo {
+			pid: self.pid,
+			parent: self.parent,
+			name: &self.name,
+			state: if all_terminated {
+					ProcessState::Exited
+				}
+				else if exit_status.is_some() {
+					ProcessState::Exiting
+				}
+				else {
+					ProcessState::Running
+				},
+			exit_status: exit_status,
+			thread_count: threads.len(),
+			cpu_time: threads.iter().fold(*self.exited_cpu_time.lock(), |sum, b| sum + b.cpu_time(now)),
+			// User memory is released once all threads have terminated (or is in the process of being released)
+			resident_pages: if all_terminated { 0 } else { self.address_space.resident_user_pages() },
+		}
+	}
+}
+impl ::core::ops::Drop for Process {
+	fn drop(&mut self) {
+		S_PROCESSES.lock().remove(self.pid);
+	}
 }
 
 impl ProcessHandle
 {
-	pub fn new<S: Into<String>+::core::fmt::Debug>(name: S, clone_start: usize, clone_end: usize) -> ProcessHandle {
-		ProcessHandle( Process::new(name, ::memory::virt::AddressSpace::new(clone_start, clone_end).expect("ProcessHandle::new - OOM")) )
+	/// Create a new (not yet started) process, fails if all PIDs or TIDs are in use
+	pub fn new<S: Into<String>+::core::fmt::Debug>(name: S, clone_start: usize, clone_end: usize) -> Result<ProcessHandle, ()> {
+		let rv = try!(Process::new(name, ::memory::virt::AddressSpace::new(clone_start, clone_end).expect("ProcessHandle::new - OOM")));
+		// NOTE: On failure, dropping the process releases the PID
+		let root_tid = match allocate_tid()
+			{
+			Some(v) => v,
+			None => {
+				log_notice!("ProcessHandle::new - All TIDs in use, can't start {}", rv.name);
+				return Err( () );
+				},
+			};
+		if clone_start == 0 && clone_end == ::arch::memory::addresses::USER_END {
+			// A fork, the child starts with a copy of all of this process's allocated memory
+			let pages = super::with_cur_thread(|cur| cur.get_process_info().user_pages.load(::core::sync::atomic::Ordering::Relaxed));
+			rv.user_pages.store(pages, ::core::sync::atomic::Ordering::Relaxed);
+		}
+		Ok( ProcessHandle(rv, Some(root_tid)) )
 	}
 	
+	/// Lower one of the process's resource limits (before it's started), returning the resulting limit
+	pub fn lower_limit(&self, r: super::limits::Resource, value: usize) -> usize {
+		self.0.lower_limit(r, value)
+	}
+	/// Get one of the process's resource limits
+	pub fn get_limit(&self, r: super::limits::Resource) -> usize {
+		self.0.get_limit(r)
+	}
+
 	pub fn start_root_thread(&mut self, ip: usize, sp: usize) {
 		log_trace!("start_thread(self={:?}, ip={:#x}, sp={:#x})", self, ip, sp);
 		assert!( Arc::get_mut(&mut self.0).is_some() );
 		
-		let mut thread = Thread::new_boxed(allocate_tid(), format!("{}#1", self.0.name), self.0.clone());
+		let tid = self.1.take().expect("start_root_thread - Already started");
+		let mut thread = Thread::new_boxed(tid, format!("{}#1", self.0.name), self.0.clone());
 		::arch::threads::start_thread( &mut thread,
 			// SAFE: Well... trusting caller to give us sane addresses etc, but that's the user's problem
 			move || unsafe {
 					log_debug!("Dropping to {:#x} SP={:#x}", ip, sp);
+					super::account_user_entry();
 					::arch::drop_to_user(ip, sp, 0)
 				}
 			);
@@ -265,18 +603,28 @@ impl ProcessHandle
 	pub fn get_exit_status(&self) -> Option<u32> {
 		self.0.exit_status.lock().0
 	}
+
+	/// Terminate the process (see `Process::kill`)
+	pub fn kill(&self, status: u32) {
+		self.0.kill(status)
+	}
 }
 impl ::core::ops::Drop for ProcessHandle {
 	fn drop(&mut self) {
 		log_notice!("Dropping handle {:?} - ref_count={}", self, Arc::strong_count(&self.0));
+		if let Some(tid) = self.1.take() {
+			// Never started, release the reserved root TID
+			S_THREADS.lock().remove(tid);
+		}
 	}
 }
 
 impl ThreadHandle
 {
-	pub fn new<F: FnOnce()+Send+'static, S: Into<String>>(name: S, fcn: F, process: Arc<Process>) -> ThreadHandle
+	pub fn new<F: FnOnce()+Send+'static, S: Into<String>>(name: S, priority: super::Priority, fcn: F, process: Arc<Process>) -> ThreadHandle
 	{
-		let mut thread = Thread::new_boxed(allocate_tid(), name, process);
+		let mut thread = Thread::new_boxed(allocate_tid().expect("ThreadHandle::new - All TIDs in use"), name, process);
+		thread.set_base_priority(priority);
 		let handle = ThreadHandle {
 			block: thread.block.clone(),
 			};
@@ -307,6 +655,84 @@ impl ::core::ops::Drop for ThreadHandle
 	}
 }
 
+impl UserThreadHandle
+{
+	/// Start a new thread in the current process, running user code at `ip` with the provided stack and TLS base
+	///
+	/// Fails if the process's thread limit has been reached
+	pub fn new(ip: usize, sp: usize, tls_base: usize) -> Result<UserThreadHandle, ()>
+	{
+		let process = super::with_cur_thread(|cur| cur.block.process.clone());
+		let tid = match allocate_tid()
+			{
+			Some(v) => v,
+			None => {
+				log_notice!("UserThreadHandle::new - All TIDs in use");
+				return Err( () );
+				},
+			};
+		let mut thread = match Thread::new_boxed_limited(tid, format!("{}#{}", process.name, tid), process.clone())
+			{
+			Ok(v) => v,
+			Err( () ) => {
+				log_notice!("UserThreadHandle::new - {} has reached its thread limit", process);
+				return Err( () );
+				},
+			};
+		let handle = UserThreadHandle {
+			block: thread.block.clone(),
+			};
+		log_trace!("UserThreadHandle::new(ip={:#x}, sp={:#x}, tls_base={:#x}) {}", ip, sp, tls_base, handle.block);
+		::arch::threads::set_user_tls_base(&mut thread, tls_base);
+		::arch::threads::start_thread(&mut thread,
+			// SAFE: Addresses are only used in user-space, bad values will just fault the process
+			move || unsafe {
+					super::account_user_entry();
+					::arch::drop_to_user(ip, sp, 0)
+				}
+			);
+		super::yield_to(thread);
+		Ok(handle)
+	}
+
+	pub fn get_tid(&self) -> ThreadID {
+		self.block.tid
+	}
+
+	pub fn bind_wait_terminate(&self, obj: &mut ::threads::SleepObject) {
+		let mut lh = self.block.exit_state.lock();
+		if lh.status.is_some() {
+			obj.signal();
+		}
+		else {
+			lh.waiters.push( obj.get_ref() );
+		}
+	}
+	pub fn clear_wait_terminate(&self, obj: &mut ::threads::SleepObject) -> bool {
+		let mut lh = self.block.exit_state.lock();
+		if let Some(i) = lh.waiters.iter().position(|r| r.is_from(obj)) {
+			lh.waiters.remove(i);
+		}
+		lh.status.is_some()
+	}
+
+	pub fn get_exit_status(&self) -> Option<u32> {
+		self.block.exit_state.lock().status
+	}
+
+	/// Queue a notification for the thread's exception handler (delivered when it next returns to user-mode)
+	pub fn notify(&self, value: u32) {
+		self.block.exceptions.notify(value);
+	}
+}
+impl ::core::fmt::Debug for UserThreadHandle
+{
+	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> Result<(),::core::fmt::Error>
+	{
+		write!(f, "UserThreadHandle({})", self.block)
+	}
+}
+
 impl ThreadPtr {
 	pub fn new(ptr: Box<Thread>) -> ThreadPtr {
 		// SAFE: Non-zero value
@@ -377,30 +803,106 @@ impl Thread
 	/// Create a new thread
 	pub fn new_boxed<S: Into<String>>(tid: ThreadID, name: S, process: Arc<Process>) -> ThreadPtr
 	{
+		Self::new_boxed_int(tid, name.into(), process, false).ok().expect("Thread::new_boxed - Limit checked")
+	}
+	/// Create a new thread, failing if the process's thread limit has been reached
+	///
+	/// The check and the addition to the process's thread list are done under the same lock, so concurrent
+	/// creations can't exceed the limit.
+	pub fn new_boxed_limited<S: Into<String>>(tid: ThreadID, name: S, process: Arc<Process>) -> Result<ThreadPtr, ()>
+	{
+		Self::new_boxed_int(tid, name.into(), process, true)
+	}
+	fn new_boxed_int(tid: ThreadID, name: String, process: Arc<Process>, check_limit: bool) -> Result<ThreadPtr, ()>
+	{
+		let limits = *process.limits.lock();
 		let rv = box Thread {
 			cpu_state: process.empty_cpu_state(),
 			block: Arc::new(SharedBlock {
 				tid: tid,
-				name: name.into(),
+				name: name,
 				process: process,
 				complete: crate::sync::EventChannel::new(),
+				exit_state: Default::default(),
+				sleep_object: ::sync::Spinlock::new(0),
+				stats: ::sync::Spinlock::new(ThreadStats {
+					state: ThreadState::Runnable,
+					priority: super::PRIORITY_DEFAULT,
+					cpu_time: Default::default(),
+					in_user: false,
+					run_start: None,
+					}),
+				exceptions: super::exceptions::ThreadExceptions::new(),
 				}),
 			run_state: RunState::Runnable,
+			sched: super::sched::SchedState::new(super::PRIORITY_DEFAULT),
 			next: None,
 			};
+		{
+			let mut lh = rv.block.process.threads.lock();
+			if check_limit && !limits.allows(super::limits::Resource::Threads, lh.len(), 1) {
+				// NOTE: Dropping the thread releases the TID
+				drop(lh);
+				return Err( () );
+			}
+			lh.push( rv.block.clone() );
+		}
+		S_THREADS.lock().insert(tid, &*rv.block);
 		
-		// TODO: Add to global list of threads (removed on destroy)
 		log_debug!("Creating thread {:?}", rv);
 		
-		ThreadPtr::new( rv )
+		Ok( ThreadPtr::new( rv ) )
 	}
 	
 	pub fn get_tid(&self) -> ThreadID { self.block.tid }
+
+	/// User exception handler state
+	pub(super) fn exceptions(&self) -> &super::exceptions::ThreadExceptions {
+		&self.block.exceptions
+	}
 	
 	/// Set the execution state of this thread
 	pub fn set_state(&mut self, state: RunState) {
+		// Woken from sleep: The thread didn't use all of its time slice, so is no longer demoted
+		if is!(state, RunState::Runnable) && !self.is_runnable() {
+			self.sched.reset();
+		}
+		let summary = match state
+			{
+			RunState::Runnable => ThreadState::Runnable,
+			RunState::ListWait(_) | RunState::Sleep(_) => ThreadState::Sleeping,
+			RunState::Dead(_) => ThreadState::Dead,
+			};
+		self.block.with_stats(|s| s.state = summary);
 		self.run_state = state;
 	}
+	/// Set the base scheduling priority
+	pub fn set_base_priority(&mut self, priority: super::Priority) {
+		self.sched.set_base(priority);
+		let p = self.sched.base();
+		self.block.with_stats(|s| s.priority = p);
+	}
+
+	/// CPU time accounting: The thread is about to be switched to
+	pub fn account_start(&self, now: u64) {
+		self.block.with_stats(|s| s.run_start = Some(now));
+	}
+	/// CPU time accounting: The thread is being switched away from
+	pub fn account_stop(&self, now: u64) {
+		self.block.with_stats(|s| if let Some(start) = s.run_start.take() {
+			s.charge(now - start);
+			});
+	}
+	/// CPU time accounting: The thread is switching between user and kernel mode
+	pub fn account_mode(&self, now: u64, in_user: bool) {
+		self.block.with_stats(|s| {
+			if let Some(start) = s.run_start {
+				s.charge(now - start);
+				s.run_start = Some(now);
+			}
+			s.in_user = in_user;
+			});
+	}
 	
 	pub fn is_runnable(&self) -> bool { is!(self.run_state, RunState::Runnable) }
 	
@@ -414,14 +916,117 @@ impl Thread
 	pub fn get_process_info(&self) -> &Process {
 		&*self.block.process
 	}
+
+	/// Record the sleep object the thread is about to sleep on (zero when done)
+	///
+	/// If `killable` is set, returns `false` (without recording) if the process has been killed, in which case the
+	/// thread must not sleep
+	pub fn set_sleep_object(&self, addr: usize, killable: bool) -> bool {
+		let mut lh = self.block.sleep_object.lock();
+		if addr != 0 && killable && self.block.process.is_killed() {
+			false
+		}
+		else {
+			*lh = addr;
+			true
+		}
+	}
+
+	/// Called by the thread itself as it terminates
+	pub fn on_terminate(&self) {
+		self.block.process.thread_terminated(&self.block);
+	}
+
+	/// Record the thread's exit status and wake anything waiting for it (the first call wins)
+	pub fn mark_exit(&self, status: u32) {
+		let mut lh = self.block.exit_state.lock();
+		if lh.status.is_none() {
+			lh.status = Some(status);
+			// NOTE: Signalled (and dropped) with the lock held, so the waiter's object stays valid
+			while let Some(r) = lh.waiters.pop() {
+				r.signal();
+			}
+		}
+	}
 }
 
 pub fn new_idle_thread(cpu: usize) -> ThreadPtr {
-	let mut thread = Thread::new_boxed(allocate_tid(), format!("Idle#{}", cpu), super::S_PID0.clone());
+	let mut thread = Thread::new_boxed(allocate_tid().expect("new_idle_thread - All TIDs in use"), format!("Idle#{}", cpu), super::S_PID0.clone());
 	::arch::threads::start_thread(&mut thread, super::idle_thread);
 	thread
 }
 
+impl SharedBlock
+{
+	fn with_stats<R, F: FnOnce(&mut ThreadStats)->R>(&self, f: F) -> R {
+		// NOTE: Updated from interrupt context (e.g. when woken), so interrupts are held
+		let _irq = ::arch::sync::hold_interrupts();
+		f(&mut self.stats.lock())
+	}
+	/// CPU time used, including the current run if the thread is running
+	fn cpu_time(&self, now: u64) -> CpuTime {
+		self.with_stats(|s| s.cpu_time_at(now))
+	}
+
+	fn get_info(&self, now: u64) -> ThreadInfo {
+		let (state, priority, cpu_time) = self.with_stats(|s| (
+			if s.run_start.is_some() { ThreadState::Running } else { s.state },
+			s.priority,
+			s.cpu_time_at(now),
+			));
+		ThreadInfo {
+			tid: self.tid,
+			pid: self.process.pid,
+			name: &self.name,
+			state: state,
+			priority: priority,
+			exit_status: self.exit_state.lock().status,
+			cpu_time: cpu_time,
+		}
+	}
+
+	/// Wake the thread if it's sleeping on a `SleepObject`
+	fn wake_sleeper(&self) {
+		let lh = self.sleep_object.lock();
+		if *lh != 0 {
+			// SAFE: The sleeper clears this (under the same lock) before the object can be destroyed
+			unsafe { (*(*lh as *const ::threads::SleepObject)).signal(); }
+		}
+	}
+}
+
+impl ThreadStats
+{
+	fn charge(&mut self, time: u64) {
+		if self.in_user {
+			self.cpu_time.user += time;
+		}
+		else {
+			self.cpu_time.kernel += time;
+		}
+	}
+	/// CPU time used, including the current accounting period
+	fn cpu_time_at(&self, now: u64) -> CpuTime {
+		let mut rv = self.cpu_time;
+		if let Some(start) = self.run_start {
+			if self.in_user {
+				rv.user += now - start;
+			}
+			else {
+				rv.kernel += now - start;
+			}
+		}
+		rv
+	}
+}
+
+impl ::core::ops::Drop for SharedBlock
+{
+	fn drop(&mut self) {
+		S_THREADS.lock().remove(self.tid);
+	}
+}
+
 impl ::core::fmt::Display for SharedBlock
 {
 	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result
@@ -444,11 +1049,35 @@ impl_fmt! {
 	}
 }
 
+/// Release the memory of terminated processes once their address space is no longer loaded (called by the reaper)
+pub fn release_deferred()
+{
+	// NOTE: try_lock, as this is called by the idle thread (which can't sleep), a contended list is retried later
+	let mut lh = match S_DEFERRED_RELEASE.try_lock()
+		{
+		Some(v) => v,
+		None => return,
+		};
+	lh.retain(|p| {
+		if p.address_space.is_loaded() {
+			true
+		}
+		else {
+			let _lh = p.threads.lock();
+			p.address_space.release_user();
+			false
+		}
+		});
+}
+
 impl ::core::ops::Drop for Thread
 {
 	fn drop(&mut self)
 	{
-		// TODO: Remove self from the global thread map
+		// If the thread was destroyed without exiting (e.g. killed), report that to waiters
+		self.mark_exit(EXIT_STATUS_KILLED);
+		// If this was the last thread, the process's memory can now be released
+		Process::release_memory(&self.block.process);
 		log_debug!("Destroying thread {:?} - {} handles to block, {} to process", self, Arc::strong_count(&self.block), Arc::strong_count(&self.block.process));
 	}
 }
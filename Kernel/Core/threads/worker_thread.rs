@@ -15,7 +15,12 @@ impl WorkerThread
 	/// Construct a new worker thread
 	pub fn new<F: FnOnce()+Send+'static>(name: &str, fcn: F) -> WorkerThread
 	{
-		let handle = super::thread::ThreadHandle::new(name, fcn, super::S_PID0.clone());
+		WorkerThread::new_with_priority(name, super::PRIORITY_DEFAULT, fcn)
+	}
+	/// Construct a new worker thread with a non-default scheduling priority
+	pub fn new_with_priority<F: FnOnce()+Send+'static>(name: &str, priority: super::Priority, fcn: F) -> WorkerThread
+	{
+		let handle = super::thread::ThreadHandle::new(name, priority, fcn, super::S_PID0.clone());
 		WorkerThread(handle)
 	}
 
@@ -3,6 +3,9 @@
 //
 // Core/time.rs
 //! Kernel timing and timers
+use prelude::*;
+use core::sync::atomic::{AtomicUsize,Ordering};
+use threads::{SleepObject,SleepObjectRef};
 
 /// Timer ticks (ms)
 pub type TickCount = u64;
@@ -12,7 +15,243 @@ pub fn ticks() -> u64
 {
 	::arch::cur_timestamp()
 }
+/// Obtain a high-resolution monotonic timestamp (nanoseconds, same origin as `ticks`)
+pub fn ticks_ns() -> u64
+{
+	::arch::cur_timestamp_ns()
+}
+
+/// Pending timers, a binary min-heap ordered by expiry time
+static S_TIMERS: ::sync::Mutex<TimerHeap> = ::sync::Mutex::new(TimerHeap { ents: Vec::new_const() });
+static S_NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(0);
+/// Expiry time of the first timer (checked by `tick` without locking)
+static S_NEXT_EXPIRY: ::sync::atomic::AtomicValue<TickCount> = ::sync::atomic::AtomicValue::new(!0);
+static S_TIMER_SIGNAL: ::lib::LazyStatic<SleepObject<'static>> = lazystatic_init!();
+static S_TIMER_WORKER: ::lib::LazyStatic<::threads::WorkerThread> = lazystatic_init!();
+
+struct TimerEnt
+{
+	expiry: TickCount,
+	/// Re-arm interval (zero for one-shot timers)
+	period: TickCount,
+	id: usize,
+	obj: SleepObjectRef,
+}
+struct TimerHeap
+{
+	ents: Vec<TimerEnt>,
+}
+
+/// A pending wakeup of a sleep object, cancelled when dropped
+pub struct Timer(usize);
+
+pub fn init()
+{
+	// SAFE: Called in a single-threaded context
+	unsafe {
+		// SAFE: The SleepObject here is static, so is never invalidated
+		S_TIMER_SIGNAL.prep(|| SleepObject::new("Timer Worker"));
+		S_TIMER_WORKER.prep(|| ::threads::WorkerThread::new_with_priority("Timer Worker", ::threads::PRIORITY_HIGH, timer_worker));
+	}
+}
+
+/// Called by the architecture's timer interrupt, wakes the timer worker if a timer has expired
+//#[is_safe(irq)]
+pub fn tick()
+{
+	if S_TIMER_SIGNAL.ls_is_valid() && ticks() >= S_NEXT_EXPIRY.load(Ordering::Relaxed) {
+		S_TIMER_SIGNAL.signal();
+	}
+	rearm(::threads::cur_slice_end());
+}
+
+/// Program this CPU's timer interrupt for the next deadline
+///
+/// The deadline is the earlier of the first pending timer and `slice_end` (the end of the running thread's time
+/// slice, ignored if already passed). With neither, no timer interrupt is requested (tickless idle), unless the
+/// profiler is enabled, which keeps a periodic deadline so every CPU (including idle ones) is sampled.
+//#[is_safe(irq)]
+pub fn rearm(slice_end: TickCount)
+{
+	let now = ticks();
+	let next_timer = S_NEXT_EXPIRY.load(Ordering::Relaxed);
+	let next = ::core::cmp::min(next_timer, if slice_end > now { slice_end } else { !0 });
+	let next = if ::profiler::is_enabled() { ::core::cmp::min(next, now + ::profiler::SAMPLE_PERIOD) } else { next };
+	::arch::set_timer_deadline( if next == !0 { !0 } else { next * 1_000_000 } );
+}
+
+fn timer_worker()
+{
+	loop
+	{
+		S_TIMER_SIGNAL.wait();
+		let now = ticks();
+		let mut lh = S_TIMERS.lock();
+		// NOTE: Signalled (and the reference dropped) with the lock held, so a cancelling `Timer` can't return early
+		while lh.ents.len() > 0 && lh.ents[0].expiry <= now
+		{
+			let mut ent = lh.remove(0);
+			ent.obj.signal();
+			if ent.period > 0 {
+				ent.expiry = ent.next_period(now);
+				lh.push(ent);
+			}
+		}
+		lh.update_next();
+	}
+}
+
+impl TimerEnt
+{
+	/// Expiry of the first period after `now` (skipping any missed periods, instead of firing repeatedly to catch up)
+	fn next_period(&self, now: TickCount) -> TickCount
+	{
+		let missed = (now - self.expiry) / self.period;
+		self.expiry + (missed + 1) * self.period
+	}
+}
+
+impl TimerHeap
+{
+	fn push(&mut self, ent: TimerEnt)
+	{
+		self.ents.push(ent);
+		let i = self.ents.len() - 1;
+		self.sift_up(i);
+	}
+	fn remove(&mut self, idx: usize) -> TimerEnt
+	{
+		let last = self.ents.len() - 1;
+		self.ents.swap(idx, last);
+		let rv = self.ents.pop().unwrap();
+		if idx < self.ents.len() {
+			let i = self.sift_up(idx);
+			self.sift_down(i);
+		}
+		rv
+	}
+	fn sift_up(&mut self, mut i: usize) -> usize
+	{
+		while i > 0 && self.ents[(i-1)/2].expiry > self.ents[i].expiry {
+			self.ents.swap(i, (i-1)/2);
+			i = (i-1)/2;
+		}
+		i
+	}
+	fn sift_down(&mut self, mut i: usize)
+	{
+		loop
+		{
+			let mut smallest = i;
+			for c in 2*i+1 .. 2*i+3 {
+				if c < self.ents.len() && self.ents[c].expiry < self.ents[smallest].expiry {
+					smallest = c;
+				}
+			}
+			if smallest == i {
+				break;
+			}
+			self.ents.swap(i, smallest);
+			i = smallest;
+		}
+	}
+	fn update_next(&self)
+	{
+		let next = self.ents.first().map(|e| e.expiry).unwrap_or(!0);
+		let prev = S_NEXT_EXPIRY.swap(next, Ordering::Relaxed);
+		// An earlier first timer needs the interrupt brought forward (later ones just cause a spurious tick)
+		if next < prev {
+			rearm(::threads::cur_slice_end());
+		}
+	}
+}
 
+impl Timer
+{
+	/// Signal the sleep object once `ticks()` reaches `expiry`
+	pub fn new(expiry: TickCount, obj: SleepObjectRef) -> Timer
+	{
+		Timer::new_inner(expiry, 0, obj)
+	}
+	/// Signal the sleep object every `period` ticks, starting at `first_expiry`
+	pub fn new_periodic(first_expiry: TickCount, period: TickCount, obj: SleepObjectRef) -> Timer
+	{
+		assert!(period > 0, "Timer::new_periodic - Zero period");
+		Timer::new_inner(first_expiry, period, obj)
+	}
+	fn new_inner(expiry: TickCount, period: TickCount, obj: SleepObjectRef) -> Timer
+	{
+		let id = S_NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+		let mut lh = S_TIMERS.lock();
+		lh.push(TimerEnt { expiry: expiry, period: period, id: id, obj: obj });
+		lh.update_next();
+		// If the expiry has already passed, make sure the worker notices
+		if expiry <= ticks() {
+			tick();
+		}
+		Timer(id)
+	}
+}
+impl ::core::ops::Drop for Timer
+{
+	fn drop(&mut self)
+	{
+		let mut lh = S_TIMERS.lock();
+		if let Some(i) = lh.ents.iter().position(|e| e.id == self.0) {
+			lh.remove(i);
+			lh.update_next();
+		}
+	}
+}
+
+/// Hardware real-time clock (used as the source of wall-clock time)
+pub trait RealTimeClock: Send + Sync
+{
+	/// Read the current time (seconds since 1970-01-01 00:00:00 UTC)
+	fn read(&self) -> Option<u64>;
+	/// Set the time (seconds since 1970-01-01 00:00:00 UTC)
+	fn write(&self, secs: u64);
+}
+
+static S_RTC: ::sync::Mutex<Option<Box<dyn RealTimeClock>>> = ::sync::Mutex::new(None);
+/// Wall-clock time (ms since 1970) at tick zero, zero until a RTC is registered
+static S_WALL_OFFSET: ::sync::atomic::AtomicValue<u64> = ::sync::atomic::AtomicValue::new(0);
+
+/// Register a real-time clock, and initialise the wall-clock from it
+pub fn register_rtc(rtc: Box<dyn RealTimeClock>)
+{
+	let mut lh = S_RTC.lock();
+	if lh.is_some() {
+		log_warning!("register_rtc - A RTC is already registered, ignoring");
+		return ;
+	}
+	match rtc.read()
+	{
+	Some(secs) => {
+		log_notice!("Wall-clock time from RTC: {} s since epoch", secs);
+		S_WALL_OFFSET.store(secs * 1000 - ticks(), Ordering::Relaxed);
+		},
+	None => log_warning!("register_rtc - Unable to read time"),
+	}
+	*lh = Some(rtc);
+}
+
+/// Current wall-clock time (milliseconds since 1970-01-01 00:00:00 UTC)
+///
+/// NOTE: Counts from zero at boot if there is no RTC
+pub fn wall_time() -> u64
+{
+	S_WALL_OFFSET.load(Ordering::Relaxed) + ticks()
+}
+/// Set the wall-clock time (milliseconds since 1970-01-01 00:00:00 UTC), updating the RTC
+pub fn set_wall_time(ms: u64)
+{
+	let lh = S_RTC.lock();
+	S_WALL_OFFSET.store(ms.saturating_sub(ticks()), Ordering::Relaxed);
+	if let Some(ref rtc) = *lh {
+		rtc.write(ms / 1000);
+	}
+}
 
 /// Records the current time on construction, and prints the elapsed time with {:?} / {}
 pub struct ElapsedLogger(TickCount);
@@ -50,6 +289,72 @@ impl CacheTimer
 	pub fn bump(&self) {
 		self.0.store(ticks(), ::core::sync::atomic::Ordering::SeqCst)
 	}
+
+	/// Tick count at the last access
+	pub fn get(&self) -> TickCount {
+		self.0.load(::core::sync::atomic::Ordering::SeqCst)
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use prelude::*;
+	use threads::SleepObject;
+	use super::{TickCount,TimerEnt,TimerHeap};
+
+	/// Remove every entry from the front of the heap, returning the expiry times in removal order
+	fn drain(h: &mut TimerHeap) -> Vec<TickCount>
+	{
+		let mut rv = Vec::new();
+		while h.ents.len() > 0 {
+			rv.push( h.remove(0).expiry );
+		}
+		rv
+	}
+
+	#[test]
+	fn heap_ordering()
+	{
+		SleepObject::with_new("test", |obj| {
+			let mut h = TimerHeap { ents: Vec::new() };
+			for (i, &e) in [50, 10, 40, 20, 30, 10].iter().enumerate() {
+				h.push(TimerEnt { expiry: e, period: 0, id: i, obj: obj.get_ref() });
+			}
+			assert_eq!(h.ents[0].expiry, 10);
+			assert_eq!(&drain(&mut h)[..], &[10, 10, 20, 30, 40, 50]);
+			});
+	}
+
+	#[test]
+	fn heap_remove()
+	{
+		SleepObject::with_new("test", |obj| {
+			let mut h = TimerHeap { ents: Vec::new() };
+			for (i, &e) in [70, 10, 60, 20, 50, 30, 40].iter().enumerate() {
+				h.push(TimerEnt { expiry: e, period: 0, id: i, obj: obj.get_ref() });
+			}
+			// Cancel (by ID, as `Timer::drop` does) entries from within the heap
+			for &id in &[3, 2] {
+				let i = h.ents.iter().position(|e| e.id == id).unwrap();
+				h.remove(i);
+			}
+			assert_eq!(&drain(&mut h)[..], &[10, 30, 40, 50, 70]);
+			});
+	}
+
+	#[test]
+	fn periodic_rearm()
+	{
+		SleepObject::with_new("test", |obj| {
+			let ent = TimerEnt { expiry: 100, period: 30, id: 0, obj: obj.get_ref() };
+			assert_eq!(ent.next_period(100), 130);
+			assert_eq!(ent.next_period(129), 130);
+			assert_eq!(ent.next_period(130), 160);
+			// Missed periods are skipped
+			assert_eq!(ent.next_period(175), 190);
+			});
+	}
 }
 
 // vim: ft=rust
@@ -7,7 +7,7 @@
 use prelude::*;
 use metadevs::storage::VolumeHandle;
 
-module_define!(VFS, [], init);
+module_define!(VFS, [Storage], init);
 
 pub type Result<T> = ::core::result::Result<T,Error>;
 
@@ -75,6 +75,7 @@ pub mod mount;
 pub mod handle;
 mod path;
 mod ramfs;
+pub mod procfs;
 
 fn init()
 {
@@ -82,6 +83,7 @@ fn init()
 	mount::init();
 	node::init();
 	ramfs::init();
+	procfs::init();
 	// 2. Start the root/builtin filesystems
 	mount::mount("/".as_ref(), VolumeHandle::new_ramdisk(0), "ramfs", &[]).expect("Unable to mount /");
 	// 3. Initialise root filesystem layout
@@ -93,5 +95,7 @@ fn init()
 	root.mkdir("system").unwrap();
 	root.mkdir("volumes").unwrap();
 	root.mkdir("temp").unwrap();
+	root.mkdir("proc").unwrap();
+	mount::mount("/proc".as_ref(), VolumeHandle::new_ramdisk(0), "procfs", &[]).expect("Unable to mount /proc");
 }
 
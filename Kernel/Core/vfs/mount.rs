@@ -9,7 +9,7 @@ use super::node::{InodeId,Node,CacheHandle};
 use sync::RwLock;
 use lib::{LazyStatic,SparseVec,VecMap};
 
-use metadevs::storage::VolumeHandle;
+use metadevs::storage::{self,VolumeHandle};
 
 /// A handle to a mounted filesystem
 /// 
@@ -26,6 +26,8 @@ struct MountedVolume
 {
 	mountpoint_node: CacheHandle,
 	fs: Box<dyn Filesystem>,
+	/// Index of the backing logical volume
+	volume: usize,
 }
 
 
@@ -35,12 +37,18 @@ pub trait Filesystem:
 {
 	fn root_inode(&self) -> InodeId;
 	fn get_node_by_inode(&self, InodeId) -> Option<Node>;
+
+	/// Called when the backing volume has been removed, just before the filesystem is force-unmounted
+	///
+	/// All IO to the volume will fail, so any cached dirty data should be discarded.
+	fn volume_removed(&self);
 }
 
 struct NullFs;
 impl Filesystem for NullFs {
 	fn root_inode(&self) -> InodeId { 0 }
 	fn get_node_by_inode(&self, _: InodeId) -> Option<Node> { None }
+	fn volume_removed(&self) { }
 }
 
 /// Filesystem instance trait
@@ -74,6 +82,33 @@ pub fn init()
 		S_DRIVERS.prep( || Default::default() );
 		S_VOLUMES.prep( || Default::default() );
 	}
+	storage::register_event_handler(volume_event);
+}
+
+/// Storage event handler, force-unmounts filesystems on removed volumes
+fn volume_event(ev: &storage::VolumeEvent)
+{
+	if let &storage::VolumeEvent::Removed(lv_idx, ref name) = ev
+	{
+		let mut removed = Vec::new();
+		{
+			let mut lh = S_VOLUMES.write();
+			for vidx in 0 .. lh.len()
+			{
+				if lh.get(vidx).map(|v| v.volume != lv_idx).unwrap_or(true) {
+					continue ;
+				}
+				let v = &mut lh[vidx];
+				log_warning!("Volume '{}' removed while mounted (mount #{}), forcing unmount", name, vidx + 1);
+				v.fs.volume_removed();
+				v.mountpoint_node.unmount(vidx + 1);
+				// Leave a placeholder in the slot, as cached nodes may still reference this mount ID
+				removed.push( ::core::mem::replace(&mut v.fs, Box::new(NullFs)) );
+			}
+		}
+		// Drop the filesystem instances outside of the lock
+		drop(removed);
+	}
 }
 
 /// Mount a volume at the provided location
@@ -134,7 +169,7 @@ pub fn mount(location: &Path, vol: VolumeHandle, fs: &str, _options: &[&str]) ->
 		
 		// 3. Reserve the mountpoint ID (using a placeholder instance)
 		// NOTE: Nothing should know of this index until after mount is completed
-		let vidx = S_VOLUMES.write().insert(MountedVolume { mountpoint_node: nh, fs: Box::new(NullFs) });
+		let vidx = S_VOLUMES.write().insert(MountedVolume { mountpoint_node: nh, fs: Box::new(NullFs), volume: vol.idx() });
 
 		// 4. Mount and register volume
 		let fs = match driver.mount(vol, SelfHandle(vidx))
@@ -387,6 +387,16 @@ impl CacheHandle
 		_ => false,
 		}
 	}
+	/// Returns `true` if the specified filesystem was bound here (and has now been unbound)
+	pub fn unmount(&self, filesystem_id: usize) -> bool {
+		match self.as_ref()
+		{
+		&CacheNodeInt::Dir { ref mountpoint, .. } => {
+			mountpoint.compare_and_swap(filesystem_id, 0, atomic::Ordering::Relaxed) == filesystem_id
+			},
+		_ => false,
+		}
+	}
 }
 /// Normal file methods
 impl CacheHandle
@@ -0,0 +1,156 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/vfs/procfs.rs
+//! Kernel information filesystem (mounted at `/proc`)
+//!
+//! Each file is backed by a generator function, called to produce the file's contents when it is read.
+use prelude::*;
+use vfs;
+use super::{mount, node};
+use metadevs::storage::VolumeHandle;
+use lib::byte_str::ByteStr;
+use core::fmt;
+
+/// Function that writes the contents of a proc file
+pub type Generator = fn(&mut dyn fmt::Write) -> fmt::Result;
+
+pub struct Driver;
+pub static S_DRIVER: Driver = Driver;
+
+/// Registered files, the inode number is the index plus one (inode zero is the root)
+static S_FILES: ::sync::RwLock<Vec<(&'static str, Generator)>> = ::sync::RwLock::new(Vec::new_const());
+
+struct ProcFS;
+struct RootDir;
+struct ProcFile(node::InodeId, Generator);
+
+pub fn init()
+{
+	let h = mount::DriverRegistration::new("procfs", &S_DRIVER);
+	::core::mem::forget(h);
+
+	register("heap", ::memory::heap::dump_stats);
+	register("threads", ::threads::dump_threads);
+	register("profile", ::profiler::dump);
+}
+
+/// Add a file to the root of `/proc`
+pub fn register(name: &'static str, generator: Generator)
+{
+	let mut lh = S_FILES.write();
+	assert!( !lh.iter().any(|e| e.0 == name), "Duplicate procfs file '{}'", name );
+	lh.push( (name, generator) );
+}
+
+impl mount::Driver for Driver
+{
+	fn detect(&self, _vol: &VolumeHandle) -> vfs::Result<usize> {
+		// Only mounted explicitly
+		Ok(0)
+	}
+	fn mount(&self, _vol: VolumeHandle, _: mount::SelfHandle) -> vfs::Result<Box<dyn mount::Filesystem>> {
+		Ok( Box::new(ProcFS) )
+	}
+}
+
+impl mount::Filesystem for ProcFS
+{
+	fn root_inode(&self) -> node::InodeId {
+		0
+	}
+	fn get_node_by_inode(&self, id: node::InodeId) -> Option<node::Node> {
+		if id == 0 {
+			Some( node::Node::Dir(Box::new(RootDir)) )
+		}
+		else {
+			S_FILES.read().get(id as usize - 1).map(|e| node::Node::File(Box::new(ProcFile(id, e.1))))
+		}
+	}
+	fn volume_removed(&self) {
+		// Not backed by the volume
+	}
+}
+
+impl node::NodeBase for RootDir {
+	fn get_id(&self) -> node::InodeId {
+		0
+	}
+	fn get_any(&self) -> &dyn (::core::any::Any) {
+		self
+	}
+}
+impl node::Dir for RootDir {
+	fn lookup(&self, name: &ByteStr) -> vfs::Result<node::InodeId> {
+		match S_FILES.read().iter().position(|e| e.0.as_bytes() == name.as_bytes())
+		{
+		Some(i) => Ok(i as node::InodeId + 1),
+		None => Err(vfs::Error::NotFound),
+		}
+	}
+	fn read(&self, start_ofs: usize, callback: &mut node::ReadDirCallback) -> node::Result<usize> {
+		let lh = S_FILES.read();
+		let mut count = 0;
+		for (i, e) in lh.iter().enumerate().skip(start_ofs)
+		{
+			count += 1;
+			if ! callback(i as node::InodeId + 1, &mut e.0.bytes()) {
+				break ;
+			}
+		}
+		Ok(start_ofs + count)
+	}
+	fn create(&self, _name: &ByteStr, _nodetype: node::NodeType) -> vfs::Result<node::InodeId> {
+		Err(vfs::Error::ReadOnlyFilesystem)
+	}
+	fn link(&self, _name: &ByteStr, _node: &dyn node::NodeBase) -> vfs::Result<()> {
+		Err(vfs::Error::ReadOnlyFilesystem)
+	}
+	fn unlink(&self, _name: &ByteStr) -> vfs::Result<()> {
+		Err(vfs::Error::ReadOnlyFilesystem)
+	}
+}
+
+impl ProcFile {
+	fn generate(&self) -> String {
+		let mut rv = String::new();
+		if let Err(_) = (self.1)(&mut rv) {
+			log_warning!("procfs: Generator for inode {} failed", self.0);
+		}
+		rv
+	}
+}
+impl node::NodeBase for ProcFile {
+	fn get_id(&self) -> node::InodeId {
+		self.0
+	}
+	fn get_any(&self) -> &dyn (::core::any::Any) {
+		self
+	}
+}
+impl node::File for ProcFile {
+	fn size(&self) -> u64 {
+		self.generate().len() as u64
+	}
+	fn truncate(&self, _newsize: u64) -> vfs::Result<u64> {
+		Err(vfs::Error::ReadOnlyFilesystem)
+	}
+	fn clear(&self, _ofs: u64, _size: u64) -> vfs::Result<()> {
+		Err(vfs::Error::ReadOnlyFilesystem)
+	}
+	fn read(&self, ofs: u64, buf: &mut [u8]) -> vfs::Result<usize> {
+		// NOTE: Contents are regenerated on each read, so reading in pieces may see inconsistent data
+		let data = self.generate();
+		let data = data.as_bytes();
+		if ofs >= data.len() as u64 {
+			return Ok(0);
+		}
+		let src = &data[ofs as usize ..];
+		let len = ::core::cmp::min(src.len(), buf.len());
+		buf[..len].copy_from_slice(&src[..len]);
+		Ok(len)
+	}
+	fn write(&self, _ofs: u64, _buf: &[u8]) -> vfs::Result<usize> {
+		Err(vfs::Error::ReadOnlyFilesystem)
+	}
+}
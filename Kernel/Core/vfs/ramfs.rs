@@ -101,6 +101,9 @@ impl mount::Filesystem for RamFS
 			}
 		}
 	}
+	fn volume_removed(&self) {
+		// All data is held in memory, nothing to discard
+	}
 }
 
 impl FileRef {
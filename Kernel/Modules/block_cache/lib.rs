@@ -13,22 +13,44 @@ use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use kernel::metadevs::storage::{VolumeHandle,IoError};
 use kernel::sync::{RwLock,rwlock};
 use kernel::sync::mutex::LazyMutex;
+use kernel::sync::EventChannel;
+use kernel::lib::mem::Arc;
 
 // NOTES:
 // - Handles wrap logical volume handles
 // - Presents:
 //  > read/write (unbuffered)
-//  > read_inner/get/edit (buffered)
+//  > read_inner/read_cached/get/edit (buffered)
+// - Cache entries are at least a page, but can be larger (as requested by the handle's owner) so that a
+//   filesystem block never spans entries. Page-sized entries use the page cache, larger ones are heap-backed.
+// - Entries never overlap: each starts at a fixed multiple of the entry size (offset by the handle's base block,
+//   with a shorter entry for any blocks before the first full entry).
 //
 // - The global cache is registered with the PMM as a source of reclaimable memory
+// - Modified blocks are written back by a worker thread (after a short delay), or by `sync`
+// - Unreferenced clean blocks are evicted (least-recently-used first) when the cache is over its limit, or
+//   when the PMM requests memory. Eviction leaves the (empty) entry in the map, it's re-read on the next access,
+//   or removed by `Cache::prune`.
+// - Disk IO and releasing evicted data (which unmaps pages) are done without the cache locked. The PMM's reclaim
+//   path can't do either (the allocating thread could hold the kernel address space lock), so it just asks the
+//   write-back thread to evict.
 
 #[macro_use]
 extern crate kernel;
 
+/// Soft limit on the number of pages used by cached blocks, above this LRU blocks are evicted
+const MAX_CACHED_PAGES: usize = 1024;
+/// Time (ms) that modified blocks are left before being written back
+const WRITEBACK_DELAY_MS: u64 = 1000;
+
 /// A handle into the cache corresponding to a logical volume
 pub struct CacheHandle
 {
-	vh: VolumeHandle,
+	vh: Arc<VolumeHandle>,
+	/// Number of volume blocks in each cache entry
+	blocks_per_entry: u64,
+	/// Offset of entry boundaries from the start of the volume (always less than `blocks_per_entry`)
+	entry_phase: u64,
 }
 
 /// A handle to a block in the cache
@@ -40,20 +62,43 @@ struct MetaBlockHandle<'a>(&'a CachedBlock);
 #[derive(Default)]
 struct Cache
 {
-	map: ::kernel::lib::VecMap< (usize, u64), Box<CachedBlock> >,
+	/// Keyed by (volume, blocks per entry, entry phase, first block)
+	map: ::kernel::lib::VecMap< (usize, u64, u64, u64), Box<CachedBlock> >,
+	/// Total number of pages used by cached blocks
+	page_count: usize,
+	/// Volumes with active cache handles (used by the write-back thread)
+	volumes: ::kernel::lib::VecMap< usize, CachedVolume >,
+}
+
+/// A volume with at least one active cache handle
+struct CachedVolume
+{
+	vh: Arc<VolumeHandle>,
+	/// Number of `CacheHandle`s for this volume, the volume's blocks are released when the last one is dropped
+	handle_count: usize,
 }
 
 struct CachedBlock
 {
 	// Constant:
 	index: u64,
-	block_paddr: ::kernel::memory::phys::FrameHandle,
 
 	reference_count: AtomicUsize,
 	last_access: ::kernel::time::CacheTimer,
 	is_dirty: AtomicBool,
 
-	mapping: RwLock<Option<::kernel::memory::page_cache::CachedPage>>,
+	data: RwLock<BlockData>,
+}
+
+/// Backing storage for a cache entry
+enum BlockData
+{
+	/// Single page, from the page cache
+	Page(::kernel::memory::page_cache::CachedPage),
+	/// Multi-page entry
+	Heap(Box<[u8]>),
+	/// Not loaded (new entry, or data released by eviction)
+	Evicted,
 }
 
 
@@ -61,22 +106,81 @@ static S_BLOCK_CACHE: LazyMutex<Cache> = LazyMutex::new();
 //static S_BLOCK_CACHE: Mutex<Cache> = Mutex::new(Cache {
 //	map: ::kernel::lib::VecMap::new(),
 //	});
+static S_WRITEBACK_THREAD: LazyMutex<::kernel::threads::WorkerThread> = LazyMutex::new();
+/// Signalled when a block is modified
+static S_WRITEBACK_REQUEST: EventChannel = EventChannel::new();
+/// Pages requested by the PMM, evicted by the write-back thread
+static S_RECLAIM_TARGET: AtomicUsize = AtomicUsize::new(0);
+static S_RECLAIMER: Reclaimer = Reclaimer;
 
 impl CacheHandle
 {
+	/// Create a cache handle using the smallest possible entry size (one page, or the volume's block size if larger)
 	pub fn new(vol: VolumeHandle) -> CacheHandle
 	{
-		if vol.block_size() > ::kernel::PAGE_SIZE {
-			todo!("Support caching volumes with block sizes > page size");
-		}
+		let bs = vol.block_size();
+		Self::new_with_unit(vol, bs)
+	}
 
+	/// Create a cache handle where each entry holds at least `unit_size` bytes
+	///
+	/// Used by filesystems with blocks larger than a page, so `get_block` can return an entire filesystem block.
+	/// `unit_size` must be a multiple of the volume's block size.
+	pub fn new_with_unit(vol: VolumeHandle, unit_size: usize) -> CacheHandle
+	{
+		Self::new_with_unit_at(vol, unit_size, 0)
+	}
+
+	/// Create a cache handle with `unit_size` entries aligned to `base_block` (instead of the start of the volume)
+	///
+	/// Used when filesystem units don't start at a multiple of their size (e.g. FAT clusters start after the FAT),
+	/// so each unit is held in a single entry. Blocks before the first full entry are held in a shorter entry.
+	pub fn new_with_unit_at(vol: VolumeHandle, unit_size: usize, base_block: u64) -> CacheHandle
+	{
+		let bs = vol.block_size();
+		assert!(unit_size > 0 && unit_size % bs == 0, "CacheHandle::new_with_unit - Unit size {} not a multiple of block size {}", unit_size, bs);
+		let entry_size = ::kernel::lib::num::round_up(::core::cmp::max(unit_size, PAGE_SIZE), unit_size);
+
+		let vh = Arc::new(vol);
+		{
+			use kernel::lib::vec_map::Entry;
+			let mut lh = S_BLOCK_CACHE.lock_init(|| {
+				// First use: register with the PMM and start the write-back worker
+				::kernel::memory::phys::register_reclaimer(&S_RECLAIMER);
+				S_WRITEBACK_THREAD.init(|| ::kernel::threads::WorkerThread::new("Block Cache Writeback", writeback_thread));
+				Default::default()
+				});
+			match lh.volumes.entry(vh.idx())
+			{
+			Entry::Occupied(v) => v.into_mut().handle_count += 1,
+			Entry::Vacant(v) => { v.insert(CachedVolume { vh: vh.clone(), handle_count: 1 }); },
+			}
+		}
+		let blocks_per_entry = (entry_size / bs) as u64;
 		CacheHandle {
-			vh: vol,
+			vh: vh,
+			blocks_per_entry: blocks_per_entry,
+			entry_phase: base_block % blocks_per_entry,
 			}
 	}
 
-	pub fn blocks_per_page(&self) -> u64 {
-		(PAGE_SIZE / self.vh.block_size()) as u64
+	/// Number of volume blocks in each cache entry
+	pub fn blocks_per_entry(&self) -> u64 {
+		self.blocks_per_entry
+	}
+	/// Size of each cache entry in bytes
+	pub fn entry_size(&self) -> usize {
+		self.blocks_per_entry as usize * self.vh.block_size()
+	}
+
+	/// Get the first block and block count of the entry containing `block`
+	fn entry_range(&self, block: u64) -> (u64, u64) {
+		if block < self.entry_phase {
+			(0, self.entry_phase)
+		}
+		else {
+			(block - (block - self.entry_phase) % self.blocks_per_entry, self.blocks_per_entry)
+		}
 	}
 }
 
@@ -105,20 +209,36 @@ impl CacheHandle
 /// Cached accesses
 impl CacheHandle
 {
+	/// Obtain the entry containing `block`
 	fn get_block_meta(&self, block: u64) -> Result<MetaBlockHandle, IoError>
 	{
-		let cache_block = block - block % self.blocks_per_page();
-		let handle = {
+		let (cache_block, count) = self.entry_range(block);
+		let size = count as usize * self.block_size();
+		let entry_pages = ::kernel::lib::num::div_up(size, PAGE_SIZE);
+		let (handle, victims) = {
 			use kernel::lib::vec_map::Entry;
-			let mut lh = S_BLOCK_CACHE.lock_init(|| Default::default());
-			let handle = match lh.map.entry( (self.vh.idx(), cache_block) )
+			let mut lh = S_BLOCK_CACHE.lock();
+			let victims = if lh.page_count + entry_pages > MAX_CACHED_PAGES {
+					let excess = lh.page_count + entry_pages - MAX_CACHED_PAGES;
+					let v = lh.evict(excess);
+					lh.prune();
+					v
+				}
+				else {
+					Vec::new()
+				};
+			let handle = match lh.map.entry( (self.vh.idx(), self.blocks_per_entry, self.entry_phase, cache_block) )
 				{
 				Entry::Occupied(v) => v.into_mut().borrow(),
-				Entry::Vacant(v) => v.insert( Box::new( try!(CachedBlock::new(&self.vh, cache_block)) ) ).borrow(),
+				// Inserted empty, the data is read below (with the cache unlocked)
+				Entry::Vacant(v) => v.insert( Box::new(CachedBlock::new(cache_block)) ).borrow(),
 				};
 			// SAFE: 1. The internal data is boxed, 2. The box won't be dropped while a borrow exists.
-			unsafe { ::core::mem::transmute::<MetaBlockHandle, MetaBlockHandle>(handle) }
+			(unsafe { ::core::mem::transmute::<MetaBlockHandle, MetaBlockHandle>(handle) }, victims)
 			};
+		// Release the evicted data (unmapping pages) now that the cache is unlocked
+		drop(victims);
+		try!(handle.0.load(&self.vh, size));
 		Ok(handle)
 	}
 
@@ -129,110 +249,351 @@ impl CacheHandle
 		Ok( try!(self.get_block_meta(block)).into_ro() )
 	}
 
+	/// Obtain a handle to the cache entry containing all of `count` blocks starting at `block`
+	///
+	/// Returns `InvalidParameter` if the range crosses an entry boundary (see `new_with_unit_at` for units that
+	/// aren't aligned to the start of the volume). As with `get_block`, check the returned index.
+	pub fn get_blocks(&self, block: u64, count: usize) -> Result<CachedBlockHandle, IoError>
+	{
+		let (first, n) = self.entry_range(block);
+		if block + count as u64 > first + n {
+			return Err(IoError::InvalidParameter);
+		}
+		Ok( try!(self.get_block_meta(block)).into_ro() )
+	}
+
 	/// Read out of a cached block
+	///
+	/// The read can extend past the end of `block`, but must not cross a cache entry boundary.
 	pub fn read_inner(&self, block: u64, offset: usize, data: &mut [u8]) -> Result<(),IoError>
 	{
 		let cached_block = try!(self.get_block(block));
 		let blk_ofs = (block - cached_block.index()) as usize * self.block_size();
 
-		if offset >= self.block_size() {
-			return Err(IoError::InvalidParameter);
-		}
-		if offset >= self.block_size() - blk_ofs {
+		if blk_ofs + offset + data.len() > cached_block.data().len() {
 			return Err(IoError::InvalidParameter);
 		}
-		assert!(data.len() <= self.block_size() - offset);
 		let bytes = data.len();
 		data.clone_from_slice( &cached_block.data()[blk_ofs + offset .. ][ .. bytes] );
 		Ok( () )
 	}
 	/// Write into a cached block
+	///
+	/// As with `read_inner`, the write must not cross a cache entry boundary.
 	pub fn write_inner(&self, block: u64, offset: usize, data: &[u8]) -> Result<(), IoError>
 	{
-		let cached_block = try!(self.get_block_meta(block));
-		let blk_ofs = (block - cached_block.index()) as usize * self.block_size();
-
-		if offset >= self.block_size() {
-			return Err(IoError::InvalidParameter);
-		}
-		if blk_ofs + offset >= self.block_size() {
+		let (first, n) = self.entry_range(block);
+		let blk_ofs = (block - first) as usize * self.block_size();
+		if blk_ofs + offset + data.len() > n as usize * self.block_size() {
 			return Err(IoError::InvalidParameter);
 		}
 
+		let cached_block = try!(self.get_block_meta(block));
 		cached_block.edit(|block_data| {
-			block_data[blk_ofs + offset ..].clone_from_slice( data );
+			block_data[blk_ofs + offset ..][.. data.len()].clone_from_slice( data );
 			Ok( () )
 			})
 	}
+	/// Read a sequence of blocks through the cache (can span multiple cache entries)
+	pub fn read_cached(&self, mut block: u64, mut data: &mut [u8]) -> Result<(), IoError>
+	{
+		let bs = self.block_size();
+		if data.len() % bs != 0 {
+			return Err(IoError::InvalidParameter);
+		}
+		while data.len() > 0
+		{
+			let cached_block = try!(self.get_block(block));
+			let blk_ofs = (block - cached_block.index()) as usize * bs;
+			let bytes = ::core::cmp::min(data.len(), cached_block.data().len() - blk_ofs);
+			data[..bytes].clone_from_slice( &cached_block.data()[blk_ofs ..][.. bytes] );
+			block += (bytes / bs) as u64;
+			data = &mut {data}[bytes..];
+		}
+		Ok( () )
+	}
 	/// Edit block
 	pub fn edit<F: FnOnce(&mut [u8])->R,R>(&self, block: u64, count: usize, f: F) -> Result<R, IoError>
 	{
-		let cached_block = try!(self.get_block_meta(block));
-		let blk_ofs = (block - cached_block.index()) as usize * self.block_size();
-
-		if (block - cached_block.index()) as usize + count > self.blocks_per_page() as usize {
+		let (first, n) = self.entry_range(block);
+		if block + count as u64 > first + n {
 			return Err(IoError::InvalidParameter);
 		}
 
+		let cached_block = try!(self.get_block_meta(block));
+		let blk_ofs = (block - cached_block.index()) as usize * self.block_size();
+
 		let rv = cached_block.edit(|block_data| {
 			f( &mut block_data[blk_ofs ..][ .. count * self.block_size()] )
 			});
 
-		try!(cached_block.0.flush(&self.vh));
-
 		Ok( rv )
 	}
+
+	/// Write all modified blocks for this volume back to disk
+	pub fn sync(&self) -> Result<(), IoError>
+	{
+		flush_volume(&self.vh)
+	}
+	/// Drop all pending modifications for this volume without writing them (e.g. when the volume has been removed)
+	pub fn discard(&self)
+	{
+		discard_volume(&self.vh)
+	}
+}
+impl ::core::ops::Drop for CacheHandle
+{
+	fn drop(&mut self)
+	{
+		if let Err(e) = self.sync() {
+			log_error!("Error writing back cache for '{}': {:?}", self.vh.name(), e);
+		}
+		let idx = self.vh.idx();
+		let released = release_volume(idx);
+		// Dropped with the cache unlocked, as releasing page-cache data unmaps it
+		drop(released);
+	}
+}
+
+/// Drop a handle's count on a volume, removing the volume's blocks from the cache (and returning them) if it was the last
+fn release_volume(idx: usize) -> Vec<Box<CachedBlock>>
+{
+	let mut lh = S_BLOCK_CACHE.lock();
+	let lh = &mut *lh;
+	let is_last = match lh.volumes.get_mut(&idx)
+		{
+		Some(v) => {
+			v.handle_count -= 1;
+			v.handle_count == 0
+			},
+		None => true,
+		};
+	if !is_last {
+		// Other handles are still using the volume's blocks
+		return Vec::new();
+	}
+	lh.volumes.remove(&idx);
+
+	// Release all cached blocks for this volume
+	let keys: Vec<_> = lh.map.iter()
+		.filter(|&(k,_)| k.0 == idx)
+		.filter(|&(k,b)| if b.reference_count.load(Ordering::Relaxed) > 0 {
+				log_error!("BUG: Cached block {:?} still referenced when cache handle dropped", k);
+				false
+			}
+			else {
+				true
+			})
+		.map(|(k,_)| *k)
+		.collect();
+	let mut rv = Vec::with_capacity(keys.len());
+	for k in keys
+	{
+		if let Some(b) = lh.map.remove(&k) {
+			lh.page_count -= b.page_count();
+			rv.push(b);
+		}
+	}
+	rv
+}
+}
+
+/// Write all modified blocks for the specified volume (by index) back to disk
+pub fn sync(volume_idx: usize) -> Result<(), IoError>
+{
+	let vh = match S_BLOCK_CACHE.lock().volumes.get(&volume_idx)
+		{
+		Some(v) => v.vh.clone(),
+		None => return Ok( () ),
+		};
+	flush_volume(&vh)
+}
+
+fn flush_volume(vh: &VolumeHandle) -> Result<(), IoError>
+{
+	if vh.is_removed() {
+		// Volume is gone, modifications can never be written
+		discard_volume(vh);
+		return Err( IoError::NoMedium );
+	}
+	// Grab handles to all dirty blocks (preventing eviction), then write them out without the cache locked
+	let dirty: Vec<MetaBlockHandle<'static>> = {
+		let lh = S_BLOCK_CACHE.lock();
+		lh.map.iter()
+			.filter(|&(k,b)| k.0 == vh.idx() && b.is_dirty.load(Ordering::Relaxed))
+			// SAFE: The block is boxed, and won't be dropped while a borrow exists
+			.map(|(_,b)| unsafe { ::core::mem::transmute::<MetaBlockHandle, MetaBlockHandle<'static>>(b.borrow()) })
+			.collect()
+		};
+	if dirty.len() > 0 {
+		log_debug!("Writing back {} blocks to '{}'", dirty.len(), vh.name());
+	}
+	let mut rv = Ok( () );
+	for b in dirty
+	{
+		if let Err(e) = b.0.flush(vh) {
+			log_error!("Write-back of block {} to '{}' failed: {:?}", b.index(), vh.name(), e);
+			rv = Err(e);
+		}
+	}
+	rv
+}
+
+fn discard_volume(vh: &VolumeHandle)
+{
+	for (_,b) in S_BLOCK_CACHE.lock().map.iter().filter(|&(k,_)| k.0 == vh.idx()) {
+		if b.is_dirty.swap(false, Ordering::Relaxed) {
+			log_warning!("Discarding modified block {} of '{}'", b.index, vh.name());
+		}
+	}
+}
+
+fn writeback_thread()
+{
+	loop
+	{
+		S_WRITEBACK_REQUEST.sleep();
+
+		// Memory pressure: evict on behalf of the PMM, releasing the data once the cache is unlocked
+		let target = S_RECLAIM_TARGET.swap(0, Ordering::Relaxed);
+		if target > 0 {
+			let victims = {
+				let mut lh = S_BLOCK_CACHE.lock();
+				let v = lh.evict(target);
+				lh.prune();
+				v
+				};
+			log_debug!("Reclaim: {} entries evicted", victims.len());
+			drop(victims);
+		}
+
+		// Let more modifications accumulate before writing
+		::kernel::threads::SleepObject::with_new("writeback_delay", |obj| {
+			let _timer = ::kernel::time::Timer::new(::kernel::time::ticks() + WRITEBACK_DELAY_MS, obj.get_ref());
+			obj.wait();
+			});
+
+		let volumes: Vec<_> = S_BLOCK_CACHE.lock().volumes.iter().map(|(_,v)| v.vh.clone()).collect();
+		for vh in volumes
+		{
+			// Errors are logged by flush_volume, and the blocks left dirty for the next pass
+			let _ = flush_volume(&vh);
+		}
+	}
+}
+
+impl Cache
+{
+	/// Evict unreferenced clean blocks (least recently used first) until `count` pages are released
+	///
+	/// Returns the evicted data, which must be dropped after the cache is unlocked (releasing a page-cache page
+	/// unmaps it). Evicted entries stay in the map until `prune`.
+	fn evict(&mut self, count: usize) -> Vec<BlockData>
+	{
+		let mut rv = Vec::new();
+		let mut n = 0;
+		while n < count
+		{
+			// NOTE: Referenced blocks are skipped before the data lock is touched, as a loading block's is held over IO
+			let oldest = self.map.iter()
+				.map(|(_,b)| b)
+				.filter(|b| b.reference_count.load(Ordering::Relaxed) == 0 && !b.is_dirty.load(Ordering::Relaxed))
+				.filter(|b| !b.is_evicted())
+				.min_by_key(|b| b.last_access.get());
+			match oldest
+			{
+			Some(b) => {
+				n += b.page_count();
+				rv.push( ::core::mem::replace(&mut *b.data.write(), BlockData::Evicted) );
+				},
+			None => break,
+			}
+		}
+		self.page_count -= n;
+		if n < count {
+			// Dirty blocks can be evicted once written back
+			S_WRITEBACK_REQUEST.post();
+		}
+		rv
+	}
+
+	/// Remove evicted entries from the map
+	fn prune(&mut self)
+	{
+		self.map.retain(|_,b| !(b.reference_count.load(Ordering::Relaxed) == 0 && b.is_evicted()));
+	}
 }
 
-fn map_cached_frame(frame: &::kernel::memory::phys::FrameHandle) -> ::kernel::memory::page_cache::CachedPage
+/// Memory pressure callback
+struct Reclaimer;
+impl ::kernel::memory::phys::Reclaimer for Reclaimer
 {
-	// TODO: If this returns that there's no free mappings, go and steal one from within the cache
-	// - Or just do a GC pass, then try again.
-	::kernel::memory::page_cache::S_PAGE_CACHE.map(frame).expect("TODO: OOM in CachedBlock::borrow")
+	fn name(&self) -> &str {
+		"block_cache"
+	}
+	fn reclaim(&self, target: usize) -> usize {
+		// Releasing cached pages unmaps them, which can't be done here (the allocating thread could hold the address
+		// space lock), so hand the request to the write-back thread.
+		S_RECLAIM_TARGET.fetch_add(target, Ordering::Relaxed);
+		S_WRITEBACK_REQUEST.post();
+		0
+	}
 }
 
 // --------------------------------------------------------------------
 impl CachedBlock
 {
-	fn new(vol: &VolumeHandle, first_block: u64) -> Result<CachedBlock, IoError>
+	/// Create an empty entry, filled by `load`
+	fn new(first_block: u64) -> CachedBlock
 	{
-		let mut mapping = try!(::kernel::memory::page_cache::S_PAGE_CACHE.create().map_err(|_| IoError::Unknown("OOM")));
-
-		// TODO: Defer disk read until after the cache entry is created
-		try!( vol.read_blocks(first_block, mapping.data_mut()) );
-		
-		Ok(CachedBlock {
+		CachedBlock {
 			index: first_block,
-			block_paddr: mapping.get_frame_handle(),
 			reference_count: AtomicUsize::new(0),
 
 			last_access: Default::default(),
 			is_dirty: AtomicBool::new(false),
-			mapping: RwLock::new(Some(mapping)),
-			})
+			data: RwLock::new(BlockData::Evicted),
+			}
+	}
+	/// Read the block's data if it's not present (called with a reference held, and the cache unlocked)
+	fn load(&self, vol: &VolumeHandle, size: usize) -> Result<(), IoError>
+	{
+		if !self.is_evicted() {
+			return Ok( () );
+		}
+		let mut lh = self.data.write();
+		// Another thread could have loaded it while this one waited for the lock
+		if let BlockData::Evicted = *lh {
+			*lh = try!(BlockData::load(vol, self.index, size));
+			S_BLOCK_CACHE.lock().page_count += ::kernel::lib::num::div_up(size, PAGE_SIZE);
+		}
+		Ok( () )
+	}
+
+	fn is_evicted(&self) -> bool {
+		match *self.data.read()
+		{
+		BlockData::Evicted => true,
+		_ => false,
+		}
+	}
+	/// Number of pages of data held by this block
+	fn page_count(&self) -> usize {
+		::kernel::lib::num::div_up(self.data.read().data().len(), PAGE_SIZE)
 	}
 	
 	/// Write a modified block back to disk
 	fn flush(&self, vol: &VolumeHandle) -> Result<(), IoError>
 	{
-		let lh = self.mapping.read();
+		let lh = self.data.read();
 		if self.is_dirty.swap(false, Ordering::Acquire)
 		{
-			try!( vol.write_blocks(self.index, lh.as_ref().expect("CachedBlock::flush - None mapping").data()) );
+			try!( vol.write_blocks(self.index, lh.data()) );
 		}
 		Ok( () )
 	}
 	
 	fn borrow(&self) -> MetaBlockHandle {
-
-		if self.mapping.read().is_none()
-		{
-			let mut lh = self.mapping.write();
-			if lh.is_none() {
-				*lh = Some( map_cached_frame(&self.block_paddr) );
-			}
-		}
-
 		self.reference_count.fetch_add(1, Ordering::Acquire);
 		self.last_access.bump();
 
@@ -247,14 +608,16 @@ impl<'a> MetaBlockHandle<'a>
 	}
 
 	pub fn edit<F: FnOnce(&mut [u8])->R, R>(&self, f: F) -> R {
-		let mut lh = self.0.mapping.write();
-		let dataptr = lh.as_mut().expect("CachedBlock mapping is None").data_mut();
+		let mut lh = self.0.data.write();
+		let dataptr = lh.data_mut();
 		self.0.is_dirty.store(true, Ordering::Relaxed);
-		f(dataptr)
+		let rv = f(dataptr);
+		S_WRITEBACK_REQUEST.post();
+		rv
 	}
 
 	pub fn into_ro(self) -> CachedBlockHandle<'a> {
-		let read_handle = self.0.mapping.read();
+		let read_handle = self.0.data.read();
 		::core::mem::forget(read_handle);
 		CachedBlockHandle( self/*, read_handle*/ )
 	}
@@ -263,15 +626,9 @@ impl<'a> ::core::ops::Drop for MetaBlockHandle<'a>
 {
 	fn drop(&mut self)
 	{
-		// TODO: Clearing this when the refcount reaches zero will lead to a lot of mapping/unmapping
-		// - Maybe keep a local queue or run a purge every now and then. Using a LRU list could work.
-		/*
-		if self.0.reference_count.fetch_sub(1, Ordering::Release) == 1
-		{
-			let mut wh = self.0.mapping.write();
-			*wh = None;
-		}
-		*/
+		// NOTE: The data is left in place when the last reference is dropped, unreferenced blocks are
+		// released by LRU eviction.
+		self.0.reference_count.fetch_sub(1, Ordering::Release);
 	}
 }
 
@@ -288,8 +645,8 @@ impl<'a> CachedBlockHandle<'a>
 	pub fn data(&self) -> &[u8] {
 		// SAFE: Read handle is constructed from a read-locked RwLock, and forgotten soon after
 		let rawptr: *const [u8] = unsafe {
-			let rh = rwlock::Read::from_raw(&self.block().mapping);
-			let p: *const [u8] = rh.as_ref().expect("None mapping").data();
+			let rh = rwlock::Read::from_raw(&self.block().data);
+			let p: *const [u8] = rh.data();
 			::core::mem::forget(rh);
 			p
 			};
@@ -302,7 +659,40 @@ impl<'a> Drop for CachedBlockHandle<'a>
 	fn drop(&mut self)
 	{
 		// SAFE: Read hanle is constructed from a read-locked RwLock
-		let _ = unsafe { rwlock::Read::from_raw(&self.block().mapping) };
+		let _ = unsafe { rwlock::Read::from_raw(&self.block().data) };
+	}
+}
+
+impl BlockData
+{
+	fn load(vol: &VolumeHandle, first_block: u64, size: usize) -> Result<BlockData, IoError>
+	{
+		let mut data = if size == PAGE_SIZE {
+				BlockData::Page( try!(::kernel::memory::page_cache::S_PAGE_CACHE.create().map_err(|_| IoError::Unknown("OOM"))) )
+			}
+			else {
+				BlockData::Heap( vec![0u8; size].into_boxed_slice() )
+			};
+
+		try!( vol.read_blocks(first_block, data.data_mut()) );
+		Ok(data)
+	}
+
+	fn data(&self) -> &[u8] {
+		match *self
+		{
+		BlockData::Page(ref p) => p.data(),
+		BlockData::Heap(ref b) => &b[..],
+		BlockData::Evicted => &[],
+		}
+	}
+	fn data_mut(&mut self) -> &mut [u8] {
+		match *self
+		{
+		BlockData::Page(ref mut p) => p.data_mut(),
+		BlockData::Heap(ref mut b) => &mut b[..],
+		BlockData::Evicted => &mut [],
+		}
 	}
 }
 
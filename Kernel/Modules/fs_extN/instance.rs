@@ -169,7 +169,8 @@ impl Instance
 			superblock: superblock,
 			group_descriptors: group_descs,
 			mount_handle: mount_handle,
-			vol: ::block_cache::CacheHandle::new(vol),
+			// Cache entries hold at least a whole filesystem block
+			vol: ::block_cache::CacheHandle::new_with_unit(vol, fs_block_size),
 			};
 
 		// SAFE: Boxed instantly
@@ -210,6 +211,9 @@ impl vfs::mount::Filesystem for Instance
 			},
 		}
 	}
+	fn volume_removed(&self) {
+		self.0.vol.discard();
+	}
 }
 
 impl InstanceInner
@@ -221,7 +225,7 @@ impl InstanceInner
 }
 
 /// Structure representing a view into a BlockCache entry
-pub struct Block<'a>(::block_cache::CachedBlockHandle<'a>, u16,u16);
+pub struct Block<'a>(::block_cache::CachedBlockHandle<'a>, u32,u32);
 impl<'a> ::core::ops::Deref for Block<'a>
 {
 	type Target = [u32];
@@ -242,17 +246,12 @@ impl InstanceInner
 	/// Obtain a block (using the block cache)
 	pub fn get_block(&self, block: u32) -> vfs::node::Result<Block>
 	{
-		if self.fs_block_size > ::kernel::PAGE_SIZE {
-			// TODO: To handle extN blocks larger than the system's page size, we'd need to start packing multiple cache handles into
-			//       the `Block` structure
-			todo!("Handle extN block sizes > PAGE_SIZE - {} > {}", self.fs_block_size, ::kernel::PAGE_SIZE);
-		}
 		log_trace!("get_block({})", block);
 		let sector = block as u64 * self.vol_blocks_per_fs_block();
 
 		let ch = try!(self.vol.get_block(sector));
 		let ofs = (sector - ch.index()) as usize * self.vol.block_size();
-		Ok( Block(ch, ofs as u16, self.fs_block_size as u16) )
+		Ok( Block(ch, ofs as u32, self.fs_block_size as u32) )
 	}
 
 	/// Edit a block in the cache using the provided closure
@@ -260,12 +259,7 @@ impl InstanceInner
 	where
 		F: FnOnce(&mut [u32]) -> vfs::node::Result<R>
 	{
-		if self.fs_block_size > ::kernel::PAGE_SIZE {
-			// TODO: To handle extN blocks larger than the system's page size, we'd need to start packing multiple cache handles into
-			//       the `Block` structure
-			todo!("Handle extN block sizes > PAGE_SIZE - {} > {}", self.fs_block_size, ::kernel::PAGE_SIZE);
-		}
-		log_trace!("get_block({})", block);
+		log_trace!("edit_block({})", block);
 		let sector = block as u64 * self.vol_blocks_per_fs_block();
 
 		try!(self.vol.edit(sector, self.vol_blocks_per_fs_block() as usize, |data| {
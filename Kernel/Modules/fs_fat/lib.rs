@@ -12,10 +12,8 @@ use kernel::prelude::*;
 use kernel::vfs::{self, mount, node};
 use kernel::metadevs::storage::{self,VolumeHandle,SizePrinter};
 use kernel::lib::mem::aref::{ArefInner,ArefBorrow};
-use kernel::lib::mem::Arc;
 
 extern crate utf16;
-extern crate blockcache;
 extern crate block_cache;
 
 module_define!{FS_FAT, [VFS], init}
@@ -73,11 +71,6 @@ pub struct FilesystemInner
 	
 	root_first_cluster: u32,
 	root_sector_count: u32,
-	
-	//fat_cache: vfs::Cache<[u32; FAT_CACHE_BLOCK_SIZE]>,
-	// XXX: Should really use the above line for this, but BlockCache exists
-	/// A cache of metadata clusters (i.e. directories)
-	metadata_block_cache: ::blockcache::BlockCache,
 }
 
 /// Inodes IDs destrucure into two 28-bit cluster IDs, and a 16-bit dir offset
@@ -126,12 +119,11 @@ impl mount::Driver for Driver
 		}
 	}
 	fn mount(&self, vol: VolumeHandle, _mounthandle: mount::SelfHandle) -> vfs::Result<Box<dyn mount::Filesystem>> {
-		let vol = ::block_cache::CacheHandle::new(vol);
-
-		// Read the bootsector
+		// Read the bootsector (uncached, as the cache unit depends on the cluster size)
 		let bs = {
-			let blk = try!(vol.get_block(0));
-			on_disk::BootSect::read(&mut &blk.data()[..512])
+			let mut blk = vec![0u8; ::core::cmp::max(512, vol.block_size())];
+			try!(vol.read_blocks(0, &mut blk));
+			on_disk::BootSect::read(&mut &blk[..512])
 			};
 		let bs_c = bs.common();
 		if bs_c.bps != 512 {
@@ -166,6 +158,7 @@ impl mount::Driver for Driver
 			+ fat_size + spare_fat_sectors
 			+ root_dir_sectors;
 		let cluster_count = (total_sectors - first_data_sector - spare_fat_sectors) / spc;
+		let cluster_size = spc * vol.block_size();
 		
 		// Determine the FAT type
 		let fat_type = if cluster_count < FAT16_MIN_CLUSTERS {
@@ -185,7 +178,7 @@ impl mount::Driver for Driver
 			inner: unsafe { ArefInner::new(FilesystemInner {
 				ty: fat_type,
 				spc: spc,
-				cluster_size: spc * vol.block_size(),
+				cluster_size: cluster_size,
 				cluster_count: cluster_count,
 				first_fat_sector: bs_c.reserved_sect_count as usize,
 				first_data_sector: first_data_sector,
@@ -194,16 +187,33 @@ impl mount::Driver for Driver
 					_ => FATL_ROOT_CLUSTER as u32,
 					},
 				root_sector_count: root_dir_sectors as u32,
-				
-				metadata_block_cache: ::blockcache::BlockCache::new(),
 
-				vh: vol,
+				// Cache entries hold at least a whole cluster (aligned to the first cluster), so `load_cluster` can
+				// return a view into the cache
+				vh: ::block_cache::CacheHandle::new_with_unit_at(vol, cluster_size, first_data_sector as u64),
 				}) },
 			}))
 	}
 }
 
-type Cluster = Arc<[u8]>;
+/// A loaded cluster
+enum Cluster<'a>
+{
+	/// View into a block cache entry (handle, offset, length)
+	Cached(::block_cache::CachedBlockHandle<'a>, usize, usize),
+	/// Copy of the data, for FAT12/16 root directory clusters that aren't aligned with the cache entries
+	Buffered(Vec<u8>),
+}
+impl<'a> ::core::ops::Deref for Cluster<'a> {
+	type Target = [u8];
+	fn deref(&self) -> &[u8] {
+		match *self
+		{
+		Cluster::Cached(ref h, ofs, len) => &h.data()[ofs ..][.. len],
+		Cluster::Buffered(ref b) => &b[..],
+		}
+	}
+}
 
 impl FilesystemInner
 {
@@ -215,39 +225,51 @@ impl FilesystemInner
 	fn read_clusters(&self, cluster: u32, dst: &mut [u8]) -> Result<(), storage::IoError> {
 		log_trace!("Filesystem::read_clusters({:#x}, {})", cluster, dst.len() / self.cluster_size);
 		assert_eq!(dst.len() % self.cluster_size, 0);
-		// For now, just read the bytes, screw caching
-		let sector = if !is!(self.ty, Size::Fat32) && cluster >= FATL_ROOT_CLUSTER {
-				// Root directory (for FAT12/16, where it was not a normal file)
-				let rc = cluster - FATL_ROOT_CLUSTER;
-				assert!( (rc as u64 * self.spc as u64) < self.root_sector_count as u64);
-				(self.first_data_sector - self.root_sector_count as usize) as u64
-				+ (rc * self.spc as u32) as u64
-			}
-			else {
-				// Anything else
-				assert!(cluster >= 2);
-				assert!(cluster - 2 < self.cluster_count as u32);
-				self.first_data_sector as u64 + (cluster as u64 - 2) * self.spc as u64
-			};
+		// Bulk reads bypass the block cache (file data is cached by higher layers)
+		let sector = self.cluster_sector(cluster);
 		log_debug!("read_clusters: cluster = {:#x}, sector = 0x{:x}", cluster, sector);
 		try!(self.vh.read_blocks(sector, dst));
 		//::kernel::logging::hex_dump("FAT Cluster", &buf);
 		Ok( () )
 	}
+	/// Get the first volume sector of a cluster
+	fn cluster_sector(&self, cluster: u32) -> u64 {
+		if !is!(self.ty, Size::Fat32) && cluster >= FATL_ROOT_CLUSTER {
+			// Root directory (for FAT12/16, where it was not a normal file)
+			let rc = cluster - FATL_ROOT_CLUSTER;
+			assert!( (rc as u64 * self.spc as u64) < self.root_sector_count as u64);
+			(self.first_data_sector - self.root_sector_count as usize) as u64
+			+ (rc * self.spc as u32) as u64
+		}
+		else {
+			// Anything else
+			assert!(cluster >= 2);
+			assert!(cluster - 2 < self.cluster_count as u32);
+			self.first_data_sector as u64 + (cluster as u64 - 2) * self.spc as u64
+		}
+	}
 
-	// TODO: Locking/Cache
+	/// Load a cluster via the block cache (used for metadata, e.g. directories, and partial file reads)
+	// TODO: Locking
 	// - Should this function lock the cluster somehow to prevent accidental overlap?
-	// - Could also cache somehow (with a refcount) along with the 'writing' flag
 	fn load_cluster(&self, cluster: u32) -> Result<Cluster, storage::IoError>
 	{
-		self.metadata_block_cache.get(
-			cluster,
-			|_| {
-				log_debug!("load_cluster: miss {}", cluster);
-				let mut buf: Cluster = Arc::from_iter( (0..self.spc * self.vh.block_size()).map(|_| 0) );
-				try!(self.read_cluster( cluster, Arc::get_mut(&mut buf).unwrap() ));
-				Ok( buf )
-			})
+		log_trace!("load_cluster({:#x})", cluster);
+		let sector = self.cluster_sector(cluster);
+		match self.vh.get_blocks(sector, self.spc)
+		{
+		Ok(blk) => {
+			let ofs = (sector - blk.index()) as usize * self.vh.block_size();
+			Ok( Cluster::Cached(blk, ofs, self.cluster_size) )
+			},
+		// The FAT12/16 root directory precedes the first cluster, so its "clusters" can cross cache entries
+		Err(storage::IoError::InvalidParameter) if !is!(self.ty, Size::Fat32) && cluster >= FATL_ROOT_CLUSTER => {
+			let mut buf = vec![0u8; self.cluster_size];
+			try!(self.read_cluster(cluster, &mut buf));
+			Ok( Cluster::Buffered(buf) )
+			},
+		Err(e) => Err(e),
+		}
 	}
 	
 	/// Obtain the next cluster in a chain
@@ -327,6 +349,9 @@ impl mount::Filesystem for Filesystem
 			dn.find_node(r.first_cluster)
 		}
 	}
+	fn volume_removed(&self) {
+		self.vh.discard();
+	}
 }
 
 impl InodeRef
@@ -10,16 +10,22 @@ use kernel::vfs::{self, mount, node};
 use kernel::metadevs::storage::{self,VolumeHandle};
 use kernel::lib::mem::aref::{ArefInner,ArefBorrow};
 use kernel::lib::byteorder::{ByteOrder,LittleEndian};
-use kernel::lib::byte_str::ByteStr;
+use kernel::lib::byte_str::{ByteStr,ByteString};
+use kernel::lib::borrow::Cow;
 
 #[macro_use]
 extern crate kernel;
 
 extern crate block_cache;
+extern crate utf16;
 
 module_define!{FS_ISO9660, [VFS], init}
 
 //mod ondisk;
+mod susp;
+
+/// Maximum number of volume descriptors to search before giving up
+const MAX_VOLUME_DESCRIPTORS: usize = 32;
 
 struct Driver;
 static S_DRIVER: Driver = Driver;
@@ -38,6 +44,8 @@ struct InstanceInner
 	root_size: u32,
 
 	susp_len_skip: Option<u8>,
+	/// Names are UCS-2 (read from the Joliet supplementary volume descriptor)
+	is_joliet: bool,
 }
 
 fn init()
@@ -70,61 +78,85 @@ impl mount::Driver for Driver
 		}
 		let scale = 2048 / vol.block_size();
 		
-		// Search the start of the disk for the primary volume descriptor
-		// - TODO: Limit the number of sectors searched.
+		// Search the start of the disk for the primary (and Joliet supplementary) volume descriptor
 		let mut block = vec![0u8; 2048];
-		for sector in 16 .. 
+		let mut pvd = None;
+		let mut joliet = None;
+		for sector in 16 .. 16 + MAX_VOLUME_DESCRIPTORS
 		{
 			try!(vol.read_blocks((sector*scale) as u64, &mut block));
 			if &block[1..6] != b"CD001" {
 				return Err( vfs::Error::Unknown("Invalid volume descriptor present") );
 			}
 			else if block[0] == 255 {
-				return Err( vfs::Error::Unknown("Can't find ISO9660 primary volume descriptor") );
+				break ;
 			}
 			else if block[0] == 0x01 {
-				// Found it!
-				break ;
+				if pvd.is_none() {
+					pvd = Some( VolumeDescriptor::from_block(&block) );
+				}
+			}
+			else if block[0] == 0x02 {
+				// Supplementary volume descriptor, check the escape sequences for a Joliet UCS-2 level
+				match &block[88..91]
+				{
+				b"%/@" | b"%/C" | b"%/E" => {
+					if joliet.is_none() {
+						joliet = Some( VolumeDescriptor::from_block(&block) );
+					}
+					},
+				_ => {},
+				}
 			}
 			else {
 				// Try the next one
 			}
 		}
-		//::kernel::logging::hex_dump("ISO966 PVD", &block);
-		
-		// Obtain the logical block size (different from medium sector size)
-		let lb_size = LittleEndian::read_u16(&block[128..]);
-		// Extract the root directory entry
-		// - We want the LBA and byte length
-		let root_lba  = LittleEndian::read_u32(&block[156+ 2..]);
-		let root_size = LittleEndian::read_u32(&block[156+10..]);
-		
-		log_debug!("lb_size = {}, root = {:#x} + {:#x} bytes", lb_size, root_lba, root_size);
-	
+		let pvd = match pvd
+			{
+			Some(v) => v,
+			None => return Err( vfs::Error::Unknown("Can't find ISO9660 primary volume descriptor") ),
+			};
+
+		log_debug!("lb_size = {}, root = {:#x} + {:#x} bytes", pvd.lb_size, pvd.root_lba, pvd.root_size);
+		if pvd.lb_size == 0 || pvd.lb_size as usize % vol.block_size() != 0 {
+			return Err( vfs::Error::Unknown("Unsupported ISO9660 logical block size") );
+		}
 	
 		let mut inner = InstanceInner {
-			vh: ::block_cache::CacheHandle::new(vol),
-			lb_size: lb_size as usize,
-			root_lba: root_lba,
-			root_size: root_size,
+			vh: ::block_cache::CacheHandle::new_with_unit(vol, pvd.lb_size as usize),
+			lb_size: pvd.lb_size as usize,
+			root_lba: pvd.root_lba,
+			root_size: pvd.root_size,
 			susp_len_skip: None,
+			is_joliet: false,
 			};
 
 		// Determine if SUSP is in use (used for RockRidge extensions)
 		inner.susp_len_skip = {
-			let mut it = DirSector::new(&inner, try!(inner.get_sector(root_lba)), 0 );
+			let mut it = DirSector::new(&inner, try!(inner.get_sector(pvd.root_lba)), 0 );
 			let first_ent = match try!(it.next())
 				{
 				None => return Err(vfs::Error::InconsistentFilesystem),
 				Some(v) => v,
 				};
-			if first_ent.sys_use.len() >= 6 && &first_ent.sys_use[..6] == b"SP\x07\x01\xBE\xEF" {
+			if first_ent.sys_use.len() >= 7 && &first_ent.sys_use[..6] == b"SP\x07\x01\xBE\xEF" {
 				Some(first_ent.sys_use[6])
 			}
 			else {
 				None
 			}
 			};
+
+		// Rock Ridge provides everything Joliet does (and more), so only use Joliet if SUSP isn't present
+		if inner.susp_len_skip.is_none() {
+			if let Some(svd) = joliet {
+				log_debug!("Using Joliet, root = {:#x} + {:#x} bytes", svd.root_lba, svd.root_size);
+				inner.root_lba = svd.root_lba;
+				inner.root_size = svd.root_size;
+				inner.is_joliet = true;
+			}
+		}
 		
 		// SAFE: Stored in a box, and not moved out.
 		Ok( Box::new( Instance(unsafe { ArefInner::new( inner ) }) ) )
@@ -138,7 +170,7 @@ impl mount::Filesystem for Instance
 	}
 	fn get_node_by_inode(&self, id: node::InodeId) -> Option<node::Node> {
 		if id == 0 {
-			Some(Dir::new_node(self.0.borrow(), self.root_lba, self.root_size) )
+			Some(Dir::new_node(self.0.borrow(), 0, self.root_lba, self.root_size) )
 		}
 		else {
 			// Look up (or read) parent directory to obtain the info
@@ -159,24 +191,67 @@ impl mount::Filesystem for Instance
 				None
 			}
 			else {
-				if ent.flags & (1 << 7) != 0 {
+				if let Some(ref target) = ent.rr.symlink {
+					Some(Symlink::new_node(id, ByteString::from(&target[..])))
+				}
+				else if let Some(lba) = ent.rr.child_link {
+					// Relocated directory, the real size is in the `.` entry at the start of the directory
+					let blk = match self.get_sector(lba)
+						{
+						Ok(v) => v,
+						Err(_) => return None,
+						};
+					let mut it = DirSector::new(&self.0, blk, 0);
+					match it.next()
+					{
+					Ok(Some(dot)) => Some(Dir::new_node(self.0.borrow(), id, dot.start, dot.size)),
+					_ => None,
+					}
+				}
+				else if ent.flags & (1 << 7) != 0 {
 					// Multi-extent file!
 					None
 				}
 				else if ent.flags & (1 << 1) != 0 {
-					Some(Dir::new_node(self.0.borrow(), ent.start, ent.size))
+					Some(Dir::new_node(self.0.borrow(), id, ent.start, ent.size))
 				}
 				else if ent.flags & 0x64 != 0 {
 					None
 				}
 				else {
-					Some(File::new_node(self.0.borrow(), ent.start, ent.size))
+					Some(File::new_node(self.0.borrow(), id, ent.start, ent.size))
 				}
 			}
 		}
 	}
+	fn volume_removed(&self) {
+		// Read-only, so there's never any modified data
+	}
 }
-struct Sector<'a>(::block_cache::CachedBlockHandle<'a>,u16,u16);
+
+/// Extracted fields from a primary/supplementary volume descriptor
+struct VolumeDescriptor
+{
+	lb_size: u16,
+	root_lba: u32,
+	root_size: u32,
+}
+impl VolumeDescriptor
+{
+	fn from_block(block: &[u8]) -> VolumeDescriptor
+	{
+		VolumeDescriptor {
+			// Obtain the logical block size (different from medium sector size)
+			lb_size: LittleEndian::read_u16(&block[128..]),
+			// Extract the root directory entry
+			// - We want the LBA and byte length
+			root_lba: LittleEndian::read_u32(&block[156+ 2..]),
+			root_size: LittleEndian::read_u32(&block[156+10..]),
+			}
+	}
+}
+
+struct Sector<'a>(::block_cache::CachedBlockHandle<'a>,u32,u32);
 impl<'a> ::core::ops::Deref for Sector<'a> {
 	type Target = [u8];
 	fn deref(&self) -> &[u8] {
@@ -199,9 +274,12 @@ impl InstanceInner
 	fn get_sector(&self, sector: u32) -> Result<Sector, storage::IoError> {
 		assert!(sector > 0);
 		
-		let blk = try!(self.vh.get_block(sector as u64));
-		let ofs = (sector as u64 - blk.index()) as usize * self.vh.block_size();
-		Ok( Sector(blk, ofs as u16, self.vh.block_size() as u16) )
+		// - Will be round, Driver::mount() ensures this
+		let hwsects_per_lb = (self.lb_size / self.vh.block_size()) as u64;
+		let hwsector = sector as u64 * hwsects_per_lb;
+		let blk = try!(self.vh.get_block(hwsector));
+		let ofs = (hwsector - blk.index()) as usize * self.vh.block_size();
+		Ok( Sector(blk, ofs as u32, self.lb_size as u32) )
 	}
 }
 
@@ -209,14 +287,17 @@ impl InstanceInner
 struct File
 {
 	fs: ArefBorrow<InstanceInner>,
+	id: node::InodeId,
 	first_lba: u32,
 	size: u32,
 }
 impl File
 {
-	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32) -> node::Node {
+	fn new_node(fs: ArefBorrow<InstanceInner>, id: node::InodeId, first_lba: u32, size: u32) -> node::Node {
+		log_trace!("File::new_node({:#x}, {:#x}+{:#x})", id, first_lba, size);
 		node::Node::File( Box::new( File {
 			fs: fs,
+			id: id,
 			first_lba: first_lba,
 			size: size,
 			} ) )
@@ -225,7 +306,7 @@ impl File
 impl node::NodeBase for File
 {
 	fn get_id(&self) -> node::InodeId {
-		todo!("File::get_id")
+		self.id
 	}
 	fn get_any(&self) -> &dyn core::any::Any {
 		self
@@ -298,14 +379,17 @@ impl node::File for File
 struct Dir
 {
 	fs: ArefBorrow<InstanceInner>,
+	id: node::InodeId,
 	first_lba: u32,
 	size: u32,
 }
 impl Dir
 {
-	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32) -> node::Node {
+	fn new_node(fs: ArefBorrow<InstanceInner>, id: node::InodeId, first_lba: u32, size: u32) -> node::Node {
+		log_trace!("Dir::new_node({:#x}, {:#x}+{:#x})", id, first_lba, size);
 		node::Node::Dir( Box::new( Dir {
 			fs: fs,
+			id: id,
 			first_lba: first_lba,
 			size: size,
 			} ) )
@@ -314,7 +398,7 @@ impl Dir
 impl node::NodeBase for Dir
 {
 	fn get_id(&self) -> node::InodeId {
-		todo!("Dir::get_id")
+		self.id
 	}
 	fn get_any(&self) -> &dyn core::any::Any {
 		self
@@ -330,7 +414,7 @@ impl node::Dir for Dir
 
 			while let Some(ent) = try!(it.next())
 			{
-				if ent.name == name.as_bytes()
+				if !ent.rr.is_relocated && &ent.name[..] == name.as_bytes()
 				{
 					let inode = (self.first_lba + sector) as u64 * self.fs.lb_size as u64 + ent.this_ofs as u64;
 					return Ok( inode );
@@ -354,7 +438,7 @@ impl node::Dir for Dir
 
 			while let Some(ent) = try!(it.next())
 			{
-				if ent.name.len() > 0 && ent.name != b"\0" && ent.name != b"\x01"
+				if ent.name.len() > 0 && &ent.name[..] != b"\0" && &ent.name[..] != b"\x01" && !ent.rr.is_relocated
 				{
 					log_debug!("ent = {:?}", ent);
 					let inode = (self.first_lba + sector) as u64 * self.fs.lb_size as u64 + ent.this_ofs as u64;
@@ -386,7 +470,40 @@ impl node::Dir for Dir
 }
 
 
-#[derive(Default)]
+// --------------------------------------------------------------------
+/// Rock Ridge symbolic link (`SL`)
+struct Symlink
+{
+	id: node::InodeId,
+	target: ByteString,
+}
+impl Symlink
+{
+	fn new_node(id: node::InodeId, target: ByteString) -> node::Node {
+		log_trace!("Symlink::new_node({:#x}, {:?})", id, target);
+		node::Node::Symlink( Box::new( Symlink {
+			id: id,
+			target: target,
+			} ) )
+	}
+}
+impl node::NodeBase for Symlink
+{
+	fn get_id(&self) -> node::InodeId {
+		self.id
+	}
+	fn get_any(&self) -> &dyn core::any::Any {
+		self
+	}
+}
+impl node::Symlink for Symlink
+{
+	fn read(&self) -> ByteString {
+		self.target.clone()
+	}
+}
+
+
 struct DirEnt<'a>
 {
 	this_ofs: usize,
@@ -395,19 +512,42 @@ struct DirEnt<'a>
 	flags: u8,
 	start: u32,
 	size: u32,
-	name: &'a [u8],
+	name: Cow<'a, [u8]>,
 	sys_use: &'a [u8],
+	rr: susp::RockRidge,
+}
+impl<'a> Default for DirEnt<'a> {
+	fn default() -> Self {
+		DirEnt {
+			this_ofs: 0,
+			next_ofs: 0,
+			flags: 0,
+			start: 0,
+			size: 0,
+			name: Cow::Borrowed(&[]),
+			sys_use: &[],
+			rr: Default::default(),
+			}
+	}
 }
 impl<'a> ::core::fmt::Debug for DirEnt<'a> {
 	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
 		write!(f, "DirEnt {{ start: {:#x}, size: {:#x}, name: {:?} }}",
-			self.start, self.size, ByteStr::new(self.name)
+			self.start, self.size, ByteStr::new(&self.name[..])
 			)
 	}
 }
 
-impl<'a> DirEnt<'a>
+/// Decode a Joliet (UCS-2 big-endian) name into UTF-8, stripping the version suffix
+fn decode_joliet_name(raw: &[u8]) -> Vec<u8>
 {
+	let units: Vec<u16> = raw.chunks(2).filter(|c| c.len() == 2).map(|c| (c[0] as u16) << 8 | c[1] as u16).collect();
+	// SAFE: WTF-8 output handles unpaired surrogates
+	let mut rv: Vec<u8> = unsafe { ::utf16::Str16::new_unchecked(&units) }.wtf8().collect();
+	if let Some(pos) = rv.iter().rposition(|&c| c == b';') {
+		rv.truncate(pos);
+	}
+	rv
 }
 
 struct DirSector<'a> {
@@ -459,26 +599,33 @@ impl<'a> DirSector<'a>
 					log_warning!("Name overruns end of entry");
 					return Err(vfs::Error::InconsistentFilesystem);
 				}
-				let su = &ent[33 + namelen ..];
-
-				let mut name = &ent[33..][..namelen];
+				// System use area starts after the name (padded to an even offset)
+				let su_ofs = 33 + namelen + (1 - namelen % 2);
+				let su = if su_ofs < len { &ent[su_ofs ..] } else { &[][..] };
 
-				if let Some(skip) = self.fs.susp_len_skip {
-					let skip = skip as usize;
-					if su.len() < skip {
-						log_warning!("System use area smaller than SUSP skip value");
-						return Err(vfs::Error::InconsistentFilesystem);
+				let raw_name = &ent[33..][..namelen];
+				let mut name = if self.fs.is_joliet && namelen > 1 {
+						Cow::Owned(decode_joliet_name(raw_name))
 					}
-
-					for ent in SuspIterator(&su[skip..])
-					{
-						//log_trace!("ent={:?}", ent);
-						// TODO: Need to handle this _FAR_ better
-						if let SuspItem::AlternateName(0, new_name) = ent {
-							name = new_name;
+					else {
+						Cow::Borrowed(raw_name)
+					};
+
+				let rr = if let Some(skip) = self.fs.susp_len_skip {
+						let skip = skip as usize;
+						if su.len() < skip {
+							log_warning!("System use area smaller than SUSP skip value");
+							return Err(vfs::Error::InconsistentFilesystem);
+						}
+						let rr = try!(susp::RockRidge::parse(self.fs, &su[skip..]));
+						if let Some(ref new_name) = rr.name {
+							name = Cow::Owned(new_name.clone());
 						}
+						rr
 					}
-				}
+					else {
+						Default::default()
+					};
 
 				Ok(Some(DirEnt {
 					this_ofs: cur_ofs,
@@ -488,108 +635,28 @@ impl<'a> DirSector<'a>
 					size: LittleEndian::read_u32(&ent[10..]),
 					name: name,
 					sys_use: su,
+					rr: rr,
 					}))
 			}
 		}
 	}
 }
 
-struct SuspIterator<'a>(&'a [u8]);
-
-#[derive(Debug)]
-enum SuspItem<'a>
+#[cfg(test)]
+mod tests
 {
-	// SUSP Base
-	ContinuationEntry(u32, u32, u32),
-	Pad(&'a [u8]),
-	Identifer,
-	//End,
-	
-	// RockRidge
-	RockRidge(u8),
-	PosixMode {
-		mode: u32,
-		n_links: u32,
-		uid: u32,
-		gid: u32,
-		serial_number: u32,
-		},
-	AlternateName(u8, &'a [u8]),
-	Timestamps {
-		flags: u8,
-		data: &'a [u8],
-		},
-
-	Unknown([u8; 2], u8, &'a[u8]),
-}
+	use super::decode_joliet_name;
 
-impl<'a> Iterator for SuspIterator<'a>
-{
-	type Item = SuspItem<'a>;
-	fn next(&mut self) -> Option<SuspItem<'a>>
+	#[test]
+	fn joliet_name()
 	{
-		if self.0.len() == 0 {
-			None
-		}
-		else if self.0.len() < 4 {
-			None
-		}
-		else {
-			let tag = [self.0[0], self.0[1]];
-			let len = self.0[2] as usize;
-			let ver = self.0[3];
-			if len < 4 {
-				return None;
-			}
-			if self.0.len() < len {
-				return None;
-			}
-			let data = &self.0[4..len];
-
-			self.0 = &self.0[len..];
-			
-			log_debug!("tag = {}{} - data={} [{:?}]", tag[0] as char, tag[1] as char, len-4, data);
-			Some(match &tag[..]
-				{
-				b"ST" => return None,	// Terminated
-				b"SP" => SuspItem::Identifer,
-				b"PD" => SuspItem::Pad(data),
-				b"CE" => {
-					if data.len() < 3*8 { return None; }
-					SuspItem::ContinuationEntry(
-						LittleEndian::read_u32(&data[0..]),
-						LittleEndian::read_u32(&data[8..]),
-						LittleEndian::read_u32(&data[16..])
-						)
-					},
-				b"RR" => {
-					if data.len() < 1 { return None; }
-					SuspItem::RockRidge(data[0])
-					},
-				b"PX" => {
-					if data.len() < 4*8 { return None; }
-					SuspItem::PosixMode {
-						mode:    LittleEndian::read_u32(&data[0..]),
-						n_links: LittleEndian::read_u32(&data[8..]),
-						uid:     LittleEndian::read_u32(&data[16..]),
-						gid:     LittleEndian::read_u32(&data[24..]),
-						serial_number: if data.len() >= 32+8 { LittleEndian::read_u32(&data[32..]) } else { 0 },
-						}
-					},
-				b"TF" => {
-					if data.len() < 1 { return None; }
-					SuspItem::Timestamps {
-						flags: data[0],
-						data: &data[1..],
-						}
-					},
-				//b"SL" => SuspItem::Symlink(),
-				b"NM" => {
-					if data.len() < 1 { return None; }
-					SuspItem::AlternateName(data[0], &data[1..])
-					},
-				_ => SuspItem::Unknown(tag, ver, data),
-				})
-		}
+		assert_eq!(&decode_joliet_name(b"\0F\0O\0O\0.\0T\0X\0T\0;\01")[..], b"FOO.TXT");
+		// No version suffix
+		assert_eq!(&decode_joliet_name(b"\0a\0b")[..], b"ab");
+		// Non-ASCII BMP characters are encoded as UTF-8
+		assert_eq!(&decode_joliet_name(b"\0c\0a\0f\0\xE9\x20\xAC")[..], "caf\u{e9}\u{20ac}".as_bytes());
+		// Trailing odd byte is ignored
+		assert_eq!(&decode_joliet_name(b"\0x\0")[..], b"x");
+		assert_eq!(&decode_joliet_name(b"")[..], b"");
 	}
 }
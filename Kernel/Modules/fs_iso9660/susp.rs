@@ -0,0 +1,350 @@
+// "Tifflin" Kernel - ISO9660 Filesystem Driver
+// - By John Hodge (thePowersGang)
+//
+// Modules/fs_iso9660/susp.rs
+//! System Use Sharing Protocol (SUSP) and Rock Ridge (RRIP) parsing
+use kernel::prelude::*;
+use kernel::metadevs::storage;
+use kernel::lib::byteorder::{ByteOrder,LittleEndian};
+
+/// Maximum number of continuation areas followed for a single entry (protects against loops)
+const MAX_CONTINUATIONS: usize = 16;
+
+// NM flags
+const NM_CONTINUE: u8 = 1 << 0;
+const NM_CURRENT: u8 = 1 << 1;
+const NM_PARENT: u8 = 1 << 2;
+
+// SL record flags
+const SL_CONTINUE: u8 = 1 << 0;
+// SL component flags
+const SLC_CONTINUE: u8 = 1 << 0;
+const SLC_CURRENT: u8 = 1 << 1;
+const SLC_PARENT: u8 = 1 << 2;
+const SLC_ROOT: u8 = 1 << 3;
+
+pub struct SuspIterator<'a>(pub &'a [u8]);
+
+#[derive(Debug)]
+pub enum SuspItem<'a>
+{
+	// SUSP Base
+	ContinuationEntry(u32, u32, u32),
+	Pad(&'a [u8]),
+	Identifer,
+	//End,
+
+	// RockRidge
+	RockRidge(u8),
+	PosixMode {
+		mode: u32,
+		n_links: u32,
+		uid: u32,
+		gid: u32,
+		serial_number: u32,
+		},
+	AlternateName(u8, &'a [u8]),
+	Symlink(u8, &'a [u8]),
+	Timestamps {
+		flags: u8,
+		data: &'a [u8],
+		},
+	ChildLink(u32),
+	Relocated,
+
+	Unknown([u8; 2], u8, &'a[u8]),
+}
+
+impl<'a> Iterator for SuspIterator<'a>
+{
+	type Item = SuspItem<'a>;
+	fn next(&mut self) -> Option<SuspItem<'a>>
+	{
+		if self.0.len() == 0 {
+			None
+		}
+		else if self.0.len() < 4 {
+			None
+		}
+		else {
+			let tag = [self.0[0], self.0[1]];
+			let len = self.0[2] as usize;
+			let ver = self.0[3];
+			if len < 4 {
+				return None;
+			}
+			if self.0.len() < len {
+				return None;
+			}
+			let data = &self.0[4..len];
+
+			self.0 = &self.0[len..];
+
+			log_trace!("tag = {}{} - data={} [{:?}]", tag[0] as char, tag[1] as char, len-4, data);
+			Some(match &tag[..]
+				{
+				b"ST" => return None,	// Terminated
+				b"SP" => SuspItem::Identifer,
+				b"PD" => SuspItem::Pad(data),
+				b"CE" => {
+					if data.len() < 3*8 { return None; }
+					SuspItem::ContinuationEntry(
+						LittleEndian::read_u32(&data[0..]),
+						LittleEndian::read_u32(&data[8..]),
+						LittleEndian::read_u32(&data[16..])
+						)
+					},
+				b"RR" => {
+					if data.len() < 1 { return None; }
+					SuspItem::RockRidge(data[0])
+					},
+				b"PX" => {
+					if data.len() < 4*8 { return None; }
+					SuspItem::PosixMode {
+						mode:    LittleEndian::read_u32(&data[0..]),
+						n_links: LittleEndian::read_u32(&data[8..]),
+						uid:     LittleEndian::read_u32(&data[16..]),
+						gid:     LittleEndian::read_u32(&data[24..]),
+						serial_number: if data.len() >= 32+8 { LittleEndian::read_u32(&data[32..]) } else { 0 },
+						}
+					},
+				b"TF" => {
+					if data.len() < 1 { return None; }
+					SuspItem::Timestamps {
+						flags: data[0],
+						data: &data[1..],
+						}
+					},
+				b"SL" => {
+					if data.len() < 1 { return None; }
+					SuspItem::Symlink(data[0], &data[1..])
+					},
+				b"NM" => {
+					if data.len() < 1 { return None; }
+					SuspItem::AlternateName(data[0], &data[1..])
+					},
+				b"CL" => {
+					if data.len() < 8 { return None; }
+					SuspItem::ChildLink( LittleEndian::read_u32(&data[0..]) )
+					},
+				b"RE" => SuspItem::Relocated,
+				_ => SuspItem::Unknown(tag, ver, data),
+				})
+		}
+	}
+}
+
+/// Rock Ridge information collected from an entry's system use area (and any continuation areas)
+#[derive(Debug,Default)]
+pub struct RockRidge
+{
+	/// Long name (`NM`), `None` if not present (or if the entry is `.`/`..`)
+	pub name: Option<Vec<u8>>,
+	/// Symbolic link target (`SL`)
+	pub symlink: Option<Vec<u8>>,
+	/// Relocated directory location (`CL`), this entry is a placeholder for a directory at this LBA
+	pub child_link: Option<u32>,
+	/// This is a relocated directory (`RE`), and should be hidden from its (fake) parent
+	pub is_relocated: bool,
+
+	// NM/SL parsing state
+	name_done: bool,
+	symlink_done: bool,
+	symlink_component_continues: bool,
+}
+
+impl RockRidge
+{
+	/// Parse the system use area of an entry, following `CE` continuations
+	pub fn parse(fs: &::InstanceInner, area: &[u8]) -> Result<RockRidge, storage::IoError>
+	{
+		let mut rv = RockRidge::default();
+		let mut next = rv.parse_area(area);
+		let mut count = 0;
+		while let Some( (block, ofs, len) ) = next
+		{
+			count += 1;
+			if count > MAX_CONTINUATIONS {
+				log_warning!("Too many SUSP continuation areas, ignoring the remainder");
+				break;
+			}
+			let (ofs, len) = (ofs as usize, len as usize);
+			let sector = try!(fs.get_sector(block));
+			if ofs + len > sector.len() {
+				log_warning!("SUSP continuation area overruns sector ({}+{} > {})", ofs, len, sector.len());
+				break;
+			}
+			next = rv.parse_area(&sector[ofs..][..len]);
+		}
+		Ok(rv)
+	}
+
+	/// Parse a single area, returning the continuation area (if any)
+	fn parse_area(&mut self, area: &[u8]) -> Option<(u32, u32, u32)>
+	{
+		let mut continuation = None;
+		for item in SuspIterator(area)
+		{
+			match item
+			{
+			SuspItem::ContinuationEntry(block, ofs, len) => continuation = Some( (block, ofs, len) ),
+			SuspItem::AlternateName(flags, name) =>
+				if flags & (NM_CURRENT|NM_PARENT) != 0 {
+					// Leave `.` and `..` with their on-disk names
+				}
+				else if !self.name_done {
+					self.name.get_or_insert_with(Vec::new).extend_from_slice(name);
+					self.name_done = flags & NM_CONTINUE == 0;
+				},
+			SuspItem::Symlink(flags, components) =>
+				if !self.symlink_done {
+					self.push_symlink_components(components);
+					self.symlink_done = flags & SL_CONTINUE == 0;
+				},
+			SuspItem::ChildLink(lba) => self.child_link = Some(lba),
+			SuspItem::Relocated => self.is_relocated = true,
+			_ => {},
+			}
+		}
+		continuation
+	}
+
+	fn push_symlink_components(&mut self, mut data: &[u8])
+	{
+		let continues = &mut self.symlink_component_continues;
+		let buf = self.symlink.get_or_insert_with(Vec::new);
+		while data.len() >= 2
+		{
+			let flags = data[0];
+			let len = data[1] as usize;
+			if data.len() < 2 + len {
+				log_warning!("SL component overruns entry");
+				break;
+			}
+			let content = &data[2..][..len];
+			data = &data[2 + len..];
+
+			if !*continues && buf.len() > 0 && buf.last() != Some(&b'/') {
+				buf.push(b'/');
+			}
+			if flags & SLC_ROOT != 0 {
+				if buf.len() == 0 {
+					buf.push(b'/');
+				}
+			}
+			else if flags & SLC_PARENT != 0 {
+				buf.extend_from_slice(b"..");
+			}
+			else if flags & SLC_CURRENT != 0 {
+				buf.push(b'.');
+			}
+			else {
+				buf.extend_from_slice(content);
+			}
+			*continues = flags & SLC_CONTINUE != 0;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use kernel::prelude::*;
+	use kernel::lib::byteorder::{ByteOrder,LittleEndian};
+	use super::*;
+
+	/// Build a SUSP entry with the given tag and payload
+	fn ent(tag: &[u8; 2], data: &[u8]) -> Vec<u8>
+	{
+		let mut rv = vec![tag[0], tag[1], (4 + data.len()) as u8, 1];
+		rv.extend_from_slice(data);
+		rv
+	}
+	fn both_endian(vals: &[u32]) -> Vec<u8>
+	{
+		let mut rv = vec![0u8; vals.len() * 8];
+		for (i, &v) in vals.iter().enumerate() {
+			LittleEndian::write_u32(&mut rv[i*8..], v);
+			rv[i*8+4..][..4].copy_from_slice(&[(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]);
+		}
+		rv
+	}
+
+	#[test]
+	fn iterator()
+	{
+		let mut area = Vec::new();
+		area.extend( ent(b"SP", &[0xBE, 0xEF, 0]) );
+		area.extend( ent(b"PX", &both_endian(&[0o100644, 1, 1000, 100, 42])) );
+		area.extend( ent(b"RE", &[]) );
+		area.extend( ent(b"ST", &[]) );
+		area.extend( ent(b"NM", &[0, b'x']) );
+
+		let items: Vec<_> = SuspIterator(&area).collect();
+		assert_eq!(items.len(), 3, "{:?}", items);
+		match items[0] { SuspItem::Identifer => {}, ref v => panic!("{:?}", v), }
+		match items[1] {
+		SuspItem::PosixMode { mode: 0o100644, n_links: 1, uid: 1000, gid: 100, serial_number: 42 } => {},
+		ref v => panic!("{:?}", v),
+		}
+		match items[2] { SuspItem::Relocated => {}, ref v => panic!("{:?}", v), }
+	}
+
+	#[test]
+	fn iterator_malformed()
+	{
+		// Length shorter than the header
+		assert_eq!(SuspIterator(&[b'R', b'E', 3, 1]).count(), 0);
+		// Length overruns the area
+		assert_eq!(SuspIterator(&[b'R', b'E', 8, 1, 0]).count(), 0);
+		// Truncated PX (missing the gid)
+		assert_eq!(SuspIterator(&ent(b"PX", &both_endian(&[0o755, 1, 0]))).count(), 0);
+	}
+
+	#[test]
+	fn alternate_name()
+	{
+		let mut area = Vec::new();
+		area.extend( ent(b"NM", &[NM_CURRENT]) );
+		area.extend( ent(b"NM", b"\x01long_") );
+		area.extend( ent(b"NM", b"\x00name.txt") );
+		area.extend( ent(b"NM", b"\x00ignored") );
+		let mut rr = RockRidge::default();
+		assert!(rr.parse_area(&area).is_none());
+		assert_eq!(rr.name.as_ref().map(|v| &v[..]), Some(&b"long_name.txt"[..]));
+
+		let mut rr = RockRidge::default();
+		rr.parse_area(&ent(b"NM", &[NM_PARENT]));
+		assert!(rr.name.is_none());
+	}
+
+	#[test]
+	fn symlink()
+	{
+		// `/usr/../lib`
+		let mut rr = RockRidge::default();
+		rr.parse_area(&ent(b"SL", &[0, SLC_ROOT,0, 0,3,b'u',b's',b'r', SLC_PARENT,0, 0,3,b'l',b'i',b'b']));
+		assert_eq!(rr.symlink.as_ref().map(|v| &v[..]), Some(&b"/usr/../lib"[..]));
+
+		// `./foobar`, with the final component split across two SL records
+		let mut area = Vec::new();
+		area.extend( ent(b"SL", &[SL_CONTINUE, SLC_CURRENT,0, SLC_CONTINUE,3,b'f',b'o',b'o']) );
+		area.extend( ent(b"SL", &[0, 0,3,b'b',b'a',b'r']) );
+		area.extend( ent(b"SL", &[0, 0,1,b'x']) );
+		let mut rr = RockRidge::default();
+		rr.parse_area(&area);
+		assert_eq!(rr.symlink.as_ref().map(|v| &v[..]), Some(&b"./foobar"[..]));
+	}
+
+	#[test]
+	fn links()
+	{
+		let mut area = Vec::new();
+		area.extend( ent(b"CL", &both_endian(&[0x1234])) );
+		area.extend( ent(b"CE", &both_endian(&[0x20, 0x100, 0x40])) );
+		let mut rr = RockRidge::default();
+		assert_eq!(rr.parse_area(&area), Some( (0x20, 0x100, 0x40) ));
+		assert_eq!(rr.child_link, Some(0x1234));
+		assert!(!rr.is_relocated);
+	}
+}
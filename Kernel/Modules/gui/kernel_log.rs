@@ -89,7 +89,7 @@ impl KernelLog
 			};
 	
 		// Kernel's window group	
-		let mut wgh = WindowGroupHandle::alloc("Kernel");
+		let mut wgh = WindowGroupHandle::alloc("Kernel").expect("Kernel window group - session limit reached");
 		
 		// - Log Window
 		let mut wh = wgh.create_window("Kernel Log");
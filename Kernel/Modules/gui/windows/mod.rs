@@ -89,7 +89,7 @@ pub fn init()
 	// Create render thread
 	// SAFE: Called in single-threaded context
 	unsafe { S_EVENT_QUEUE.prep(|| ::kernel::lib::ring_buffer::AtomicRingBuf::new(32)); }
-	S_RENDER_THREAD.init( || ::kernel::threads::WorkerThread::new("GUI Compositor", render_thread) );
+	S_RENDER_THREAD.init( || ::kernel::threads::WorkerThread::new_with_priority("GUI Compositor", ::kernel::threads::PRIORITY_HIGH, render_thread) );
 }
 
 
@@ -528,21 +528,23 @@ impl WindowGroup
 
 impl WindowGroupHandle
 {
-	pub fn alloc<T: Into<String>>(name: T) -> WindowGroupHandle {
+	/// Allocate a new window group (fails if the session limit has been reached)
+	pub fn alloc<T: Into<String>>(name: T) -> Result<WindowGroupHandle, ()> {
 		let new_group = Arc::new( Mutex::new( WindowGroup::new(name.into()) ) );
 		// Locate unused slot
 		let idx = {
 			let mut grps = S_WINDOW_GROUPS.lock();
 			if grps.count() == C_MAX_SESSIONS
 			{
-				panic!("TODO: Handle exceeding session limit");
+				log_notice!("WindowGroupHandle::alloc - Session limit ({}) reached", C_MAX_SESSIONS);
+				return Err( () );
 			}
 			else
 			{
 				grps.insert(new_group)
 			}
 			};
-		WindowGroupHandle(idx as GrpId)
+		Ok( WindowGroupHandle(idx as GrpId) )
 	}
 
 	fn with_wg<R, F: FnOnce(&mut WindowGroup)->R>(&self, fcn: F) -> R {
@@ -291,7 +291,7 @@ pub fn register<T: Interface>(mac_addr: [u8; 6], int: T) -> Registration<T> {
 		});
 	let reg = InterfaceListEnt {
 		data: int_data.clone(),
-		thread: ::kernel::threads::WorkerThread::new("Network Rx", move || rx_thread(&int_data)),
+		thread: ::kernel::threads::WorkerThread::new_with_priority("Network Rx", ::kernel::threads::PRIORITY_HIGH, move || rx_thread(&int_data)),
 		};
 
 	fn insert_opt<T>(list: &mut Vec<Option<T>>, val: T) -> usize {
@@ -0,0 +1,177 @@
+// "Tifflin" Kernel - Real-time clock drivers
+// - By John Hodge (thePowersGang)
+//
+// Modules/rtc/cmos.rs
+//! PC CMOS real-time clock (MC146818 compatible)
+use kernel::prelude::*;
+use kernel::arch::x86_io::{inb,outb};
+use kernel::arch::acpi;
+use kernel::sync::Spinlock;
+
+const PORT_ADDR: u16 = 0x70;
+const PORT_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Status A: Update in progress
+const STSA_UIP: u8 = 0x80;
+/// Status B: Updates inhibited (set while writing)
+const STSB_SET: u8 = 0x80;
+/// Status B: Values are binary (instead of BCD)
+const STSB_BINARY: u8 = 0x04;
+/// Status B: 24-hour mode (instead of 12-hour with bit 7 of hours as PM)
+const STSB_24HOUR: u8 = 0x02;
+
+/// Raw register values (as returned by the hardware)
+#[derive(PartialEq,Copy,Clone)]
+struct Regs
+{
+	sec: u8,
+	min: u8,
+	hour: u8,
+	day: u8,
+	month: u8,
+	year: u8,
+	/// Zero if there's no century register
+	century: u8,
+}
+
+/// Serialises access to the index/data port pair
+struct Cmos
+{
+	lock: Spinlock<()>,
+	/// Century register index (from the ACPI FADT, `None` if the RTC doesn't have one)
+	century_reg: Option<u8>,
+}
+
+pub fn init()
+{
+	let century_reg = match acpi::find::<acpi::Fadt>("FACP", 0)
+		{
+		Some(fadt) => match fadt.data().century
+			{
+			0 => None,
+			v => Some(v),
+			},
+		None => None,
+		};
+	log_debug!("CMOS century register: {:?}", century_reg);
+	::kernel::time::register_rtc( Box::new(Cmos { lock: Spinlock::new( () ), century_reg: century_reg }) );
+}
+
+impl Cmos
+{
+	/// Read a CMOS register (caller must hold the lock)
+	fn read_reg(&self, reg: u8) -> u8 {
+		// SAFE: Only touches the CMOS ports, with NMIs left enabled (bit 7 clear)
+		unsafe {
+			outb(PORT_ADDR, reg);
+			inb(PORT_DATA)
+		}
+	}
+	/// Write a CMOS register (caller must hold the lock)
+	fn write_reg(&self, reg: u8, val: u8) {
+		// SAFE: Only touches the CMOS ports
+		unsafe {
+			outb(PORT_ADDR, reg);
+			outb(PORT_DATA, val);
+		}
+	}
+
+	fn read_raw(&self) -> Regs {
+		// Wait for any in-progress update to finish
+		while self.read_reg(REG_STATUS_A) & STSA_UIP != 0 {
+		}
+		Regs {
+			sec: self.read_reg(REG_SECONDS),
+			min: self.read_reg(REG_MINUTES),
+			hour: self.read_reg(REG_HOURS),
+			day: self.read_reg(REG_DAY),
+			month: self.read_reg(REG_MONTH),
+			year: self.read_reg(REG_YEAR),
+			century: match self.century_reg { Some(r) => self.read_reg(r), None => 0 },
+			}
+	}
+}
+
+fn from_bcd(v: u8) -> u32 {
+	(v >> 4) as u32 * 10 + (v & 0xF) as u32
+}
+fn to_bcd(v: u32) -> u8 {
+	((v / 10) << 4 | (v % 10)) as u8
+}
+
+impl ::kernel::time::RealTimeClock for Cmos
+{
+	fn read(&self) -> Option<u64> {
+		let _irq = ::kernel::arch::sync::hold_interrupts();
+		let _lh = self.lock.lock();
+		// Read until two consecutive reads match (an update can start between the UIP check and the reads)
+		let mut regs = self.read_raw();
+		loop
+		{
+			let r2 = self.read_raw();
+			if r2 == regs {
+				break;
+			}
+			regs = r2;
+		}
+		let stsb = self.read_reg(REG_STATUS_B);
+
+		let conv = |v: u8| if stsb & STSB_BINARY != 0 { v as u32 } else { from_bcd(v) };
+		let is_pm = stsb & STSB_24HOUR == 0 && regs.hour & 0x80 != 0;
+		let mut hour = conv(regs.hour & 0x7F);
+		if stsb & STSB_24HOUR == 0 {
+			// 12-hour mode: 12AM is midnight, 12PM is noon
+			hour = hour % 12 + if is_pm { 12 } else { 0 };
+		}
+		let century = match conv(regs.century)
+			{
+			// No century register, assume 20xx
+			c @ 19 ..= 99 => c,
+			_ => 20,
+			};
+		let year = century * 100 + conv(regs.year);
+		let (month, day) = (conv(regs.month), conv(regs.day));
+		if month < 1 || month > 12 || day < 1 || day > 31 || hour > 23 || year < 1970 {
+			log_warning!("CMOS RTC returned invalid time {:04}-{:02}-{:02} {:02}", year, month, day, hour);
+			return None;
+		}
+		Some( ::secs_from_civil(year, month, day, hour, conv(regs.min), conv(regs.sec)) )
+	}
+	fn write(&self, secs: u64) {
+		let (year, month, day, hour, min, sec) = ::civil_from_secs(secs);
+
+		let _irq = ::kernel::arch::sync::hold_interrupts();
+		let _lh = self.lock.lock();
+		let stsb = self.read_reg(REG_STATUS_B);
+		let conv = |v: u32| if stsb & STSB_BINARY != 0 { v as u8 } else { to_bcd(v) };
+		let hour_val = if stsb & STSB_24HOUR != 0 {
+				conv(hour)
+			}
+			else {
+				let h12 = if hour % 12 == 0 { 12 } else { hour % 12 };
+				conv(h12) | if hour >= 12 { 0x80 } else { 0 }
+			};
+
+		// Inhibit updates while the registers are changed
+		self.write_reg(REG_STATUS_B, stsb | STSB_SET);
+		self.write_reg(REG_SECONDS, conv(sec));
+		self.write_reg(REG_MINUTES, conv(min));
+		self.write_reg(REG_HOURS, hour_val);
+		self.write_reg(REG_DAY, conv(day));
+		self.write_reg(REG_MONTH, conv(month));
+		self.write_reg(REG_YEAR, conv(year % 100));
+		if let Some(r) = self.century_reg {
+			self.write_reg(r, conv(year / 100));
+		}
+		self.write_reg(REG_STATUS_B, stsb & !STSB_SET);
+	}
+}
@@ -0,0 +1,108 @@
+// "Tifflin" Kernel - Real-time clock drivers
+// - By John Hodge (thePowersGang)
+//
+// Modules/rtc/lib.rs
+//! Real-time clock drivers (source of the kernel's wall-clock time)
+#![feature(linkage)]
+#![no_std]
+
+#[macro_use] extern crate kernel;
+
+#[allow(unused_imports)]
+use kernel::prelude::*;
+
+module_define!{RTC, [
+	DeviceManager,
+	// Locates the CMOS century register
+	#[cfg(any(arch="x86",arch="amd64",target_arch="x86",target_arch="x86_64"))]
+	ACPI
+	], init}
+
+#[cfg(any(arch="x86",arch="amd64",target_arch="x86",target_arch="x86_64"))]
+mod cmos;
+#[cfg(any(arch="armv7",arch="armv8"))]
+mod pl031;
+
+#[cfg(any(arch="x86",arch="amd64",target_arch="x86",target_arch="x86_64"))]
+fn init()
+{
+	cmos::init();
+}
+#[cfg(any(arch="armv7",arch="armv8"))]
+fn init()
+{
+	pl031::init();
+}
+
+/// Convert a civil (proleptic Gregorian, UTC) date and time into seconds since 1970-01-01
+pub fn secs_from_civil(year: u32, month: u32, day: u32, hour: u32, min: u32, sec: u32) -> u64
+{
+	// Shift the year to start in March, so the leap day is the last day
+	let y = (if month <= 2 { year - 1 } else { year }) as u64;
+	let era = y / 400;
+	let yoe = y - era * 400;
+	let m = month as u64;
+	let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as u64 - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	// 719468 = days from 0000-03-01 to 1970-01-01
+	let days = era * 146097 + doe - 719468;
+	days * 86400 + hour as u64 * 3600 + min as u64 * 60 + sec as u64
+}
+
+/// Convert seconds since 1970-01-01 into a civil date and time: `(year, month, day, hour, minute, second)`
+pub fn civil_from_secs(secs: u64) -> (u32, u32, u32, u32, u32, u32)
+{
+	let days = secs / 86400 + 719468;
+	let tod = secs % 86400;
+	let era = days / 146097;
+	let doe = days - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year as u32, month as u32, day as u32, (tod / 3600) as u32, (tod / 60 % 60) as u32, (tod % 60) as u32)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::{secs_from_civil,civil_from_secs};
+
+	#[test]
+	fn known_dates()
+	{
+		const DATES: &[((u32,u32,u32,u32,u32,u32), u64)] = &[
+			((1970, 1, 1, 0, 0, 0), 0),
+			((1999,12,31,23,59,59), 946684799),
+			// 2000 is a leap year (divisible by 400)
+			((2000, 2,29, 0, 0, 0), 951782400),
+			((2000, 3, 1, 0, 0, 0), 951868800),
+			((2038, 1,19, 3,14, 7), 2147483647),
+			((2024,12,31,12,34,56), 1735648496),
+			// 2100 is not a leap year (divisible by 100), so 28-Feb is followed by 1-Mar
+			((2100, 2,28,23,59,59), 4107542399),
+			((2100, 3, 1, 0, 0, 0), 4107542400),
+			];
+		for &(c, secs) in DATES
+		{
+			assert_eq!(secs_from_civil(c.0, c.1, c.2, c.3, c.4, c.5), secs, "{:?}", c);
+			assert_eq!(civil_from_secs(secs), c, "{}", secs);
+		}
+	}
+
+	#[test]
+	fn round_trip()
+	{
+		// Every day from 1970 to past 2100, at a time that moves through the day
+		let mut secs = 0;
+		while secs < 4200000000
+		{
+			let c = civil_from_secs(secs);
+			assert!(1 <= c.1 && c.1 <= 12 && 1 <= c.2 && c.2 <= 31, "{}: {:?}", secs, c);
+			assert_eq!(secs_from_civil(c.0, c.1, c.2, c.3, c.4, c.5), secs, "{:?}", c);
+			secs += 86400 + 3607;
+		}
+	}
+}
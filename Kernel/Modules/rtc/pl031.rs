@@ -0,0 +1,78 @@
+// "Tifflin" Kernel - Real-time clock drivers
+// - By John Hodge (thePowersGang)
+//
+// Modules/rtc/pl031.rs
+//! ARM PL031 real-time clock
+use kernel::prelude::*;
+use kernel::device_manager;
+
+/// Data register (current time, read-only)
+const REG_RTCDR: usize = 0x00;
+/// Load register (sets the time)
+const REG_RTCLR: usize = 0x08;
+/// Control register
+const REG_RTCCR: usize = 0x0C;
+/// Control: Start the counter
+const RTCCR_START: u32 = 0x1;
+
+static S_FDT_DRIVER: FdtDriver = FdtDriver;
+
+pub fn init()
+{
+	device_manager::register_driver(&S_FDT_DRIVER);
+}
+
+struct FdtDriver;
+impl device_manager::Driver for FdtDriver
+{
+	fn name(&self) -> &str {
+		"pl031"
+	}
+	fn bus_type(&self) -> &str {
+		"fdt"
+	}
+	fn handles(&self, bus_dev: &dyn device_manager::BusDevice) -> u32
+	{
+		// `compatible` is a NUL-separated list (e.g. "arm,pl031\0arm,primecell\0")
+		if bus_dev.get_attr("compatible").unwrap_str().split('\0').any(|v| v == "arm,pl031") {
+			1
+		}
+		else {
+			0
+		}
+	}
+	fn bind(&self, bus_dev: &mut dyn device_manager::BusDevice) -> Box<dyn device_manager::DriverInstance+'static>
+	{
+		let io = bus_dev.bind_io(0);
+		// SAFE: Enabling the counter has no other side-effects
+		unsafe {
+			if io.read_32(REG_RTCCR) & RTCCR_START == 0 {
+				io.write_32(REG_RTCCR, RTCCR_START);
+			}
+		}
+		::kernel::time::register_rtc( Box::new(Pl031 { io: io }) );
+		Box::new(Instance)
+	}
+}
+
+/// Bound device (the clock itself is owned by the kernel's time module)
+struct Instance;
+impl device_manager::DriverInstance for Instance {
+}
+
+struct Pl031
+{
+	io: device_manager::IOBinding,
+}
+impl ::kernel::time::RealTimeClock for Pl031
+{
+	fn read(&self) -> Option<u64> {
+		// SAFE: Read-only register
+		Some( unsafe { self.io.read_32(REG_RTCDR) } as u64 )
+	}
+	fn write(&self, secs: u64) {
+		// NOTE: 32-bit counter, wraps in 2106
+		// SAFE: Only changes the time
+		unsafe { self.io.write_32(REG_RTCLR, secs as u32) }
+	}
+}
@@ -32,6 +32,8 @@ unsafe impl Pod for u32 {}
 unsafe impl Pod for ::values::WaitItem {}
 unsafe impl Pod for ::values::GuiEvent {}	// Kinda lies, but meh
 unsafe impl Pod for ::values::RpcMessage {}
+unsafe impl Pod for ::values::ProcessInfo {}
+unsafe impl Pod for ::values::ThreadInfo {}
 
 impl<T: Pod> SyscallArg for Freeze<T>
 {
@@ -0,0 +1,66 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/syscalls/devices.rs
+//! Userland interface to device events
+use args::Args;
+use kernel::memory::freeze::FreezeMut;
+use kernel::metadevs::storage;
+
+unsafe impl ::args::Pod for ::values::VolumeEvent { }
+
+/// Create a new volume event listener
+pub fn new_volume_events() -> u32
+{
+	::objects::new_object( VolumeEvents(storage::EventSubscription::new()) )
+}
+
+struct VolumeEvents(storage::EventSubscription);
+impl ::objects::Object for VolumeEvents
+{
+	fn class(&self) -> u16 { ::values::CLASS_DEV_VOLUMEEVENTS }
+	fn as_any(&self) -> &dyn core::any::Any { self }
+	fn try_clone(&self) -> Option<u32> {
+		None
+	}
+	fn handle_syscall_ref(&self, call: u16, args: &mut Args) -> Result<u64,::Error> {
+		match call
+		{
+		::values::DEV_VOLUMEEVENTS_READ => {
+			let mut dst: FreezeMut<::values::VolumeEvent> = try!(args.get());
+			let (kind, name) = match self.0.pop()
+				{
+				Some(storage::VolumeEvent::Added(_, name)) => (::values::VolumeEventKind::Added, name),
+				Some(storage::VolumeEvent::Removed(_, name)) => (::values::VolumeEventKind::Removed, name),
+				None => return Ok(0),
+				};
+			let mut ev = ::values::VolumeEvent::default();
+			let len = ::core::cmp::min(name.len(), ev.name.len());
+			ev.kind = kind.into();
+			ev.name_len = len as u8;
+			ev.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+			*dst = ev;
+			Ok(1)
+			},
+		_ => ::objects::object_has_no_such_method_ref("devices::VolumeEvents", call),
+		}
+	}
+	fn bind_wait(&self, flags: u32, obj: &mut ::kernel::threads::SleepObject) -> u32 {
+		let mut ret = 0;
+		if flags & ::values::EV_DEV_VOLUMEEVENTS_PENDING != 0 {
+			self.0.wait_upon(obj);
+			ret |= ::values::EV_DEV_VOLUMEEVENTS_PENDING;
+		}
+		ret
+	}
+	fn clear_wait(&self, flags: u32, obj: &mut ::kernel::threads::SleepObject) -> u32 {
+		let mut ret = 0;
+		if flags & ::values::EV_DEV_VOLUMEEVENTS_PENDING != 0 {
+			self.0.clear_wait(obj);
+			if self.0.has_event() {
+				ret |= ::values::EV_DEV_VOLUMEEVENTS_PENDING;
+			}
+		}
+		ret
+	}
+}
@@ -0,0 +1,116 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Modules/syscalls/futex.rs
+//! Userland futexes (wait queues keyed by the identity of a word)
+//!
+//! Words in private memory are keyed by address space and address, as the frame behind them changes when a
+//! copy-on-write page is written or a page is swapped. Words in shared (read-only) mappings are keyed by their
+//! physical address, so every process mapping the frame sees the same futex.
+use kernel::prelude::*;
+use kernel::memory::freeze::Freeze;
+use kernel::threads::{SleepObject,SleepObjectRef};
+use kernel::arch::memory::PAddr;
+use kernel::memory::virt::ProtectionMode;
+use core::sync::atomic::{AtomicUsize,Ordering};
+use Error;
+
+/// Identity of a futex word
+#[derive(Copy,Clone,PartialEq,Debug)]
+enum Key
+{
+	/// Private memory: (address space root, address)
+	Private(PAddr, usize),
+	/// Shared mapping: physical address
+	Shared(PAddr),
+}
+
+/// Waiting threads, in the order they started waiting
+static S_WAITERS: ::kernel::sync::Mutex<Vec<(Key, SleepObjectRef)>> = ::kernel::sync::Mutex::new(Vec::new_const());
+
+/// Obtain (and freeze) the futex word at the provided user address
+fn get_word(addr: usize) -> Result<(Freeze<[usize]>, Key), Error>
+{
+	// SAFE: Validates the pointer, and freezes the memory so it can't be unmapped while in use
+	let word = unsafe {
+		match ::kernel::memory::buf_to_slice(addr as *const usize, 1)
+		{
+		Some(v) => try!(Freeze::new(v)),
+		None => return Err( Error::InvalidBuffer(addr as *const (), ::core::mem::size_of::<usize>()) ),
+		}
+		};
+	// Touch the word, so it's resident (swapped-in, or the demand-zero page mapped) when the mapping is inspected
+	// SAFE: Valid pointer
+	unsafe { ::core::ptr::read_volatile(&word[0]); }
+	let key = match ::kernel::arch::memory::virt::get_info(&word[0])
+		{
+		Some( (paddr, ProtectionMode::UserRO) ) | Some( (paddr, ProtectionMode::UserRX) ) => Key::Shared(paddr),
+		_ => Key::Private(::kernel::arch::memory::virt::current_address_space(), addr),
+		};
+	Ok( (word, key) )
+}
+
+/// Sleep until woken, as long as the word at `addr` is `val` (checked atomically with respect to `wake`)
+///
+/// `wake_time_mono` is an absolute timeout in ticks (`!0` for no timeout)
+pub fn sleep(addr: usize, val: usize, wake_time_mono: u64) -> Result<u32, Error>
+{
+	SleepObject::with_new("futex", |obj| {
+		{
+			// NOTE: The freeze is only held until queued, so it doesn't block other accesses to the page while asleep
+			let (word, key) = try!(get_word(addr));
+			// SAFE: usize and AtomicUsize have the same representation
+			let word = unsafe { &*(&word[0] as *const usize as *const AtomicUsize) };
+			// Check the value with the lock held, so a waker can't run between the check and the queueing
+			let mut lh = S_WAITERS.lock();
+			if word.load(Ordering::SeqCst) != val {
+				return Ok( ::values::FUTEX_VALUE_CHANGED );
+			}
+			lh.push( (key, obj.get_ref()) );
+		}
+
+		{
+			let _timer = if wake_time_mono != !0 {
+					Some( ::kernel::time::Timer::new(wake_time_mono, obj.get_ref()) )
+				}
+				else {
+					None
+				};
+			// If killed, the entry is removed below and the thread terminates at the syscall boundary
+			let _ = obj.wait_killable();
+		}
+
+		// If the entry is still present, the wake came from the timer
+		let mut lh = S_WAITERS.lock();
+		match lh.iter().position(|e| e.1.is_from(obj))
+		{
+		Some(i) => {
+			lh.remove(i);
+			Ok( ::values::FUTEX_TIMED_OUT )
+			},
+		None => Ok( ::values::FUTEX_WOKEN ),
+		}
+		})
+}
+
+/// Wake up to `count` threads sleeping on the word at `addr`, returning the number woken
+pub fn wake(addr: usize, count: usize) -> Result<u32, Error>
+{
+	let (_word, key) = try!(get_word(addr));
+
+	let mut lh = S_WAITERS.lock();
+	let mut n_woken = 0;
+	let mut i = 0;
+	while i < lh.len() && n_woken < count
+	{
+		if lh[i].0 == key {
+			// NOTE: Signalled (and dropped) with the lock held, so the sleeper's object stays valid
+			lh.remove(i).1.signal();
+			n_woken += 1;
+		}
+		else {
+			i += 1;
+		}
+	}
+	Ok( n_woken as u32 )
+}
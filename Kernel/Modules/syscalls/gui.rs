@@ -8,6 +8,8 @@ use kernel::prelude::*;
 use kernel::memory::freeze::{Freeze,FreezeMut};
 use gui::{Rect};
 use kernel::sync::Mutex;
+use kernel::lib::mem::Arc;
+use core::sync::atomic::{AtomicUsize,Ordering};
 
 use super::{values,objects};
 use super::{Error,ObjectHandle};
@@ -39,7 +41,12 @@ pub fn newgroup(name: &str) -> Result<ObjectHandle,u32> {
 	// Only init can create new sessions
 	// TODO: Use a capability system instead of hardcoding to only PID0
 	if ::kernel::threads::get_process_id() == 0 {
-		Ok(objects::new_object(Group(::gui::WindowGroupHandle::alloc(name))))
+		match ::gui::WindowGroupHandle::alloc(name)
+		{
+		Ok(grp) => Ok(objects::new_object(Group(grp))),
+		// Session limit reached
+		Err(()) => Err(1),
+		}
 	}
 	else {
 		todo!("syscall_gui_newgroup(name={}) - PID != 0", name);
@@ -105,7 +112,16 @@ impl objects::Object for Group
 }
 
 /// Window
-struct Window(Mutex<::gui::WindowHandle>);
+struct Window(Mutex<::gui::WindowHandle>, WindowCount);
+
+/// Counts a window against its process's window limit, released when the window is dropped
+struct WindowCount(Arc<AtomicUsize>);
+impl Drop for WindowCount
+{
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
 impl objects::Object for Window
 {
 	fn class(&self) -> u16 { values::CLASS_GUI_WIN }
@@ -240,8 +256,21 @@ impl objects::Object for Window
 }
 
 #[derive(Default)]
-struct PLWindowGroup( Mutex<Option< ::gui::WindowGroupHandle >> );
+struct PLWindowGroup( Mutex<Option< ::gui::WindowGroupHandle >>, Arc<AtomicUsize> );
 impl PLWindowGroup {
+	/// Count a new window against the process's window limit (None if the limit has been reached)
+	fn reserve_window(&self) -> Option<WindowCount> {
+		let limit = ::kernel::threads::get_limit(::kernel::threads::Resource::Windows);
+		// Counted before the check, the `WindowCount` undoes it if the limit was already reached
+		let prev = self.1.fetch_add(1, Ordering::SeqCst);
+		let count = WindowCount(self.1.clone());
+		if prev < limit {
+			Some(count)
+		}
+		else {
+			None
+		}
+	}
 	fn with<O, F: FnOnce(&mut ::gui::WindowGroupHandle)->O>(&self, f: F) -> Result<O,u32> {
 		match *self.0.lock()
 		{
@@ -256,6 +285,14 @@ pub fn newwindow(name: &str) -> Result<ObjectHandle,u32> {
 	log_trace!("syscall_gui_newwindow(name={})", name);
 	// Get window group for this process
 	let wgh = ::kernel::threads::get_process_local::<PLWindowGroup>();
-	wgh.with( |wgh| objects::new_object( Window(Mutex::new(wgh.create_window(name))) ) )
+	let count = match wgh.reserve_window()
+		{
+		Some(v) => v,
+		None => {
+			log_notice!("syscall_gui_newwindow - Window limit reached");
+			return Err(1);
+			},
+		};
+	wgh.with( |wgh| objects::new_object( Window(Mutex::new(wgh.create_window(name)), count) ) )
 }
 
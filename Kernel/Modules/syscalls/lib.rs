@@ -20,11 +20,13 @@ mod objects;
 mod args;
 
 mod threads;
+mod futex;
 #[path="gui.rs"]
 mod gui_calls;
 mod vfs;
 mod ipc_calls;
 mod network_calls;
+mod devices;
 
 pub type ObjectHandle = u32;
 
@@ -74,19 +76,28 @@ pub fn init(loader_handle: ::kernel::vfs::handle::File, init_handle: ::kernel::v
 pub unsafe extern "C" fn syscalls_handler(id: u32, first_arg: *const usize, count: u32) -> u64
 {
 	//log_debug!("syscalls_handler({}, {:p}+{})", id, first_arg, count);
-	invoke(id, ::core::slice::from_raw_parts(first_arg, count as usize))
+	::kernel::threads::account_kernel_entry();
+	let rv = invoke(id, ::core::slice::from_raw_parts(first_arg, count as usize));
+	::kernel::threads::account_user_entry();
+	::kernel::threads::exceptions::check_user_events();
+	rv
 }
 
 fn invoke(call_id: u32, args: &[usize]) -> u64 {
-	match invoke_int(call_id, &mut Args::new(args))
-	{
-	Ok(v) => v,
-	Err(e) => {
-		log_log!("Syscall formatting error in call {:#x} - {:?} {}", call_id, e, e);
-		::kernel::threads::exit_process(0x8000_0000);
-		// !0
-		},
+	let rv = match invoke_int(call_id, &mut Args::new(args))
+		{
+		Ok(v) => v,
+		Err(e) => {
+			log_log!("Syscall formatting error in call {:#x} - {:?} {}", call_id, e, e);
+			::kernel::threads::exit_process(0x8000_0000);
+			// !0
+			},
+		};
+	// If the process was killed (during or before the call), terminate this thread now that the call's state is released
+	if ::kernel::threads::is_cur_process_killed() {
+		::kernel::threads::exit_thread(!0);
 	}
+	rv
 }
 
 fn error_code(value: u32) -> usize {
@@ -148,11 +159,12 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			// TODO: Use a Result here
 			syscall_core_textinfo(group, id, &mut buf) as u64
 			},
-		// - 0/2: Terminate current thread
+		// - 0/4: Terminate current thread (with an exit status)
 		CORE_EXITTHREAD => {
-			threads::terminate(); 0
+			let status: u32 = try!(args.get());
+			threads::terminate(status); 0
 			},
-		// - 0/3: Start process
+		// - 0/5: Start process
 		CORE_STARTPROCESS => {
 			let name: Freeze<str>  = try!(args.get());
 			let start: usize = try!(args.get());
@@ -163,23 +175,116 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			}
 			threads::newprocess(&name, start, end) as u64
 			},
-		// - 0/4: Start thread
+		// - 0/10: Start process (cloning the entire address space)
+		CORE_FORKPROCESS => {
+			let name: Freeze<str>  = try!(args.get());
+			threads::newprocess(&name, 0, ::kernel::arch::memory::addresses::USER_END) as u64
+			},
+		// - 0/6: Start thread
 		CORE_STARTTHREAD => {
 			let ip: usize = try!(args.get());
 			let sp: usize = try!(args.get());
-			threads::newthread(sp, ip) as u64
+			let tls_base: usize = try!(args.get());
+			threads::newthread(sp, ip, tls_base) as u64
 			},
-		// - 0/5: Wait for event
+		// - 0/7: Wait for event
 		CORE_WAIT => {
 			let mut events: FreezeMut<[WaitItem]> = try!(args.get());
 			let timeout: u64 = try!(args.get());
 			try!(threads::wait(&mut events, timeout)) as u64
 			},
+		// - 0/8: Sleep on a futex
 		CORE_FUTEX_SLEEP => {
-			todo!("FUTEX_SLEEP");
+			let addr: usize = try!(args.get());
+			let val: usize = try!(args.get());
+			let timeout: u64 = try!(args.get());
+			try!(futex::sleep(addr, val, timeout)) as u64
 			},
+		// - 0/9: Wake sleepers on a futex
 		CORE_FUTEX_WAKE => {
-			todo!("FUTEX_SLEEP");
+			let addr: usize = try!(args.get());
+			let count: usize = try!(args.get());
+			try!(futex::wake(addr, count)) as u64
+			},
+		// - 0/11: Random data
+		CORE_GETRANDOM => {
+			let mut buf: FreezeMut<[u8]> = try!(args.get());
+			let flags: u32 = try!(args.get());
+			syscall_core_getrandom(&mut buf, flags); 0
+			},
+		// - 0/12: Set the current thread's TLS base
+		CORE_SETTLS => {
+			let base: usize = try!(args.get());
+			// NOTE: Not validated, only used by user code
+			::kernel::arch::threads::set_cur_user_tls_base(base);
+			0
+			},
+		// - 0/13: Get the monotonic time
+		CORE_GETTICKS => {
+			::kernel::time::ticks()
+			},
+		// - 0/14: Set the current thread's priority
+		CORE_SETPRIORITY => {
+			let priority: u32 = try!(args.get());
+			threads::set_priority(priority) as u64
+			},
+		// - 0/15: List processes
+		CORE_LISTPROCESSES => {
+			let start: u32 = try!(args.get());
+			let mut buf: FreezeMut<[ProcessInfo]> = try!(args.get());
+			threads::list_processes(start, &mut buf) as u64
+			},
+		// - 0/16: List threads
+		CORE_LISTTHREADS => {
+			let start: u32 = try!(args.get());
+			let mut buf: FreezeMut<[ThreadInfo]> = try!(args.get());
+			threads::list_threads(start, &mut buf) as u64
+			},
+		// - 0/17: Get the wall-clock time
+		CORE_GETTIME => {
+			::kernel::time::wall_time()
+			},
+		// - 0/18: Set the wall-clock time
+		CORE_SETTIME => {
+			let ms: u64 = try!(args.get());
+			// Only init can set the clock
+			// TODO: Use a capability system instead of hardcoding to only PID0
+			if ::kernel::threads::get_process_id() == 0 {
+				::kernel::time::set_wall_time(ms);
+				0
+			}
+			else {
+				log_notice!("CORE_SETTIME - PID {} not permitted", ::kernel::threads::get_process_id());
+				error_code(0) as u64
+			}
+			},
+		// - 0/19: Set the current thread's exception handler
+		CORE_SETEXCEPTIONHANDLER => {
+			let entry: usize = try!(args.get());
+			let stack_top: usize = try!(args.get());
+			::kernel::threads::exceptions::set_handler(entry, stack_top) as u64
+			},
+		// - 0/20: Return from an exception handler
+		CORE_EXCEPTIONRETURN => {
+			let frame_ptr: usize = try!(args.get());
+			match ::kernel::threads::exceptions::exception_return(frame_ptr)
+			{
+			Ok(frame) => {
+				::kernel::threads::account_user_entry();
+				::kernel::arch::threads::exception_return(&frame)
+				},
+			Err(()) => {
+				log_log!("CORE_EXCEPTIONRETURN - Invalid frame pointer {:#x}", frame_ptr);
+				::kernel::threads::exit_process(0x8000_0000);
+				},
+			}
+			},
+		// - 0/21: Lower a resource limit
+		CORE_LOWERLIMIT => {
+			let resource: u32 = try!(args.get());
+			let value: usize = try!(args.get());
+			let resource = try!( ::kernel::threads::Resource::from_u32(resource).ok_or(Error::BadValue) );
+			::kernel::threads::lower_limit(resource, value) as u64
 			},
 		// === 1: Window Manager / GUI
 		// - 1/0: New group (requires permission, has other restrictions)
@@ -211,10 +316,18 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			let addr: usize = try!(args.get());
 			let count: usize = try!(args.get());
 			log_debug!("MEM_ALLOCATE({:#x},{})", addr, count);
+			// NOTE: The limit is checked (and the pages reserved) atomically by allocate_user
 			match ::kernel::memory::virt::allocate_user(addr as *mut (), count)
 			{
 			Ok(_) => 0,
-			Err(e) => todo!("MEM_ALLOCATE - error {:?}", e),
+			Err(::kernel::memory::virt::MapError::LimitReached) => {
+				log_notice!("MEM_ALLOCATE({:#x},{}) - Memory limit reached", addr, count);
+				error_code(1) as u64
+				},
+			Err(e) => {
+				log_notice!("MEM_ALLOCATE({:#x},{}) - {:?}", addr, count, e);
+				error_code(0) as u64
+				},
 			}
 			},
 		MEM_REPROTECT => {
@@ -274,6 +387,10 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			Err(e) => e as u8 as u64,
 			}
 			},
+		// === 5: Devices
+		DEV_VOLUME_EVENTS => {
+			devices::new_volume_events() as u64
+			},
 		// === *: Default
 		_ => {
 			log_error!("Unknown syscall {:05x}", call_id);
@@ -331,6 +448,20 @@ fn syscall_core_dbgvalue(msg: &[u8], val: usize) {
 	log_debug!("USER DBG> {} {:#x}", ::core::str::from_utf8(msg).unwrap_or("BADUTF"), val);
 }
 
+#[inline(never)]
+fn syscall_core_getrandom(buf: &mut [u8], flags: u32)
+{
+	// The ASLR boot flag disables randomisation (for reproducible debugging) by handing out zeroes
+	if flags & ::values::GETRANDOM_ASLR != 0 && ::kernel::config::get_string(::kernel::config::Value::Aslr) == "0" {
+		for b in buf.iter_mut() {
+			*b = 0;
+		}
+	}
+	else {
+		::kernel::entropy::fill(buf);
+	}
+}
+
 #[inline(never)]
 fn syscall_core_textinfo(group: u32, id: usize, buf: &mut [u8]) -> usize
 {
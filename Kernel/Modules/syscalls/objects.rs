@@ -182,15 +182,22 @@ impl ProcessObjects {
 		}
 	}
 
-	fn find_and_fill_slot<F: FnOnce()->UserObject>(&self, fcn: F) -> Result<u32, super::Error> {
-		for (i,ent) in self.iter().enumerate()
+	/// Store an object in the first free slot, only slots below `limit` are used (so at most `limit` are in use)
+	fn find_and_fill_slot<F: FnOnce()->UserObject>(&self, limit: usize, fcn: F) -> Result<u32, super::Error> {
+		self.find_and_try_fill_slot(limit, || Ok(fcn()))
+	}
+	/// Store an object in the first free slot (as `find_and_fill_slot`), `fcn` is only called once a slot is found
+	///
+	/// If `fcn` fails, the slot is left free and the error is returned.
+	fn find_and_try_fill_slot<F: FnOnce()->Result<UserObject,super::Error>>(&self, limit: usize, fcn: F) -> Result<u32, super::Error> {
+		for (i,ent) in self.iter().enumerate().take(limit)
 		{
 			// If a free slot is found,
 			if ent.read().is_none() {
 				// lock for writing then ensure that it is free
 				let mut wh = ent.write();
 				if wh.is_none() {
-					*wh = Some(fcn());
+					*wh = Some(try!(fcn()));
 					let name = wh.as_ref().unwrap().data.type_name();
 					log_debug!("Object created #{}: {}", i, name);
 					return Ok(i as u32);
@@ -227,7 +234,8 @@ impl Drop for ProcessObjects {
 pub fn new_object<T: Object+'static>(val: T) -> u32
 {
 	log_debug!("new_object() - size_of {} = {}", type_name!(T), ::core::mem::size_of::<T>());
-	get_process_local::<ProcessObjects>().find_and_fill_slot(|| UserObject::new(val)).unwrap_or(!0)
+	let limit = ::kernel::threads::get_limit(::kernel::threads::Resource::Handles);
+	get_process_local::<ProcessObjects>().find_and_fill_slot(limit, || UserObject::new(val)).unwrap_or(!0)
 }
 
 /// Startup: Pushes the specified index as an unclaimed object
@@ -330,9 +338,14 @@ pub fn clear_wait(handle: u32, mask: u32, sleeper: &mut ::kernel::threads::Sleep
 pub fn give_object(target: &::kernel::threads::ProcessHandle, tag: &str, handle: u32) -> Result<(),super::Error> {
 	log_debug!("give_object(target={:?}, handle={:?})", target, handle);
 	let target_list = target.get_process_local_alloc::<ProcessObjects>();
-	let obj = try!(get_process_local::<ProcessObjects>().take_object(handle));
-	let class_id = obj.class();
-	let id = try!( target_list.find_and_fill_slot(|| UserObject { data: obj }) );
+	let limit = target.get_limit(::kernel::threads::Resource::Handles);
+	// NOTE: The object is only taken from this process once a free slot (under the limit) is held, so it isn't lost if the target is full
+	let mut class_id = 0;
+	let id = try!( target_list.find_and_try_fill_slot(limit, || {
+		let obj = try!(get_process_local::<ProcessObjects>().take_object(handle));
+		class_id = obj.class();
+		Ok( UserObject { data: obj } )
+		}) );
 	
 	log_trace!("- Giving object {} ({} {}) as '{}' (handle {})",
 		handle, class_id, ::values::get_class_name(class_id),
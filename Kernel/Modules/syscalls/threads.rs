@@ -49,19 +49,100 @@ pub fn exit(status: u32) {
 	::kernel::threads::exit_process(status);
 }
 #[inline(never)]
-pub fn terminate() {
-	todo!("terminate()");
+pub fn terminate(status: u32) {
+	::kernel::threads::exit_thread(status);
 }
 #[inline(never)]
-pub fn newthread(sp: usize, ip: usize) -> ObjectHandle {
-	todo!("newthread(sp={:#x},ip={:#x})", sp, ip);
+pub fn newthread(sp: usize, ip: usize, tls_base: usize) -> ObjectHandle {
+	// NOTE: Don't need to validate these values, as they're used only in user-space
+	match ::kernel::threads::UserThreadHandle::new(ip, sp, tls_base)
+	{
+	Ok(thread) => ::objects::new_object( Thread(thread) ),
+	// Thread limit reached
+	Err(()) => !0,
+	}
+}
+/// Set the current thread's priority, clamped to the range available to user code
+#[inline(never)]
+pub fn set_priority(priority: u32) -> u32 {
+	let priority = ::core::cmp::min(priority, ::kernel::threads::PRIORITY_USER_MAX as u32);
+	::kernel::threads::set_cur_priority(priority as ::kernel::threads::Priority) as u32
+}
+/// Fill `buf` with information on processes with a PID of at least `start`
+#[inline(never)]
+pub fn list_processes(start: u32, buf: &mut [values::ProcessInfo]) -> u32 {
+	use kernel::threads::ProcessState;
+	let mut count = 0;
+	::kernel::threads::enumerate_processes(start, |info| {
+		if count == buf.len() {
+			return false;
+		}
+		let ent = &mut buf[count];
+		*ent = Default::default();
+		ent.pid = info.pid;
+		ent.parent = info.parent;
+		ent.thread_count = info.thread_count as u32;
+		ent.exit_status = info.exit_status.unwrap_or(0);
+		ent.user_time = info.cpu_time.user;
+		ent.kernel_time = info.cpu_time.kernel;
+		ent.resident_pages = info.resident_pages as u64;
+		ent.state = match info.state
+			{
+			ProcessState::Running => values::ProcessState::Running,
+			ProcessState::Exiting => values::ProcessState::Exiting,
+			ProcessState::Exited  => values::ProcessState::Exited,
+			}.into();
+		ent.name_len = copy_name(&mut ent.name, info.name);
+		count += 1;
+		true
+		});
+	count as u32
+}
+/// Fill `buf` with information on threads with a TID of at least `start`
+#[inline(never)]
+pub fn list_threads(start: u32, buf: &mut [values::ThreadInfo]) -> u32 {
+	use kernel::threads::ThreadState;
+	let mut count = 0;
+	::kernel::threads::enumerate_threads(start, |info| {
+		if count == buf.len() {
+			return false;
+		}
+		let ent = &mut buf[count];
+		*ent = Default::default();
+		ent.tid = info.tid;
+		ent.pid = info.pid;
+		ent.exit_status = info.exit_status.unwrap_or(0);
+		ent.state = match info.state
+			{
+			ThreadState::Running  => values::ThreadState::Running,
+			ThreadState::Runnable => values::ThreadState::Runnable,
+			ThreadState::Sleeping => values::ThreadState::Sleeping,
+			ThreadState::Dead     => values::ThreadState::Dead,
+			}.into();
+		ent.priority = info.priority;
+		ent.user_time = info.cpu_time.user;
+		ent.kernel_time = info.cpu_time.kernel;
+		ent.name_len = copy_name(&mut ent.name, info.name);
+		count += 1;
+		true
+		});
+	count as u32
+}
+/// Copy a (possibly truncated) name into a fixed-size buffer, returning the length
+fn copy_name(dst: &mut [u8], name: &str) -> u8 {
+	let len = ::core::cmp::min(dst.len(), name.len());
+	dst[..len].copy_from_slice(&name.as_bytes()[..len]);
+	len as u8
 }
 #[inline(never)]
 pub fn newprocess(name: &str,  clone_start: usize, clone_end: usize) -> ObjectHandle {
 	// 1. Create a new process image (virtual address space)
-	let process = ::kernel::threads::ProcessHandle::new(name, clone_start, clone_end);
-	
-	::objects::new_object( ProtoProcess(process) )
+	match ::kernel::threads::ProcessHandle::new(name, clone_start, clone_end)
+	{
+	Ok(process) => ::objects::new_object( ProtoProcess(process) ),
+	// All PIDs/TIDs in use
+	Err(()) => !0,
+	}
 }
 
 // ret: number of events triggered
@@ -77,7 +158,8 @@ pub fn wait(events: &mut [values::WaitItem], wake_time_mono: u64) -> Result<u32,
 		if num_bound == 0 && wake_time_mono == !0 {
 			// Attempting to sleep on no events with an infinite timeout! Would sleep forever
 			log_error!("TODO: What to do when a thread tries to sleep forever");
-			waiter.wait();
+			// NOTE: A kill returns to the syscall boundary (where the thread terminates) via the normal path
+			let _ = waiter.wait_killable();
 		}
 
 		// A wake time of 0 means to not sleep at all, just check the status of the events
@@ -85,11 +167,14 @@ pub fn wait(events: &mut [values::WaitItem], wake_time_mono: u64) -> Result<u32,
 		if wake_time_mono > 0 {
 			// !0 indicates an unbounded wait (no need to set a wakeup time)
 			if wake_time_mono != !0 {
-				todo!("Set a wakeup timer at {}", wake_time_mono);
-				//waiter.wait_until(wake_time_mono);
+				// If the time has already passed, just check the events
+				if wake_time_mono > ::kernel::time::ticks() {
+					let _timer = ::kernel::time::Timer::new(wake_time_mono, waiter.get_ref());
+					let _ = waiter.wait_killable();
+				}
 			}
 			else {
-				waiter.wait();
+				let _ = waiter.wait_killable();
 			}
 		}
 
@@ -113,8 +198,21 @@ impl ::objects::Object for ProtoProcess
 		values::CORE_PROTOPROCESS_SENDOBJ => {
 			let tag: ::values::FixedStr8 = try!(args.get());
 			let handle: u32 = try!(args.get());
-			::objects::give_object(&self.0, &tag, handle).map(|_| 0)
+			match ::objects::give_object(&self.0, &tag, handle)
+			{
+			Ok(_) => Ok(0),
+			// The child's handle limit was reached (the object stays with this process)
+			Err(Error::TooManyObjects) => Ok(::error_code(0) as u64),
+			Err(e) => Err(e),
+			}
 			}
+		// Lower one of the child's resource limits
+		values::CORE_PROTOPROCESS_LOWERLIMIT => {
+			let resource: u32 = try!(args.get());
+			let value: usize = try!(args.get());
+			let resource = try!( ::kernel::threads::Resource::from_u32(resource).ok_or(Error::BadValue) );
+			Ok( self.0.lower_limit(resource, value) as u64 )
+			},
 		_ => ::objects::object_has_no_such_method_ref("threads::ProtoProcess", call),
 		}
 	}
@@ -157,7 +255,15 @@ impl ::objects::Object for Process
 		match call
 		{
 		// Request termination of child process
-		values::CORE_PROCESS_KILL => todo!("CORE_PROCESS_KILL"),
+		values::CORE_PROCESS_KILL => {
+			self.0.kill(values::EXITSTATUS_KILLED);
+			Ok(0)
+			},
+		values::CORE_PROCESS_GETSTATUS => Ok( match self.0.get_exit_status()
+			{
+			Some(v) => v as u64,
+			None => values::STATUS_RUNNING,
+			}),
 		_ => ::objects::object_has_no_such_method_ref("threads::Process", call),
 		}
 	}
@@ -184,3 +290,47 @@ impl ::objects::Object for Process
 		ret
 	}
 }
+
+pub struct Thread(::kernel::threads::UserThreadHandle);
+impl ::objects::Object for Thread
+{
+	fn class(&self) -> u16 { values::CLASS_CORE_THREAD }
+	fn as_any(&self) -> &dyn Any { self }
+	fn try_clone(&self) -> Option<u32> {
+		None
+	}
+	fn handle_syscall_ref(&self, call: u16, args: &mut Args) -> Result<u64,Error>
+	{
+		match call
+		{
+		values::CORE_THREAD_GETSTATUS => Ok( match self.0.get_exit_status()
+			{
+			Some(v) => v as u64,
+			None => values::STATUS_RUNNING,
+			}),
+		values::CORE_THREAD_NOTIFY => {
+			let value: u32 = try!(args.get());
+			self.0.notify(value);
+			Ok(0)
+			},
+		_ => ::objects::object_has_no_such_method_ref("threads::Thread", call),
+		}
+	}
+	fn bind_wait(&self, flags: u32, obj: &mut ::kernel::threads::SleepObject) -> u32 {
+		let mut ret = 0;
+		if flags & values::EV_THREAD_TERMINATED != 0 {
+			self.0.bind_wait_terminate(obj);
+			ret += 1;
+		}
+		ret
+	}
+	fn clear_wait(&self, flags: u32, obj: &mut ::kernel::threads::SleepObject) -> u32 {
+		let mut ret = 0;
+		if flags & values::EV_THREAD_TERMINATED != 0 {
+			if self.0.clear_wait_terminate(obj) {
+				ret |= values::EV_THREAD_TERMINATED;
+			}
+		}
+		ret
+	}
+}
@@ -43,11 +43,13 @@ pub extern "C" fn kmain()
 	::kernel::memory::phys::init();
 	::kernel::memory::virt::init();
 	::kernel::memory::heap::init();
+	::kernel::memory::phys::init_buddy();
 	::kernel::memory::page_cache::init();
 	::kernel::threads::init();
 	
 	log_log!("Command line = '{}'", ::kernel::arch::boot::get_boot_string());
 	::kernel::config::init( ::kernel::arch::boot::get_boot_string() );
+	::kernel::profiler::init();
 	
 	// Dump active video mode
 	let vidmode = ::kernel::arch::boot::get_video_mode();
@@ -59,8 +61,9 @@ pub extern "C" fn kmain()
 	None => log_debug!("No video mode present")
 	}
 	
-	// Intialise the IRQ worker
+	// Intialise the IRQ worker and timers
 	::kernel::irqs::init();
+	::kernel::time::init();
 	
 	// Modules (dependency tree included)
 	// - Requests that the GUI be started as soon as possible
@@ -88,6 +91,14 @@ fn sysinit() -> !
 		::kernel::threads::SleepObject::with_new("noinit", |so| so.wait());
 	}
 	
+	// 0. Enable swap (if configured)
+	let swapdisk = get_string(Value::Swap);
+	if swapdisk != "" {
+		if let Err(e) = ::kernel::memory::swap::enable(swapdisk) {
+			log_error!("Unable to enable swap on {}: {}", swapdisk, e);
+		}
+	}
+	
 	// 1. Mount /system to the specified volume
 	let sysdisk = ::kernel::config::get_string(::kernel::config::Value::SysDisk);
 	match VolumeHandle::open_named(sysdisk)
@@ -181,6 +192,13 @@ fn automount()
 				},
 			Ok(v) => v,
 			};
+		let info = vh.partition_info();
+		log_debug!("'{}': {:?}", v, info);
+		// Skip partitions that are known to not contain a filesystem (e.g. swap)
+		if info.system_id == Some(0x82) {
+			log_log!("Not automounting '{}' (swap partition)", v);
+			continue;
+		}
 		mountdir.mkdir(&v).unwrap();
 		let mountpt = format!("/mount/{}",v);
 		match mount::mount( mountpt.as_ref(), vh, "", &[] )
@@ -246,6 +264,7 @@ fn spawn_init(loader_path: &str, init_cmdline: &str) -> Result<::kernel::Void, &
 	::syscalls::init(loader, init);
 	
 	log_notice!("Entering userland at {:#x} '{}' '{}'", header_ptr.entrypoint, loader_path, init_cmdline);
+	::kernel::threads::account_user_entry();
 	// SAFE: This pointer is as validated as it can be...
 	unsafe {
 		::kernel::arch::drop_to_user(header_ptr.entrypoint, 0, argslen);
@@ -10,10 +10,13 @@ fn main()
     ::kernel::memory::page_cache::init();
     (::kernel::metadevs::storage::S_MODULE.init)();
     (::kernel::hw::mapper_mbr::S_MODULE.init)();
+    (::kernel::hw::mapper_gpt::S_MODULE.init)();
+    (::kernel::hw::mapper_md::S_MODULE.init)();
     (::kernel::vfs::S_MODULE.init)();
 
     (::fs_fat::S_MODULE.init)();
     (::fs_extN::S_MODULE.init)();
+    (::fs_iso9660::S_MODULE.init)();
     
     let disks: [(&str, &::std::path::Path); 1] = [
         ("virt0", "data/hda.img".as_ref()),
@@ -6,10 +6,13 @@ use core::mem::{align_of,size_of};
 
 #[cfg(target_arch="x86_64")] const HEAP_LIMITS: (usize,usize) = (0x1000_0000_0000, 0x7000_0000_0000);
 #[cfg(target_arch="x86_64")] const PAGE_SIZE: usize = 0x1000;
+#[cfg(target_arch="x86_64")] const ASLR_RANGE: usize = 0x10_0000_0000;	// 64GB
 #[cfg(target_arch="arm")] const HEAP_LIMITS: (usize,usize) = (0x1000_0000, 0x7000_0000);
 #[cfg(target_arch="arm")] const PAGE_SIZE: usize = 0x2000;
+#[cfg(target_arch="arm")] const ASLR_RANGE: usize = 0x1000_0000;	// 256MB
 #[cfg(target_arch="aarch64")] const HEAP_LIMITS: (usize,usize) = (0x1000_0000, 0x7000_0000);
 #[cfg(target_arch="aarch64")] const PAGE_SIZE: usize = 0x4000;
+#[cfg(target_arch="aarch64")] const ASLR_RANGE: usize = 0x1000_0000;	// 256MB
 
 
 pub const EMPTY: *mut u8 = 1 as *mut u8;
@@ -214,8 +217,10 @@ impl AllocState
 		assert!(self.past_end != HEAP_LIMITS.1 as *mut Block);
 		assert!(self.past_end as usize + (npages * PAGE_SIZE) <= HEAP_LIMITS.1);	// TODO: This isn't an assert conditon, it's an OOM
 		if self.start.is_null() {
-			self.start = HEAP_LIMITS.0 as *mut Block;
-			self.past_end = HEAP_LIMITS.0 as *mut Block;
+			// Randomise the heap base (the offset is zero if ASLR is disabled)
+			let base = HEAP_LIMITS.0 + (::syscalls::get_aslr_random() as usize % (ASLR_RANGE / PAGE_SIZE)) * PAGE_SIZE;
+			self.start = base as *mut Block;
+			self.past_end = base as *mut Block;
 		}
 
 		// SAFE: Allocates only in controlled region.
@@ -13,6 +13,10 @@ pub trait WaitController
 	fn get_count(&self) -> usize;
 	fn populate(&self, cb: &mut dyn FnMut(::syscalls::WaitItem));
 	fn handle(&mut self, events: &[::syscalls::WaitItem]);
+	/// Monotonic time (`syscalls::threads::get_ticks`) at which `handle` should next be called, `!0` for none
+	fn wake_time(&self) -> u64 {
+		!0
+	}
 }
 
 /// Idle, handling events on each WaitController passed
@@ -27,7 +31,8 @@ pub fn idle_loop<'a,'b,'c>(items: &'a mut [&'b mut (dyn WaitController+'c)])
 			ctrlr.populate(&mut |wi| objects.push(wi));
 		}
 
-		::syscalls::threads::wait(&mut objects, !0);
+		let wake_time = items.iter().map(|ctrlr| ctrlr.wake_time()).min().unwrap_or(!0);
+		::syscalls::threads::wait(&mut objects, wake_time);
 
 		let mut ofs = 0;
 		for ctrlr in items.iter_mut()
@@ -155,5 +155,9 @@ pub use alloc::{vec, string, borrow};
 
 pub mod os;
 
+pub mod thread;
+
+pub mod time;
+
 pub mod heap;
 
@@ -12,6 +12,7 @@ pub trait Termination
 #[lang="start"]
 fn lang_start<T: Termination+'static>(main: fn()->T, argc: isize, argv: *const *const u8) -> isize {
 	kernel_log!("lang_start(main={:p}, argc={}, argv={:p})", main, argc, argv);
+	::thread::init_main_thread();
 	
 	main().report() as isize
 }
@@ -0,0 +1,155 @@
+// Tifflin OS - Standard Library (clone)
+// - By John Hodge (thePowersGang)
+//
+//! Threads
+use prelude::v1::*;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::mem::ManuallyDrop;
+use core::any::Any;
+
+/// Size of the stack allocated for each spawned thread
+const STACK_SIZE: usize = 0x4_0000;
+/// Size of the alternate stack used by each thread's exception handler
+const EXCEPTION_STACK_SIZE: usize = 0x4000;
+
+pub type Result<T> = ::core::result::Result<T, Box<dyn Any + Send + 'static>>;
+
+/// Per-thread information block, pointed to by the thread's TLS base
+#[repr(C)]
+struct ThreadInfo
+{
+	/// Pointer to this structure (MUST be first, read via %fs:0 on amd64)
+	self_ptr: *const ThreadInfo,
+	main: Option<Box<dyn FnOnce() + Send>>,
+	/// Alternate stack for the exception handler (a fault on the main stack may be an overflow)
+	exception_stack: Vec<u8>,
+}
+
+/// Return value storage, written by the thread and read by `join`
+struct Packet<T>(UnsafeCell<Option<T>>);
+// SAFE: The value is only written by the thread, and only read after it has terminated
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+/// An owned permission to join on a thread
+///
+/// NOTE: If this is dropped without calling `join`, the thread's stack is leaked (as it could still be running)
+pub struct JoinHandle<T>
+{
+	thread: ::syscalls::threads::Thread,
+	packet: Arc<Packet<T>>,
+	info: ManuallyDrop<Box<ThreadInfo>>,
+	stack: ManuallyDrop<Vec<u8>>,
+}
+
+/// Spawn a new thread, returning a handle that can be used to wait for its result
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+	F: FnOnce() -> T,
+	F: Send + 'static,
+	T: Send + 'static
+{
+	let packet = Arc::new(Packet(UnsafeCell::new(None)));
+	let main = {
+		let packet = packet.clone();
+		move || {
+			let rv = f();
+			// SAFE: Only this thread writes the packet, and the reader waits for this thread to terminate
+			unsafe { *packet.0.get() = Some(rv); }
+		}
+		};
+	let mut exception_stack = Vec::with_capacity(EXCEPTION_STACK_SIZE);
+	exception_stack.resize(EXCEPTION_STACK_SIZE, 0u8);
+	let mut info = Box::new(ThreadInfo {
+		self_ptr: ::core::ptr::null(),
+		main: Some(Box::new(main)),
+		exception_stack: exception_stack,
+		});
+	info.self_ptr = &*info;
+	let mut stack = Vec::with_capacity(STACK_SIZE);
+	stack.resize(STACK_SIZE, 0u8);
+
+	let stack_top = (stack.as_ptr() as usize + STACK_SIZE) & !0xF;
+	// amd64 functions expect to be entered with a return address pushed
+	#[cfg(target_arch="x86_64")]
+	let stack_top = stack_top - 8;
+
+	// SAFE: The stack and info block are only released after the thread has terminated
+	let thread = match unsafe { ::syscalls::threads::start_thread(thread_start as usize, stack_top, &*info as *const _ as usize) }
+		{
+		Ok(v) => v,
+		Err(e) => panic!("spawn - Failed to start thread: {}", e),
+		};
+	JoinHandle {
+		thread: thread,
+		packet: packet,
+		info: ManuallyDrop::new(info),
+		stack: ManuallyDrop::new(stack),
+	}
+}
+
+/// Entrypoint for new threads, the TLS base points to the thread's `ThreadInfo`
+extern "C" fn thread_start() -> !
+{
+	// SAFE: `spawn` sets the TLS base to a valid `ThreadInfo`, which is kept until the thread terminates
+	let info = unsafe { &mut *(::syscalls::threads::get_tls_base() as *mut ThreadInfo) };
+	let main = info.main.take().expect("thread_start - No main function");
+	register_exception_handler(&mut info.exception_stack);
+	main();
+	::syscalls::threads::exit_thread(0);
+}
+
+/// Register the runtime's exception handler for the current thread (see `rt::exception_handler`)
+///
+/// The passed stack must remain allocated until the thread terminates.
+fn register_exception_handler(stack: &mut [u8])
+{
+	let stack_top = (stack.as_mut_ptr() as usize + stack.len()) & !0xF;
+	// SAFE: The stack is only used by this thread's handler, and the caller keeps it allocated
+	unsafe {
+		::syscalls::threads::set_exception_handler(Some(::rt::exception_handler), stack_top);
+	}
+}
+/// Register the exception handler for the main thread (called by `lang_start`)
+#[doc(hidden)]
+pub fn init_main_thread()
+{
+	static mut S_MAIN_EXCEPTION_STACK: [u8; EXCEPTION_STACK_SIZE] = [0; EXCEPTION_STACK_SIZE];
+	// SAFE: Only called once, on the main thread (which runs until the process exits)
+	register_exception_handler(unsafe { &mut S_MAIN_EXCEPTION_STACK[..] });
+}
+
+/// Put the current thread to sleep for at least the specified duration
+pub fn sleep(dur: ::time::Duration)
+{
+	let wake_time = (::time::Instant::now() + dur).as_ticks();
+	// Loop in case of an early wake (there are no events, so only the timer should fire)
+	while ::syscalls::threads::get_ticks() < wake_time {
+		::syscalls::threads::wait(&mut [], wake_time);
+	}
+}
+
+impl<T> JoinHandle<T>
+{
+	/// Wait for the thread to complete, and obtain its return value
+	pub fn join(self) -> Result<T>
+	{
+		let mut waits = [self.thread.wait_terminate()];
+		while self.thread.get_status().is_none() {
+			::syscalls::threads::wait(&mut waits, !0);
+		}
+
+		let JoinHandle { thread, packet, info, stack } = self;
+		drop(thread);
+		// The thread has terminated, so the stack and info are no longer in use
+		drop(ManuallyDrop::into_inner(info));
+		drop(ManuallyDrop::into_inner(stack));
+		// SAFE: Thread has terminated, so nothing else is accessing the packet
+		match unsafe { (*packet.0.get()).take() }
+		{
+		Some(v) => Ok(v),
+		// The thread terminated without returning (e.g. killed)
+		None => Err(Box::new(())),
+		}
+	}
+}
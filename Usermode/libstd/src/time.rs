@@ -0,0 +1,136 @@
+// Tifflin OS - Standard Library (clone)
+// - By John Hodge (thePowersGang)
+//
+//! Temporal quantification
+use core::ops;
+
+pub use core::time::Duration;
+
+/// A measurement of the monotonic clock (millisecond resolution)
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub struct Instant(u64);
+
+impl Instant
+{
+	pub fn now() -> Instant {
+		Instant( ::syscalls::threads::get_ticks() )
+	}
+
+	/// Time elapsed between `earlier` and `self` (zero if `earlier` is later)
+	pub fn duration_since(&self, earlier: Instant) -> Duration {
+		Duration::from_millis( self.0.saturating_sub(earlier.0) )
+	}
+	/// Time elapsed since this instant was created
+	pub fn elapsed(&self) -> Duration {
+		Instant::now().duration_since(*self)
+	}
+
+	/// Raw tick value (as used by `syscalls::threads::wait`)
+	pub fn as_ticks(&self) -> u64 {
+		self.0
+	}
+}
+
+/// Convert a duration to ticks, rounding up so waits are never short
+fn to_ticks(d: Duration) -> u64 {
+	d.as_secs() * 1000 + ((d.subsec_nanos() as u64 + 999_999) / 1_000_000)
+}
+
+impl ops::Add<Duration> for Instant {
+	type Output = Instant;
+	fn add(self, d: Duration) -> Instant {
+		Instant(self.0 + to_ticks(d))
+	}
+}
+impl ops::AddAssign<Duration> for Instant {
+	fn add_assign(&mut self, d: Duration) {
+		self.0 += to_ticks(d);
+	}
+}
+impl ops::Sub<Duration> for Instant {
+	type Output = Instant;
+	fn sub(self, d: Duration) -> Instant {
+		Instant(self.0 - to_ticks(d))
+	}
+}
+impl ops::Sub<Instant> for Instant {
+	type Output = Duration;
+	fn sub(self, other: Instant) -> Duration {
+		self.duration_since(other)
+	}
+}
+
+/// A measurement of the wall-clock (millisecond resolution, not monotonic)
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub struct SystemTime(u64);
+
+/// 1970-01-01 00:00:00 UTC
+pub const UNIX_EPOCH: SystemTime = SystemTime(0);
+
+/// Error from `SystemTime::duration_since` (the "earlier" time was later)
+#[derive(Clone,Debug)]
+pub struct SystemTimeError(Duration);
+
+impl SystemTime
+{
+	pub const UNIX_EPOCH: SystemTime = UNIX_EPOCH;
+
+	pub fn now() -> SystemTime {
+		SystemTime( ::syscalls::threads::get_time() )
+	}
+
+	/// Time elapsed between `earlier` and `self` (an error holding the difference if `earlier` is later)
+	pub fn duration_since(&self, earlier: SystemTime) -> Result<Duration, SystemTimeError> {
+		if earlier.0 <= self.0 {
+			Ok( Duration::from_millis(self.0 - earlier.0) )
+		}
+		else {
+			Err( SystemTimeError(Duration::from_millis(earlier.0 - self.0)) )
+		}
+	}
+	/// Time elapsed since this time was obtained (an error if the clock has since been set backwards)
+	pub fn elapsed(&self) -> Result<Duration, SystemTimeError> {
+		SystemTime::now().duration_since(*self)
+	}
+}
+
+impl SystemTimeError
+{
+	/// Amount by which the "earlier" time was later
+	pub fn duration(&self) -> Duration {
+		self.0
+	}
+}
+impl ::core::fmt::Display for SystemTimeError {
+	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+		write!(f, "second time provided was later than self")
+	}
+}
+
+/// Convert a duration to milliseconds (truncating, wall-clock times aren't used as deadlines)
+fn to_millis(d: Duration) -> u64 {
+	d.as_secs() * 1000 + d.subsec_millis() as u64
+}
+
+impl ops::Add<Duration> for SystemTime {
+	type Output = SystemTime;
+	fn add(self, d: Duration) -> SystemTime {
+		SystemTime(self.0 + to_millis(d))
+	}
+}
+impl ops::AddAssign<Duration> for SystemTime {
+	fn add_assign(&mut self, d: Duration) {
+		self.0 += to_millis(d);
+	}
+}
+impl ops::Sub<Duration> for SystemTime {
+	type Output = SystemTime;
+	fn sub(self, d: Duration) -> SystemTime {
+		SystemTime(self.0 - to_millis(d))
+	}
+}
+impl ops::SubAssign<Duration> for SystemTime {
+	fn sub_assign(&mut self, d: Duration) {
+		self.0 -= to_millis(d);
+	}
+}
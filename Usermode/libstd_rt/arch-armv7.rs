@@ -29,6 +29,16 @@ impl Backtrace {
 		let rs = aeabi_unwind::UnwindState::new_cur();
 		Backtrace(rs)
 	}
+	/// Backtrace of the code interrupted by an exception
+	pub fn from_exception(frame: &::syscalls::threads::ExceptionFrame) -> Backtrace {
+		let mut regs = [0u32; 16];
+		for (d, s) in Iterator::zip( regs.iter_mut(), frame.regs.iter() ) {
+			*d = *s as u32;
+		}
+		regs[13] = frame.sp as u32;
+		regs[15] = frame.ip as u32;
+		Backtrace(aeabi_unwind::UnwindState::from_regs(regs))
+	}
 }
 
 impl ::core::fmt::Debug for Backtrace {
@@ -3,6 +3,9 @@ impl Backtrace {
 	pub fn new() -> Backtrace {
 		Backtrace(0)
 	}
+	pub fn from_exception(_frame: &::syscalls::threads::ExceptionFrame) -> Backtrace {
+		Backtrace(0)
+	}
 }
 impl ::core::fmt::Debug for Backtrace {
 	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
@@ -10,6 +10,10 @@ impl Backtrace {
 			};
 		Backtrace(bp)
 	}
+	/// Backtrace of the code interrupted by an exception
+	pub fn from_exception(frame: &::syscalls::threads::ExceptionFrame) -> Backtrace {
+		Backtrace(frame.fp)
+	}
 }
 
 impl ::core::fmt::Debug for Backtrace {
@@ -51,6 +51,46 @@ fn begin_panic_fmt(msg: &::core::fmt::Arguments, file_line: (&str, u32)) -> ! {
 	::syscalls::threads::exit(0xFFFF_FFFF);
 }
 
+/// Exception handler registered by `std` for each thread
+///
+/// Notifications and breakpoints resume the thread, faults are reported like a panic and terminate the process.
+pub extern "C" fn exception_handler(frame: &mut ::syscalls::threads::ExceptionFrame) -> !
+{
+	use syscalls::threads::ExceptionKind;
+	match ExceptionKind::try_from(frame.kind)
+	{
+	Ok(ExceptionKind::Notification) => {},
+	Ok(ExceptionKind::Breakpoint) => {
+		kernel_log!("Breakpoint at {:#x}", frame.ip);
+		},
+	Ok(kind) => {
+		// A page fault close to the stack pointer is (most likely) a guard page hit
+		let is_stack_overflow = match kind
+			{
+			ExceptionKind::PageFault => {
+				let dist = if frame.address < frame.sp { frame.sp - frame.address } else { frame.address - frame.sp };
+				dist < 0x1000
+				},
+			_ => false,
+			};
+		if is_stack_overflow {
+			kernel_log!("PANIC: thread has overflowed its stack (address {:#x}, SP={:#x}, IP={:#x})", frame.address, frame.sp, frame.ip);
+		}
+		else {
+			kernel_log!("PANIC: {:?} at IP={:#x} (code {:#x}, address {:#x})", kind, frame.ip, frame.code, frame.address);
+		}
+		let bt = arch::Backtrace::from_exception(frame);
+		kernel_log!("- IP={:#x}, Backtrace: {:?}", frame.ip, bt);
+		::syscalls::threads::exit(0xFFFF_FFFF);
+		},
+	Err(v) => {
+		kernel_log!("PANIC: Unknown exception kind {} at IP={:#x}", v, frame.ip);
+		::syscalls::threads::exit(0xFFFF_FFFF);
+		},
+	}
+	::syscalls::threads::exception_return(frame)
+}
+
 #[panic_handler]
 pub extern fn rust_begin_unwind(info: &::core::panic::PanicInfo) -> ! {
 	let file_line = match info.location()
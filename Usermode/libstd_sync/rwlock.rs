@@ -4,11 +4,14 @@
 //! Reader-writer lock
 use core::ops;
 use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize,Ordering};
 use mutex::Mutex;
 
 pub struct RwLock<T: ?Sized>
 {
 	int: ::mutex::Mutex<Inner>,
+	/// Futex word, incremented on every release that could unblock a waiter
+	seq: AtomicUsize,
 	data: UnsafeCell<T>,
 }
 unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
@@ -18,6 +21,8 @@ struct Inner
 {
 	readers: usize,
 	writers: usize,
+	/// Number of threads sleeping on `seq`
+	waiters: usize,
 }
 
 impl<T> RwLock<T>
@@ -27,7 +32,9 @@ impl<T> RwLock<T>
 			int: Mutex::new(Inner {
 				readers: 0,
 				writers: 0,
+				waiters: 0,
 				}),
+			seq: AtomicUsize::new(0),
 			data: UnsafeCell::new(v),
 			}
 	}
@@ -36,34 +43,38 @@ impl<T> RwLock<T>
 impl<T: ?Sized> RwLock<T>
 {
 	pub fn write(&self) -> Write<T> {
-		loop {
-			let mut lh = self.int.lock();
-			if lh.readers > 0 {
-				panic!("TODO: RwLock::write - wait for readers to release");
-			}
-			else {
-				lh.writers += 1;
-				if lh.writers > 1 {
-					panic!("TODO: RwLock::write - wait for other writer to release");
-				}
-				return Write { p: self };
-			}
+		let mut lh = self.int.lock();
+		while lh.readers > 0 || lh.writers > 0 {
+			lh = self.wait(lh);
 		}
+		lh.writers += 1;
+		Write { p: self }
 	}
 	pub fn read(&self) -> Read<T> {
-		loop {
-			let mut lh = self.int.lock();
-			if lh.readers > 0 {
-				lh.readers += 1;
-				return Read { p: self };
-			}
-			else if lh.writers > 0 {
-				panic!("TODO: RwLock::read - wait for writer to release");
-			}
-			else {
-				lh.readers += 1;
-				return Read { p: self };
-			}
+		let mut lh = self.int.lock();
+		while lh.writers > 0 {
+			lh = self.wait(lh);
+		}
+		lh.readers += 1;
+		Read { p: self }
+	}
+
+	/// Release the internal lock and sleep until a release happens, then re-acquire
+	fn wait<'a>(&'a self, mut lh: ::mutex::HeldMutex<'a, Inner>) -> ::mutex::HeldMutex<'a, Inner> {
+		// Sample the sequence number with the lock held, so a release after unlocking prevents the sleep
+		let seq = self.seq.load(Ordering::Acquire);
+		lh.waiters += 1;
+		drop(lh);
+		::syscalls::sync::futex_wait(&self.seq, seq);
+		let mut lh = self.int.lock();
+		lh.waiters -= 1;
+		lh
+	}
+	/// Wake all waiters (called with the internal lock held, after the state has been updated)
+	fn wake_waiters(&self, lh: &::mutex::HeldMutex<Inner>) {
+		if lh.waiters > 0 {
+			self.seq.fetch_add(1, Ordering::Release);
+			::syscalls::sync::futex_wake(&self.seq, !0);
 		}
 	}
 
@@ -87,8 +98,9 @@ impl<'a, T: 'a + ?Sized> ops::Drop for Read<'a, T> {
 	fn drop(&mut self) {
 		let mut lh = self.p.int.lock();
 		lh.readers -= 1;
-		if lh.readers == 0 && lh.writers > 0 {
-			panic!("TODO: rwlock::Read::drop - Wake writers");
+		if lh.readers == 0 {
+			// Only writers can be waiting on readers
+			self.p.wake_waiters(&lh);
 		}
 	}
 }
@@ -113,15 +125,8 @@ impl<'a, T: 'a + ?Sized> ops::Drop for Write<'a, T> {
 	fn drop(&mut self) {
 		let mut lh = self.p.int.lock();
 		lh.writers -= 1;
-		if lh.writers > 0 {
-			panic!("TODO: rwlock::Write::drop - Wake writers");
-		}
-		else if lh.readers > 0 {
-			panic!("TODO: rwlock::Write::drop - Wake readers");
-		}
-		else {
-			// Uncontended release
-		}
+		// Wake both readers and writers, whoever gets the lock first wins
+		self.p.wake_waiters(&lh);
 	}
 }
 
@@ -0,0 +1,63 @@
+// Tifflin OS - System Calls
+// - By John Hodge (thePowersGang)
+//
+// devices.rs
+/// Device management (storage volume events)
+
+pub use ::values::VolumeEvent;
+pub use ::values::VolumeEventKind;
+
+/// Listener for storage volume arrival/removal events
+pub struct VolumeEvents(::ObjectHandle);
+
+impl ::Object for VolumeEvents
+{
+	const CLASS: u16 = ::values::CLASS_DEV_VOLUMEEVENTS;
+	fn class() -> u16 { Self::CLASS }
+	fn from_handle(handle: ::ObjectHandle) -> Self {
+		VolumeEvents(handle)
+	}
+	fn into_handle(self) -> ::ObjectHandle {
+		self.0
+	}
+	fn handle(&self) -> &::ObjectHandle {
+		&self.0
+	}
+
+	type Waits = VolumeEventsWaits;
+	fn get_wait(&self, waits: Self::Waits) -> ::values::WaitItem {
+		self.0.get_wait(waits.0)
+	}
+	fn check_wait(&self, wi: &::values::WaitItem) -> Self::Waits {
+		VolumeEventsWaits(wi.flags)
+	}
+}
+define_waits!{ VolumeEventsWaits => (
+	pending:has_pending = ::values::EV_DEV_VOLUMEEVENTS_PENDING,
+)}
+
+impl VolumeEvents
+{
+	/// Open a new listener (only events after this call are seen)
+	pub fn open() -> Result<VolumeEvents, u32> {
+		// SAFE: Zero-operand syscall
+		::ObjectHandle::new( unsafe { syscall!(DEV_VOLUME_EVENTS) as usize } )
+			.map(|v| VolumeEvents(v))
+	}
+
+	/// Read the next event (if any are waiting)
+	pub fn read(&self) -> Option<VolumeEvent> {
+		let mut ev = VolumeEvent::default();
+		// SAFE: Syscall with valid pointer
+		if unsafe { self.0.call_1(::values::DEV_VOLUMEEVENTS_READ, &mut ev as *mut _ as usize) } != 0 {
+			Some(ev)
+		}
+		else {
+			None
+		}
+	}
+
+	pub fn wait_pending(&self) -> ::values::WaitItem {
+		self.0.get_wait(::values::EV_DEV_VOLUMEEVENTS_PENDING)
+	}
+}
@@ -42,9 +42,8 @@ impl Group
 		match super::ObjectHandle::new( unsafe { syscall!(GUI_NEWGROUP, name.as_ptr() as usize, name.len()) } as usize )
 		{
 		Ok(rv) => Ok( Group(rv) ),
-		Err(code) => {
-			panic!("TODO: Error code {}", code);
-			},
+		// Session limit reached
+		Err(_code) => Err( () ),
 		}
 	}
 	
@@ -98,9 +97,8 @@ impl Window
 		match super::ObjectHandle::new( unsafe { syscall!(GUI_NEWWINDOW, name.as_ptr() as usize, name.len()) } as usize )
 		{
 		Ok(rv) => Ok( Window(rv) ),
-		Err(code) => {
-			panic!("TODO: Error code {}", code);
-			},
+		// No window group, or the window limit was reached
+		Err(_code) => Err( () ),
 		}
 	}
 	
@@ -84,6 +84,7 @@ pub mod threads;
 pub mod sync;
 pub mod ipc;
 pub mod net;
+pub mod devices;
 
 pub use values::WaitItem;
 
@@ -318,6 +319,20 @@ pub fn debug_value<S: ?Sized+AsRef<[u8]>>(msg: &S, v: usize) {
 }
 
 
+#[inline]
+/// Fill a buffer with random bytes (NOT cryptographically secure)
+pub fn get_random(buf: &mut [u8]) {
+	// SAFE: Syscall
+	unsafe { syscall!(CORE_GETRANDOM, buf.as_mut_ptr() as usize, buf.len(), 0); }
+}
+/// Obtain a random value for address-space layout randomisation (zero if ASLR is disabled)
+pub fn get_aslr_random() -> u64 {
+	let mut buf = [0; 8];
+	// SAFE: Syscall
+	unsafe { syscall!(CORE_GETRANDOM, buf.as_mut_ptr() as usize, buf.len(), ::values::GETRANDOM_ASLR as usize); }
+	buf.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64)
+}
+
 pub use values::TEXTINFO_KERNEL;
 
 #[inline]
@@ -66,18 +66,32 @@ impl<'a, T: 'a> ops::Drop for HeldMutex<'a, T>
 	}
 }
 
+/// Sleep while the value at `addr` is `sleep_if_val`, until woken by `futex_wake`
+///
+/// NOTE: Can return spuriously, callers must re-check their condition
 pub fn futex_wait(addr: &AtomicUsize, sleep_if_val: usize)
+{
+	futex_wait_until(addr, sleep_if_val, !0);
+}
+/// Sleep while the value at `addr` is `sleep_if_val`, with an absolute timeout (in system ticks, `!0` for none)
+///
+/// Returns `false` if the timeout expired before a wake
+pub fn futex_wait_until(addr: &AtomicUsize, sleep_if_val: usize, wake_time: u64) -> bool
 {
 	// SAFE: Assumed
-	unsafe {
-		syscall!(CORE_FUTEX_SLEEP, addr as *const _ as usize, sleep_if_val);
-	}
+	let rv = unsafe {
+		#[cfg(target_pointer_width="64")]
+		{ syscall!(CORE_FUTEX_SLEEP, addr as *const _ as usize, sleep_if_val, wake_time as usize) }
+		#[cfg(target_pointer_width="32")]
+		{ syscall!(CORE_FUTEX_SLEEP, addr as *const _ as usize, sleep_if_val, (wake_time & 0xFFFFFFFF) as usize, (wake_time >> 32) as usize) }
+		};
+	rv != ::values::FUTEX_TIMED_OUT as u64
 }
-pub fn futex_wake(addr: &AtomicUsize, num_to_wake: usize)
+/// Wake up to `num_to_wake` threads waiting on `addr`, returning the number woken
+pub fn futex_wake(addr: &AtomicUsize, num_to_wake: usize) -> usize
 {
 	// SAFE: Assumed
 	unsafe {
-		syscall!(CORE_FUTEX_WAKE, addr as *const _ as usize, num_to_wake);
+		syscall!(CORE_FUTEX_WAKE, addr as *const _ as usize, num_to_wake) as usize
 	}
 }
-
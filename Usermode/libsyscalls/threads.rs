@@ -19,19 +19,90 @@ impl ::core::fmt::Display for RecvObjectError {
 	}
 }
 
+/// Start a new thread in this process, executing at `ip` with the stack pointer `sp` and TLS base `tlsbase`
+///
+/// UNSAFE: The entrypoint and stack must be valid for the lifetime of the thread
 #[inline]
-pub unsafe fn start_thread(ip: usize, sp: usize, tlsbase: usize) -> Result<u32, u32> {
-	::to_result( syscall!(CORE_STARTTHREAD, ip, sp, tlsbase) as usize )
+pub unsafe fn start_thread(ip: usize, sp: usize, tlsbase: usize) -> Result<Thread, u32> {
+	::ObjectHandle::new( syscall!(CORE_STARTTHREAD, ip, sp, tlsbase) as usize ).map(|h| Thread(h))
 }
 #[inline]
-pub fn exit_thread() -> ! {
+pub fn exit_thread(status: u32) -> ! {
 	// SAFE: Syscall
 	unsafe {
-		syscall!(CORE_EXITTHREAD);
+		syscall!(CORE_EXITTHREAD, status as usize);
 		::core::intrinsics::unreachable();
 	}
 }
 
+/// Set the current thread's TLS base (see `get_tls_base`)
+///
+/// NOTE: On amd64, the first word of the TLS block must point to the block itself
+#[inline]
+pub fn set_tls_base(base: usize) {
+	// SAFE: Syscall
+	unsafe {
+		syscall!(CORE_SETTLS, base);
+	}
+}
+/// Get the current thread's TLS base
+///
+/// UNSAFE: On amd64 this reads the self pointer from the TLS block, so a base must have been set
+#[inline]
+pub unsafe fn get_tls_base() -> usize {
+	let rv: usize;
+	#[cfg(target_arch="x86_64")]
+	asm!("mov %fs:0, $0" : "=r" (rv));
+	#[cfg(target_arch="arm")]
+	asm!("mrc p15,0, $0, c13,c0,3" : "=r" (rv));
+	#[cfg(target_arch="aarch64")]
+	asm!("mrs $0, TPIDRRO_EL0" : "=r" (rv));
+	rv
+}
+
+define_waits!{ ThreadWaits => (
+	terminate:get_terminate = ::values::EV_THREAD_TERMINATED,
+)}
+/// Handle to a thread in this process
+pub struct Thread(::ObjectHandle);
+impl Thread {
+	/// Exit status of the thread (`None` if still running)
+	#[inline]
+	pub fn get_status(&self) -> Option<u32> {
+		// SAFE: Syscall
+		let rv = unsafe { self.0.call_0(::values::CORE_THREAD_GETSTATUS) };
+		if rv == ::values::STATUS_RUNNING {
+			None
+		}
+		else {
+			Some(rv as u32)
+		}
+	}
+
+	/// Queue a notification for the thread's exception handler (see `set_exception_handler`)
+	#[inline]
+	pub fn notify(&self, value: u32) {
+		// SAFE: Syscall
+		unsafe { self.0.call_1(::values::CORE_THREAD_NOTIFY, value as usize); }
+	}
+
+	#[inline]
+	pub fn wait_terminate(&self) -> ::values::WaitItem {
+		self.0.get_wait(::values::EV_THREAD_TERMINATED)
+	}
+}
+impl ::Object for Thread {
+	const CLASS: u16 = ::values::CLASS_CORE_THREAD;
+	fn class() -> u16 { Self::CLASS }
+	fn from_handle(handle: ::ObjectHandle) -> Self {
+		Thread(handle)
+	}
+	fn into_handle(self) -> ::ObjectHandle { self.0 }
+	fn handle(&self) -> &::ObjectHandle { &self.0 }
+	
+	type Waits = ThreadWaits;
+}
+
 // Object 0 : This process
 /// Current process handle
 pub static S_THIS_PROCESS: ThisProcess = ThisProcess;//( ::ObjectHandle(0) );
@@ -93,6 +164,20 @@ pub fn start_process(name: &str,  clone_start: usize, clone_end: usize) -> Resul
 	}
 }
 
+/// Start a new process sharing (copy-on-write) all of this process's memory
+///
+/// The new process only starts executing once `ProtoProcess::start` is called.
+#[inline]
+pub fn fork_process(name: &str) -> Result<ProtoProcess,()> {
+	// SAFE: Syscall
+	let rv = unsafe { syscall!(CORE_FORKPROCESS, name.as_ptr() as usize, name.len()) };
+	match ::ObjectHandle::new(rv as usize)
+	{
+	Ok(v) => Ok( ProtoProcess(v) ),
+	Err(_e) => Err( () ),
+	}
+}
+
 pub struct ProtoProcess(::ObjectHandle);
 impl ::Object for ProtoProcess {
 	const CLASS: u16 = ::values::CLASS_CORE_PROTOPROCESS;
@@ -113,11 +198,25 @@ impl ProtoProcess
 {
 	#[inline]
 	/// Send an object to the child process. `tag` is a up-to 6 byte string naming the object for the child.
+	///
+	/// If the child's handle limit has been reached, the object is dropped.
 	pub fn send_obj<O: ::Object>(&self, tag: &str, obj: O) {
 		assert!(tag.len() <= 6);
 		let oh = obj.into_handle().into_raw();
 		// SAFE: Syscall
-		unsafe { self.0.call_2l(::values::CORE_PROTOPROCESS_SENDOBJ, ::values::FixedStr8::from(tag).into(), oh as usize); }
+		let rv = unsafe { self.0.call_2l(::values::CORE_PROTOPROCESS_SENDOBJ, ::values::FixedStr8::from(tag).into(), oh as usize) };
+		if ::to_result(rv as usize).is_err() {
+			// The kernel left the object with this process, release it
+			drop( ::ObjectHandle(oh) );
+		}
+	}
+
+	/// Lower one of the child's resource limits (it starts with this process's limits), returns the resulting limit
+	#[inline]
+	pub fn lower_limit(&self, resource: ResourceLimit, value: usize) -> usize {
+		let resource: u32 = resource.into();
+		// SAFE: Syscall
+		unsafe { self.0.call_2(::values::CORE_PROTOPROCESS_LOWERLIMIT, resource as usize, value) as usize }
 	}
  
  	#[inline]
@@ -133,11 +232,24 @@ define_waits!{ ProcessWaits => (
 )}
 pub struct Process(::ObjectHandle);
 impl Process {
+	/// Request that the process terminate (its exit status becomes `EXITSTATUS_KILLED` if it hasn't already exited)
 	#[inline]
 	pub fn terminate(&self) {
 		// SAFE: Syscall
 		unsafe { self.0.call_0(::values::CORE_PROCESS_KILL); }
 	}
+	/// Exit status of the process (`None` if still running)
+	#[inline]
+	pub fn get_status(&self) -> Option<u32> {
+		// SAFE: Syscall
+		let rv = unsafe { self.0.call_0(::values::CORE_PROCESS_GETSTATUS) };
+		if rv == ::values::STATUS_RUNNING {
+			None
+		}
+		else {
+			Some(rv as u32)
+		}
+	}
 
 	#[inline]
 	pub fn wait_terminate(&self) -> ::values::WaitItem {
@@ -166,6 +278,111 @@ pub fn exit(code: u32) -> ! {
 }
 
 pub use values::WaitItem;
+pub use values::{EXITSTATUS_KILLED,EXITSTATUS_FAULT};
+pub use values::{ExceptionFrame,ExceptionKind};
+pub use values::{ResourceLimit,LIMIT_UNLIMITED};
+pub use values::{PRIORITY_DEFAULT,PRIORITY_MAX};
+pub use values::{ProcessInfo,ProcessState,ThreadInfo,ThreadState};
+
+/// Obtain the monotonic time (milliseconds since boot), the timebase used by `wait`
+#[inline]
+pub fn get_ticks() -> u64 {
+	// SAFE: Syscall
+	unsafe {
+		syscall!(CORE_GETTICKS)
+	}
+}
+
+/// Obtain the wall-clock time (milliseconds since 1970-01-01 00:00:00 UTC)
+#[inline]
+pub fn get_time() -> u64 {
+	// SAFE: Syscall
+	unsafe {
+		syscall!(CORE_GETTIME)
+	}
+}
+/// Set the wall-clock time (milliseconds since 1970-01-01 00:00:00 UTC)
+///
+/// Only permitted for init (PID 0).
+#[inline]
+pub fn set_time(ms: u64) -> Result<(),()> {
+	// SAFE: Syscall
+	let rv = unsafe {
+		#[cfg(target_pointer_width="64")]
+		{ syscall!(CORE_SETTIME, ms as usize) }
+		#[cfg(target_pointer_width="32")]
+		{ syscall!(CORE_SETTIME, (ms & 0xFFFFFFFF) as usize, (ms >> 32) as usize) }
+		};
+	::to_result(rv as usize).map(|_| ()).map_err(|_| ())
+}
+
+/// Set the current thread's exception handler, returning the previous handler's address (zero if none)
+///
+/// The handler is called (on the stack ending at `stack_top`, or below the interrupted stack pointer if zero) when
+/// the thread faults or receives a notification, and must end by calling `exception_return`. Faults while the handler
+/// is running terminate the process, and notifications wait until it returns.
+///
+/// UNSAFE: The stack must be valid (and unused by anything else) while the handler runs
+#[inline]
+pub unsafe fn set_exception_handler(handler: Option<extern "C" fn(&mut ExceptionFrame)->!>, stack_top: usize) -> usize {
+	let entry = match handler
+		{
+		Some(f) => f as usize,
+		None => 0,
+		};
+	syscall!(CORE_SETEXCEPTIONHANDLER, entry, stack_top) as usize
+}
+/// Leave an exception handler, resuming the thread with the (possibly modified) state in `frame`
+#[inline]
+pub fn exception_return(frame: &ExceptionFrame) -> ! {
+	// SAFE: Syscall, the kernel sanitises the restored state
+	unsafe {
+		syscall!(CORE_EXCEPTIONRETURN, frame as *const _ as usize);
+		::core::intrinsics::unreachable();
+	}
+}
+
+/// Lower one of this process's resource limits (limits can't be raised), returns the resulting limit
+///
+/// Passing `LIMIT_UNLIMITED` leaves the limit unchanged, and just returns it.
+#[inline]
+pub fn lower_limit(resource: ResourceLimit, value: usize) -> usize {
+	let resource: u32 = resource.into();
+	// SAFE: Syscall
+	unsafe {
+		syscall!(CORE_LOWERLIMIT, resource as usize, value) as usize
+	}
+}
+
+/// Set the current thread's scheduling priority (higher runs first, clamped to `PRIORITY_MAX`)
+///
+/// Returns the previous priority
+#[inline]
+pub fn set_priority(priority: u32) -> u32 {
+	// SAFE: Syscall
+	unsafe {
+		syscall!(CORE_SETPRIORITY, priority as usize) as u32
+	}
+}
+
+/// Obtain information on processes with a PID of at least `start_pid` (in PID order), returns the number of entries filled
+///
+/// To list all processes, call again starting after the last returned PID until zero is returned.
+#[inline]
+pub fn list_processes(start_pid: u32, buf: &mut [ProcessInfo]) -> usize {
+	// SAFE: Syscall, buffer is valid for writes
+	unsafe {
+		syscall!(CORE_LISTPROCESSES, start_pid as usize, buf.as_mut_ptr() as usize, buf.len()) as usize
+	}
+}
+/// Obtain information on threads with a TID of at least `start_tid` (in TID order), returns the number of entries filled
+#[inline]
+pub fn list_threads(start_tid: u32, buf: &mut [ThreadInfo]) -> usize {
+	// SAFE: Syscall, buffer is valid for writes
+	unsafe {
+		syscall!(CORE_LISTTHREADS, start_tid as usize, buf.as_mut_ptr() as usize, buf.len()) as usize
+	}
+}
 
 /// Blocks the current thread on the passed set of objects.
 /// 
@@ -173,6 +173,12 @@ impl<'a, D: 'a + Decorator> Window<'a, D>
 	pub fn rerender(&mut self)  {
 		WindowTrait::rerender(self)
 	}
+	/// Clear and re-draw the entire window (for when element contents are changed outside of event handling)
+	pub fn redraw_all(&mut self) {
+		self.update_surface_size();
+		WindowTrait::rerender(self);
+		self.win.redraw();
+	}
 
 	/// Obtain the states of all "modifier" keys
 	pub fn get_modifiers(&self) -> &ModifierStates {
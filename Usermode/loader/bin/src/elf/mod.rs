@@ -38,6 +38,8 @@ pub struct ElfModuleHandle<R: Read+Seek>
 {
 	file: R,
 	header: Header,
+	/// Offset added to all addresses in the file (non-zero only for position-independent executables)
+	load_bias: usize,
 }
 
 pub fn load_executable(mut fh: File) -> Result<ElfModuleHandle<File>,Error>
@@ -64,6 +66,7 @@ pub fn load_executable(mut fh: File) -> Result<ElfModuleHandle<File>,Error>
 	Ok(ElfModuleHandle{
 		file: fh,
 		header: hdr,
+		load_bias: 0,
 		})
 }
 	
@@ -95,10 +98,24 @@ impl<R: Read+Seek> ElfModuleHandle<R>
 impl<R: Read+Seek> ElfModuleHandle<R>
 {
 	pub fn get_entrypoint(&self) -> usize {
-		self.header.e_entry
+		self.load_bias + self.header.e_entry
+	}
+	/// Returns true if the executable can be loaded at any address (ET_DYN)
+	pub fn is_position_independent(&self) -> bool {
+		match self.header.object_type
+		{
+		ObjectType::Dyn => true,
+		_ => false,
+		}
+	}
+	/// Set the address that a position-independent executable is loaded at (must be called before loading)
+	pub fn set_load_bias(&mut self, bias: usize) {
+		assert!(self.is_position_independent(), "Setting load bias on a fixed-address executable");
+		self.load_bias = bias;
 	}
 	pub fn load_segments(&mut self) -> LoadSegments<R> {
-		LoadSegments( self.phents() )
+		let bias = self.load_bias;
+		LoadSegments( self.phents(), bias )
 	}
 	
 	pub fn do_relocation(&mut self) -> Result<(),Error> {
@@ -115,24 +132,25 @@ impl<R: Read+Seek> ElfModuleHandle<R>
 		let (mut rel_addr, mut rel_sz, mut rel_esz) = Default::default();
 		let (mut rela_addr, mut rela_sz, mut rela_esz) = Default::default();
 		let (mut plt_addr, mut plt_sz, mut plt_type) = (None, None, RelocType::RelA);
+		let bias = self.load_bias;
 		for ent in self.dyntab(pt_dyn.p_offset, pt_dyn.p_filesz)
 		{
 			match ent
 			{
-			DtEnt::SymTab(addr) => symtab_addr = Some(addr as *const _),
+			DtEnt::SymTab(addr) => symtab_addr = Some((bias + addr as usize) as *const _),
 			DtEnt::SymEntSz(count) => symtab_esz = Some(count),
-			DtEnt::StrTab(addr) => strtab_addr = Some(addr as *const _),
+			DtEnt::StrTab(addr) => strtab_addr = Some((bias + addr as usize) as *const _),
 			DtEnt::StrSz(count) => strtab_len = Some(count),
 			
-			DtEnt::RelA(addr) => rela_addr = Some(addr),
+			DtEnt::RelA(addr) => rela_addr = Some((bias + addr as usize) as *const u8),
 			DtEnt::RelASz(size) => rela_sz = Some(size),
 			DtEnt::RelAEnt(size) => rela_esz = Some(size),
 			
-			DtEnt::Rel(addr) => rel_addr = Some(addr),
+			DtEnt::Rel(addr) => rel_addr = Some((bias + addr as usize) as *const u8),
 			DtEnt::RelSz(size) => rel_sz = Some(size),
 			DtEnt::RelEnt(size) => rel_esz = Some(size),
 			
-			DtEnt::Plt(addr) => plt_addr = Some(addr),
+			DtEnt::Plt(addr) => plt_addr = Some((bias + addr as usize) as *const u8),
 			DtEnt::PltRel(ty) => plt_type = match ty {
 				 7 => RelocType::RelA,	// DT_RELA
 				17 => RelocType::Rel,	// DT_REL
@@ -198,7 +216,7 @@ impl<R: Read+Seek> ElfModuleHandle<R>
 		kernel_log!("Applying relocations:");
 		{
 			let rs = RelocationState {
-				base: 0,
+				base: self.load_bias,
 				machine: self.header.machine,
 				strtab: strtab,
 				symtab: symtab,
@@ -266,7 +284,7 @@ impl<'a> RelocationState<'a>
 			},
 		R_X86_64_PC32 => {
 			let (addr,_size) = self.get_symbol_r(r.sym as usize)?;
-			self.relocate_32(r.addr, |val| (addr + r.addend.unwrap_or(val as usize) - r.addr) as u32);
+			self.relocate_32(r.addr, |val| (addr + r.addend.unwrap_or(val as usize) - (self.base + r.addr)) as u32);
 			},
 		R_X86_64_GOT32 => todo!("apply_reloc_x86_64 - GOT32"),
 		R_X86_64_PLT32 => todo!("apply_reloc_x86_64 - PLT32"),
@@ -320,7 +338,7 @@ impl<'a> RelocationState<'a>
 		// SAFE: (uncheckable) Assumes that the file is valid
 		unsafe {
 			// TODO: Ensure that address is valid (i.e. within the newly loaded sections)
-			let ptr = addr as *mut u64;
+			let ptr = (self.base + addr) as *mut u64;
 			// TODO: Ensure that endianness is native endian
 			*ptr = fcn(*ptr);
 		}
@@ -329,7 +347,7 @@ impl<'a> RelocationState<'a>
 		// SAFE: (uncheckable) Assumes that the file is valid
 		unsafe {
 			// TODO: Ensure that address is valid
-			let ptr = addr as *mut u32;
+			let ptr = (self.base + addr) as *mut u32;
 			// TODO: Ensure that endianness is native endian
 			*ptr = fcn(*ptr);
 		}
@@ -564,7 +582,7 @@ impl<'a, R: 'a+Read> Iterator for DtEntIterator<'a, R>
 	}
 }
 
-pub struct LoadSegments<'a, R: 'a + Read>(PhEntIterator<'a,R>);
+pub struct LoadSegments<'a, R: 'a + Read>(PhEntIterator<'a,R>, usize);
 impl<'a, R: 'a + Read> ::load::SegmentIterator<R> for LoadSegments<'a, R>
 {
 	fn get_file(&self) -> &R { self.0.file }
@@ -578,7 +596,7 @@ impl<'a, R: 'a+Read> ::std::iter::Iterator for LoadSegments<'a, R> {
 			if e.p_type == PT_LOAD
 			{
 				return Some(Segment {
-					load_addr: e.p_paddr,
+					load_addr: self.1 + e.p_paddr,
 					file_addr: e.p_offset,
 					file_size: e.p_filesz,
 					mem_size: e.p_memsz,
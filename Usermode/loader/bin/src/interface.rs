@@ -131,11 +131,8 @@ fn new_process_entry() -> !
 	}
 	kernel_log!("args = {:?}", &*args);
 	
-	// TODO: Switch stacks into a larger dynamically-allocated stack
-	// SAFE: Entrypoint assumed to have this signature
-	let ep: fn(&[&::std::ffi::OsStr]) -> ! = unsafe { ::std::mem::transmute(entrypoint) };
-	kernel_log!("Calling entry {:p} for {:?}", ep as *const (), process_name);
-	ep(&args);
+	kernel_log!("Calling entry {:#x} for {:?}", entrypoint, process_name);
+	::call_entrypoint(entrypoint, &args);
 }
 
 
@@ -33,6 +33,23 @@ const PAGE_SIZE: usize = 0x2000;
 #[cfg(not(arch="armv7"))]
 const PAGE_SIZE: usize = 0x1000;
 
+/// Base address and randomisation range for position-independent executables
+#[cfg(target_pointer_width="64")]
+const PIE_REGION: (usize,usize) = (0x20_0000, 0x10_0000_0000);
+#[cfg(target_pointer_width="32")]
+const PIE_REGION: (usize,usize) = (0x20_0000, 0x0800_0000);
+/// Region that the initial stack is placed within (between the heap and the loader)
+#[cfg(target_arch="x86_64")]
+const STACK_REGION: (usize,usize) = (0x7000_0000_0000, 0x7F00_0000_0000);
+#[cfg(not(target_arch="x86_64"))]
+const STACK_REGION: (usize,usize) = (0x7000_0000, 0x7800_0000);
+const STACK_SIZE: usize = 0x4_0000;
+
+extern "C" {
+	/// Switch to the provided stack and call `entry(args)`, exiting the process if it returns
+	fn call_with_stack(entry: usize, args_ptr: *const &::std::ffi::OsStr, args_len: usize, stack_top: usize) -> !;
+}
+
 // Main: This is the initial boot entrypoint
 // NOTE: If you're looking for the new process entrypoint, see interface.rs
 #[no_mangle]
@@ -73,13 +90,27 @@ pub extern "C" fn loader_main(cmdline: *mut u8, cmdline_len: usize) -> !
 	}
 	kernel_log!("args = {:?}", &*args);
 	
-	// TODO: Switch stacks into a larger dynamically-allocated stack
-	// SAFE: Entrypoint assumed to have this format... will likely crash if it isn't
-	let ep: fn(&[&::std::ffi::OsStr]) = unsafe { ::std::mem::transmute(entrypoint) };
-	kernel_log!("Calling entry {:p} for INIT {:?}", ep as *const (), init_path);
-	ep(&args);
-	kernel_log!("User entrypoint returned");
-	::syscalls::threads::exit(!0);
+	kernel_log!("Calling entry {:#x} for INIT {:?}", entrypoint, init_path);
+	call_entrypoint(entrypoint, &args);
+}
+
+/// Pick a random (page-aligned) offset below `range`, zero if ASLR is disabled
+fn aslr_offset(range: usize) -> usize
+{
+	(::syscalls::get_aslr_random() as usize % (range / PAGE_SIZE)) * PAGE_SIZE
+}
+
+/// Call the entrypoint of a loaded executable on a new (randomly placed) stack
+fn call_entrypoint(entrypoint: usize, args: &[&::std::ffi::OsStr]) -> !
+{
+	// NOTE: The page below the stack is left unmapped as a guard page
+	let stack_base = STACK_REGION.0 + PAGE_SIZE + aslr_offset(STACK_REGION.1 - STACK_REGION.0 - STACK_SIZE - PAGE_SIZE);
+	kernel_log!("- stack = {:#x}+{:#x}", stack_base, STACK_SIZE);
+	// SAFE: Allocating in the region reserved for the stack. Entrypoint assumed to have the `fn(&[&OsStr])` signature
+	unsafe {
+		::syscalls::memory::allocate(stack_base, STACK_SIZE / PAGE_SIZE).expect("Unable to allocate stack");
+		call_with_stack(entrypoint, args.as_ptr(), args.len(), stack_base + STACK_SIZE)
+	}
 }
 
 struct FixedVec<T> {
@@ -129,6 +160,11 @@ fn load_binary(path: &::std::ffi::OsStr, fh: ::syscalls::vfs::File) -> usize
 			panic!("ERROR: Binary '{:?}' cannot be loaded: {:?}", ::std::ffi::OsStr::new(path), e);
 			},
 		};
+	if handle.is_position_independent() {
+		let bias = PIE_REGION.0 + aslr_offset(PIE_REGION.1);
+		kernel_log!("- load bias = {:#x}", bias);
+		handle.set_load_bias(bias);
+	}
 	
 	let entrypoint = handle.get_entrypoint();
 	kernel_log!("- entrypoint = {:#x}", entrypoint);
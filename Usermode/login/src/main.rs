@@ -172,5 +172,13 @@ fn spawn_console_and_wait(path: &str)
 		};
 	//::syscalls::threads::wait(&mut [console.wait_terminate()], !0);
 	::syscalls::threads::wait(&mut [console.wait_terminate(), handle_server.wait_terminate()], !0);
+
+	// Session has ended (or one half crashed), terminate whatever is left
+	for p in [&console, &handle_server].iter() {
+		if p.get_status().is_none() {
+			p.terminate();
+		}
+		kernel_log!("Session process exited with status {:?}", p.get_status());
+	}
 }
 
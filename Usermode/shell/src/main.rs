@@ -12,6 +12,7 @@ extern crate loader;
 
 use syscalls::gui::KeyCode;
 use wtk::ModifierKey;
+use async::WaitController;
 
 macro_rules! imgpath {
 		($p:expr) => {concat!("/system/Tifflin/shared/images/",$p)};
@@ -45,7 +46,8 @@ fn main()
 		use wtk::menu::{Menu,Entry,Spacer};
 		Menu::new("Power Menu", (
 			Entry::new("Lock", 0, "", || {}),
-			Entry::new("Logout", 1, "", || {}),
+			// Exiting the session root ends the session (login cleans up the rest)
+			Entry::new("Logout", 1, "", || ::syscalls::threads::exit(0)),
 			Spacer,
 			Entry::new("Restart", 0, "", || {}),
 			Entry::new("Shut Down", 0, "", || {}),
@@ -80,7 +82,7 @@ fn main()
 			|_,_| system_menu.show()
 			);
 		let taskbar = ();
-		let clock_widget = ::std::cell::RefCell::new(::wtk::OwnedLabel::new("12:34".to_owned(), ::wtk::Colour::theme_text()));
+		let clock_widget = ::std::cell::RefCell::new(::wtk::OwnedLabel::new(clock_text(), ::wtk::Colour::theme_text()));
 		let power_button = ::wtk::Button::new(
 			::wtk::image::RasterMonoA::new_img(imgpath!("power.r8"), ::wtk::Colour::theme_text()).expect("Error loading power icon"),
 			|_button, _window| power_menu.show()
@@ -114,23 +116,70 @@ fn main()
 	win_background.show();
 	win_menu.show();
 
-	//let clock_timer = AsyncTimer::new_realtime( time::Realtime::now(), || {
-	//		let mut cur_time = time::Realtime::now();
-	//		// Update clock
-	//		menubar.inner().2.inner().borrow_mut().set( cur_time.format("%H:%M").to_string() );
-	//		// Re-schedule for the next minute
-	//		Some(cur_time % time::Interval::minute(1) + time::Interval::minute(1))
-	//		});
+	let mut menu_with_clock = ClockUpdater::new(&mut win_menu, menubar.inner().2.inner());
 
 	::async::idle_loop(&mut [
 		&mut win_background,
-		&mut win_menu,
+		&mut menu_with_clock,
 		&mut system_menu.waiter(),
 		&mut power_menu.waiter(),
 		]);
 
 }
 
+/// Current time of day for the menubar clock (UTC, no timezone support yet)
+fn clock_text() -> String
+{
+	let secs = ::std::time::SystemTime::now().duration_since(::std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+	format!("{:02}:{:02}", secs / 3600 % 24, secs / 60 % 60)
+}
+
+/// Monotonic time at which the wall-clock next reaches a whole minute
+fn next_clock_update() -> u64
+{
+	let ms = ::std::time::SystemTime::now().duration_since(::std::time::UNIX_EPOCH).map(|d| d.as_secs() * 1000 + d.subsec_millis() as u64).unwrap_or(0);
+	::syscalls::threads::get_ticks() + (60_000 - ms % 60_000)
+}
+
+/// Wraps the menubar window, updating the clock at the start of each minute
+struct ClockUpdater<'a, 'b: 'a, D: 'b>
+{
+	win: &'a mut ::wtk::Window<'b, D>,
+	label: &'a ::std::cell::RefCell<::wtk::OwnedLabel>,
+	/// Next update time (monotonic ticks)
+	next_update: u64,
+}
+impl<'a, 'b, D: ::wtk::decorator::Decorator> ClockUpdater<'a, 'b, D>
+{
+	fn new(win: &'a mut ::wtk::Window<'b, D>, label: &'a ::std::cell::RefCell<::wtk::OwnedLabel>) -> Self {
+		ClockUpdater {
+			win: win,
+			label: label,
+			next_update: next_clock_update(),
+			}
+	}
+}
+impl<'a, 'b, D: ::wtk::decorator::Decorator> ::async::WaitController for ClockUpdater<'a, 'b, D>
+{
+	fn get_count(&self) -> usize {
+		self.win.get_count()
+	}
+	fn populate(&self, cb: &mut dyn FnMut(::syscalls::WaitItem)) {
+		self.win.populate(cb)
+	}
+	fn handle(&mut self, events: &[::syscalls::WaitItem]) {
+		self.win.handle(events);
+		if ::syscalls::threads::get_ticks() >= self.next_update {
+			self.label.borrow_mut().set( clock_text() );
+			self.win.redraw_all();
+			self.next_update = next_clock_update();
+		}
+	}
+	fn wake_time(&self) -> u64 {
+		self.next_update
+	}
+}
+
 fn start_app<F>(args: &[&str], cb: F)
 where
 	F: FnOnce(&mut ::loader::ProtoProcess)
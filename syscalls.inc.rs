@@ -59,11 +59,11 @@ def_groups! {
 		=2: CORE_EXITPROCESS,
 		/// Request a text string from the kernel
 		=3: CORE_TEXTINFO,
-		/// Terminate the current thread
+		/// Terminate the current thread (with an exit status)
 		=4: CORE_EXITTHREAD,
 		/// Start a new process (loader only, use loader API instead)
 		=5: CORE_STARTPROCESS,
-		/// Start a new thread in the current process
+		/// Start a new thread in the current process (returns a `CLASS_CORE_THREAD` handle)
 		=6: CORE_STARTTHREAD,
 		/// Wait for any of a set of events
 		=7: CORE_WAIT,
@@ -71,6 +71,30 @@ def_groups! {
 		=8: CORE_FUTEX_SLEEP,
 		/// Wake a number of sleepers on a futex
 		=9: CORE_FUTEX_WAKE,
+		/// Start a new process with a copy-on-write clone of the entire address space
+		=10: CORE_FORKPROCESS,
+		/// Fill a buffer with random bytes (not cryptographically secure)
+		=11: CORE_GETRANDOM,
+		/// Set the current thread's TLS base
+		=12: CORE_SETTLS,
+		/// Get the monotonic time (milliseconds since boot, the timebase for `CORE_WAIT` and `CORE_FUTEX_SLEEP`)
+		=13: CORE_GETTICKS,
+		/// Set the current thread's scheduling priority (returns the previous priority)
+		=14: CORE_SETPRIORITY,
+		/// List processes (fills a `ProcessInfo` buffer starting at a PID, returns the number of entries written)
+		=15: CORE_LISTPROCESSES,
+		/// List threads (fills a `ThreadInfo` buffer starting at a TID, returns the number of entries written)
+		=16: CORE_LISTTHREADS,
+		/// Get the wall-clock time (milliseconds since 1970-01-01 00:00:00 UTC)
+		=17: CORE_GETTIME,
+		/// Set the wall-clock time (milliseconds since 1970-01-01 00:00:00 UTC, also updates the hardware clock). Init (PID 0) only
+		=18: CORE_SETTIME,
+		/// Set the current thread's exception handler (entry point and alternate stack top, zero entry to remove; returns the previous entry)
+		=19: CORE_SETEXCEPTIONHANDLER,
+		/// Return from an exception handler, resuming with the state in the passed `ExceptionFrame`
+		=20: CORE_EXCEPTIONRETURN,
+		/// Lower one of the current process's resource limits (`ResourceLimit`, new value), returns the resulting limit
+		=21: CORE_LOWERLIMIT,
 	},
 	/// GUI System calls
 	=1: GROUP_GUI = {
@@ -102,12 +126,40 @@ def_groups! {
 		=1: NET_LISTEN,
 		/// Open a free-form datagram 'socket'
 		=2: NET_BIND,
+	},
+	/// Device management
+	=5: GROUP_DEVICES = {
+		/// Open a handle that receives storage volume arrival/removal events
+		=0: DEV_VOLUME_EVENTS,
 	}
 }
 
 /// Value for `get_text_info`'s `unit` argument, indicating kernel core
 pub const TEXTINFO_KERNEL: u32 = 0;
 
+/// `CORE_PROCESS_GETSTATUS`/`CORE_THREAD_GETSTATUS` result while not yet terminated (outside the range of exit statuses)
+pub const STATUS_RUNNING: u64 = 1 << 32;
+
+/// Exit status of a process terminated by `CORE_PROCESS_KILL`
+pub const EXITSTATUS_KILLED: u32 = 0x8000_0001;
+/// Exit status of a process terminated by a CPU exception that the faulting thread didn't handle
+pub const EXITSTATUS_FAULT: u32 = 0x8000_0002;
+
+/// `CORE_FUTEX_SLEEP` result: Woken by `CORE_FUTEX_WAKE`
+pub const FUTEX_WOKEN: u32 = 0;
+/// `CORE_FUTEX_SLEEP` result: The value didn't match, so the thread didn't sleep
+pub const FUTEX_VALUE_CHANGED: u32 = 1;
+/// `CORE_FUTEX_SLEEP` result: The timeout expired before a wake
+pub const FUTEX_TIMED_OUT: u32 = 2;
+
+/// `CORE_SETPRIORITY`: Priority of newly created threads
+pub const PRIORITY_DEFAULT: u32 = 3;
+/// `CORE_SETPRIORITY`: Highest priority available to user threads (larger values are clamped)
+pub const PRIORITY_MAX: u32 = 5;
+
+/// `CORE_GETRANDOM` flag: Value is used for layout randomisation (zeroed if ASLR is disabled)
+pub const GETRANDOM_ASLR: u32 = 1;
+
 #[repr(C)]
 #[derive(Debug)]
 /// Object reference used by the CORE_WAIT system call
@@ -178,6 +230,8 @@ def_classes! {
 		/// Give the process one of this process's objects
 		/// This method blocks if the child process hasn't popped the previous object
 		=0: CORE_PROTOPROCESS_SENDOBJ,
+		/// Lower one of the process's resource limits (`ResourceLimit`, new value), returns the resulting limit
+		=1: CORE_PROTOPROCESS_LOWERLIMIT,
 		--
 		/// Start the process executing
 		=0: CORE_PROTOPROCESS_START,
@@ -187,6 +241,8 @@ def_classes! {
 	=1: CLASS_CORE_PROCESS = {
 		/// Request that the process be terminated
 		=0: CORE_PROCESS_KILL,
+		/// Get the exit status (`STATUS_RUNNING` if still running)
+		=1: CORE_PROCESS_GETSTATUS,
 		--
 	}|{
 		/// Wakes if the child process terminates
@@ -322,6 +378,26 @@ def_classes! {
 	--
 	}|{
 	},
+	/// Storage volume event listener
+	=14: CLASS_DEV_VOLUMEEVENTS = {
+		/// Read the next event into a `VolumeEvent` (returns 1 if an event was read, 0 if none waiting)
+		=0: DEV_VOLUMEEVENTS_READ,
+	--
+	}|{
+		/// Fires when an event is waiting
+		=0: EV_DEV_VOLUMEEVENTS_PENDING,
+	},
+	/// Handle to a thread in the current process
+	=15: CLASS_CORE_THREAD = {
+		/// Get the exit status (`STATUS_RUNNING` if still running)
+		=0: CORE_THREAD_GETSTATUS,
+		/// Queue a notification for the thread's exception handler (delivered when the thread next returns to user-mode)
+		=1: CORE_THREAD_NOTIFY,
+	--
+	}|{
+		/// Wakes if the thread terminates
+		=0: EV_THREAD_TERMINATED,
+	},
 /*
 	/// A registered read/write buffer
 	=12: CLASS_BUFFER = {
@@ -518,6 +594,188 @@ pub enum GuiEvent
 
 pub type RpcMessage = [u8; 32];
 
+// --------------------------------------------------------------------
+// Processes and threads
+// --------------------------------------------------------------------
+enum_to_from!{ ProcessState => u8:
+	/// At least one thread is still running
+	Running = 0,
+	/// An exit status has been set (e.g. the process was killed), but threads are still running
+	Exiting = 1,
+	/// All threads have terminated (a handle is keeping the process around)
+	Exited = 2,
+}
+/// Process information, returned by `CORE_LISTPROCESSES`
+#[derive(Copy,Clone)]
+#[repr(C)]
+pub struct ProcessInfo
+{
+	pub pid: u32,
+	/// PID of the process that started this process
+	pub parent: u32,
+	/// Number of threads that haven't terminated
+	pub thread_count: u32,
+	/// Exit status (only valid if `state` isn't `ProcessState::Running`)
+	pub exit_status: u32,
+	/// CPU time used by all threads in user-mode (milliseconds)
+	pub user_time: u64,
+	/// CPU time used by all threads in the kernel (milliseconds)
+	pub kernel_time: u64,
+	/// Resident user memory (pages)
+	pub resident_pages: u64,
+	/// `ProcessState` value
+	pub state: u8,
+	/// Length of the name (names longer than `name` are truncated)
+	pub name_len: u8,
+	pub _reserved: [u8; 6],
+	pub name: [u8; 48],
+}
+impl Default for ProcessInfo {
+	fn default() -> ProcessInfo {
+		ProcessInfo { pid: 0, parent: 0, thread_count: 0, exit_status: 0, user_time: 0, kernel_time: 0, resident_pages: 0, state: 0, name_len: 0, _reserved: [0; 6], name: [0; 48] }
+	}
+}
+impl ProcessInfo {
+	/// Obtain the process name (as bytes)
+	pub fn name(&self) -> &[u8] {
+		&self.name[.. self.name_len as usize]
+	}
+}
+
+enum_to_from!{ ThreadState => u8:
+	/// Executing on a CPU
+	Running = 0,
+	/// Waiting to be scheduled
+	Runnable = 1,
+	/// Waiting for an event
+	Sleeping = 2,
+	/// Terminated (a handle is keeping the thread around)
+	Dead = 3,
+}
+/// Thread information, returned by `CORE_LISTTHREADS`
+#[derive(Copy,Clone)]
+#[repr(C)]
+pub struct ThreadInfo
+{
+	pub tid: u32,
+	/// Owning process
+	pub pid: u32,
+	/// Exit status (only valid if `state` is `ThreadState::Dead`)
+	pub exit_status: u32,
+	/// `ThreadState` value
+	pub state: u8,
+	/// Base scheduling priority
+	pub priority: u8,
+	/// Length of the name (names longer than `name` are truncated)
+	pub name_len: u8,
+	pub _reserved: u8,
+	/// CPU time used in user-mode (milliseconds)
+	pub user_time: u64,
+	/// CPU time used in the kernel (milliseconds)
+	pub kernel_time: u64,
+	pub name: [u8; 48],
+}
+impl Default for ThreadInfo {
+	fn default() -> ThreadInfo {
+		ThreadInfo { tid: 0, pid: 0, exit_status: 0, state: 0, priority: 0, name_len: 0, _reserved: 0, user_time: 0, kernel_time: 0, name: [0; 48] }
+	}
+}
+impl ThreadInfo {
+	/// Obtain the thread name (as bytes)
+	pub fn name(&self) -> &[u8] {
+		&self.name[.. self.name_len as usize]
+	}
+}
+
+enum_to_from!{ ExceptionKind => u32:
+	/// Access to unmapped memory, or an access not permitted by the page's protection (`address` is the target)
+	PageFault = 0,
+	/// Undefined or privileged instruction
+	InvalidInstruction = 1,
+	/// Integer division by zero (or overflow)
+	DivideError = 2,
+	/// Other protection violation (e.g. a non-canonical address on amd64)
+	ProtectionFault = 3,
+	/// Breakpoint instruction (resuming continues after the breakpoint)
+	Breakpoint = 4,
+	/// Asynchronous notification from `CORE_THREAD_NOTIFY` (`code` is the notification value)
+	Notification = 5,
+}
+/// Saved state passed to an exception handler (on the handler's stack), and restored by `CORE_EXCEPTIONRETURN`
+#[derive(Copy,Clone)]
+#[repr(C)]
+pub struct ExceptionFrame
+{
+	/// `ExceptionKind` value
+	pub kind: u32,
+	/// Architecture-specific error code, or the notification value
+	pub code: u32,
+	/// Faulting address (for `ExceptionKind::PageFault`)
+	pub address: usize,
+	/// Instruction pointer (faulting instruction, or where execution resumes)
+	pub ip: usize,
+	/// Stack pointer
+	pub sp: usize,
+	/// Frame pointer (for backtraces)
+	pub fp: usize,
+	/// All general purpose registers, in architecture order
+	///
+	/// amd64: RAX, RCX, RDX, RBX, RSP, RBP, RSI, RDI, R8-R15, then RFLAGS. On return `ip` and `sp` take precedence over
+	/// the copies here, and `fp` is ignored.
+	pub regs: [usize; 32],
+}
+impl Default for ExceptionFrame {
+	fn default() -> ExceptionFrame {
+		ExceptionFrame { kind: 0, code: 0, address: 0, ip: 0, sp: 0, fp: 0, regs: [0; 32] }
+	}
+}
+
+enum_to_from!{ ResourceLimit => u32:
+	/// Mapped user memory pages (`MEM_ALLOCATE` fails once reached)
+	MemoryPages = 0,
+	/// Open object handles (object creation returns an error once reached)
+	Handles = 1,
+	/// Threads, including the root thread (`CORE_STARTTHREAD` fails once reached)
+	Threads = 2,
+	/// GUI windows (`GUI_NEWWINDOW` fails once reached)
+	Windows = 3,
+}
+/// Value of an unlimited resource limit
+pub const LIMIT_UNLIMITED: usize = !0;
+
+// --------------------------------------------------------------------
+// Devices
+// --------------------------------------------------------------------
+enum_to_from!{ VolumeEventKind => u8:
+	/// A new logical volume is avaliable
+	Added = 0,
+	/// A logical volume has been removed (e.g. the device was unplugged)
+	Removed = 1,
+}
+/// Storage volume event, read from a `CLASS_DEV_VOLUMEEVENTS` object
+#[derive(Copy,Clone)]
+#[repr(C)]
+pub struct VolumeEvent
+{
+	/// `VolumeEventKind` value
+	pub kind: u8,
+	/// Length of the name (names longer than `name` are truncated)
+	pub name_len: u8,
+	/// Logical volume name
+	pub name: [u8; 62],
+}
+impl Default for VolumeEvent {
+	fn default() -> VolumeEvent {
+		VolumeEvent { kind: 0, name_len: 0, name: [0; 62] }
+	}
+}
+impl VolumeEvent {
+	/// Obtain the volume name (as bytes)
+	pub fn name(&self) -> &[u8] {
+		&self.name[.. self.name_len as usize]
+	}
+}
+
 // --------------------------------------------------------------------
 // Network
 // --------------------------------------------------------------------